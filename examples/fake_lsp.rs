@@ -0,0 +1,173 @@
+// A minimal language server for the LSP client tests. It keeps the documents
+// it is sent and logs its copy after every change as
+// `<event> <uri> <json text>` through `window/logMessage`.
+//
+//   --full-sync           ask for whole documents instead of incremental edits
+//   --exit-marker <path>  record the shutdown sequence in <path> on exit
+//
+// A document containing CRASH makes it exit without shutting down, one containing
+// HANG makes it stop reading its input, and every ERROR in a document is published
+// as an error diagnostic.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let full_sync = args.iter().any(|a| a == "--full-sync");
+    let exit_marker = args
+        .iter()
+        .position(|a| a == "--exit-marker")
+        .and_then(|i| args.get(i + 1))
+        .cloned();
+
+    eprintln!("fake language server started");
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+    let mut shutdown = false;
+
+    while let Some(message) = read(&mut input) {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        match method {
+            "initialize" => {
+                let sync = if full_sync { 1 } else { 2 };
+                respond(&message["id"], json!({ "capabilities": { "textDocumentSync": sync } }));
+            }
+            "initialized" => {
+                send(json!({
+                    "jsonrpc": "2.0",
+                    "id": "configuration",
+                    "method": "workspace/configuration",
+                    "params": { "items": [{ "section": "fake" }] },
+                }));
+            }
+            "textDocument/didOpen" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+                let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                log(&format!("open {} {}", uri, json!(text)));
+                publish_diagnostics(&uri, &text);
+                documents.insert(uri, text);
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+                let text = documents.entry(uri.clone()).or_default();
+                for change in params["contentChanges"].as_array().into_iter().flatten() {
+                    let new_text = change["text"].as_str().unwrap_or("");
+                    if change.get("range").is_some() {
+                        let start = offset(text, &change["range"]["start"]);
+                        let end = offset(text, &change["range"]["end"]);
+                        text.replace_range(start..end, new_text);
+                    } else {
+                        *text = new_text.to_string();
+                    }
+                }
+                if text.contains("CRASH") {
+                    std::process::exit(1);
+                }
+                if text.contains("HANG") {
+                    loop {
+                        std::thread::sleep(std::time::Duration::from_secs(60));
+                    }
+                }
+                log(&format!("change {} {}", uri, json!(text)));
+                publish_diagnostics(&uri, text);
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                documents.remove(uri);
+                log(&format!("close {}", uri));
+            }
+            "textDocument/didSave" => log(&format!("save {}", params["textDocument"]["uri"].as_str().unwrap_or(""))),
+            "shutdown" => {
+                shutdown = true;
+                respond(&message["id"], Value::Null);
+            }
+            "exit" => {
+                if let Some(path) = &exit_marker {
+                    let sequence = if shutdown { "shutdown exit" } else { "exit" };
+                    std::fs::write(path, sequence).unwrap();
+                }
+                std::process::exit(if shutdown { 0 } else { 1 });
+            }
+            // The reply to `workspace/configuration`
+            "" if message["id"] == "configuration" => log(&format!("configuration {}", message["result"])),
+            _ => {}
+        }
+    }
+}
+
+fn read(input: &mut impl BufRead) -> Option<Value> {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok()?;
+        }
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn send(message: Value) {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    stdout.flush().unwrap();
+}
+
+fn respond(id: &Value, result: Value) {
+    send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+}
+
+fn log(message: &str) {
+    send(json!({ "jsonrpc": "2.0", "method": "window/logMessage", "params": { "type": 3, "message": message } }));
+}
+
+fn publish_diagnostics(uri: &str, text: &str) {
+    let mut diagnostics = Vec::new();
+    for (line, line_text) in text.split('\n').enumerate() {
+        for (start, _) in line_text.match_indices("ERROR") {
+            let character = line_text[..start].encode_utf16().count();
+            diagnostics.push(json!({
+                "range": {
+                    "start": { "line": line, "character": character },
+                    "end": { "line": line, "character": character + 5 },
+                },
+                "severity": 1,
+                "source": "fake",
+                "message": "found an ERROR",
+            }));
+        }
+    }
+    send(json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    }));
+}
+
+// The byte offset of an LSP position, whose column counts UTF-16 code units
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let line_start = text.split_inclusive('\n').take(line).map(str::len).sum::<usize>();
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
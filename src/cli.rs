@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage: exodus [options] [paths...]
+
+Paths may end in :line or :line:column to open a file at that position.
+A folder opens in the file explorer. A path that doesn't exist yet opens as
+a new file that is created on save.
+
+Options:
+  -                  Read the text to edit from standard input
+  -n, --new-window   Open a new window instead of reusing a running one
+  -w, --wait         Wait for the opened files to be closed before exiting,
+                     for use as $EDITOR or GIT_EDITOR
+  -d, --diff <a> <b> Compare two files
+  -h, --help         Print this help
+  -V, --version      Print the version";
+
+#[derive(Serialize, Deserialize)]
+pub enum Target {
+    // `line` and `column` are one-based, as typed
+    File {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    Folder(PathBuf),
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Args {
+    pub targets: Vec<Target>,
+    pub stdin: bool,
+    pub new_window: bool,
+    pub wait: bool,
+    pub diff: Option<(PathBuf, PathBuf)>,
+    pub help: bool,
+    pub version: bool,
+}
+
+// Parses the arguments after the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+    let mut only_paths = false;
+
+    while let Some(arg) = args.next() {
+        if only_paths {
+            parsed.targets.push(target(&arg));
+            continue;
+        }
+        match arg.as_str() {
+            "-" => parsed.stdin = true,
+            "--" => only_paths = true,
+            "-n" | "--new-window" => parsed.new_window = true,
+            "-w" | "--wait" => parsed.wait = true,
+            "-d" | "--diff" => {
+                let (Some(a), Some(b)) = (args.next(), args.next()) else {
+                    return Err(format!("{} needs two files to compare", arg));
+                };
+                parsed.diff = Some((PathBuf::from(a), PathBuf::from(b)));
+            }
+            "-h" | "--help" => parsed.help = true,
+            "-V" | "--version" => parsed.version = true,
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ => parsed.targets.push(target(&arg)),
+        }
+    }
+    Ok(parsed)
+}
+
+impl Args {
+    // Resolves relative paths, so they mean the same to another process
+    pub fn make_absolute(&mut self, cwd: &Path) {
+        for target in &mut self.targets {
+            match target {
+                Target::File { path, .. } | Target::Folder(path) => *path = cwd.join(&*path),
+            }
+        }
+        if let Some((old, new)) = &mut self.diff {
+            *old = cwd.join(&*old);
+            *new = cwd.join(&*new);
+        }
+    }
+}
+
+fn target(arg: &str) -> Target {
+    let path = Path::new(arg);
+    if path.is_dir() {
+        return Target::Folder(path.to_path_buf());
+    }
+    // A file may really be called `notes:2`, so the suffix only counts as a
+    // position when the full name doesn't exist
+    if !path.exists() {
+        if let Some((path, line, column)) = split_position(arg) {
+            return Target::File {
+                path: PathBuf::from(path),
+                line: Some(line),
+                column,
+            };
+        }
+    }
+    Target::File {
+        path: path.to_path_buf(),
+        line: None,
+        column: None,
+    }
+}
+
+// Splits `file:line` and `file:line:column`
+fn split_position(arg: &str) -> Option<(&str, usize, Option<usize>)> {
+    let (rest, last) = arg.rsplit_once(':')?;
+    let last: usize = last.parse().ok()?;
+    match rest.rsplit_once(':') {
+        Some((path, line)) if !path.is_empty() => match line.parse() {
+            Ok(line) => Some((path, line, Some(last))),
+            Err(_) => Some((rest, last, None)),
+        },
+        _ if !rest.is_empty() => Some((rest, last, None)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Args, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    // The path, line and column of a file target
+    fn position(target: &Target) -> (&Path, Option<usize>, Option<usize>) {
+        match target {
+            Target::File { path, line, column } => (path, *line, *column),
+            Target::Folder(path) => panic!("{} opened as a folder", path.display()),
+        }
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(split_position("file:10:5"), Some(("file", 10, Some(5))));
+        assert_eq!(split_position("file:10"), Some(("file", 10, None)));
+        assert_eq!(split_position("file"), None);
+        assert_eq!(split_position("file:"), None);
+        assert_eq!(split_position(":10"), None);
+        assert_eq!(split_position("file:ten"), None);
+        // Only the last two parts count, so a drive letter stays part of the path
+        assert_eq!(split_position(r"C:\x:3"), Some((r"C:\x", 3, None)));
+        assert_eq!(split_position(r"C:\x:3:4"), Some((r"C:\x", 3, Some(4))));
+    }
+
+    #[test]
+    fn targets() {
+        let args = parse_args(&["missing.rs:10:5", "missing.rs:10", "missing.rs"]).unwrap();
+        let positions: Vec<_> = args.targets.iter().map(position).collect();
+        assert_eq!(
+            positions,
+            [
+                (Path::new("missing.rs"), Some(10), Some(5)),
+                (Path::new("missing.rs"), Some(10), None),
+                (Path::new("missing.rs"), None, None),
+            ]
+        );
+
+        let args = parse_args(&["src"]).unwrap();
+        assert!(matches!(&args.targets[..], [Target::Folder(path)] if path == Path::new("src")));
+    }
+
+    #[test]
+    fn options() {
+        let args = parse_args(&["-", "-n", "--wait", "-d", "old", "new"]).unwrap();
+        assert!(args.stdin && args.new_window && args.wait);
+        assert_eq!(args.diff, Some((PathBuf::from("old"), PathBuf::from("new"))));
+        assert!(args.targets.is_empty());
+
+        let args = parse_args(&["--help", "-V"]).unwrap();
+        assert!(args.help && args.version);
+    }
+
+    #[test]
+    fn paths_after_double_dash() {
+        let args = parse_args(&["--", "-n", "--", "-"]).unwrap();
+        assert!(!args.new_window && !args.stdin);
+        let paths: Vec<_> = args.targets.iter().map(|target| position(target).0).collect();
+        assert_eq!(paths, [Path::new("-n"), Path::new("--"), Path::new("-")]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_args(&["--frobnicate"]).err(), Some("Unknown option: --frobnicate".to_string()));
+        assert_eq!(parse_args(&["-x", "file"]).err(), Some("Unknown option: -x".to_string()));
+        assert_eq!(parse_args(&["--diff", "old"]).err(), Some("--diff needs two files to compare".to_string()));
+    }
+}
@@ -0,0 +1,327 @@
+use egui::{Context, Key, KeyboardShortcut, Modifiers};
+
+use crate::keybindings::KeySequence;
+use crate::completion::Trigger;
+use crate::{Exodus, StatusItem};
+
+pub type CommandFn = fn(&mut Exodus, &Context);
+
+#[derive(Clone)]
+pub enum CommandHandler {
+    Builtin(CommandFn),
+    // `command` is None for the generic "run plugin" entry, which calls `Plugin::execute`
+    Plugin { plugin: String, command: Option<String> },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Menu {
+    File,
+    Edit,
+    View,
+    Go,
+    Terminal,
+    Plugins,
+}
+
+impl Menu {
+    pub const ALL: [Menu; 6] = [Menu::File, Menu::Edit, Menu::View, Menu::Go, Menu::Terminal, Menu::Plugins];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Menu::File => "File",
+            Menu::Edit => "Edit",
+            Menu::View => "View",
+            Menu::Go => "Go",
+            Menu::Terminal => "Terminal",
+            Menu::Plugins => "Plugins",
+        }
+    }
+}
+
+// Where a command shows up in the menu bar. Commands with different groups
+// in the same menu are separated by a separator.
+#[derive(Clone, Copy)]
+pub struct MenuPlacement {
+    pub menu: Menu,
+    pub group: u8,
+}
+
+#[derive(Clone)]
+pub struct Command {
+    pub id: String,
+    pub title: String,
+    // Default keybinding; keybindings.toml can override it
+    pub keybinding: Option<KeySequence>,
+    pub menu: Option<MenuPlacement>,
+    pub handler: CommandHandler,
+}
+
+impl Command {
+    pub fn new(id: &str, title: &str, handler: CommandFn) -> Self {
+        Self {
+            id: id.to_string(),
+            title: title.to_string(),
+            keybinding: None,
+            menu: None,
+            handler: CommandHandler::Builtin(handler),
+        }
+    }
+
+    pub fn keybinding(mut self, modifiers: Modifiers, key: Key) -> Self {
+        self.keybinding = Some(KeySequence::single(modifiers, key));
+        self
+    }
+
+    // Two-step binding such as `Ctrl+K Ctrl+O`
+    pub fn chord(mut self, first: (Modifiers, Key), second: (Modifiers, Key)) -> Self {
+        self.keybinding = Some(KeySequence(vec![
+            KeyboardShortcut::new(first.0, first.1),
+            KeyboardShortcut::new(second.0, second.1),
+        ]));
+        self
+    }
+
+    pub fn menu(mut self, menu: Menu, group: u8) -> Self {
+        self.menu = Some(MenuPlacement { menu, group });
+        self
+    }
+}
+
+pub struct CommandRegistry {
+    commands: Vec<Command>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        for command in builtin_commands() {
+            registry.register(command);
+        }
+        registry
+    }
+
+    // Registering a command with an existing id replaces the old one
+    pub fn register(&mut self, command: Command) {
+        if let Some(existing) = self.commands.iter_mut().find(|c| c.id == command.id) {
+            *existing = command;
+        } else {
+            self.commands.push(command);
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Command> {
+        self.commands.iter().find(|c| c.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.commands.iter()
+    }
+
+    pub fn in_menu(&self, menu: Menu) -> Vec<&Command> {
+        let mut commands: Vec<&Command> = self
+            .commands
+            .iter()
+            .filter(|c| c.menu.is_some_and(|m| m.menu == menu))
+            .collect();
+        // Stable sort keeps registration order within a group
+        commands.sort_by_key(|c| c.menu.map(|m| m.group));
+        commands
+    }
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn builtin_commands() -> Vec<Command> {
+    vec![
+        Command::new("file.new", "New File", |app, _| app.editor.new_file())
+            .keybinding(Modifiers::COMMAND, Key::N)
+            .menu(Menu::File, 0),
+        Command::new("file.open", "Open File...", |app, _| {
+            if let Some(path) = rfd::FileDialog::new().pick_file() {
+                app.open_file(path);
+            }
+        })
+        .keybinding(Modifiers::COMMAND, Key::O)
+        .menu(Menu::File, 0),
+        Command::new("file.open_hex", "Open File in Hex Editor...", |app, _| {
+            if let Some(path) = rfd::FileDialog::new().pick_file() {
+                app.open_hex(path);
+            }
+        })
+        .menu(Menu::File, 0),
+        Command::new("file.reopen_in_hex_editor", "Reopen in Hex Editor", |app, _| {
+            if let Some(path) = app.editor.current_file_path().cloned() {
+                app.open_hex(path);
+            }
+        }),
+        Command::new("file.open_folder", "Open Folder...", |app, _| {
+            if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                app.editor.open_folder(path);
+            }
+        })
+        .chord((Modifiers::COMMAND, Key::K), (Modifiers::COMMAND, Key::O))
+        .menu(Menu::File, 0),
+        Command::new("file.save", "Save File", |app, _| app.save_current())
+            .keybinding(Modifiers::COMMAND, Key::S)
+            .menu(Menu::File, 1),
+        Command::new("file.save_as", "Save As...", |app, _| app.save_current_as())
+            .keybinding(Modifiers::COMMAND | Modifiers::SHIFT, Key::S)
+            .menu(Menu::File, 1),
+        Command::new("file.save_all", "Save All", |app, _| app.save_all())
+            .menu(Menu::File, 1),
+        Command::new("file.revert", "Revert File", |app, _| app.revert_current())
+            .menu(Menu::File, 1),
+        Command::new("file.close", "Close Tab", |app, _| app.editor.close_file())
+            .keybinding(Modifiers::COMMAND, Key::W)
+            .menu(Menu::File, 1),
+        Command::new("file.close_others", "Close Other Tabs", |app, _| app.editor.close_other_tabs()),
+        Command::new("file.toggle_pin", "Pin/Unpin Tab", |app, _| app.editor.toggle_pin_current()),
+        Command::new("file.copy_path", "Copy Path of Active File", |app, ctx| {
+            if let Some(path) = app.editor.current_file_path() {
+                ctx.output_mut(|o| o.copied_text = path.display().to_string());
+            }
+        }),
+        Command::new("file.reveal", "Reveal Active File in File Explorer", |app, _| app.reveal_current()),
+        Command::new("file.reopen_closed", "Reopen Closed Tab", |app, _| app.reopen_closed_tab())
+            .keybinding(Modifiers::COMMAND | Modifiers::SHIFT, Key::T)
+            .menu(Menu::File, 1),
+        Command::new("file.change_encoding", "Change File Encoding...", |app, _| {
+            app.open_status_picker(StatusItem::Encoding)
+        }),
+        Command::new("file.change_line_ending", "Change End of Line Sequence...", |app, _| {
+            app.open_status_picker(StatusItem::LineEnding)
+        }),
+        Command::new("preferences.open_settings", "Preferences: Open Settings", |app, _| {
+            app.open_settings_file()
+        })
+        .menu(Menu::File, 2),
+        Command::new("preferences.keyboard_shortcuts", "Preferences: Keyboard Shortcuts", |app, _| {
+            app.show_keybindings = true
+        })
+        .chord((Modifiers::COMMAND, Key::K), (Modifiers::COMMAND, Key::S))
+        .menu(Menu::File, 2),
+        Command::new("preferences.open_keybindings", "Preferences: Open Keybindings File", |app, _| {
+            app.open_keybindings_file()
+        })
+        .menu(Menu::File, 2),
+        Command::new("preferences.reload_keybindings", "Preferences: Reload Keybindings", |app, _| {
+            app.reload_keybindings()
+        }),
+        Command::new("app.quit", "Quit", |_, _| std::process::exit(0))
+            .keybinding(Modifiers::COMMAND, Key::Q)
+            .menu(Menu::File, 3),
+        Command::new("edit.undo", "Undo", |app, _| app.editor.undo())
+            .keybinding(Modifiers::COMMAND, Key::Z)
+            .menu(Menu::Edit, 0),
+        Command::new("edit.redo", "Redo", |app, _| app.editor.redo())
+            .keybinding(Modifiers::COMMAND, Key::Y)
+            .menu(Menu::Edit, 0),
+        Command::new("editor.trigger_suggest", "Trigger Suggest", |app, _| {
+            app.completion_trigger = Some(Trigger::Invoked)
+        })
+        .keybinding(Modifiers::COMMAND, Key::Space)
+        .menu(Menu::Edit, 0),
+        Command::new("editor.rename", "Rename Symbol", |app, _| app.start_rename())
+            .keybinding(Modifiers::NONE, Key::F2)
+            .menu(Menu::Edit, 0),
+        Command::new("editor.code_actions", "Quick Fix...", |app, _| app.show_code_actions())
+            .keybinding(Modifiers::ALT, Key::Enter)
+            .menu(Menu::Edit, 0),
+        Command::new("editor.format_document", "Format Document", |app, _| app.format_document())
+            .keybinding(Modifiers::SHIFT | Modifiers::ALT, Key::F)
+            .menu(Menu::Edit, 0),
+        Command::new("editor.format_selection", "Format Selection", |app, _| app.format_selection())
+            .chord((Modifiers::COMMAND, Key::K), (Modifiers::COMMAND, Key::F))
+            .menu(Menu::Edit, 0),
+        Command::new("editor.toggle_format_on_save", "Toggle Format on Save", |app, _| {
+            app.toggle_format_on_save()
+        }),
+        Command::new("git.stage_change", "Stage Change", |app, _| app.stage_change()).menu(Menu::Edit, 2),
+        Command::new("git.revert_change", "Revert Change", |app, _| app.editor.revert_change()).menu(Menu::Edit, 2),
+        Command::new("search.toggle", "Find", |app, _| app.toggle_search())
+            .keybinding(Modifiers::COMMAND, Key::F)
+            .menu(Menu::Edit, 1),
+        Command::new("search.next", "Find Next", |app, _| {
+            if !app.search_query.is_empty() {
+                app.editor.find_next(&app.search_query);
+            }
+        })
+        .keybinding(Modifiers::NONE, Key::F3)
+        .menu(Menu::Edit, 1),
+        Command::new("navigate.back", "Go Back", |app, _| app.editor.navigate_back())
+            .keybinding(Modifiers::ALT, Key::ArrowLeft)
+            .menu(Menu::Go, 0),
+        Command::new("navigate.forward", "Go Forward", |app, _| app.editor.navigate_forward())
+            .keybinding(Modifiers::ALT, Key::ArrowRight)
+            .menu(Menu::Go, 0),
+        Command::new("navigate.go_to_line", "Go to Line...", |app, _| {
+            app.open_status_picker(StatusItem::GoToLine)
+        })
+        .keybinding(Modifiers::COMMAND, Key::G)
+        .menu(Menu::Go, 1),
+        Command::new("navigate.go_to_symbol", "Go to Symbol in File...", |app, _| app.open_symbol_picker())
+            .keybinding(Modifiers::COMMAND | Modifiers::SHIFT, Key::O)
+            .menu(Menu::Go, 1),
+        Command::new("navigate.go_to_definition", "Go to Definition", |app, _| app.go_to_definition())
+            .keybinding(Modifiers::NONE, Key::F12)
+            .menu(Menu::Go, 1),
+        Command::new("navigate.find_references", "Find All References", |app, _| app.find_references())
+            .keybinding(Modifiers::SHIFT, Key::F12)
+            .menu(Menu::Go, 1),
+        Command::new("navigate.next_problem", "Next Problem", |app, _| app.go_to_problem(true))
+            .keybinding(Modifiers::NONE, Key::F8)
+            .menu(Menu::Go, 2),
+        Command::new("navigate.previous_problem", "Previous Problem", |app, _| app.go_to_problem(false))
+            .keybinding(Modifiers::SHIFT, Key::F8)
+            .menu(Menu::Go, 2),
+        Command::new("navigate.next_change", "Next Change", |app, _| app.editor.go_to_change(true))
+            .keybinding(Modifiers::ALT, Key::F5)
+            .menu(Menu::Go, 2),
+        Command::new("navigate.previous_change", "Previous Change", |app, _| app.editor.go_to_change(false))
+            .keybinding(Modifiers::SHIFT | Modifiers::ALT, Key::F5)
+            .menu(Menu::Go, 2),
+        Command::new("view.toggle_explorer", "Toggle File Explorer", |app, _| {
+            app.show_file_explorer = !app.show_file_explorer;
+        })
+        .keybinding(Modifiers::COMMAND, Key::B)
+        .menu(Menu::View, 0),
+        Command::new("terminal.new", "New Terminal", |app, ctx| app.new_terminal(ctx))
+            .keybinding(Modifiers::COMMAND | Modifiers::SHIFT, Key::J)
+            .menu(Menu::Terminal, 0),
+        Command::new("terminal.toggle", "Toggle Terminal", |app, ctx| app.toggle_terminal(ctx))
+            .keybinding(Modifiers::COMMAND, Key::J)
+            .menu(Menu::Terminal, 0),
+        Command::new("terminal.kill", "Kill Terminal", |app, _| app.kill_terminal()).menu(Menu::Terminal, 0),
+        Command::new("tasks.run", "Run Task...", |app, _| app.pick_task())
+            .keybinding(Modifiers::COMMAND | Modifiers::SHIFT, Key::B)
+            .menu(Menu::Terminal, 1),
+        Command::new("tasks.rerun", "Rerun Last Task", |app, ctx| app.rerun_task(ctx)).menu(Menu::Terminal, 1),
+        Command::new("tasks.kill", "Kill Task", |app, _| app.kill_task()).menu(Menu::Terminal, 1),
+        Command::new("tasks.toggle_output", "Toggle Task Output", |app, _| app.show_tasks = !app.show_tasks)
+            .menu(Menu::Terminal, 1),
+        Command::new("tasks.configure", "Configure Tasks", |app, _| app.configure_tasks()).menu(Menu::Terminal, 1),
+        Command::new("view.toggle_output", "Toggle Output", |app, _| app.show_output = !app.show_output)
+            .keybinding(Modifiers::COMMAND | Modifiers::SHIFT, Key::U)
+            .menu(Menu::View, 0),
+        Command::new("view.toggle_problems", "Toggle Problems", |app, _| app.show_problems = !app.show_problems)
+            .keybinding(Modifiers::COMMAND | Modifiers::SHIFT, Key::M)
+            .menu(Menu::View, 0),
+        Command::new("view.toggle_vim_mode", "Toggle Vim Mode", |app, _| app.toggle_vim_mode())
+            .menu(Menu::View, 1),
+        Command::new("view.command_palette", "Command Palette...", |app, ctx| {
+            app.command_palette.open(ctx, &app.commands, &app.keymap)
+        })
+        .keybinding(Modifiers::COMMAND | Modifiers::SHIFT, Key::P)
+        .menu(Menu::View, 0),
+    ]
+}
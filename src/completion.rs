@@ -0,0 +1,472 @@
+use egui::{Context, Frame, Order, Pos2, RichText, ScrollArea};
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::fuzzy::fuzzy_score;
+
+// Words from the buffer shorter than this aren't worth suggesting
+const MIN_WORD_LENGTH: usize = 3;
+const MAX_BUFFER_WORDS: usize = 5000;
+const MAX_SHOWN: usize = 200;
+
+#[derive(Clone)]
+pub struct CompletionItem {
+    pub label: String,
+    // Short kind shown next to the label, e.g. "fn"
+    pub kind: &'static str,
+    pub detail: Option<String>,
+    pub documentation: Option<String>,
+    // Replaces the typed word; in LSP snippet syntax when `snippet` is set
+    pub insert_text: String,
+    pub snippet: bool,
+    // What the typed word is matched against
+    pub filter_text: String,
+    // Order while nothing distinguishes the matches
+    pub sort_text: String,
+}
+
+impl CompletionItem {
+    pub fn word(word: &str) -> Self {
+        Self {
+            label: word.to_string(),
+            kind: "abc",
+            detail: None,
+            documentation: None,
+            insert_text: word.to_string(),
+            snippet: false,
+            filter_text: word.to_string(),
+            sort_text: word.to_string(),
+        }
+    }
+}
+
+// Why completion was asked for
+pub enum Trigger {
+    // Ctrl+Space
+    Invoked,
+    // A word character was typed
+    Typing,
+    // One of the server's trigger characters, such as `.`, was typed
+    Character(String),
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Char index where the word ending at `cursor` starts
+pub fn word_start(text: &str, cursor: usize) -> usize {
+    let before: Vec<char> = text.chars().take(cursor).collect();
+    let word = before.iter().rev().take_while(|&&c| is_word_char(c)).count();
+    cursor - word
+}
+
+// The whole word around `cursor`, e.g. the symbol to rename
+pub fn word_at(text: &str, cursor: usize) -> String {
+    let start = word_start(text, cursor);
+    text.chars().skip(start).take_while(|&c| is_word_char(c)).collect()
+}
+
+// Identifiers in the buffer, except `skip`, which is the word being typed
+pub fn buffer_words(text: &str, skip: &str) -> Vec<CompletionItem> {
+    let mut seen = HashSet::new();
+    text.split(|c: char| !is_word_char(c))
+        .filter(|word| {
+            word.chars().count() >= MIN_WORD_LENGTH
+                && !word.starts_with(|c: char| c.is_ascii_digit())
+                && *word != skip
+        })
+        .filter(|word| seen.insert(*word))
+        .take(MAX_BUFFER_WORDS)
+        .map(CompletionItem::word)
+        .collect()
+}
+
+// Suggestions for the word being typed, filtered as it grows
+pub struct CompletionSession {
+    tab_id: usize,
+    // Char index where the word being completed starts
+    anchor: usize,
+    query: String,
+    lsp_items: Vec<CompletionItem>,
+    word_items: Vec<CompletionItem>,
+    // Indices into `lsp_items` followed by `word_items`, best first
+    matches: Vec<usize>,
+    selected: usize,
+    scroll_to_selected: bool,
+    // The outstanding request to the language server
+    request: Option<i64>,
+    requested_query: String,
+    // The server's list depends on the typed word, so it is asked again as it changes
+    incomplete: bool,
+}
+
+impl CompletionSession {
+    pub fn new(tab_id: usize, anchor: usize, query: &str, word_items: Vec<CompletionItem>) -> Self {
+        let mut session = Self {
+            tab_id,
+            anchor,
+            query: query.to_string(),
+            lsp_items: Vec::new(),
+            word_items,
+            matches: Vec::new(),
+            selected: 0,
+            scroll_to_selected: false,
+            request: None,
+            requested_query: String::new(),
+            incomplete: false,
+        };
+        session.filter();
+        session
+    }
+
+    pub fn tab_id(&self) -> usize {
+        self.tab_id
+    }
+
+    pub fn anchor(&self) -> usize {
+        self.anchor
+    }
+
+    pub fn set_query(&mut self, query: &str) {
+        if query != self.query {
+            self.query = query.to_string();
+            self.selected = 0;
+            self.filter();
+        }
+    }
+
+    pub fn requested(&mut self, id: i64) {
+        self.request = Some(id);
+        self.requested_query = self.query.clone();
+    }
+
+    pub fn needs_request(&self) -> bool {
+        self.incomplete && self.request.is_none() && self.requested_query != self.query
+    }
+
+    // Takes the server's items if they answer the latest request
+    pub fn receive(&mut self, id: i64, items: Vec<CompletionItem>, incomplete: bool) -> bool {
+        if self.request != Some(id) {
+            return false;
+        }
+        self.request = None;
+        self.incomplete = incomplete;
+        // The server knows better than the buffer what these words are
+        let labels: HashSet<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        self.word_items.retain(|item| !labels.contains(item.label.as_str()));
+        self.lsp_items = items;
+        self.filter();
+        true
+    }
+
+    fn item(&self, index: usize) -> &CompletionItem {
+        match index.checked_sub(self.lsp_items.len()) {
+            Some(word) => &self.word_items[word],
+            None => &self.lsp_items[index],
+        }
+    }
+
+    fn filter(&mut self) {
+        let mut matches: Vec<(i32, usize)> = (0..self.lsp_items.len() + self.word_items.len())
+            .filter_map(|i| {
+                let item = self.item(i);
+                // Words only once something has been typed, and never the word itself
+                if i >= self.lsp_items.len() && (self.query.is_empty() || item.label == self.query) {
+                    return None;
+                }
+                fuzzy_score(&self.query, &item.filter_text).map(|score| (score, i))
+            })
+            .collect();
+        // Server items come first among equals, then their own order
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .cmp(a_score)
+                .then((*a >= self.lsp_items.len()).cmp(&(*b >= self.lsp_items.len())))
+                .then_with(|| self.item(*a).sort_text.cmp(&self.item(*b).sort_text))
+        });
+        matches.truncate(MAX_SHOWN);
+        self.matches = matches.into_iter().map(|(_, i)| i).collect();
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+    }
+
+    pub fn is_visible(&self) -> bool {
+        !self.matches.is_empty()
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + 1) % self.matches.len();
+            self.scroll_to_selected = true;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + self.matches.len() - 1) % self.matches.len();
+            self.scroll_to_selected = true;
+        }
+    }
+
+    pub fn selected_item(&self) -> Option<&CompletionItem> {
+        self.matches.get(self.selected).map(|&i| self.item(i))
+    }
+
+    // Whether Enter should accept rather than start a new line: not when the
+    // word is already typed out in full
+    pub fn accepts_enter(&self) -> bool {
+        self.selected_item().is_some_and(|item| item.label != self.query)
+    }
+
+    // The list below the cursor with the selected item's documentation beside it.
+    // Returns an item that was clicked.
+    pub fn show(&mut self, ctx: &Context, position: Pos2) -> Option<CompletionItem> {
+        if !self.is_visible() {
+            return None;
+        }
+        let mut clicked = None;
+        egui::Area::new("completion")
+            .order(Order::Foreground)
+            .fixed_pos(position)
+            .show(ctx, |ui| {
+                ui.horizontal_top(|ui| {
+                    Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_width(320.0);
+                        ScrollArea::vertical().max_height(220.0).show(ui, |ui| {
+                            for (row, &i) in self.matches.iter().enumerate() {
+                                let item = self.item(i);
+                                ui.horizontal(|ui| {
+                                    ui.add_sized([28.0, 14.0], egui::Label::new(RichText::new(item.kind).weak().small()));
+                                    let label = ui.selectable_label(row == self.selected, &item.label);
+                                    if row == self.selected && self.scroll_to_selected {
+                                        label.scroll_to_me(None);
+                                    }
+                                    if label.clicked() {
+                                        clicked = Some(item.clone());
+                                    }
+                                });
+                            }
+                        });
+                    });
+
+                    let Some(item) = self.selected_item() else {
+                        return;
+                    };
+                    if item.detail.is_none() && item.documentation.is_none() {
+                        return;
+                    }
+                    Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(360.0);
+                        ScrollArea::vertical().id_source("completion_documentation").max_height(220.0).show(ui, |ui| {
+                            if let Some(detail) = &item.detail {
+                                ui.label(RichText::new(detail).monospace());
+                            }
+                            if let Some(documentation) = &item.documentation {
+                                if item.detail.is_some() {
+                                    ui.separator();
+                                }
+                                ui.label(documentation);
+                            }
+                        });
+                    });
+                });
+            });
+        self.scroll_to_selected = false;
+        clicked
+    }
+}
+
+// What accepting an item inserts: the text and its tab stops as char ranges
+// within it, in the order Tab visits them
+pub struct Insertion {
+    pub text: String,
+    pub tab_stops: Vec<Range<usize>>,
+}
+
+impl Insertion {
+    pub fn new(item: &CompletionItem) -> Self {
+        if item.snippet {
+            parse_snippet(&item.insert_text)
+        } else {
+            Self { text: item.insert_text.clone(), tab_stops: Vec::new() }
+        }
+    }
+}
+
+// Expands LSP snippet syntax: `$1`, `${1:placeholder}`, `${1|one,two|}` and
+// `$0` become tab stops, variables expand to their default or nothing
+pub fn parse_snippet(snippet: &str) -> Insertion {
+    let mut parser = SnippetParser {
+        chars: snippet.chars().collect(),
+        pos: 0,
+        text: String::new(),
+        len: 0,
+        stops: Vec::new(),
+    };
+    parser.parse(false);
+
+    // $1, $2, ... and $0 last; a number used twice is visited at its first use
+    let mut stops = parser.stops;
+    stops.sort_by_key(|(number, range)| (if *number == 0 { usize::MAX } else { *number }, range.start));
+    stops.dedup_by_key(|(number, _)| *number);
+    Insertion {
+        text: parser.text,
+        tab_stops: stops.into_iter().map(|(_, range)| range).collect(),
+    }
+}
+
+struct SnippetParser {
+    chars: Vec<char>,
+    pos: usize,
+    text: String,
+    // Chars in `text` so far
+    len: usize,
+    stops: Vec<(usize, Range<usize>)>,
+}
+
+impl SnippetParser {
+    fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.len += 1;
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    // Up to the end or, in a placeholder, its closing brace
+    fn parse(&mut self, nested: bool) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '\\' => match self.peek() {
+                    Some(escaped @ ('$' | '}' | '\\')) => {
+                        self.pos += 1;
+                        self.push(escaped);
+                    }
+                    _ => self.push('\\'),
+                },
+                '}' if nested => return,
+                '$' => self.dollar(),
+                _ => self.push(c),
+            }
+        }
+    }
+
+    // After a `$`: a tab stop, a placeholder, a choice or a variable
+    fn dollar(&mut self) {
+        let braced = self.peek() == Some('{');
+        if braced {
+            self.pos += 1;
+        }
+        let name_start = self.pos;
+        while self.peek().is_some_and(is_word_char) {
+            self.pos += 1;
+        }
+        if self.pos == name_start {
+            self.push('$');
+            if braced {
+                self.push('{');
+            }
+            return;
+        }
+        let number: Option<usize> = self.chars[name_start..self.pos].iter().collect::<String>().parse().ok();
+
+        let start = self.len;
+        if braced {
+            match self.peek() {
+                Some(':') => {
+                    self.pos += 1;
+                    self.parse(true);
+                }
+                Some('|') => {
+                    self.pos += 1;
+                    self.choice();
+                }
+                Some('}') => self.pos += 1,
+                _ => {}
+            }
+        }
+        if let Some(number) = number {
+            self.stops.push((number, start..self.len));
+        }
+    }
+
+    // `one,two|}`: the first option is inserted
+    fn choice(&mut self) {
+        let mut first = true;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '\\' => {
+                    if let Some(escaped) = self.peek() {
+                        self.pos += 1;
+                        if first {
+                            self.push(escaped);
+                        }
+                    }
+                }
+                '|' => {
+                    if self.peek() == Some('}') {
+                        self.pos += 1;
+                    }
+                    return;
+                }
+                ',' => first = false,
+                _ if first => self.push(c),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Tab stops of an inserted snippet, visited with Tab and Shift+Tab. Typing in
+// a stop shifts the ones after it.
+pub struct SnippetSession {
+    tab_id: usize,
+    // Char ranges in the buffer
+    stops: Vec<Range<usize>>,
+    current: usize,
+    // Chars in the buffer when the current stop was selected
+    length: usize,
+}
+
+impl SnippetSession {
+    pub fn new(tab_id: usize, stops: Vec<Range<usize>>, length: usize) -> Self {
+        Self { tab_id, stops, current: 0, length }
+    }
+
+    pub fn tab_id(&self) -> usize {
+        self.tab_id
+    }
+
+    pub fn current(&self) -> Range<usize> {
+        self.stops[self.current].clone()
+    }
+
+    // The stop `forward` or back from the current one, after accounting for
+    // what was typed; None past the last stop
+    pub fn step(&mut self, forward: bool, length: usize) -> Option<Range<usize>> {
+        let delta = length as isize - self.length as isize;
+        self.length = length;
+        let current = self.stops[self.current].clone();
+        for stop in &mut self.stops {
+            if stop.start > current.start {
+                // After the edited stop
+                stop.start = stop.start.saturating_add_signed(delta);
+                stop.end = stop.end.saturating_add_signed(delta);
+            } else if stop.end >= current.end {
+                // The edited stop itself, or a placeholder around it
+                stop.end = stop.end.saturating_add_signed(delta).max(stop.start);
+            }
+        }
+
+        if forward {
+            self.current += 1;
+            self.stops.get(self.current).cloned()
+        } else {
+            self.current = self.current.saturating_sub(1);
+            Some(self.current())
+        }
+    }
+}
@@ -1,13 +1,50 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use crate::error::{Error, Result};
+
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
     pub theme: String,
     pub font_size: f32,
     pub tab_size: usize,
     pub auto_save: bool,
     pub line_numbers: bool,
+    pub vim_mode: bool,
+    // Files larger than this open in the read-only large file viewer
+    pub large_file_threshold_mb: u64,
+    // Keep a copy of the previous version when saving
+    pub backup_on_save: bool,
+    // Folder for those copies; next to the file as `<name>.bak` when unset
+    pub backup_directory: Option<PathBuf>,
+    // Overwrite files in place when their folder isn't writable, instead of failing
+    pub save_in_place: bool,
+    // Language server to start per language, keyed by language name as shown in
+    // the status bar, e.g. "Rust"
+    pub language_servers: BTreeMap<String, LanguageServerConfig>,
+    // External formatter per language, used instead of the language server's
+    // formatting, e.g. "Rust" = { command = "rustfmt", args = ["--emit", "stdout"] }
+    pub formatters: BTreeMap<String, FormatterConfig>,
+    // Format files with their formatter or language server when saving
+    pub format_on_save: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LanguageServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+// A command that reads the text on stdin and writes it formatted to stdout.
+// `{file}` in the arguments stands for the file's path.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FormatterConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
 }
 
 impl Default for Config {
@@ -18,34 +55,48 @@ impl Default for Config {
             tab_size: 4,
             auto_save: false,
             line_numbers: true,
+            vim_mode: false,
+            large_file_threshold_mb: 20,
+            backup_on_save: false,
+            backup_directory: None,
+            save_in_place: false,
+            language_servers: BTreeMap::from([(
+                "Rust".to_string(),
+                LanguageServerConfig {
+                    command: "rust-analyzer".to_string(),
+                    args: Vec::new(),
+                },
+            )]),
+            formatters: BTreeMap::new(),
+            format_on_save: false,
         }
     }
 }
 
 impl Config {
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn load() -> Result<Self> {
         let config_path = Self::config_path()?;
         if config_path.exists() {
-            let content = std::fs::read_to_string(config_path)?;
-            Ok(toml::from_str(&content)?)
+            let content = std::fs::read_to_string(&config_path)
+                .map_err(|e| Error::io("read", &config_path, e))?;
+            toml::from_str(&content).map_err(|e| Error::toml(&config_path, &content, e))
         } else {
             Ok(Self::default())
         }
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save(&self) -> Result<()> {
         let config_path = Self::config_path()?;
         if let Some(parent) = config_path.parent() {
-            std::fs::create_dir_all(parent)?;
+            std::fs::create_dir_all(parent).map_err(|e| Error::io("create", parent, e))?;
         }
-        let content = toml::to_string_pretty(self)?;
-        std::fs::write(config_path, content)?;
+        let content = toml::to_string_pretty(self).map_err(|e| Error::Serialize(e.to_string()))?;
+        std::fs::write(&config_path, content).map_err(|e| Error::io("save", &config_path, e))?;
         Ok(())
     }
 
-    fn config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
-        let config_dir = dirs::config_dir()
-            .ok_or("Could not find config directory")?;
+    pub fn config_path() -> Result<PathBuf> {
+        let config_dir = dirs::config_dir().ok_or(Error::NoConfigDir)?;
         Ok(config_dir.join("Exodus").join("config.toml"))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,137 @@
+use egui::Color32;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    pub fn color(self) -> Color32 {
+        match self {
+            Severity::Error => Color32::from_rgb(255, 100, 100),
+            Severity::Warning => Color32::from_rgb(255, 200, 0),
+            Severity::Information => Color32::from_rgb(100, 170, 255),
+            Severity::Hint => Color32::GRAY,
+        }
+    }
+
+    pub fn icon(self) -> &'static str {
+        match self {
+            Severity::Error => "⊗",
+            Severity::Warning => "⚠",
+            Severity::Information => "ℹ",
+            Severity::Hint => "💡",
+        }
+    }
+}
+
+// Zero-based line and column; the column counts chars
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone)]
+pub struct Diagnostic {
+    pub start: Position,
+    pub end: Position,
+    pub severity: Severity,
+    pub message: String,
+    // What reported it, e.g. "rustc" or "clippy"
+    pub source: Option<String>,
+}
+
+impl Diagnostic {
+    // The byte range in `text`, clamped to it since the text may have changed
+    // since the diagnostic was reported. Empty ranges cover the next char, or
+    // the previous one at the end of a line, so they can be seen.
+    pub fn byte_range(&self, text: &str) -> Range<usize> {
+        let start = byte_offset(text, self.start);
+        let end = byte_offset(text, self.end).max(start);
+        if start < end {
+            return start..end;
+        }
+        if let Some(next) = text[start..].chars().next().filter(|&c| c != '\n') {
+            return start..start + next.len_utf8();
+        }
+        match text[..start].chars().next_back().filter(|&c| c != '\n') {
+            Some(previous) => start - previous.len_utf8()..start,
+            None => start..start,
+        }
+    }
+}
+
+pub fn byte_offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    line_start + line.char_indices().nth(position.column).map_or(line.len(), |(i, _)| i)
+}
+
+// Diagnostics per file and per producer (a language server, a linter), so each
+// producer replaces only its own results. Paths are canonical.
+#[derive(Default)]
+pub struct Diagnostics {
+    files: BTreeMap<PathBuf, BTreeMap<String, Vec<Diagnostic>>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, path: PathBuf, producer: &str, diagnostics: Vec<Diagnostic>) {
+        let producers = self.files.entry(path.clone()).or_default();
+        if diagnostics.is_empty() {
+            producers.remove(producer);
+        } else {
+            producers.insert(producer.to_string(), diagnostics);
+        }
+        if producers.is_empty() {
+            self.files.remove(&path);
+        }
+    }
+
+    // Drops everything a producer reported, e.g. when its server stops
+    pub fn clear_producer(&mut self, producer: &str) {
+        for producers in self.files.values_mut() {
+            producers.remove(producer);
+        }
+        self.files.retain(|_, producers| !producers.is_empty());
+    }
+
+    // Ordered by position
+    pub fn for_file(&self, path: &Path) -> Vec<&Diagnostic> {
+        let mut diagnostics: Vec<&Diagnostic> = self.files.get(path).into_iter().flat_map(|p| p.values().flatten()).collect();
+        diagnostics.sort_by_key(|d| (d.start, d.severity));
+        diagnostics
+    }
+
+    // Files with diagnostics, ordered by path
+    pub fn files(&self) -> impl Iterator<Item = (&Path, Vec<&Diagnostic>)> {
+        self.files.keys().map(|path| (path.as_path(), self.for_file(path)))
+    }
+
+    // Errors and warnings across all files
+    pub fn counts(&self) -> (usize, usize) {
+        let all = self.files.values().flat_map(|p| p.values().flatten());
+        all.fold((0, 0), |(errors, warnings), d| match d.severity {
+            Severity::Error => (errors + 1, warnings),
+            Severity::Warning => (errors, warnings + 1),
+            _ => (errors, warnings),
+        })
+    }
+}
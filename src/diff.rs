@@ -0,0 +1,375 @@
+use egui::{Color32, ScrollArea, TextStyle, Ui};
+use std::ops::Range;
+
+// Beyond this many differing lines the remaining lines are shown as one
+// removed and one added block; keeps memory bounded for unrelated files
+const MAX_EDIT_DISTANCE: usize = 2000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Same,
+    Removed,
+    Added,
+}
+
+// One row of a unified diff. Line numbers are one-based.
+pub struct DiffLine {
+    pub change: Change,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+// Line-by-line comparison of two texts using Myers' algorithm
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // The common start and end are cheap to find and usually most of the file
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut changes = vec![Change::Same; prefix];
+    changes.extend(myers(&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]));
+    changes.extend(std::iter::repeat_n(Change::Same, suffix));
+
+    let (mut i, mut j) = (0, 0);
+    changes
+        .into_iter()
+        .map(|change| {
+            let line = match change {
+                Change::Same => DiffLine {
+                    change,
+                    old_line: Some(i + 1),
+                    new_line: Some(j + 1),
+                    text: a[i].to_string(),
+                },
+                Change::Removed => DiffLine {
+                    change,
+                    old_line: Some(i + 1),
+                    new_line: None,
+                    text: a[i].to_string(),
+                },
+                Change::Added => DiffLine {
+                    change,
+                    old_line: None,
+                    new_line: Some(j + 1),
+                    text: b[j].to_string(),
+                },
+            };
+            if change != Change::Added {
+                i += 1;
+            }
+            if change != Change::Removed {
+                j += 1;
+            }
+            line
+        })
+        .collect()
+}
+
+// Shortest edit script between `a` and `b`, as one change per output line
+fn myers(a: &[&str], b: &[&str]) -> Vec<Change> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // The furthest x per diagonal k in -d..=d before each round d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    for d in 0..=max.min(MAX_EDIT_DISTANCE) as isize {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let index = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                return backtrack(&trace, n, m);
+            }
+        }
+    }
+
+    let mut changes = vec![Change::Removed; a.len()];
+    changes.extend(std::iter::repeat_n(Change::Added, b.len()));
+    changes
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let previous_x = at(previous_k);
+        let previous_y = previous_x - previous_k;
+
+        while x > previous_x && y > previous_y {
+            changes.push(Change::Same);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            changes.push(if x == previous_x { Change::Added } else { Change::Removed });
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    changes.reverse();
+    changes
+}
+
+// A run of changed lines: zero-based line ranges in the old and the new text
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+impl Hunk {
+    pub fn change(&self) -> HunkChange {
+        if self.old.is_empty() {
+            HunkChange::Added
+        } else if self.new.is_empty() {
+            HunkChange::Deleted
+        } else {
+            HunkChange::Modified
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HunkChange {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl HunkChange {
+    pub fn label(self) -> &'static str {
+        match self {
+            HunkChange::Added => "Added",
+            HunkChange::Modified => "Modified",
+            HunkChange::Deleted => "Deleted",
+        }
+    }
+
+    pub fn color(self) -> Color32 {
+        match self {
+            HunkChange::Added => Color32::from_rgb(100, 200, 100),
+            HunkChange::Modified => Color32::from_rgb(100, 150, 255),
+            HunkChange::Deleted => Color32::from_rgb(255, 100, 100),
+        }
+    }
+}
+
+pub fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut in_hunk = false;
+    for line in diff_lines(old, new) {
+        let (old_start, new_start) = (i, j);
+        if line.change != Change::Added {
+            i += 1;
+        }
+        if line.change != Change::Removed {
+            j += 1;
+        }
+        match hunks.last_mut() {
+            _ if line.change == Change::Same => in_hunk = false,
+            Some(hunk) if in_hunk => {
+                hunk.old.end = i;
+                hunk.new.end = j;
+            }
+            _ => {
+                hunks.push(Hunk { old: old_start..i, new: new_start..j });
+                in_hunk = true;
+            }
+        }
+    }
+    hunks
+}
+
+// `into` with the lines in `replaced` swapped for the lines in `lines` of `from`.
+// Reverting a hunk splices the old lines into the new text, staging one does the opposite.
+pub fn splice_lines(into: &str, replaced: Range<usize>, from: &str, lines: Range<usize>) -> String {
+    let target: Vec<&str> = into.split_inclusive('\n').collect();
+    let source: Vec<&str> = from.split_inclusive('\n').collect();
+    let pieces = target[..replaced.start]
+        .iter()
+        .chain(&source[lines])
+        .chain(&target[replaced.end..]);
+
+    let mut text = String::with_capacity(into.len());
+    for piece in pieces {
+        // A last line without a newline may have moved away from the end
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(piece);
+    }
+    text
+}
+
+// Read-only unified view of the differences between two files
+pub struct DiffView {
+    old_name: String,
+    new_name: String,
+    lines: Vec<DiffLine>,
+}
+
+impl DiffView {
+    pub fn new(old_name: String, old: &str, new_name: String, new: &str) -> Self {
+        Self {
+            old_name,
+            new_name,
+            lines: diff_lines(old, new),
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) {
+        let removed = self.lines.iter().filter(|l| l.change == Change::Removed).count();
+        let added = self.lines.iter().filter(|l| l.change == Change::Added).count();
+        ui.horizontal(|ui| {
+            ui.colored_label(Color32::from_rgb(255, 100, 100), format!("− {}", self.old_name));
+            ui.colored_label(Color32::from_rgb(100, 200, 100), format!("+ {}", self.new_name));
+            if removed == 0 && added == 0 {
+                ui.weak("The files are identical");
+            } else {
+                ui.weak(format!("{} removed, {} added", removed, added));
+            }
+        });
+        ui.separator();
+
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        let width = self.lines.len().to_string().len();
+        ScrollArea::both()
+            .auto_shrink([false, false])
+            .show_rows(ui, row_height, self.lines.len(), |ui, rows| {
+                for line in &self.lines[rows] {
+                    let number = |n: Option<usize>| n.map_or_else(|| " ".repeat(width), |n| format!("{:>width$}", n));
+                    let (sign, color) = match line.change {
+                        Change::Same => (' ', ui.visuals().text_color()),
+                        Change::Removed => ('-', Color32::from_rgb(255, 100, 100)),
+                        Change::Added => ('+', Color32::from_rgb(100, 200, 100)),
+                    };
+                    let text = format!(
+                        "{} {} {} {}",
+                        number(line.old_line),
+                        number(line.new_line),
+                        sign,
+                        line.text
+                    );
+                    ui.label(egui::RichText::new(text).monospace().color(color));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(old: &str, new: &str) -> String {
+        diff_lines(old, new)
+            .iter()
+            .map(|line| match line.change {
+                Change::Same => ' ',
+                Change::Removed => '-',
+                Change::Added => '+',
+            })
+            .collect()
+    }
+
+    fn lines(words: &str) -> String {
+        words.split(' ').map(|word| format!("{}\n", word)).collect()
+    }
+
+    #[test]
+    fn shortest_edit_script() {
+        // The example from Myers' paper, with an edit distance of five
+        let (old, new) = (lines("a b c a b b a"), lines("c b a b a c"));
+        let script = changes(&old, &new);
+        assert_eq!(script.chars().filter(|&c| c != ' ').count(), 5);
+
+        let diff = diff_lines(&old, &new);
+        let kept_old: Vec<&str> = diff.iter().filter(|l| l.change != Change::Added).map(|l| l.text.as_str()).collect();
+        let kept_new: Vec<&str> = diff.iter().filter(|l| l.change != Change::Removed).map(|l| l.text.as_str()).collect();
+        assert_eq!(kept_old, old.lines().collect::<Vec<_>>());
+        assert_eq!(kept_new, new.lines().collect::<Vec<_>>());
+
+        assert_eq!(changes("", ""), "");
+        assert_eq!(changes("a\n", ""), "-");
+        assert_eq!(changes("", "a\n"), "+");
+        assert_eq!(changes(&lines("a b c"), &lines("a x c")), " -+ ");
+    }
+
+    #[test]
+    fn line_numbers() {
+        let diff = diff_lines(&lines("a b c"), &lines("a c d"));
+        let numbers: Vec<_> = diff.iter().map(|line| (line.old_line, line.new_line)).collect();
+        assert_eq!(numbers, [(Some(1), Some(1)), (Some(2), None), (Some(3), Some(2)), (None, Some(3))]);
+    }
+
+    #[test]
+    fn gives_up_on_unrelated_texts() {
+        // A line in common between differing blocks is kept while the edit distance is small...
+        let text = |prefix: &str, count: usize| -> String {
+            let block = |part: usize| (0..count).map(move |i| format!("{} {} {}\n", prefix, part, i));
+            block(0).chain(["shared\n".to_string()]).chain(block(1)).collect()
+        };
+        let script = changes(&text("old", 10), &text("new", 10));
+        assert_eq!(script.matches(' ').count(), 1);
+
+        // ...but lost once it's past the limit, where everything is replaced
+        let count = MAX_EDIT_DISTANCE / 2;
+        let script = changes(&text("old", count), &text("new", count));
+        let lines = 2 * count + 1;
+        assert_eq!(script, "-".repeat(lines) + &"+".repeat(lines));
+    }
+
+    #[test]
+    fn hunk_ranges() {
+        let old = lines("a b c d e f");
+        let new = lines("a B c e f g");
+        assert_eq!(
+            hunks(&old, &new),
+            [
+                Hunk { old: 1..2, new: 1..2 },
+                Hunk { old: 3..4, new: 3..3 },
+                Hunk { old: 6..6, new: 5..6 },
+            ]
+        );
+        let kinds: Vec<_> = hunks(&old, &new).iter().map(|hunk| hunk.change().label()).collect();
+        assert_eq!(kinds, ["Modified", "Deleted", "Added"]);
+        assert!(hunks(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn splicing() {
+        let old = lines("a b c d");
+        let new = lines("a B c");
+        // Reverting a hunk puts the old lines back into the new text
+        assert_eq!(splice_lines(&new, 1..2, &old, 1..2), lines("a b c"));
+        // Staging one puts the new lines into the old text
+        assert_eq!(splice_lines(&old, 3..4, &new, 3..3), lines("a b c"));
+        assert_eq!(splice_lines(&old, 4..4, &new, 0..1), lines("a b c d a"));
+        // Text without a final newline
+        assert_eq!(splice_lines("a\nb", 1..2, "x\ny", 1..2), "a\ny");
+    }
+}
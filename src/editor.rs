@@ -1,12 +1,176 @@
+use egui::text::{CCursor, CCursorRange};
 use egui::{ScrollArea, TextEdit, Ui, Color32};
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::diff::{self, DiffView, Hunk, HunkChange};
+use crate::encoding::{is_binary, Encoding};
+use crate::error::{Error, Result};
+use crate::hex::HexEditor;
+use crate::large_file::{FileBytes, LargeFile};
+use crate::navigation::{History, Location};
+use crate::git;
+use crate::lsp::OpenDocument;
+use crate::save::{self, SaveOptions};
+use crate::syntax::{Highlights, Symbol, SyntaxHighlighter, PLAIN_TEXT};
+use crate::tab_bar::{TabAction, TabBar, TabLabel};
 
 pub struct Editor {
     tabs: Vec<EditorTab>,
     active_tab: usize,
     next_id: usize,
     workspace_folder: Option<PathBuf>,
+    // Indentation width for new files and files without indented lines
+    tab_size: usize,
+    // Files above this many bytes open in the large file viewer
+    large_file_threshold: u64,
+    save_options: SaveOptions,
+    closed_tabs: Vec<ClosedTab>,
+    tab_bar: TabBar,
+    history: History,
+    // Where the text cursor was drawn last frame, in screen coordinates
+    cursor_rect: Option<egui::Rect>,
+    // The char under the pointer in the active tab, if any
+    hover_char: Option<usize>,
+    // The text was Ctrl+clicked; the cursor is where the click landed
+    definition_click: bool,
+    // Line of the active tab to show the code action lightbulb on
+    lightbulb: Option<usize>,
+    lightbulb_click: bool,
+    // Tab id and marker line of the change whose original lines are shown
+    change_popup: Option<(usize, usize)>,
+    // "Stage" was clicked in the change popup
+    stage_click: bool,
+    // "Reveal in File Explorer" was picked from a tab's context menu
+    reveal_click: Option<PathBuf>,
+}
+
+const MAX_CLOSED_TABS: usize = 20;
+
+// Text tabs are kept whole so unsaved content comes back; the other views are
+// reopened from disk
+enum ClosedTab {
+    Text(Box<EditorTab>),
+    File { path: PathBuf, hex: bool },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub const ALL: [LineEnding; 2] = [LineEnding::Lf, LineEnding::Crlf];
+
+    // Both endings occur; saving writes only the detected one
+    pub fn is_mixed(text: &str) -> bool {
+        let crlf = text.matches("\r\n").count();
+        crlf > 0 && text.matches('\n').count() > crlf
+    }
+
+    // The most common ending in the text wins; files without newlines default to LF
+    pub fn detect(text: &str) -> Self {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        if crlf > lf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Indentation {
+    pub use_tabs: bool,
+    pub size: usize,
+}
+
+impl Indentation {
+    // Guesses the style from the leading whitespace of the indented lines
+    pub fn detect(text: &str, default_size: usize) -> Self {
+        let mut tab_lines = 0;
+        let mut space_lines = 0;
+        let mut smallest_indent = usize::MAX;
+        for line in text.lines() {
+            if line.starts_with('\t') {
+                tab_lines += 1;
+            } else {
+                let spaces = line.len() - line.trim_start_matches(' ').len();
+                // A single space is usually alignment, e.g. in block comments
+                if spaces > 1 && spaces < line.len() {
+                    space_lines += 1;
+                    smallest_indent = smallest_indent.min(spaces);
+                }
+            }
+        }
+
+        if tab_lines > space_lines {
+            Self { use_tabs: true, size: default_size }
+        } else if space_lines > 0 {
+            Self { use_tabs: false, size: smallest_indent.min(8) }
+        } else {
+            Self { use_tabs: false, size: default_size }
+        }
+    }
+
+    pub fn label(&self) -> String {
+        if self.use_tabs {
+            format!("Tab Size: {}", self.size)
+        } else {
+            format!("Spaces: {}", self.size)
+        }
+    }
+
+    // Text inserted by the Tab key
+    pub fn unit(&self) -> String {
+        if self.use_tabs {
+            "\t".to_string()
+        } else {
+            " ".repeat(self.size)
+        }
+    }
+}
+
+// What the status bar shows about the active tab
+pub struct EditorStatus {
+    pub line: usize,
+    pub column: usize,
+    pub selected: usize,
+    pub language: String,
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    pub indentation: Indentation,
+}
+
+// Binary files open in the hex editor and very large files in a read-only
+// viewer instead of the text editor; their `content` stays empty
+enum TabKind {
+    Text,
+    Hex(HexEditor),
+    Large(LargeFile),
+    Diff(DiffView),
 }
 
 pub struct EditorTab {
@@ -14,11 +178,61 @@ pub struct EditorTab {
     title: String,
     content: String,
     file_path: Option<PathBuf>,
+    // `file_path` with symlinks resolved, for looking tabs up by path
+    canonical_path: Option<PathBuf>,
     modified: bool,
     undo_stack: Vec<String>,
     redo_stack: Vec<String>,
     cursor_pos: usize,
     search_highlights: Vec<(usize, usize)>,
+    // Char range (anchor, head) to apply to the text widget on the next frame
+    pending_selection: Option<(usize, usize)>,
+    // Number of selected chars, as reported by the text widget
+    selected_chars: usize,
+    // Detected lazily from the file name and first line; the user can override it
+    language: Option<String>,
+    // Content is kept with `\n` line endings and converted back when saving
+    line_ending: LineEnding,
+    encoding: Encoding,
+    indentation: Indentation,
+    kind: TabKind,
+    // Pinned tabs stay at the start of the tab bar and aren't closed in bulk
+    pinned: bool,
+    // Saved since the last `take_saved_paths`
+    saved: bool,
+    // The text had both LF and CRLF endings when read; reported by `take_warnings`
+    mixed_line_endings: bool,
+    // Bumped whenever `content` changes
+    content_version: u64,
+    // When `content_version` last changed; the git diff waits for typing to pause
+    edited: Instant,
+    // When the git index was last checked for changes
+    index_checked: Option<Instant>,
+    // What the gutter compares the text against; None until the tab is first shown
+    git_base: Option<GitBase>,
+    // The staged text being read and diffed on a thread
+    git_diff: Option<Receiver<GitBase>>,
+}
+
+// The file as staged in git and the hunks where the text differs from it
+struct GitBase {
+    // None when the file isn't tracked
+    text: Option<Arc<str>>,
+    line_ending: LineEnding,
+    // The index is reread when it changes, e.g. after a commit
+    index_modified: Option<SystemTime>,
+    hunks: Vec<Hunk>,
+    // `content_version` the hunks were computed for
+    version: u64,
+}
+
+enum ChangeAction {
+    Show(usize),
+    Close,
+    Revert,
+    Stage,
+    Next,
+    Previous,
 }
 
 impl Editor {    pub fn new() -> Self {
@@ -27,90 +241,305 @@ impl Editor {    pub fn new() -> Self {
             active_tab: 0,
             next_id: 0,
             workspace_folder: None,
+            tab_size: 4,
+            large_file_threshold: 20 * 1024 * 1024,
+            save_options: SaveOptions::default(),
+            closed_tabs: Vec::new(),
+            tab_bar: TabBar::new(),
+            history: History::new(),
+            cursor_rect: None,
+            hover_char: None,
+            definition_click: false,
+            lightbulb: None,
+            lightbulb_click: false,
+            change_popup: None,
+            stage_click: false,
+            reveal_click: None,
         };
         editor.new_file();
         editor
     }
 
+    pub fn set_tab_size(&mut self, tab_size: usize) {
+        self.tab_size = tab_size.max(1);
+    }
+
+    pub fn set_large_file_threshold(&mut self, bytes: u64) {
+        self.large_file_threshold = bytes;
+    }
+
+    pub fn set_save_options(&mut self, options: SaveOptions) {
+        self.save_options = options;
+    }
+
     pub fn new_file(&mut self) {
-        let tab = EditorTab {
-            id: self.next_id,
-            title: format!("Untitled {}", self.next_id + 1),
-            content: String::new(),
-            file_path: None,
-            modified: false,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
-            cursor_pos: 0,
-            search_highlights: Vec::new(),
+        let tab = EditorTab::new(
+            self.next_id,
+            format!("Untitled {}", self.next_id + 1),
+            String::new(),
+            None,
+            Encoding::Utf8,
+            self.tab_size,
+        );
+        self.push_tab(tab);
+    }
+
+    // Focuses the file's tab if it is already open
+    pub fn open_file(&mut self, path: PathBuf) -> Result<()> {
+        if let Some(index) = self.find_tab_where(&path, |kind| !matches!(kind, TabKind::Hex(_))) {
+            self.switch_tab(index);
+            return Ok(());
+        }
+        let large = self.is_large(&path)?;
+        let bytes = read_bytes(&path, large)?;
+
+        let tab = if is_binary(&bytes) {
+            self.hex_tab(path, bytes)
+        } else if large {
+            let title = file_title(&path);
+            let large_file = LargeFile::new(bytes);
+            let encoding = large_file.encoding();
+            let mut tab = EditorTab::new(self.next_id, title, String::new(), Some(path), encoding, self.tab_size);
+            tab.kind = TabKind::Large(large_file);
+            tab
+        } else {
+            let encoding = Encoding::detect(&bytes);
+            let content = encoding
+                .decode(&bytes)
+                .map_err(|source| Error::Decode { path: path.clone(), source })?;
+            EditorTab::new(self.next_id, file_title(&path), content, Some(path), encoding, self.tab_size)
         };
-        
-        self.tabs.push(tab);
-        self.active_tab = self.tabs.len() - 1;
-        self.next_id += 1;
+
+        self.push_tab(tab);
+        Ok(())
     }
 
-    pub fn open_file(&mut self, path: PathBuf) {
-        if let Ok(content) = fs::read_to_string(&path) {
-            let title = path.file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("Untitled")
-                .to_string();
-
-            let tab = EditorTab {
-                id: self.next_id,
-                title,
-                content,
-                file_path: Some(path),
-                modified: false,
-                undo_stack: Vec::new(),
-                redo_stack: Vec::new(),
-                cursor_pos: 0,
-                search_highlights: Vec::new(),
-            };
+    // Opens any file as bytes, e.g. to inspect a text file's encoding
+    pub fn open_hex(&mut self, path: PathBuf) -> Result<()> {
+        if let Some(index) = self.find_tab_where(&path, |kind| matches!(kind, TabKind::Hex(_))) {
+            self.switch_tab(index);
+            return Ok(());
+        }
+        let large = self.is_large(&path)?;
+        let bytes = read_bytes(&path, large)?;
+        let tab = self.hex_tab(path, bytes);
+        self.push_tab(tab);
+        Ok(())
+    }
 
-            self.tabs.push(tab);
-            self.active_tab = self.tabs.len() - 1;
-            self.next_id += 1;
+    // Index of the tab showing `path`. Paths are compared after resolving `.`,
+    // `..` and symlinks, so different spellings of one file find the same tab.
+    pub fn find_tab(&self, path: &Path) -> Option<usize> {
+        self.find_tab_where(path, |_| true)
+    }
+
+    fn find_tab_where(&self, path: &Path, kind: impl Fn(&TabKind) -> bool) -> Option<usize> {
+        let path = canonical_path(path);
+        self.tabs
+            .iter()
+            .position(|tab| tab.canonical_path.as_ref() == Some(&path) && kind(&tab.kind))
+    }
+
+    // Opens an untitled tab holding `content`, e.g. text piped in on stdin
+    pub fn open_text(&mut self, title: String, content: String) {
+        let tab = EditorTab::new(self.next_id, title, content, None, Encoding::Utf8, self.tab_size);
+        self.push_tab(tab);
+    }
+
+    // Opens an empty tab for a file that doesn't exist yet; saving creates it
+    pub fn open_new_file(&mut self, path: PathBuf) {
+        if let Some(index) = self.find_tab(&path) {
+            self.switch_tab(index);
+            return;
         }
+        let tab = EditorTab::new(self.next_id, file_title(&path), String::new(), Some(path), Encoding::Utf8, self.tab_size);
+        self.push_tab(tab);
+    }
+
+    // Opens a read-only comparison of two text files
+    pub fn open_diff(&mut self, old: &Path, new: &Path) -> Result<()> {
+        let read = |path: &Path| -> Result<String> {
+            let bytes = fs::read(path).map_err(|e| Error::io("open", path, e))?;
+            Encoding::detect(&bytes)
+                .decode(&bytes)
+                .map_err(|source| Error::Decode { path: path.to_path_buf(), source })
+        };
+        let (old_text, new_text) = (read(old)?, read(new)?);
+
+        let (old_name, new_name) = (file_title(old), file_title(new));
+        let title = format!("{} ↔ {}", old_name, new_name);
+        let mut tab = EditorTab::new(self.next_id, title, String::new(), None, Encoding::Utf8, self.tab_size);
+        tab.kind = TabKind::Diff(DiffView::new(old_name, &old_text, new_name, &new_text));
+        self.push_tab(tab);
+        Ok(())
+    }
+
+    pub fn active_tab_id(&self) -> Option<usize> {
+        self.tabs.get(self.active_tab).map(|tab| tab.id)
+    }
+
+    pub fn is_tab_open(&self, id: usize) -> bool {
+        self.tabs.iter().any(|tab| tab.id == id)
+    }
+
+    fn is_large(&self, path: &Path) -> Result<bool> {
+        let metadata = fs::metadata(path).map_err(|e| Error::io("open", path, e))?;
+        Ok(metadata.len() > self.large_file_threshold)
+    }
+
+    fn hex_tab(&self, path: PathBuf, bytes: FileBytes) -> EditorTab {
+        let title = file_title(&path);
+        let mut tab = EditorTab::new(self.next_id, title, String::new(), Some(path), Encoding::Utf8, self.tab_size);
+        tab.kind = TabKind::Hex(HexEditor::new(self.next_id, bytes));
+        tab
+    }
+
+    fn push_tab(&mut self, tab: EditorTab) {
+        self.record_jump();
+        self.tabs.push(tab);
+        self.active_tab = self.tabs.len() - 1;
+        self.next_id += 1;
     }
 
     pub fn close_file(&mut self) {
         if self.tabs.len() > 1 {
-            self.tabs.remove(self.active_tab);
+            self.remove_tab(self.active_tab);
             if self.active_tab >= self.tabs.len() {
                 self.active_tab = self.tabs.len() - 1;
             }
         }
     }
 
-    pub fn save_current(&mut self) {
-        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-            if let Some(path) = &tab.file_path {
-                if fs::write(path, &tab.content).is_ok() {
-                    tab.modified = false;
+    // Writes the file back in its own encoding and line endings
+    pub fn save_current(&mut self) -> Result<()> {
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return Ok(());
+        };
+        match tab.file_path.clone() {
+            Some(path) => tab.save_to(path, &self.save_options),
+            None => self.save_current_as(),
+        }
+    }
+
+    // Asks where to save the active tab; cancelling the dialog is not an error
+    pub fn save_current_as(&mut self) -> Result<()> {
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return Ok(());
+        };
+        if matches!(tab.kind, TabKind::Large(_) | TabKind::Diff(_)) {
+            return Ok(());
+        }
+
+        let mut dialog = rfd::FileDialog::new();
+        if let Some(path) = &tab.file_path {
+            if let Some(directory) = path.parent() {
+                dialog = dialog.set_directory(directory);
+            }
+            dialog = dialog.set_file_name(file_title(path));
+        }
+        match dialog.save_file() {
+            Some(path) => tab.save_to(path, &self.save_options),
+            None => Ok(()),
+        }
+    }
+
+    // Saves every modified tab except those in `skip`, asking for a location
+    // for untitled ones. Keeps going after a failure and returns all errors.
+    pub fn save_all(&mut self, skip: &[usize]) -> Vec<Error> {
+        let mut errors = Vec::new();
+        for index in 0..self.tabs.len() {
+            let tab = &mut self.tabs[index];
+            if !tab.modified || skip.contains(&tab.id) {
+                continue;
+            }
+            let result = match tab.file_path.clone() {
+                Some(path) => tab.save_to(path, &self.save_options),
+                None => {
+                    self.active_tab = index;
+                    self.save_current_as()
                 }
-            } else {
-                // Save as dialog
-                if let Some(path) = rfd::FileDialog::new().save_file() {
-                    if fs::write(&path, &tab.content).is_ok() {
-                        tab.file_path = Some(path.clone());
-                        tab.title = path.file_name()
-                            .and_then(|n| n.to_str())
-                            .unwrap_or("Untitled")
-                            .to_string();
-                        tab.modified = false;
-                    }
+            };
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        }
+        errors
+    }
+
+    // Discards unsaved changes by reading the file from disk again. The text
+    // is replaced as one undo step, so the revert itself can be undone.
+    pub fn revert_current(&mut self) -> Result<()> {
+        let threshold = self.large_file_threshold;
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return Ok(());
+        };
+        let Some(path) = tab.file_path.clone() else {
+            return Ok(());
+        };
+        match &tab.kind {
+            TabKind::Text => tab.reload_text(None),
+            TabKind::Hex(_) => {
+                let metadata = fs::metadata(&path).map_err(|e| Error::io("read", &path, e))?;
+                let bytes = read_bytes(&path, metadata.len() > threshold)?;
+                tab.kind = TabKind::Hex(HexEditor::new(tab.id, bytes));
+                tab.modified = false;
+                Ok(())
+            }
+            TabKind::Large(_) | TabKind::Diff(_) => Ok(()),
+        }
+    }
+
+    // Restores the most recently closed tab with its content and cursor
+    pub fn reopen_closed_tab(&mut self) -> Result<()> {
+        match self.closed_tabs.pop() {
+            Some(ClosedTab::Text(mut tab)) => {
+                // The file may have been opened again since
+                if let Some(index) = tab.file_path.as_deref().and_then(|path| self.find_tab(path)) {
+                    self.switch_tab(index);
+                    return Ok(());
                 }
+                let cursor = byte_to_char(&tab.content, tab.cursor_pos);
+                tab.pending_selection = Some((cursor, cursor));
+                let index = if tab.pinned { self.pinned_count() } else { self.tabs.len() };
+                self.record_jump();
+                self.tabs.insert(index, *tab);
+                self.active_tab = index;
+                Ok(())
+            }
+            Some(ClosedTab::File { path, hex: true }) => self.open_hex(path),
+            Some(ClosedTab::File { path, hex: false }) => self.open_file(path),
+            None => Ok(()),
+        }
+    }
+
+    // Removes a tab and remembers it for `reopen_closed_tab`
+    fn remove_tab(&mut self, index: usize) {
+        let tab = self.tabs.remove(index);
+        let closed = match (&tab.kind, tab.file_path.clone()) {
+            // Nothing worth restoring
+            (TabKind::Text, None) if tab.content.is_empty() => None,
+            (TabKind::Text, _) => Some(ClosedTab::Text(Box::new(tab))),
+            (TabKind::Hex(_), Some(path)) => Some(ClosedTab::File { path, hex: true }),
+            (TabKind::Large(_), Some(path)) => Some(ClosedTab::File { path, hex: false }),
+            _ => None,
+        };
+        if let Some(closed) = closed {
+            if self.closed_tabs.len() >= MAX_CLOSED_TABS {
+                self.closed_tabs.remove(0);
             }
+            self.closed_tabs.push(closed);
         }
     }
 
     pub fn undo(&mut self) {
         if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-            if let Some(previous) = tab.undo_stack.pop() {
+            if let TabKind::Hex(hex) = &mut tab.kind {
+                hex.undo();
+            } else if let Some(previous) = tab.undo_stack.pop() {
                 tab.redo_stack.push(tab.content.clone());
                 tab.content = previous;
+                tab.content_changed();
+                tab.clamp_cursor();
                 tab.modified = true;
             }
         }
@@ -118,9 +547,13 @@ impl Editor {    pub fn new() -> Self {
 
     pub fn redo(&mut self) {
         if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-            if let Some(next) = tab.redo_stack.pop() {
+            if let TabKind::Hex(hex) = &mut tab.kind {
+                hex.redo();
+            } else if let Some(next) = tab.redo_stack.pop() {
                 tab.undo_stack.push(tab.content.clone());
                 tab.content = next;
+                tab.content_changed();
+                tab.clamp_cursor();
                 tab.modified = true;
             }
         }
@@ -128,18 +561,7 @@ impl Editor {    pub fn new() -> Self {
 
     pub fn highlight_search(&mut self, query: &str) {
         if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-            tab.search_highlights.clear();
-            if !query.is_empty() {
-                let content_lower = tab.content.to_lowercase();
-                let query_lower = query.to_lowercase();
-                let mut start = 0;
-                
-                while let Some(pos) = content_lower[start..].find(&query_lower) {
-                    let actual_pos = start + pos;
-                    tab.search_highlights.push((actual_pos, actual_pos + query.len()));
-                    start = actual_pos + 1;
-                }
-            }
+            tab.search_highlights = find_ignoring_case(&tab.content, query);
         }
     }
 
@@ -150,68 +572,121 @@ impl Editor {    pub fn new() -> Self {
     }
 
     pub fn find_next(&mut self, query: &str) {
-        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-            if query.is_empty() || tab.search_highlights.is_empty() {
-                return;
-            }
+        if !query.is_empty() {
+            self.find_next_match();
+        }
+    }
 
-            // Find the next match after the current cursor position
-            let current_pos = tab.cursor_pos;
-            let mut next_match = None;
+    // Moves the cursor to the first match after it, wrapping around
+    pub fn find_next_match(&mut self) -> Option<(usize, usize)> {
+        let tab = self.tabs.get_mut(self.active_tab)?;
+        let current_pos = tab.cursor_pos;
+        let next_match = tab.search_highlights
+            .iter()
+            .find(|&&(start, _)| start > current_pos)
+            .or_else(|| tab.search_highlights.first())
+            .copied();
 
-            // Look for the first match after current position
-            for &(start, end) in &tab.search_highlights {
-                if start > current_pos {
-                    next_match = Some((start, end));
-                    break;
-                }
-            }
+        if let Some((start, end)) = next_match {
+            tab.select_bytes(start, end);
+        }
+        next_match
+    }
 
-            // If no match found after cursor, wrap to the first match
-            if next_match.is_none() && !tab.search_highlights.is_empty() {
-                next_match = Some(tab.search_highlights[0]);
-            }
+    // Moves the cursor to the last match before it, wrapping around
+    pub fn find_previous_match(&mut self) -> Option<(usize, usize)> {
+        let tab = self.tabs.get_mut(self.active_tab)?;
+        let current_pos = tab.cursor_pos;
+        let previous_match = tab.search_highlights
+            .iter()
+            .rev()
+            .find(|&&(start, _)| start < current_pos)
+            .or_else(|| tab.search_highlights.last())
+            .copied();
 
-            // Update cursor position to the found match
-            if let Some((start, _end)) = next_match {
-                tab.cursor_pos = start;
-            }
+        if let Some((start, end)) = previous_match {
+            tab.select_bytes(start, end);
         }
+        previous_match
     }
 
-    pub fn show(&mut self, ui: &mut Ui, _syntax_highlighter: &mut crate::syntax::SyntaxHighlighter) {
-        // Tab bar
-        if self.tabs.len() > 1 {
-            ui.horizontal(|ui| {
-                for (i, tab) in self.tabs.iter().enumerate() {
-                    let text = if tab.modified {
-                        format!("● {}", tab.title)
-                    } else {
-                        tab.title.clone()
-                    };
-                    
-                    if ui.selectable_label(i == self.active_tab, text).clicked() {
-                        self.active_tab = i;
-                    }
-                    
-                    if ui.small_button("×").clicked() {
-                        self.close_tab(i);
-                        break;
-                    }
+    fn show_tab_bar(&mut self, ui: &mut Ui, git_status: &git::Status) {
+        let Some(active_id) = self.tabs.get(self.active_tab).map(|tab| tab.id) else {
+            return;
+        };
+        let labels: Vec<TabLabel> = self
+            .tabs
+            .iter()
+            .map(|tab| TabLabel {
+                title: &tab.title,
+                path: tab.file_path.as_deref(),
+                modified: tab.modified,
+                pinned: tab.pinned,
+                git: tab.canonical_path.as_deref().and_then(|path| git_status.get(path)),
+            })
+            .collect();
+        let Some(action) = self.tab_bar.show(ui, &labels, self.active_tab, active_id) else {
+            return;
+        };
+
+        match action {
+            TabAction::Select(index) => self.switch_tab(index),
+            TabAction::Close(index) => self.close_tab(index),
+            TabAction::CloseOthers(index) => self.close_others(index),
+            TabAction::CloseToTheRight(index) => self.close_to_the_right(index),
+            TabAction::TogglePin(index) => self.toggle_pin(index),
+            TabAction::Move { from, to } => self.move_tab(from, to),
+            TabAction::CopyPath(index) => {
+                if let Some(path) = &self.tabs[index].file_path {
+                    ui.output_mut(|o| o.copied_text = path.display().to_string());
                 }
-            });
-            ui.separator();
+            }
+            TabAction::Reveal(index) => self.reveal_click = self.tabs[index].file_path.clone(),
         }
+    }
+
+    pub fn show(
+        &mut self,
+        ui: &mut Ui,
+        syntax_highlighter: &mut SyntaxHighlighter,
+        diagnostics: &Diagnostics,
+        git_status: &git::Status,
+    ) -> Result<()> {
+        self.show_tab_bar(ui, git_status);
+        ui.separator();
+        self.cursor_rect = None;
+        self.hover_char = None;
 
         // Handle search actions first
         let mut clear_search = false;
         let mut find_next = false;
         let mut find_previous = false;
+        let mut change_action = None;
+
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            match &mut tab.kind {
+                TabKind::Text => {}
+                TabKind::Hex(hex) => {
+                    hex.show(ui);
+                    tab.modified = hex.is_modified();
+                    return Ok(());
+                }
+                TabKind::Large(file) => {
+                    file.show(ui);
+                    return Ok(());
+                }
+                TabKind::Diff(diff) => {
+                    diff.show(ui);
+                    return Ok(());
+                }
+            }
+        }
 
         // Editor content
         if let Some(tab) = self.tabs.get_mut(self.active_tab) {
             let old_content = tab.content.clone();
             let has_highlights = !tab.search_highlights.is_empty();
+            let text_edit_id = Self::text_edit_id(tab.id);
             
             // Show search info if we have highlights
             if has_highlights {
@@ -229,249 +704,400 @@ impl Editor {    pub fn new() -> Self {
                 });
                 ui.separator();
             }
-            
-            // Create highlighted job outside of closures to avoid borrow issues
-            let highlighted_job = if has_highlights {
-                Some(Self::create_highlighted_job_static(tab))
-            } else {
-                None
+
+            let pending_selection = tab.pending_selection.take();
+            if let Some((anchor, head)) = pending_selection {
+                let mut state = egui::text_edit::TextEditState::load(ui.ctx(), text_edit_id).unwrap_or_default();
+                state.set_ccursor_range(Some(CCursorRange {
+                    primary: CCursor::new(head),
+                    secondary: CCursor::new(anchor),
+                }));
+                state.store(ui.ctx(), text_edit_id);
+            }
+
+            tab.detect_language(syntax_highlighter);
+            let language = tab.language.clone().unwrap_or_else(|| PLAIN_TEXT.to_string());
+            tab.update_hunks(ui.ctx());
+            let change_popup = self.change_popup.filter(|&(id, _)| id == tab.id).map(|(_, line)| line);
+
+            // Tab inserts the tab's indentation unit instead of moving focus
+            if ui.memory(|m| m.has_focus(text_edit_id)) {
+                let unit = tab.indentation.unit();
+                ui.input_mut(|i| {
+                    for event in &mut i.events {
+                        if let egui::Event::Key { key: egui::Key::Tab, pressed: true, modifiers, .. } = event {
+                            if modifiers.is_none() {
+                                *event = egui::Event::Text(unit.clone());
+                            }
+                        }
+                    }
+                });
+            }
+
+            // Most severe first, so they win where ranges overlap
+            let mut problems: Vec<(Range<usize>, &Diagnostic)> = tab
+                .canonical_path
+                .as_deref()
+                .map(|path| diagnostics.for_file(path))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|d| (d.byte_range(&tab.content), d))
+                .collect();
+            problems.sort_by_key(|(_, d)| d.severity);
+            let underlines: Vec<(Range<usize>, Color32)> =
+                problems.iter().map(|(range, d)| (range.clone(), d.severity.color())).collect();
+
+            // Syntax colors with the search matches drawn on top
+            let highlights = tab.search_highlights.clone();
+            let cursor_pos = tab.cursor_pos;
+            let mut layouter = |ui: &Ui, text: &str, _wrap_width: f32| {
+                let spans = syntax_highlighter.highlight(text, &language);
+                let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+                let mut job = Self::create_highlighted_job_static(text, &spans, &highlights, cursor_pos, font_id);
+                underline(&mut job, &underlines);
+                ui.fonts(|f| f.layout_job(job))
             };
-            
+
             ScrollArea::vertical()
                 .auto_shrink([false, false])
                 .show(ui, |ui| {
-                    if let Some(job) = highlighted_job {
-                        // Show highlighted content - use a more direct approach
-                        ui.add(egui::Label::new(job).wrap(false)); // Remove the .selectable(false) call
-                        
-                        // Hide the actual editor widget but still process it for changes
-                        let mut frame = egui::Frame::none();
-                        frame.fill = Color32::TRANSPARENT;
-                        frame.show(ui, |ui| {
-                            ui.set_min_size(egui::vec2(0.0, 0.0)); // Make it take no space
-                            
-                            let text_edit = TextEdit::multiline(&mut tab.content)
-                                .desired_width(0.0)
-                                .desired_rows(0)
-                                .frame(false)
-                                .interactive(true);
-
-                            let response = ui.add_sized(egui::vec2(0.0, 0.0), text_edit);
-                            
-                            if response.changed() && old_content != tab.content {
-                                tab.undo_stack.push(old_content);
-                                tab.redo_stack.clear();
-                                tab.modified = true;
-                                
-                                // Rehighlight on content change
-                                if !tab.search_highlights.is_empty() {
-                                    // Try to preserve search matches after edit
-                                    // Would need query string to properly reapply
-                                }
-                            }
-                        });
-                    } else {
-                        // Normal editor when no search
-                        let text_edit = TextEdit::multiline(&mut tab.content)
+                    ui.horizontal_top(|ui| {
+                        let (gutter, _) = ui.allocate_exact_size(egui::vec2(GUTTER_WIDTH, 0.0), egui::Sense::hover());
+                        let output = TextEdit::multiline(&mut tab.content)
+                            .id(text_edit_id)
                             .font(egui::TextStyle::Monospace)
                             .desired_width(f32::INFINITY)
-                            .desired_rows(50);
-
-                        let response = ui.add(text_edit);
+                            .desired_rows(50)
+                            .lock_focus(true)
+                            .layouter(&mut layouter)
+                            .show(ui);
 
-                        if response.changed() && old_content != tab.content {
+                        if output.response.changed() && old_content != tab.content {
                             tab.undo_stack.push(old_content);
                             tab.redo_stack.clear();
+                            tab.content_changed();
                             tab.modified = true;
                         }
-                    }
+
+                        if let Some(range) = output.cursor_range {
+                            let [start, end] = range.as_ccursor_range().sorted();
+                            tab.cursor_pos = char_to_byte(&tab.content, start.index);
+                            tab.selected_chars = end.index - start.index;
+
+                            let rect = output.galley.pos_from_cursor(&range.primary)
+                                .translate(output.text_draw_pos.to_vec2());
+                            self.cursor_rect = Some(rect);
+                            // Bring programmatic cursor moves (search, goto) into view
+                            if pending_selection.is_some() {
+                                ui.scroll_to_rect(rect, Some(egui::Align::Center));
+                            }
+                        }
+
+                        let hover_char = ui
+                            .ctx()
+                            .pointer_hover_pos()
+                            .filter(|_| output.response.hovered())
+                            .and_then(|pointer| char_at(&output.galley, pointer - output.text_draw_pos));
+                        let command = ui.input(|i| i.modifiers.command);
+                        if command && hover_char.is_some() {
+                            ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                        }
+                        if command && output.response.clicked() {
+                            self.definition_click = true;
+                        }
+                        self.hover_char = hover_char;
+
+                        show_diagnostics(ui, &output, gutter.left(), &tab.content, &problems, hover_char, self.lightbulb);
+                        if let Some((top, bottom)) = self.lightbulb.and_then(|line| line_extents(&output).get(line).copied()) {
+                            let rect = egui::Rect::from_x_y_ranges(gutter.left()..=gutter.left() + GUTTER_WIDTH, top..=bottom);
+                            let response = ui.interact(rect, text_edit_id.with("lightbulb"), egui::Sense::click());
+                            ui.painter().text(
+                                rect.center(),
+                                egui::Align2::CENTER_CENTER,
+                                "💡",
+                                egui::FontId::proportional(10.0),
+                                Severity::Warning.color(),
+                            );
+                            if response.on_hover_text("Show Code Actions (Alt+Enter)").clicked() {
+                                self.lightbulb_click = true;
+                            }
+                        }
+                        if let Some(base) = &tab.git_base {
+                            change_action = show_changes(ui, &output, gutter.left(), base, change_popup);
+                        }
+                    });
                 });
         }
 
+        match change_action {
+            Some(ChangeAction::Show(line)) => {
+                let shown = self.tabs.get(self.active_tab).map(|tab| (tab.id, line));
+                self.change_popup = if self.change_popup == shown { None } else { shown };
+            }
+            Some(ChangeAction::Close) => self.change_popup = None,
+            Some(ChangeAction::Revert) => self.revert_change(),
+            Some(ChangeAction::Stage) => self.stage_click = true,
+            Some(ChangeAction::Next) => self.go_to_change(true),
+            Some(ChangeAction::Previous) => self.go_to_change(false),
+            None => {}
+        }
+
         // Handle search actions after borrowing is done
         if clear_search {
             self.clear_search_highlights();
         }
         if find_next {
-            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                if !tab.search_highlights.is_empty() {
-                    let current_pos = tab.cursor_pos;
-                    let mut next_match = None;
-                    
-                    for &(start, _) in &tab.search_highlights {
-                        if start > current_pos {
-                            next_match = Some(start);
-                            break;
-                        }
-                    }
-                    
-                    if next_match.is_none() {
-                        next_match = Some(tab.search_highlights[0].0);
-                    }
-                    
-                    if let Some(pos) = next_match {
-                        tab.cursor_pos = pos;
-                    }
-                }
-            }
+            self.find_next_match();
         }
         if find_previous {
-            if let Some(tab) = self.tabs.get_mut(self.active_tab) {
-                if !tab.search_highlights.is_empty() {
-                    let current_pos = tab.cursor_pos;
-                    let mut prev_match = None;
-                    
-                    for &(start, _) in tab.search_highlights.iter().rev() {
-                        if start < current_pos {
-                            prev_match = Some(start);
-                            break;
-                        }
-                    }
-                    
-                    if prev_match.is_none() {
-                        prev_match = Some(tab.search_highlights.last().unwrap().0);
-                    }
-                    
-                    if let Some(pos) = prev_match {
-                        tab.cursor_pos = pos;
-                    }
-                }
+            self.find_previous_match();
+        }
+        Ok(())
+    }
+
+    pub fn text_edit_id(tab_id: usize) -> egui::Id {
+        egui::Id::new(("editor_text", tab_id))
+    }
+
+    // Id of the text widget of the active tab, used to read and move its cursor
+    pub fn active_text_edit_id(&self) -> Option<egui::Id> {
+        self.tabs.get(self.active_tab).map(|tab| Self::text_edit_id(tab.id))
+    }
+
+    pub fn active_content(&self) -> Option<&str> {
+        self.tabs.get(self.active_tab).map(|tab| tab.content.as_str())
+    }
+
+    // Replaces the whole buffer as a single undo step
+    pub fn replace_active_content(&mut self, content: String) {
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            if matches!(tab.kind, TabKind::Text) && tab.content != content {
+                let previous = std::mem::replace(&mut tab.content, content);
+                tab.undo_stack.push(previous);
+                tab.redo_stack.clear();
+                tab.content_changed();
+                tab.clamp_cursor();
+                tab.modified = true;
             }
         }
     }
 
-    fn create_highlighted_job(&self, tab: &EditorTab) -> egui::text::LayoutJob {
-        Self::create_highlighted_job_static(tab)
+    // Selects the char range from `anchor` to `head` on the next frame; equal values place the cursor
+    pub fn set_active_selection(&mut self, anchor: usize, head: usize) {
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            tab.cursor_pos = char_to_byte(&tab.content, anchor.min(head));
+            tab.pending_selection = Some((anchor, head));
+        }
     }
 
-    fn create_highlighted_job_static(tab: &EditorTab) -> egui::text::LayoutJob {
+    fn create_highlighted_job_static(
+        text: &str,
+        spans: &Highlights,
+        search_highlights: &[(usize, usize)],
+        cursor_pos: usize,
+        font_id: egui::FontId,
+    ) -> egui::text::LayoutJob {
         let mut job = egui::text::LayoutJob::default();
-        let mut last_end = 0;
         
         // Sort highlights by position
-        let mut highlights = tab.search_highlights.clone();
+        let mut highlights: Vec<(usize, usize)> = search_highlights
+            .iter()
+            .copied()
+            .filter(|&(start, end)| {
+                // Matches go stale while typing; skip ones that no longer fit the text
+                start < end && end <= text.len() && text.is_char_boundary(start) && text.is_char_boundary(end)
+            })
+            .collect();
         highlights.sort_by_key(|&(start, _)| start);
         
         // Find current highlight index
-        let current_highlight_idx = highlights.iter().position(|(start, _)| *start >= tab.cursor_pos);
-        
-        for (i, (start, end)) in highlights.iter().enumerate() {
-            let start = *start;
-            let end = *end;
-            
-            // Add normal text before highlight
-            if last_end < start && start < tab.content.len() {
-                job.append(
-                    &tab.content[last_end..start],
-                    0.0,
-                    egui::TextFormat {
-                        font_id: egui::FontId::monospace(14.0),
-                        color: Color32::GRAY, // Light gray for non-highlighted text
-                        ..Default::default()
-                    },
-                );
+        let current_highlight_idx = highlights.iter().position(|(start, _)| *start >= cursor_pos);
+
+        let plain = |color: Color32| egui::TextFormat {
+            font_id: font_id.clone(),
+            color,
+            ..Default::default()
+        };
+        let highlighted = |is_current: bool| {
+            let (bg_color, text_color) = if is_current {
+                // Current match: blue background with white text
+                (Color32::from_rgb(30, 144, 255), Color32::WHITE)
+            } else {
+                // Other matches: yellow background with black text
+                (Color32::from_rgb(255, 255, 0), Color32::BLACK)
+            };
+            egui::TextFormat {
+                font_id: font_id.clone(),
+                color: text_color,
+                background: bg_color,
+                ..Default::default()
             }
-            
-            // Add highlighted text
-            if start < tab.content.len() && end <= tab.content.len() {
-                let is_current = current_highlight_idx == Some(i);
-                let (bg_color, text_color) = if is_current { 
-                    // Current match: blue background with white text
-                    (Color32::from_rgb(30, 144, 255), Color32::WHITE)
-                } else { 
-                    // Other matches: yellow background with black text
-                    (Color32::from_rgb(255, 255, 0), Color32::BLACK)
+        };
+
+        // Split the syntax spans wherever a search match starts or ends
+        let mut next_match = 0;
+        let mut last_end = 0;
+        for (range, color) in spans.iter() {
+            let mut start = range.start.max(last_end);
+            let span_end = range.end.min(text.len());
+            while start < span_end {
+                while highlights.get(next_match).is_some_and(|&(_, end)| end <= start) {
+                    next_match += 1;
+                }
+                let (end, format) = match highlights.get(next_match) {
+                    Some(&(match_start, match_end)) if match_start <= start => {
+                        (match_end.min(span_end), highlighted(current_highlight_idx == Some(next_match)))
+                    }
+                    Some(&(match_start, _)) => (match_start.min(span_end), plain(*color)),
+                    None => (span_end, plain(*color)),
                 };
-                
-                job.append(
-                    &tab.content[start..end],
-                    0.0,
-                    egui::TextFormat {
-                        font_id: egui::FontId::monospace(14.0),
-                        color: text_color,
-                        background: bg_color,
-                        ..Default::default()
-                    },
-                );
+                if let Some(piece) = text.get(start..end) {
+                    job.append(piece, 0.0, format);
+                }
+                start = end;
             }
-            
-            last_end = end;
+            last_end = last_end.max(span_end);
         }
         
         // Add remaining text
-        if last_end < tab.content.len() {
-            job.append(
-                &tab.content[last_end..],
-                0.0,
-                egui::TextFormat {
-                    font_id: egui::FontId::monospace(14.0),
-                    color: Color32::GRAY, // Light gray for non-highlighted text
-                    ..Default::default()
-                },
-            );
+        if last_end < text.len() {
+            if let Some(piece) = text.get(last_end..) {
+                job.append(piece, 0.0, plain(Color32::GRAY));
+            }
         }
         
         job
     }
 
-    fn move_to_next_match(&mut self, tab: &mut EditorTab) {
-        if tab.search_highlights.is_empty() {
-            return;
-        }
-        
-        let current_pos = tab.cursor_pos;
-        let mut next_match = None;
-        
-        for &(start, _) in &tab.search_highlights {
-            if start > current_pos {
-                next_match = Some(start);
-                break;
+    fn close_tab(&mut self, index: usize) {
+        if self.tabs.len() > 1 {
+            self.remove_tab(index);
+            if self.active_tab >= index && self.active_tab > 0 {
+                self.active_tab -= 1;
             }
         }
-        
-        if next_match.is_none() {
-            next_match = Some(tab.search_highlights[0].0);
-        }
-        
-        if let Some(pos) = next_match {
-            tab.cursor_pos = pos;
-        }
     }
 
-    fn move_to_previous_match(&mut self, tab: &mut EditorTab) {
-        if tab.search_highlights.is_empty() {
+    pub fn close_other_tabs(&mut self) {
+        self.close_others(self.active_tab);
+    }
+
+    // Closes every unpinned tab except `index`
+    fn close_others(&mut self, index: usize) {
+        let Some(keep) = self.tabs.get(index).map(|tab| tab.id) else {
             return;
-        }
-        
-        let current_pos = tab.cursor_pos;
-        let mut prev_match = None;
-        
-        for &(start, _) in tab.search_highlights.iter().rev() {
-            if start < current_pos {
-                prev_match = Some(start);
-                break;
+        };
+        let active_id = self.tabs[self.active_tab].id;
+        for i in (0..self.tabs.len()).rev() {
+            if self.tabs[i].id != keep && !self.tabs[i].pinned {
+                self.remove_tab(i);
             }
         }
-        
-        if prev_match.is_none() {
-            prev_match = Some(tab.search_highlights.last().unwrap().0);
+        self.focus_tab_id(if self.tabs.iter().any(|tab| tab.id == active_id) { active_id } else { keep });
+    }
+
+    // Closes the unpinned tabs after `index`
+    fn close_to_the_right(&mut self, index: usize) {
+        let Some(keep) = self.tabs.get(index).map(|tab| tab.id) else {
+            return;
+        };
+        let active_id = self.tabs[self.active_tab].id;
+        for i in (index + 1..self.tabs.len()).rev() {
+            if !self.tabs[i].pinned {
+                self.remove_tab(i);
+            }
         }
-        
-        if let Some(pos) = prev_match {
-            tab.cursor_pos = pos;
+        self.focus_tab_id(if self.tabs.iter().any(|tab| tab.id == active_id) { active_id } else { keep });
+    }
+
+    pub fn toggle_pin_current(&mut self) {
+        self.toggle_pin(self.active_tab);
+    }
+
+    // Pinning moves a tab to the end of the pinned ones, unpinning to just after them
+    fn toggle_pin(&mut self, index: usize) {
+        let Some(tab) = self.tabs.get_mut(index) else {
+            return;
+        };
+        tab.pinned = !tab.pinned;
+        let pinned = tab.pinned;
+        let to = if pinned { self.pinned_count() - 1 } else { self.pinned_count() };
+        let active_id = self.tabs[self.active_tab].id;
+        let tab = self.tabs.remove(index);
+        self.tabs.insert(to, tab);
+        self.focus_tab_id(active_id);
+    }
+
+    // Moves a tab within its group; pinned and unpinned tabs don't mix
+    fn move_tab(&mut self, from: usize, to: usize) {
+        if from >= self.tabs.len() {
+            return;
         }
+        let pinned = self.pinned_count();
+        let to = if self.tabs[from].pinned {
+            to.min(pinned - 1)
+        } else {
+            to.clamp(pinned, self.tabs.len() - 1)
+        };
+        let active_id = self.tabs[self.active_tab].id;
+        let tab = self.tabs.remove(from);
+        self.tabs.insert(to, tab);
+        self.focus_tab_id(active_id);
     }
 
-    fn show_highlighted_content(&self, _ui: &mut egui::Ui, _tab: &EditorTab) {
-        // This method is no longer used but kept for compatibility
+    fn pinned_count(&self) -> usize {
+        self.tabs.iter().filter(|tab| tab.pinned).count()
     }
 
-    fn close_tab(&mut self, index: usize) {
-        if self.tabs.len() > 1 {
-            self.tabs.remove(index);
-            if self.active_tab >= index && self.active_tab > 0 {
-                self.active_tab -= 1;
-            }
+    fn switch_tab(&mut self, index: usize) {
+        if index != self.active_tab {
+            self.record_jump();
+        }
+        self.active_tab = index;
+    }
+
+    fn location(&self) -> Option<Location> {
+        let tab = self.tabs.get(self.active_tab)?;
+        Some(Location {
+            tab_id: tab.id,
+            cursor: byte_to_char(&tab.content, tab.cursor_pos),
+        })
+    }
+
+    // Remembers the current position before moving somewhere else
+    fn record_jump(&mut self) {
+        if let Some(location) = self.location() {
+            self.history.push(location);
+        }
+    }
+
+    pub fn navigate_back(&mut self) {
+        let open: Vec<usize> = self.tabs.iter().map(|tab| tab.id).collect();
+        let current = self.location();
+        if let Some(location) = self.history.back(current, |l| open.contains(&l.tab_id)) {
+            self.go_to_location(location);
+        }
+    }
+
+    pub fn navigate_forward(&mut self) {
+        let open: Vec<usize> = self.tabs.iter().map(|tab| tab.id).collect();
+        let current = self.location();
+        if let Some(location) = self.history.forward(current, |l| open.contains(&l.tab_id)) {
+            self.go_to_location(location);
+        }
+    }
+
+    fn go_to_location(&mut self, location: Location) {
+        self.focus_tab_id(location.tab_id);
+        if let Some(content) = self.active_content() {
+            let cursor = location.cursor.min(content.chars().count());
+            self.set_active_selection(cursor, cursor);
+        }
+    }
+
+    fn focus_tab_id(&mut self, id: usize) {
+        if let Some(index) = self.tabs.iter().position(|tab| tab.id == id) {
+            self.active_tab = index;
         }
     }
 
@@ -490,4 +1116,956 @@ impl Editor {    pub fn new() -> Self {
     pub fn get_workspace_folder(&self) -> Option<&PathBuf> {
         self.workspace_folder.as_ref()
     }
-}
\ No newline at end of file
+
+    pub fn current_file_path(&self) -> Option<&PathBuf> {
+        self.tabs.get(self.active_tab).and_then(|tab| tab.file_path.as_ref())
+    }
+
+    // The path diagnostics and language servers know the active file by
+    pub fn current_canonical_path(&self) -> Option<&Path> {
+        self.tabs.get(self.active_tab).and_then(|tab| tab.canonical_path.as_deref())
+    }
+
+    // Char index of the cursor, or of the start of the selection
+    pub fn cursor_char(&self) -> Option<usize> {
+        let tab = self.tabs.get(self.active_tab)?;
+        Some(byte_to_char(&tab.content, tab.cursor_pos.min(tab.content.len())))
+    }
+
+    pub fn cursor_rect(&self) -> Option<egui::Rect> {
+        self.cursor_rect
+    }
+
+    pub fn hover_char(&self) -> Option<usize> {
+        self.hover_char
+    }
+
+    pub fn take_definition_click(&mut self) -> bool {
+        std::mem::take(&mut self.definition_click)
+    }
+
+    pub fn set_lightbulb(&mut self, line: Option<usize>) {
+        self.lightbulb = line;
+    }
+
+    pub fn take_lightbulb_click(&mut self) -> bool {
+        std::mem::take(&mut self.lightbulb_click)
+    }
+
+    pub fn take_stage_click(&mut self) -> bool {
+        std::mem::take(&mut self.stage_click)
+    }
+
+    pub fn take_reveal_click(&mut self) -> Option<PathBuf> {
+        self.reveal_click.take()
+    }
+
+    // The change shown in the popup, or else the one at the cursor
+    fn target_change(&self) -> Option<Hunk> {
+        let tab = self.tabs.get(self.active_tab)?;
+        let line = match self.change_popup {
+            Some((id, line)) if id == tab.id => line,
+            _ => self.cursor_line_col().0,
+        };
+        // Hunks of older text may not fit the lines any more
+        let base = tab.git_base.as_ref().filter(|base| base.version == tab.content_version)?;
+        base.hunks.iter().find(|hunk| marker_line(hunk) == line || hunk.new.contains(&line)).cloned()
+    }
+
+    // Puts the lines of the target change back to how git has them staged
+    pub fn revert_change(&mut self) {
+        let Some(hunk) = self.target_change() else {
+            return;
+        };
+        let tab = &self.tabs[self.active_tab];
+        let Some(staged) = tab.git_base.as_ref().and_then(|base| base.text.as_deref()) else {
+            return;
+        };
+        let content = diff::splice_lines(&tab.content, hunk.new.clone(), staged, hunk.old);
+        self.replace_active_content(content);
+        self.change_popup = None;
+        self.move_to_line(hunk.new.start, 0);
+    }
+
+    // Stages the target change alone, leaving the file and any unsaved edits as they are;
+    // None when there is no change to stage
+    pub fn stage_change(&mut self) -> Option<std::result::Result<(), Box<dyn std::error::Error>>> {
+        let hunk = self.target_change()?;
+        self.change_popup = None;
+        let tab = self.tabs.get_mut(self.active_tab)?;
+        // The new index is read back on the next frame
+        tab.index_checked = None;
+        let base = tab.git_base.as_ref()?;
+        let staged = diff::splice_lines(base.text.as_deref()?, hunk.old, &tab.content, hunk.new);
+        let staged = match base.line_ending {
+            LineEnding::Lf => staged,
+            LineEnding::Crlf => staged.replace('\n', LineEnding::Crlf.as_str()),
+        };
+        // The index keeps the file in the same encoding as the work tree
+        let path = tab.canonical_path.as_deref()?;
+        Some(tab.encoding.encode(&staged).map_err(Into::into).and_then(|bytes| git::stage_bytes(path, &bytes)))
+    }
+
+    // Moves the cursor to the next or previous changed lines, wrapping around
+    pub fn go_to_change(&mut self, forward: bool) {
+        let line = self.cursor_line_col().0;
+        let Some(tab) = self.tabs.get_mut(self.active_tab) else {
+            return;
+        };
+        let lines: Vec<usize> = tab.git_base.iter().flat_map(|base| &base.hunks).map(marker_line).collect();
+        let target = if forward {
+            lines.iter().find(|&&l| l > line).or(lines.first())
+        } else {
+            lines.iter().rev().find(|&&l| l < line).or(lines.last())
+        };
+        let Some(&target) = target else {
+            return;
+        };
+        if self.change_popup.is_some() {
+            self.change_popup = Some((tab.id, target));
+        }
+        self.go_to_line(target, 0);
+    }
+
+    // Selected char range of the active tab; empty at the cursor
+    pub fn selection(&self) -> Option<Range<usize>> {
+        let start = self.cursor_char()?;
+        Some(start..start + self.tabs[self.active_tab].selected_chars)
+    }
+
+    // Text selected in the active tab, if any
+    pub fn selected_text(&self) -> Option<String> {
+        let tab = self.tabs.get(self.active_tab)?;
+        let selection = self.selection().filter(|range| !range.is_empty())?;
+        Some(tab.content.chars().skip(selection.start).take(selection.len()).collect())
+    }
+
+    // Rewrites a file with `edit`. An open text tab changes as one undo step and
+    // is left unsaved; a file that isn't open is changed on disk in its encoding.
+    pub fn edit_file(&mut self, path: &Path, edit: impl FnOnce(&str) -> String) -> Result<()> {
+        let open = self.find_tab_where(path, |kind| matches!(kind, TabKind::Text));
+        if let Some(tab) = open.map(|index| &mut self.tabs[index]) {
+            let content = edit(&tab.content);
+            if content != tab.content {
+                let cursor = byte_to_char(&tab.content, tab.cursor_pos);
+                let previous = std::mem::replace(&mut tab.content, content);
+                tab.undo_stack.push(previous);
+                tab.redo_stack.clear();
+                tab.content_changed();
+                tab.modified = true;
+                tab.cursor_pos = char_to_byte(&tab.content, cursor);
+            }
+            return Ok(());
+        }
+
+        let bytes = fs::read(path).map_err(|e| Error::io("open", path, e))?;
+        let encoding = Encoding::detect(&bytes);
+        let text = encoding
+            .decode(&bytes)
+            .map_err(|source| Error::Decode { path: path.to_path_buf(), source })?;
+        let bytes = encoding
+            .encode(&edit(&text))
+            .map_err(|source| Error::Encode { path: path.to_path_buf(), source })?;
+        save::write_file(path, &bytes, &self.save_options)
+    }
+
+    // The text of the open tab for a canonical path
+    pub fn text_of(&self, path: &Path) -> Option<&str> {
+        let index = self.find_tab_where(path, |kind| matches!(kind, TabKind::Text))?;
+        Some(self.tabs[index].content.as_str())
+    }
+
+    // Zero-based (line, column) of the cursor in the active tab
+    pub fn cursor_line_col(&self) -> (usize, usize) {
+        self.tabs.get(self.active_tab).map_or((0, 0), EditorTab::cursor_line_column)
+    }
+
+    // Hex and large file tabs handle their own keys, so modal editing stays off there
+    pub fn active_is_text(&self) -> bool {
+        self.tabs.get(self.active_tab).is_some_and(|tab| matches!(tab.kind, TabKind::Text))
+    }
+
+    // None for binary and large files, which have no cursor or text settings
+    pub fn status(&self) -> Option<EditorStatus> {
+        let tab = self.tabs.get(self.active_tab)?;
+        if !matches!(tab.kind, TabKind::Text) {
+            return None;
+        }
+        let (line, column) = self.cursor_line_col();
+        Some(EditorStatus {
+            line,
+            column,
+            selected: tab.selected_chars,
+            language: tab.language.clone().unwrap_or_else(|| PLAIN_TEXT.to_string()),
+            encoding: tab.encoding,
+            line_ending: tab.line_ending,
+            indentation: tab.indentation,
+        })
+    }
+
+    pub fn detect_language(&mut self, syntax_highlighter: &SyntaxHighlighter) {
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            tab.detect_language(syntax_highlighter);
+        }
+    }
+
+    pub fn set_language(&mut self, language: String) {
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            tab.language = Some(language);
+        }
+    }
+
+    // Problems found in files opened or reloaded since the last call
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        self.tabs
+            .iter_mut()
+            .filter_map(|tab| {
+                std::mem::take(&mut tab.mixed_line_endings).then(|| {
+                    format!(
+                        "{} has both LF and CRLF line endings; saving it converts them all to {}",
+                        tab.title,
+                        tab.line_ending.label()
+                    )
+                })
+            })
+            .collect()
+    }
+
+    // Files written since the last call, so language servers can be told
+    pub fn take_saved_paths(&mut self) -> Vec<PathBuf> {
+        self.tabs
+            .iter_mut()
+            .filter_map(|tab| {
+                let saved = std::mem::take(&mut tab.saved);
+                tab.canonical_path.clone().filter(|_| saved)
+            })
+            .collect()
+    }
+
+    // Text tabs backed by a file, as language servers should see them
+    pub fn text_documents(&mut self, syntax_highlighter: &SyntaxHighlighter) -> Vec<OpenDocument<'_>> {
+        for tab in &mut self.tabs {
+            if matches!(tab.kind, TabKind::Text) && tab.file_path.is_some() {
+                tab.detect_language(syntax_highlighter);
+            }
+        }
+        self.tabs
+            .iter()
+            .filter(|tab| matches!(tab.kind, TabKind::Text))
+            .filter_map(|tab| {
+                Some(OpenDocument {
+                    path: tab.canonical_path.as_deref()?,
+                    language: tab.language.as_deref()?,
+                    text: &tab.content,
+                })
+            })
+            .collect()
+    }
+
+    // Takes effect when the file is saved, so it only marks the tab as modified
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            if tab.line_ending != line_ending {
+                tab.line_ending = line_ending;
+                tab.modified = true;
+            }
+        }
+    }
+
+    // Like line endings, the new encoding is used the next time the file is saved
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            if tab.encoding != encoding {
+                tab.encoding = encoding;
+                tab.modified = true;
+            }
+        }
+    }
+
+    // Reads the file from disk again, decoding it as `encoding`. Unsaved changes are
+    // kept on the undo stack.
+    pub fn reopen_with_encoding(&mut self, encoding: Encoding) -> Result<()> {
+        match self.tabs.get_mut(self.active_tab) {
+            Some(tab) => tab.reload_text(Some(encoding)),
+            None => Ok(()),
+        }
+    }
+
+    // Changes what the Tab key inserts without touching existing lines
+    pub fn set_indentation(&mut self, indentation: Indentation) {
+        if let Some(tab) = self.tabs.get_mut(self.active_tab) {
+            tab.indentation = indentation;
+        }
+    }
+
+    // Rewrites the leading whitespace of every line to the new style as one undo step
+    pub fn convert_indentation(&mut self, indentation: Indentation) {
+        let Some(tab) = self.tabs.get(self.active_tab) else {
+            return;
+        };
+        let old = tab.indentation;
+        let converted = tab
+            .content
+            .split('\n')
+            .map(|line| {
+                let rest = line.trim_start_matches([' ', '\t']);
+                let leading = &line[..line.len() - rest.len()];
+                let width: usize = leading
+                    .chars()
+                    .map(|c| if c == '\t' { old.size } else { 1 })
+                    .sum();
+                let indent = if indentation.use_tabs {
+                    "\t".repeat(width / indentation.size) + &" ".repeat(width % indentation.size)
+                } else {
+                    " ".repeat(width)
+                };
+                indent + rest
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.replace_active_content(converted);
+        self.set_indentation(indentation);
+    }
+
+    // Definitions in the active text tab, for go to symbol
+    pub fn active_symbols(&self, syntax_highlighter: &SyntaxHighlighter) -> Vec<Symbol> {
+        let Some(tab) = self.tabs.get(self.active_tab).filter(|tab| matches!(tab.kind, TabKind::Text)) else {
+            return Vec::new();
+        };
+        let language = tab
+            .language
+            .clone()
+            .unwrap_or_else(|| syntax_highlighter.detect_language(tab.file_path.as_deref(), &tab.content));
+        syntax_highlighter.symbols(&tab.content, &language)
+    }
+
+    // Moves the cursor to a zero-based line and column, clamped to the text
+    pub fn go_to_line(&mut self, line: usize, column: usize) {
+        self.record_jump();
+        self.move_to_line(line, column);
+    }
+
+    // Opens a file with the cursor at a zero-based line and column, recorded
+    // as a single jump
+    pub fn open_file_at(&mut self, path: PathBuf, line: usize, column: usize) -> Result<()> {
+        let from = self.location();
+        self.open_file(path)?;
+        if let Some(from) = from {
+            self.history.push(from);
+        }
+        self.move_to_line(line, column);
+        Ok(())
+    }
+
+    fn move_to_line(&mut self, line: usize, column: usize) {
+        let Some(content) = self.active_content() else {
+            return;
+        };
+        let index = line_column_to_char(content, line, column);
+        self.set_active_selection(index, index);
+    }
+
+    // The text tab with this id as formatting needs it
+    pub fn tab_text(&self, id: usize) -> Option<TabText<'_>> {
+        let tab = self.tabs.iter().find(|tab| tab.id == id && matches!(tab.kind, TabKind::Text))?;
+        let (line, column) = tab.cursor_line_column();
+        let cursor = byte_to_char(&tab.content, tab.cursor_pos);
+        let selection = char_to_byte(&tab.content, cursor)..char_to_byte(&tab.content, cursor + tab.selected_chars);
+        let last_line = line + tab.content[selection].matches('\n').count();
+        Some(TabText {
+            path: tab.canonical_path.as_deref(),
+            language: tab.language.clone().unwrap_or_else(|| PLAIN_TEXT.to_string()),
+            text: &tab.content,
+            indentation: tab.indentation,
+            cursor: (line, column),
+            selected_lines: line..last_line + 1,
+        })
+    }
+
+    // Replaces a tab's text with a formatted version as one undo step and puts
+    // the cursor at a zero-based (line, column)
+    pub fn replace_formatted(&mut self, id: usize, content: String, cursor: (usize, usize)) {
+        let Some(tab) = self.tabs.iter_mut().find(|tab| tab.id == id && matches!(tab.kind, TabKind::Text)) else {
+            return;
+        };
+        if tab.content == content {
+            return;
+        }
+        let previous = std::mem::replace(&mut tab.content, content);
+        tab.undo_stack.push(previous);
+        tab.redo_stack.clear();
+        tab.content_changed();
+        tab.modified = true;
+        let index = line_column_to_char(&tab.content, cursor.0, cursor.1);
+        tab.cursor_pos = char_to_byte(&tab.content, index);
+        tab.pending_selection = Some((index, index));
+    }
+
+    // Ids of the modified text tabs that have a file to save to
+    pub fn modified_file_tabs(&self) -> Vec<usize> {
+        self.tabs
+            .iter()
+            .filter(|tab| tab.modified && tab.file_path.is_some() && matches!(tab.kind, TabKind::Text))
+            .map(|tab| tab.id)
+            .collect()
+    }
+
+    pub fn save_tab(&mut self, id: usize) -> Result<()> {
+        let Some(tab) = self.tabs.iter_mut().find(|tab| tab.id == id) else {
+            return Ok(());
+        };
+        match tab.file_path.clone() {
+            Some(path) => tab.save_to(path, &self.save_options),
+            None => Ok(()),
+        }
+    }
+}
+
+// A text tab's content with what formatting it needs
+pub struct TabText<'a> {
+    // Canonical, as language servers know the file
+    pub path: Option<&'a Path>,
+    pub language: String,
+    pub text: &'a str,
+    pub indentation: Indentation,
+    // Zero-based (line, column) of the cursor
+    pub cursor: (usize, usize),
+    // The lines the selection touches
+    pub selected_lines: Range<usize>,
+}
+
+impl EditorTab {
+    fn new(
+        id: usize,
+        title: String,
+        content: String,
+        file_path: Option<PathBuf>,
+        encoding: Encoding,
+        tab_size: usize,
+    ) -> Self {
+        let line_ending = LineEnding::detect(&content);
+        let mixed_line_endings = LineEnding::is_mixed(&content);
+        let content = content.replace("\r\n", "\n");
+        let indentation = Indentation::detect(&content, tab_size);
+        Self {
+            id,
+            title,
+            content,
+            canonical_path: file_path.as_deref().map(canonical_path),
+            file_path,
+            modified: false,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            cursor_pos: 0,
+            search_highlights: Vec::new(),
+            pending_selection: None,
+            selected_chars: 0,
+            language: None,
+            line_ending,
+            encoding,
+            indentation,
+            kind: TabKind::Text,
+            pinned: false,
+            saved: false,
+            mixed_line_endings,
+            content_version: 0,
+            edited: Instant::now(),
+            index_checked: None,
+            git_base: None,
+            git_diff: None,
+        }
+    }
+
+    // Writes the tab to `path`, which becomes its location
+    fn save_to(&mut self, path: PathBuf, options: &SaveOptions) -> Result<()> {
+        match &mut self.kind {
+            TabKind::Text => {
+                let bytes = self
+                    .encoding
+                    .encode(&self.disk_text())
+                    .map_err(|source| Error::Encode { path: path.clone(), source })?;
+                save::write_file(&path, &bytes, options)?;
+            }
+            TabKind::Hex(hex) => {
+                save::write_file(&path, hex.bytes_for_save(), options)?;
+                hex.mark_saved();
+            }
+            // Read-only, nothing to write
+            TabKind::Large(_) | TabKind::Diff(_) => return Ok(()),
+        }
+
+        if self.file_path.as_ref() != Some(&path) {
+            self.git_base = None;
+            self.git_diff = None;
+            self.title = file_title(&path);
+            self.canonical_path = Some(canonical_path(&path));
+            self.file_path = Some(path);
+            // A new extension may mean a different language
+            self.language = None;
+        }
+        self.modified = false;
+        self.saved = true;
+        Ok(())
+    }
+
+    // Reads the text from disk again, decoding it as `encoding` or as whatever
+    // encoding the bytes look like. Unsaved changes are kept on the undo stack.
+    fn reload_text(&mut self, encoding: Option<Encoding>) -> Result<()> {
+        let Some(path) = &self.file_path else {
+            return Ok(());
+        };
+        let bytes = fs::read(path).map_err(|e| Error::io("read", path, e))?;
+        let encoding = encoding.unwrap_or_else(|| Encoding::detect(&bytes));
+        let content = encoding
+            .decode(&bytes)
+            .map_err(|source| Error::Decode { path: path.clone(), source })?;
+
+        self.line_ending = LineEnding::detect(&content);
+        self.mixed_line_endings = LineEnding::is_mixed(&content);
+        self.encoding = encoding;
+        let content = content.replace("\r\n", "\n");
+        if content != self.content {
+            let previous = std::mem::replace(&mut self.content, content);
+            self.undo_stack.push(previous);
+            self.redo_stack.clear();
+            self.content_changed();
+            self.clamp_cursor();
+        }
+        self.modified = false;
+        Ok(())
+    }
+
+    // Replacing the content wholesale can leave the cursor past the end or inside a char
+    fn clamp_cursor(&mut self) {
+        let mut pos = self.cursor_pos.min(self.content.len());
+        while !self.content.is_char_boundary(pos) {
+            pos -= 1;
+        }
+        self.cursor_pos = pos;
+    }
+
+    // Zero-based line and column of the cursor, in chars
+    fn cursor_line_column(&self) -> (usize, usize) {
+        let end = char_to_byte(&self.content, byte_to_char(&self.content, self.cursor_pos));
+        let before = &self.content[..end];
+        let line = before.matches('\n').count();
+        let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count());
+        (line, column)
+    }
+
+    fn detect_language(&mut self, syntax_highlighter: &SyntaxHighlighter) {
+        if self.language.is_none() {
+            let path = self.file_path.as_deref();
+            self.language = Some(syntax_highlighter.detect_language(path, &self.content));
+        }
+    }
+
+    // The content as it is written to disk, with the tab's line endings
+    fn disk_text(&self) -> String {
+        match self.line_ending {
+            LineEnding::Lf => self.content.clone(),
+            LineEnding::Crlf => self.content.replace('\n', LineEnding::Crlf.as_str()),
+        }
+    }
+
+    fn content_changed(&mut self) {
+        self.content_version += 1;
+        self.edited = Instant::now();
+    }
+
+    // Rereads the staged text when the index changed and recomputes the hunks
+    // when either it or the content did. Both happen on a thread; edits are
+    // diffed once typing pauses.
+    fn update_hunks(&mut self, ctx: &egui::Context) {
+        let Some(path) = &self.canonical_path else {
+            return;
+        };
+        if let Some(receiver) = &self.git_diff {
+            match receiver.try_recv() {
+                Ok(base) => self.git_base = Some(base),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {}
+            }
+            self.git_diff = None;
+        }
+
+        let mut reread = self.git_base.is_none();
+        if self.index_checked.is_none_or(|checked| checked.elapsed() >= INDEX_CHECK_INTERVAL) {
+            self.index_checked = Some(Instant::now());
+            let index_modified = git::index_modified(path);
+            reread |= self.git_base.as_ref().is_some_and(|base| base.index_modified != index_modified);
+        }
+        if !reread {
+            if self.git_base.as_ref().is_some_and(|base| base.version == self.content_version) {
+                return;
+            }
+            let idle = self.edited.elapsed();
+            if idle < DIFF_DELAY {
+                ctx.request_repaint_after(DIFF_DELAY - idle);
+                return;
+            }
+        }
+
+        let known = self
+            .git_base
+            .as_ref()
+            .filter(|_| !reread)
+            .map(|base| (base.text.clone(), base.line_ending, base.index_modified));
+        let (path, content, version, line_ending) = (path.clone(), self.content.clone(), self.content_version, self.line_ending);
+        let (sender, receiver) = mpsc::channel();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let (text, line_ending, index_modified) = known.unwrap_or_else(|| {
+                let index_modified = git::index_modified(&path);
+                let staged = git::staged_bytes(&path).and_then(|bytes| Encoding::detect(&bytes).decode(&bytes).ok());
+                let line_ending = staged.as_deref().map_or(line_ending, LineEnding::detect);
+                (staged.map(|text| Arc::from(text.replace("\r\n", "\n"))), line_ending, index_modified)
+            });
+            let hunks = text.as_deref().map(|text| diff::hunks(text, &content)).unwrap_or_default();
+            let _ = sender.send(GitBase { text, line_ending, index_modified, hunks, version });
+            ctx.request_repaint();
+        });
+        self.git_diff = Some(receiver);
+    }
+
+    fn select_bytes(&mut self, start: usize, end: usize) {
+        self.cursor_pos = start;
+        self.pending_selection = Some((
+            byte_to_char(&self.content, start),
+            byte_to_char(&self.content, end),
+        ));
+    }
+}
+
+fn read_bytes(path: &Path, large: bool) -> Result<FileBytes> {
+    let bytes = if large {
+        FileBytes::map(path)
+    } else {
+        fs::read(path).map(FileBytes::Loaded)
+    };
+    bytes.map_err(|e| Error::io("open", path, e))
+}
+
+// Falls back to an absolute path for files that don't exist (any more)
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn file_title(path: &Path) -> String {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("Untitled")
+        .to_string()
+}
+
+// Byte ranges of the matches of `query` in `text`, ignoring case. Lowercasing can
+// change a char's length, so matches are found in the lowercased text and mapped
+// back to the original.
+fn find_ignoring_case(text: &str, query: &str) -> Vec<(usize, usize)> {
+    let query = query.to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let mut lower = String::with_capacity(text.len());
+    // The byte in `text` that each byte of `lower` came from
+    let mut origin = Vec::with_capacity(text.len() + 1);
+    for (index, c) in text.char_indices() {
+        for lower_c in c.to_lowercase() {
+            lower.push(lower_c);
+            origin.extend(std::iter::repeat_n(index, lower_c.len_utf8()));
+        }
+    }
+    origin.push(text.len());
+
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while let Some(found) = lower[start..].find(&query) {
+        let position = start + found;
+        let (match_start, match_end) = (origin[position], origin[position + query.len()]);
+        if match_start < match_end {
+            matches.push((match_start, match_end));
+        }
+        start = position + lower[position..].chars().next().map_or(1, char::len_utf8);
+    }
+    matches
+}
+
+pub fn char_to_byte(text: &str, char_index: usize) -> usize {
+    text.char_indices().nth(char_index).map_or(text.len(), |(i, _)| i)
+}
+
+pub fn byte_to_char(text: &str, byte_index: usize) -> usize {
+    text.char_indices().take_while(|&(i, _)| i < byte_index).count()
+}
+
+// Char index of a zero-based line and column, clamped to the text
+fn line_column_to_char(text: &str, line: usize, column: usize) -> usize {
+    let mut index = 0;
+    for (i, line_text) in text.split('\n').enumerate() {
+        let length = line_text.chars().count();
+        if i == line {
+            index += column.min(length);
+            break;
+        }
+        index += length + 1;
+    }
+    index.min(text.chars().count())
+}
+
+// Room left of the text for per-line markers
+const GUTTER_WIDTH: f32 = 12.0;
+
+// Longer changes are cut short in the popup showing their original lines
+const MAX_ORIGINAL_LINES: usize = 30;
+
+// How long typing pauses before the gutter markers are recomputed
+const DIFF_DELAY: Duration = Duration::from_millis(300);
+// How often the git index is checked for staging and commits
+const INDEX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Splits the job's sections where the ranges start and end and underlines the
+// covered text in the first matching range's color
+fn underline(job: &mut egui::text::LayoutJob, ranges: &[(Range<usize>, Color32)]) {
+    if ranges.is_empty() {
+        return;
+    }
+    let text = &job.text;
+    let mut sections = Vec::with_capacity(job.sections.len());
+    for section in std::mem::take(&mut job.sections) {
+        let range = section.byte_range.clone();
+        let mut bounds: Vec<usize> = ranges
+            .iter()
+            .flat_map(|(r, _)| [r.start, r.end])
+            .filter(|&b| b > range.start && b < range.end && text.is_char_boundary(b))
+            .chain([range.start, range.end])
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+        for (i, pair) in bounds.windows(2).enumerate() {
+            let mut format = section.format.clone();
+            if let Some((_, color)) = ranges.iter().find(|(r, _)| r.start <= pair[0] && pair[1] <= r.end) {
+                format.underline = egui::Stroke::new(1.5, *color);
+            }
+            sections.push(egui::text::LayoutSection {
+                leading_space: if i == 0 { section.leading_space } else { 0.0 },
+                byte_range: pair[0]..pair[1],
+                format,
+            });
+        }
+    }
+    job.sections = sections;
+}
+
+// Vertical extent of each line on screen, which may wrap over several rows
+fn line_extents(output: &egui::text_edit::TextEditOutput) -> Vec<(f32, f32)> {
+    let origin = output.text_draw_pos;
+    let mut lines: Vec<(f32, f32)> = Vec::new();
+    let mut line = 0;
+    for row in &output.galley.rows {
+        let (top, bottom) = (origin.y + row.rect.top(), origin.y + row.rect.bottom());
+        match lines.get_mut(line) {
+            Some(extent) => extent.1 = bottom,
+            None => lines.push((top, bottom)),
+        }
+        if row.ends_with_newline {
+            line += 1;
+        }
+    }
+    lines
+}
+
+// Gutter markers for lines with diagnostics, except where the lightbulb is,
+// and a tooltip with the messages under the pointer, in the text or in the gutter
+fn show_diagnostics(
+    ui: &Ui,
+    output: &egui::text_edit::TextEditOutput,
+    gutter_left: f32,
+    text: &str,
+    problems: &[(Range<usize>, &Diagnostic)],
+    hover_char: Option<usize>,
+    lightbulb: Option<usize>,
+) {
+    if problems.is_empty() {
+        return;
+    }
+    let origin = output.text_draw_pos;
+    let lines = line_extents(output);
+
+    let mut markers: Vec<(usize, Severity)> = Vec::new();
+    for (_, d) in problems {
+        if Some(d.start.line) != lightbulb && !markers.iter().any(|&(line, _)| line == d.start.line) {
+            markers.push((d.start.line, d.severity));
+        }
+    }
+    let x = gutter_left + GUTTER_WIDTH / 2.0;
+    for (line, severity) in markers {
+        if let Some(&(top, bottom)) = lines.get(line) {
+            ui.painter().circle_filled(egui::pos2(x, (top + bottom) / 2.0), 3.5, severity.color());
+        }
+    }
+
+    let gutter = egui::Rect::from_x_y_ranges(
+        gutter_left..=gutter_left + GUTTER_WIDTH,
+        origin.y..=origin.y + output.galley.size().y,
+    );
+    let hovered: Vec<&Diagnostic> = match (ui.ctx().pointer_hover_pos(), hover_char) {
+        (Some(pointer), _) if ui.rect_contains_pointer(gutter) => {
+            let line = lines.iter().position(|&(top, bottom)| top <= pointer.y && pointer.y < bottom);
+            problems.iter().filter(|(_, d)| Some(d.start.line) == line).map(|(_, d)| *d).collect()
+        }
+        (_, Some(index)) => {
+            let offset = char_to_byte(text, index);
+            problems.iter().filter(|(range, _)| range.contains(&offset)).map(|(_, d)| *d).collect()
+        }
+        _ => Vec::new(),
+    };
+    if hovered.is_empty() {
+        return;
+    }
+    egui::show_tooltip_at_pointer(ui.ctx(), output.response.id.with("diagnostics"), |ui| {
+        ui.set_max_width(500.0);
+        for d in hovered {
+            ui.horizontal_wrapped(|ui| {
+                ui.colored_label(d.severity.color(), d.severity.icon());
+                ui.label(&d.message);
+                if let Some(source) = &d.source {
+                    ui.weak(source);
+                }
+            });
+        }
+    });
+}
+
+// Deleted lines are marked on the line before them
+fn marker_line(hunk: &Hunk) -> usize {
+    if hunk.new.is_empty() {
+        hunk.new.start.saturating_sub(1)
+    } else {
+        hunk.new.start
+    }
+}
+
+// Where the bar of a change goes in the gutter; deletions get a small triangle
+// between the lines around them
+fn change_marker(hunk: &Hunk, lines: &[(f32, f32)], gutter_left: f32) -> Option<egui::Rect> {
+    let (top, bottom) = match hunk.change() {
+        HunkChange::Deleted => {
+            let y = match hunk.new.start {
+                0 => lines.first()?.0,
+                start => lines.get(start - 1)?.1,
+            };
+            (y - 4.0, y + 4.0)
+        }
+        _ => (lines.get(hunk.new.start)?.0, lines.get(hunk.new.end - 1)?.1),
+    };
+    Some(egui::Rect::from_x_y_ranges(gutter_left..=gutter_left + 4.0, top..=bottom))
+}
+
+// Gutter bars where the text differs from what git has staged, and a popup with
+// the original lines of the change whose bar was clicked
+fn show_changes(
+    ui: &Ui,
+    output: &egui::text_edit::TextEditOutput,
+    gutter_left: f32,
+    base: &GitBase,
+    popup: Option<usize>,
+) -> Option<ChangeAction> {
+    let lines = line_extents(output);
+    let mut action = None;
+
+    for hunk in &base.hunks {
+        let Some(rect) = change_marker(hunk, &lines, gutter_left) else {
+            continue;
+        };
+        let color = hunk.change().color();
+        if hunk.change() == HunkChange::Deleted {
+            let points = vec![rect.left_top(), egui::pos2(rect.right(), rect.center().y), rect.left_bottom()];
+            ui.painter().add(egui::Shape::convex_polygon(points, color, egui::Stroke::NONE));
+        } else {
+            ui.painter().rect_filled(rect.shrink2(egui::vec2(0.5, 1.0)), 0.0, color);
+        }
+
+        let response = ui.interact(rect, output.response.id.with(("change", hunk.new.start)), egui::Sense::click());
+        if response.hovered() {
+            ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+        }
+        if response.on_hover_text("Show Original Lines").clicked() {
+            action = Some(ChangeAction::Show(marker_line(hunk)));
+        }
+    }
+
+    let Some(line) = popup else {
+        return action;
+    };
+    // The change went away, e.g. because it was reverted by hand
+    let Some((hunk, rect)) = base
+        .hunks
+        .iter()
+        .find(|hunk| marker_line(hunk) == line)
+        .and_then(|hunk| Some((hunk, change_marker(hunk, &lines, gutter_left)?)))
+    else {
+        return Some(ChangeAction::Close);
+    };
+    if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+        return Some(ChangeAction::Close);
+    }
+
+    let change = hunk.change();
+    egui::Area::new(output.response.id.with("change_popup"))
+        .order(egui::Order::Foreground)
+        .fixed_pos(egui::pos2(gutter_left + GUTTER_WIDTH, rect.bottom()))
+        .show(ui.ctx(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.set_max_width(600.0);
+                ui.horizontal(|ui| {
+                    ui.colored_label(change.color(), format!("{} lines", change.label()));
+                    let buttons = [
+                        ("↑", "Previous Change", ChangeAction::Previous),
+                        ("↓", "Next Change", ChangeAction::Next),
+                        ("Stage", "Stage Change", ChangeAction::Stage),
+                        ("Revert", "Revert Change", ChangeAction::Revert),
+                        ("×", "Close", ChangeAction::Close),
+                    ];
+                    for (text, hover, clicked) in buttons {
+                        if ui.small_button(text).on_hover_text(hover).clicked() {
+                            action = Some(clicked);
+                        }
+                    }
+                });
+                ui.separator();
+
+                if hunk.old.is_empty() {
+                    ui.weak("No original lines");
+                    return;
+                }
+                let original = base.text.as_deref().unwrap_or_default().lines().skip(hunk.old.start);
+                for text in original.take(hunk.old.len().min(MAX_ORIGINAL_LINES)) {
+                    let text = egui::RichText::new(format!("- {}", text)).monospace();
+                    ui.label(text.color(HunkChange::Deleted.color()));
+                }
+                if hunk.old.len() > MAX_ORIGINAL_LINES {
+                    ui.weak(format!("… {} more lines", hunk.old.len() - MAX_ORIGINAL_LINES));
+                }
+            });
+        });
+    action
+}
+
+// Index of the char drawn at `pos`, relative to the galley; None past the end
+// of a line or of the text
+fn char_at(galley: &egui::Galley, pos: egui::Vec2) -> Option<usize> {
+    let mut index = 0;
+    for row in &galley.rows {
+        if row.min_y() <= pos.y && pos.y < row.max_y() {
+            let column = row.glyphs.iter().position(|glyph| {
+                let rect = glyph.logical_rect();
+                rect.left() <= pos.x && pos.x < rect.right()
+            })?;
+            return Some(index + column);
+        }
+        index += row.char_count_including_newline();
+    }
+    None
+}
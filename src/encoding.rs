@@ -0,0 +1,311 @@
+use std::borrow::Cow;
+use std::fmt;
+
+const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+const UTF16_LE_BOM: [u8; 2] = [0xFF, 0xFE];
+const UTF16_BE_BOM: [u8; 2] = [0xFE, 0xFF];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16LeBom,
+    Utf16Be,
+    Utf16BeBom,
+    Latin1,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 7] = [
+        Encoding::Utf8,
+        Encoding::Utf8Bom,
+        Encoding::Utf16Le,
+        Encoding::Utf16LeBom,
+        Encoding::Utf16Be,
+        Encoding::Utf16BeBom,
+        Encoding::Latin1,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 with BOM",
+            Encoding::Utf16Le => "UTF-16 LE",
+            Encoding::Utf16LeBom => "UTF-16 LE with BOM",
+            Encoding::Utf16Be => "UTF-16 BE",
+            Encoding::Utf16BeBom => "UTF-16 BE with BOM",
+            Encoding::Latin1 => "Latin-1",
+        }
+    }
+
+    // A byte order mark wins; otherwise text with a NUL in every other byte is
+    // BOM-less UTF-16, valid UTF-8 is UTF-8, and anything else falls back to Latin-1.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&UTF8_BOM) {
+            return Encoding::Utf8Bom;
+        }
+        if bytes.starts_with(&UTF16_LE_BOM) {
+            return Encoding::Utf16LeBom;
+        }
+        if bytes.starts_with(&UTF16_BE_BOM) {
+            return Encoding::Utf16BeBom;
+        }
+
+        let pairs = bytes.len() / 2;
+        if pairs > 0 {
+            let even_zeros = bytes.iter().step_by(2).filter(|&&b| b == 0).count();
+            let odd_zeros = bytes.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+            if odd_zeros * 10 > pairs * 4 && even_zeros * 10 < pairs {
+                return Encoding::Utf16Le;
+            }
+            if even_zeros * 10 > pairs * 4 && odd_zeros * 10 < pairs {
+                return Encoding::Utf16Be;
+            }
+        }
+        if std::str::from_utf8(bytes).is_ok() {
+            return Encoding::Utf8;
+        }
+        Encoding::Latin1
+    }
+
+    // Detects the encoding from the start of a file, which may end in the middle
+    // of a UTF-8 character
+    pub fn sniff(bytes: &[u8]) -> Self {
+        let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+        match Encoding::detect(sample) {
+            Encoding::Latin1 if sample.len() < bytes.len() => match std::str::from_utf8(sample) {
+                Err(e) if e.error_len().is_none() => Encoding::Utf8,
+                _ => Encoding::Latin1,
+            },
+            encoding => encoding,
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, DecodeError> {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let bytes = bytes.strip_prefix(&UTF8_BOM).unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).map_err(|e| DecodeError {
+                    encoding: *self,
+                    message: format!("invalid byte sequence at offset {}", e.utf8_error().valid_up_to()),
+                })
+            }
+            Encoding::Utf16Le | Encoding::Utf16LeBom | Encoding::Utf16Be | Encoding::Utf16BeBom => {
+                if !bytes.len().is_multiple_of(2) {
+                    return Err(DecodeError {
+                        encoding: *self,
+                        message: "odd number of bytes".to_string(),
+                    });
+                }
+                char::decode_utf16(self.utf16_units(bytes))
+                    .collect::<Result<String, _>>()
+                    .map_err(|e| DecodeError {
+                        encoding: *self,
+                        message: format!("unpaired surrogate 0x{:04X}", e.unpaired_surrogate()),
+                    })
+            }
+            Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        }
+    }
+
+    // For display only: what can't be decoded shows up as U+FFFD
+    pub fn decode_lossy<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => String::from_utf8_lossy(bytes.strip_prefix(&UTF8_BOM).unwrap_or(bytes)),
+            Encoding::Utf16Le | Encoding::Utf16LeBom | Encoding::Utf16Be | Encoding::Utf16BeBom => {
+                char::decode_utf16(self.utf16_units(bytes))
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+        }
+    }
+
+    // The code units of UTF-16 text after its byte order mark; an odd last byte is dropped
+    fn utf16_units<'a>(&self, bytes: &'a [u8]) -> impl Iterator<Item = u16> + 'a {
+        let little_endian = self.is_little_endian();
+        let bom = if little_endian { UTF16_LE_BOM } else { UTF16_BE_BOM };
+        let bytes = bytes.strip_prefix(&bom).unwrap_or(bytes);
+        bytes.chunks_exact(2).map(move |pair| {
+            if little_endian {
+                u16::from_le_bytes([pair[0], pair[1]])
+            } else {
+                u16::from_be_bytes([pair[0], pair[1]])
+            }
+        })
+    }
+
+    fn is_little_endian(&self) -> bool {
+        matches!(self, Encoding::Utf16Le | Encoding::Utf16LeBom)
+    }
+
+    // An ASCII character as one code unit, to find line breaks without decoding
+    pub fn code_unit(&self, ascii: u8) -> Vec<u8> {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16LeBom => vec![ascii, 0],
+            Encoding::Utf16Be | Encoding::Utf16BeBom => vec![0, ascii],
+            _ => vec![ascii],
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, EncodeError> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf8Bom => Ok([&UTF8_BOM[..], text.as_bytes()].concat()),
+            Encoding::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Encoding::Utf16LeBom => Ok(UTF16_LE_BOM
+                .into_iter()
+                .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+                .collect()),
+            Encoding::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Encoding::Utf16BeBom => Ok(UTF16_BE_BOM
+                .into_iter()
+                .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+                .collect()),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| {
+                    u8::try_from(c).map_err(|_| EncodeError {
+                        encoding: *self,
+                        character: c,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+// How much of a file is looked at to decide whether it is text
+const SNIFF_LEN: usize = 8192;
+
+// Text rarely contains NUL bytes or many other control characters, except for
+// UTF-16, which `Encoding::detect` recognizes
+pub fn is_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+    let encoding = Encoding::sniff(bytes);
+    if !matches!(encoding, Encoding::Utf8 | Encoding::Latin1) {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+    let control = sample
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B))
+        .count();
+    control * 10 > sample.len()
+}
+
+#[derive(Debug)]
+pub struct DecodeError {
+    pub encoding: Encoding,
+    pub message: String,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not valid {}: {}", self.encoding.label(), self.message)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Debug)]
+pub struct EncodeError {
+    pub encoding: Encoding,
+    pub character: char,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' cannot be written as {}", self.character, self.encoding.label())
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "caf\u{e9} na\u{ef}ve\r\nline\n";
+
+    fn round_trip(encoding: Encoding, text: &str) {
+        let bytes = encoding.encode(text).unwrap();
+        assert_eq!(Encoding::detect(&bytes), encoding, "{}", encoding.label());
+        assert_eq!(encoding.decode(&bytes).unwrap(), text, "{}", encoding.label());
+        assert_eq!(encoding.encode(&encoding.decode(&bytes).unwrap()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn every_encoding_round_trips() {
+        for encoding in Encoding::ALL {
+            round_trip(encoding, TEXT);
+        }
+        round_trip(Encoding::Utf8, "emoji \u{1F600} and CJK \u{4E2D}");
+        round_trip(Encoding::Utf16Le, "emoji \u{1F600} and CJK \u{4E2D}");
+        round_trip(Encoding::Utf16BeBom, "emoji \u{1F600} and CJK \u{4E2D}");
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        assert_eq!(Encoding::Utf8Bom.encode("a").unwrap(), [0xEF, 0xBB, 0xBF, b'a']);
+        assert_eq!(Encoding::Utf16LeBom.encode("a").unwrap(), [0xFF, 0xFE, b'a', 0]);
+        assert_eq!(Encoding::Utf16BeBom.encode("a").unwrap(), [0xFE, 0xFF, 0, b'a']);
+        // Files without one don't gain one when saved
+        let bytes = [b'h', 0, b'i', 0, b'\n', 0];
+        let encoding = Encoding::detect(&bytes);
+        assert_eq!(encoding, Encoding::Utf16Le);
+        assert_eq!(encoding.encode(&encoding.decode(&bytes).unwrap()).unwrap(), bytes);
+        let bytes = [0, b'h', 0, b'i'];
+        assert_eq!(Encoding::detect(&bytes), Encoding::Utf16Be);
+    }
+
+    #[test]
+    fn latin1_high_bytes() {
+        let bytes: Vec<u8> = (0x80..=0xFF).collect();
+        assert_eq!(Encoding::detect(&bytes), Encoding::Latin1);
+        let text = Encoding::Latin1.decode(&bytes).unwrap();
+        assert_eq!(text.chars().count(), 128);
+        assert_eq!(text.chars().next(), Some('\u{80}'));
+        assert_eq!(text.chars().last(), Some('\u{ff}'));
+        assert_eq!(Encoding::Latin1.encode(&text).unwrap(), bytes);
+        assert!(Encoding::Latin1.encode("\u{20AC}").is_err());
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(Encoding::Utf8.decode(&[b'a', 0xFF]).is_err());
+        assert!(Encoding::Utf16Le.decode(&[b'a', 0, b'b']).is_err());
+        assert!(Encoding::Utf16Le.decode(&[0x00, 0xD8]).is_err());
+    }
+
+    #[test]
+    fn sniffing_the_start_of_a_file() {
+        // The sample ends in the middle of a two-byte character
+        let text = "a".repeat(SNIFF_LEN - 1) + "\u{e9}";
+        assert_eq!(Encoding::detect(&text.as_bytes()[..SNIFF_LEN]), Encoding::Latin1);
+        assert_eq!(Encoding::sniff(text.as_bytes()), Encoding::Utf8);
+        // Only the sample is looked at
+        let late = [b'a'; SNIFF_LEN + 1].iter().chain(b"\xe9").copied().collect::<Vec<u8>>();
+        assert_eq!(Encoding::sniff(&late), Encoding::Utf8);
+        let latin1 = b"\xe9".iter().chain(&[b'a'; SNIFF_LEN]).copied().collect::<Vec<u8>>();
+        assert_eq!(Encoding::sniff(&latin1), Encoding::Latin1);
+    }
+
+    #[test]
+    fn lossy_decoding() {
+        assert_eq!(Encoding::Utf8Bom.decode_lossy(b"\xEF\xBB\xBFok\xff"), "ok\u{fffd}");
+        assert_eq!(Encoding::Utf16LeBom.decode_lossy(&[0xFF, 0xFE, b'o', 0, b'k', 0, 0x00, 0xD8]), "ok\u{fffd}");
+        assert_eq!(Encoding::Latin1.decode_lossy(b"\xff"), "\u{ff}");
+    }
+
+    #[test]
+    fn binary_detection() {
+        assert!(!is_binary(b"plain text\n"));
+        assert!(!is_binary(&Encoding::Utf16Le.encode("text").unwrap()));
+        assert!(!is_binary(&Encoding::Utf16BeBom.encode("text").unwrap()));
+        assert!(is_binary(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0]));
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::encoding::{DecodeError, EncodeError};
+
+// Failures of file and config operations, worded for the user
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    Decode {
+        path: PathBuf,
+        source: DecodeError,
+    },
+    Encode {
+        path: PathBuf,
+        source: EncodeError,
+    },
+    InvalidToml {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    // Saving needs more rights than Exodus has; it doesn't elevate itself with sudo
+    PermissionDenied {
+        path: PathBuf,
+    },
+    // The temporary file for an atomic save can't be created next to the file
+    DirectoryNotWritable {
+        path: PathBuf,
+    },
+    Serialize(String),
+    NoConfigDir,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    // `action` completes "Could not ... <path>", e.g. "open" or "save"
+    pub fn io(action: &'static str, path: &Path, source: io::Error) -> Self {
+        Error::Io {
+            action,
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn toml(path: &Path, content: &str, error: toml::de::Error) -> Self {
+        let offset = error.span().map_or(0, |span| span.start).min(content.len());
+        let before = &content[..offset];
+        Error::InvalidToml {
+            path: path.to_path_buf(),
+            line: before.matches('\n').count() + 1,
+            column: before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1,
+            message: error.message().to_string(),
+        }
+    }
+}
+
+// Plain descriptions for the errors users can do something about
+fn describe_io(error: &io::Error) -> String {
+    match error.kind() {
+        io::ErrorKind::NotFound => "file not found".to_string(),
+        io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+        io::ErrorKind::StorageFull => "the disk is full".to_string(),
+        io::ErrorKind::ReadOnlyFilesystem => "the file system is read-only".to_string(),
+        io::ErrorKind::IsADirectory => "it is a directory".to_string(),
+        _ => error.to_string(),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { action, path, source } => {
+                write!(f, "Could not {} {}: {}", action, path.display(), describe_io(source))
+            }
+            Error::Decode { path, source } => write!(f, "Could not read {}: {}", path.display(), source),
+            Error::Encode { path, source } => write!(f, "Could not save {}: {}", path.display(), source),
+            Error::InvalidToml { path, line, column, message } => write!(
+                f,
+                "Invalid TOML in {} at line {}, column {}: {}",
+                path.display(),
+                line,
+                column,
+                message.trim_end()
+            ),
+            Error::PermissionDenied { path } => write!(
+                f,
+                "Could not save {}: permission denied. Exodus does not save with elevated privileges; \
+                 change the file's permissions or use Save As to write a copy elsewhere",
+                path.display()
+            ),
+            Error::DirectoryNotWritable { path } => write!(
+                f,
+                "Could not save {}: its folder is not writable. Set save_in_place = true to overwrite \
+                 the file directly, at the risk of a truncated file if Exodus crashes while saving",
+                path.display()
+            ),
+            Error::Serialize(message) => write!(f, "Could not serialize settings: {}", message),
+            Error::NoConfigDir => write!(f, "Could not find the config directory"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Decode { source, .. } => Some(source),
+            Error::Encode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::config::FormatterConfig;
+use crate::diff::{self, Change};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A formatter that takes longer is assumed to hang and is stopped
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Runs `run` on a thread so a slow formatter doesn't hold up the editor
+pub fn start(formatter: FormatterConfig, path: Option<PathBuf>, text: String) -> Receiver<Result<String, String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(run(&formatter, path.as_deref(), &text));
+    });
+    receiver
+}
+
+// Runs an external formatter with the text on stdin and returns what it
+// writes to stdout
+pub fn run(formatter: &FormatterConfig, path: Option<&Path>, text: &str) -> Result<String, String> {
+    let file = path.map(|p| p.display().to_string()).unwrap_or_default();
+    let mut command = Command::new(&formatter.command);
+    command
+        .args(formatter.args.iter().map(|arg| arg.replace("{file}", &file)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Next to the file, so the formatter finds its settings, e.g. rustfmt.toml
+    if let Some(directory) = path.and_then(Path::parent) {
+        command.current_dir(directory);
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Could not start {}: {}", formatter.command, e))?;
+
+    // Written and read on threads so a full pipe can't block both sides
+    let stdin = child.stdin.take();
+    let input = text.to_string();
+    thread::spawn(move || stdin.map(|mut stdin| stdin.write_all(input.as_bytes())));
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} did not finish in time", formatter.command));
+            }
+            Err(e) => return Err(format!("{} failed: {}", formatter.command, e)),
+        }
+    };
+
+    let output = stdout.join().unwrap_or_default();
+    if !status.success() {
+        let message = stderr.join().unwrap_or_default();
+        let message = String::from_utf8_lossy(&message);
+        let message = if message.trim().is_empty() { status.to_string() } else { message.trim().to_string() };
+        return Err(format!("{} failed: {}", formatter.command, message));
+    }
+    // Nothing on stdout usually means the arguments are wrong, not an empty file
+    if output.is_empty() && !text.is_empty() {
+        return Err(format!("{} printed nothing; check its arguments", formatter.command));
+    }
+    String::from_utf8(output)
+        .map(|text| text.replace("\r\n", "\n"))
+        .map_err(|_| format!("{} printed text that isn't UTF-8", formatter.command))
+}
+
+fn read_to_end(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+// Takes the changed lines of `formatted` into `text`; with `lines`, only the
+// changes touching those lines. Returns the result and where the cursor, a
+// zero-based (line, column), ends up: on the same line when it is unchanged.
+pub fn merge(text: &str, formatted: &str, lines: Option<Range<usize>>, cursor: (usize, usize)) -> (String, (usize, usize)) {
+    let diff = diff::diff_lines(text, formatted);
+    let mut result: Vec<&str> = Vec::new();
+    let mut moved = None;
+    // Lines of `text` passed so far
+    let mut old_line = 0;
+
+    let mut i = 0;
+    while i < diff.len() {
+        if diff[i].change == Change::Same {
+            if old_line == cursor.0 {
+                moved = Some((result.len(), cursor.1));
+            }
+            result.push(&diff[i].text);
+            old_line += 1;
+            i += 1;
+            continue;
+        }
+
+        let end = i + diff[i..].iter().take_while(|line| line.change != Change::Same).count();
+        let removed: Vec<&str> = diff[i..end].iter().filter(|l| l.change == Change::Removed).map(|l| l.text.as_str()).collect();
+        let added: Vec<&str> = diff[i..end].iter().filter(|l| l.change == Change::Added).map(|l| l.text.as_str()).collect();
+        let hunk = old_line..old_line + removed.len().max(1);
+        let keep = lines.as_ref().is_some_and(|lines| hunk.start >= lines.end || hunk.end <= lines.start);
+
+        let start = result.len();
+        let replacement = if keep { &removed } else { &added };
+        if (old_line..old_line + removed.len()).contains(&cursor.0) {
+            let offset = (cursor.0 - old_line).min(replacement.len().saturating_sub(1));
+            moved = Some((start + offset, cursor.1));
+        }
+        result.extend(replacement);
+        old_line += removed.len();
+        i = end;
+    }
+
+    let text = match lines {
+        // The whole text is formatted, so it is taken as is
+        None => formatted.to_string(),
+        Some(_) => {
+            let mut merged = result.join("\n");
+            if text.ends_with('\n') && !merged.is_empty() {
+                merged.push('\n');
+            }
+            merged
+        }
+    };
+    // Past the last line, e.g. after the final newline
+    let cursor = moved.unwrap_or((result.len(), cursor.1));
+    (text, cursor)
+}
@@ -0,0 +1,50 @@
+// Subsequence matcher used by the command palette and other pickers.
+// Every query character must appear in the candidate, in order, ignoring case.
+// Consecutive runs and matches at word starts score higher.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let query: Vec<char> = query.chars().flat_map(|c| c.to_lowercase()).collect();
+    let mut score = 0;
+    let mut query_idx = 0;
+    let mut previous_matched = false;
+    let mut previous_char: Option<char> = None;
+
+    for (i, c) in candidate.chars().enumerate() {
+        if query_idx == query.len() {
+            break;
+        }
+
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        if lower == query[query_idx] {
+            score += 1;
+            if previous_matched {
+                score += 5;
+            }
+            let word_start = match previous_char {
+                None => true,
+                Some(p) => !p.is_alphanumeric() || (p.is_lowercase() && c.is_uppercase()),
+            };
+            if word_start {
+                score += 10;
+            }
+            if i == 0 {
+                score += 5;
+            }
+            query_idx += 1;
+            previous_matched = true;
+        } else {
+            previous_matched = false;
+        }
+        previous_char = Some(c);
+    }
+
+    if query_idx == query.len() {
+        // Prefer shorter candidates when the match quality is otherwise equal
+        Some(score * 100 - candidate.chars().count() as i32)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,450 @@
+use egui::{Color32, Context};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+// The `.git` directory of the repository containing `dir`, if any
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    for ancestor in dir.ancestors() {
+        let candidate = ancestor.join(".git");
+        if candidate.is_dir() {
+            return Some(candidate);
+        }
+        // Worktrees and submodules have a `.git` file pointing to the real directory
+        if candidate.is_file() {
+            let content = fs::read_to_string(&candidate).ok()?;
+            let target = content.trim().strip_prefix("gitdir:")?.trim();
+            return Some(ancestor.join(target));
+        }
+    }
+    None
+}
+
+// Reads HEAD directly so the status bar doesn't spawn a process every refresh.
+// A detached HEAD shows the abbreviated commit hash.
+pub fn current_branch(dir: &Path) -> Option<String> {
+    let head = fs::read_to_string(git_dir(dir)?.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.trim_start_matches("refs/heads/").to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+pub fn branches(dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let output = git(dir, &["branch", "--format=%(refname:short)"])?;
+    Ok(output.lines().map(str::to_string).collect())
+}
+
+pub fn checkout(dir: &Path, branch: &str) -> Result<(), Box<dyn Error>> {
+    git(dir, &["checkout", branch])?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileStatus {
+    // Ordered by importance, so a folder shows the most important status inside it
+    Ignored,
+    Untracked,
+    Added,
+    Modified,
+    Deleted,
+    Conflicted,
+}
+
+impl FileStatus {
+    pub fn badge(self) -> &'static str {
+        match self {
+            FileStatus::Ignored => "",
+            FileStatus::Untracked => "U",
+            FileStatus::Added => "A",
+            FileStatus::Modified => "M",
+            FileStatus::Deleted => "D",
+            FileStatus::Conflicted => "C",
+        }
+    }
+
+    pub fn color(self) -> Color32 {
+        match self {
+            FileStatus::Ignored => Color32::GRAY,
+            FileStatus::Untracked | FileStatus::Added => Color32::from_rgb(100, 200, 100),
+            FileStatus::Modified => Color32::from_rgb(255, 200, 0),
+            FileStatus::Deleted => Color32::from_rgb(255, 100, 100),
+            FileStatus::Conflicted => Color32::from_rgb(200, 130, 255),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FileStatus::Ignored => "Ignored",
+            FileStatus::Untracked => "Untracked",
+            FileStatus::Added => "Added",
+            FileStatus::Modified => "Modified",
+            FileStatus::Deleted => "Deleted",
+            FileStatus::Conflicted => "Conflicted",
+        }
+    }
+
+    // The two letter code of `git status --porcelain`
+    fn parse(code: &str) -> Option<FileStatus> {
+        let (index, worktree) = (code.chars().next()?, code.chars().nth(1)?);
+        Some(match (index, worktree) {
+            ('!', '!') => FileStatus::Ignored,
+            ('?', '?') => FileStatus::Untracked,
+            ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D') => FileStatus::Conflicted,
+            ('D', _) | (_, 'D') => FileStatus::Deleted,
+            ('M' | 'T', _) | (_, 'M' | 'T') => FileStatus::Modified,
+            ('A' | 'R' | 'C', _) => FileStatus::Added,
+            _ => return None,
+        })
+    }
+}
+
+// The status of every changed, untracked or ignored path in a repository
+#[derive(Default)]
+pub struct Status {
+    // The work tree as git reports it and as found from the folder we were given,
+    // which differ when the folder was opened through a symlink
+    roots: Vec<PathBuf>,
+    files: HashMap<PathBuf, FileStatus>,
+    // Untracked and ignored folders, which git lists instead of the files inside them
+    folders: HashMap<PathBuf, FileStatus>,
+    // The most important status inside each folder containing changes
+    aggregated: HashMap<PathBuf, FileStatus>,
+}
+
+impl Status {
+    pub fn get(&self, path: &Path) -> Option<FileStatus> {
+        let relative = self.roots.iter().find_map(|root| path.strip_prefix(root).ok())?;
+        self.files
+            .get(relative)
+            .or_else(|| self.aggregated.get(relative))
+            .or_else(|| relative.ancestors().find_map(|folder| self.folders.get(folder)))
+            .copied()
+    }
+
+    fn insert(&mut self, path: &str, status: FileStatus) {
+        let folder = path.ends_with('/');
+        let path = PathBuf::from(path.trim_end_matches('/'));
+        if status != FileStatus::Ignored {
+            for ancestor in path.ancestors().skip(1) {
+                let entry = self.aggregated.entry(ancestor.to_path_buf()).or_insert(status);
+                *entry = (*entry).max(status);
+            }
+        }
+        let entries = if folder { &mut self.folders } else { &mut self.files };
+        let entry = entries.entry(path).or_insert(status);
+        *entry = (*entry).max(status);
+    }
+}
+
+pub fn status(dir: &Path) -> Result<Status, Box<dyn Error>> {
+    let mut status = Status::default();
+    status.roots.push(PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim_end()));
+    if let Some(root) = dir.ancestors().find(|ancestor| ancestor.join(".git").exists()) {
+        status.roots.push(root.to_path_buf());
+    }
+
+    let output = git(dir, &["status", "--porcelain=v1", "-z", "--ignored"])?;
+    let mut entries = output.split('\0');
+    while let Some(entry) = entries.next() {
+        let (Some(code), Some(path)) = (entry.get(..2), entry.get(3..)) else {
+            continue;
+        };
+        // Renames and copies are followed by the path they came from
+        if code.starts_with(['R', 'C']) {
+            entries.next();
+        }
+        if let Some(file_status) = FileStatus::parse(code) {
+            status.insert(path, file_status);
+        }
+    }
+    Ok(status)
+}
+
+// Keeps the status of the open repository fresh by running `git status` in the
+// background every couple of seconds, or right away after a save
+#[derive(Default)]
+pub struct StatusTracker {
+    dir: Option<PathBuf>,
+    status: Status,
+    pending: Option<Receiver<Option<Status>>>,
+    checked: Option<Instant>,
+}
+
+impl StatusTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    pub fn refresh(&mut self) {
+        self.checked = None;
+    }
+
+    pub fn poll(&mut self, ctx: &Context, dir: Option<&Path>) {
+        if self.dir.as_deref() != dir {
+            self.dir = dir.map(Path::to_path_buf);
+            self.status = Status::default();
+            self.pending = None;
+            self.checked = None;
+        }
+
+        if let Some(receiver) = &self.pending {
+            match receiver.try_recv() {
+                Ok(status) => {
+                    self.status = status.unwrap_or_default();
+                    self.pending = None;
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => self.pending = None,
+            }
+        }
+
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        let stale = self.checked.is_none_or(|checked| checked.elapsed() > REFRESH_INTERVAL);
+        if stale {
+            let (sender, receiver) = mpsc::channel();
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                let _ = sender.send(status(&dir).ok());
+                ctx.request_repaint();
+            });
+            self.pending = Some(receiver);
+            self.checked = Some(Instant::now());
+        }
+        // Keep polling while idle so changes made outside the editor show up
+        ctx.request_repaint_after(REFRESH_INTERVAL);
+    }
+}
+
+// When the index was last written; staging, committing and checking out all rewrite it
+pub fn index_modified(path: &Path) -> Option<SystemTime> {
+    let index = git_dir(path.parent()?)?.join("index");
+    fs::metadata(index).and_then(|metadata| metadata.modified()).ok()
+}
+
+// The file as staged in the index, in whatever encoding it was committed in;
+// None when it isn't tracked
+pub fn staged_bytes(path: &Path) -> Option<Vec<u8>> {
+    let (dir, name) = (path.parent()?, path.file_name()?.to_str()?);
+    git_bytes(dir, &["show", &format!(":./{}", name)], None).ok()
+}
+
+// Replaces the staged version of a tracked file with `bytes`, leaving the file itself alone
+pub fn stage_bytes(path: &Path, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return Err(format!("{} is not a file", path.display()).into());
+    };
+    // "<mode> <object> <stage>\t<path>", the path relative to the work tree as --cacheinfo wants it
+    let staged = git(dir, &["ls-files", "--stage", "--full-name", "--", name])?;
+    let Some((info, full_name)) = staged.lines().next().and_then(|line| line.split_once('\t')) else {
+        return Err(format!("{} is not tracked", name).into());
+    };
+    let mode = info.split(' ').next().unwrap_or_default();
+    let object = git_bytes(dir, &["hash-object", "-w", "--stdin"], Some(bytes))?;
+    let object = String::from_utf8_lossy(&object);
+    git(dir, &["update-index", "--cacheinfo", &format!("{},{},{}", mode, object.trim(), full_name)])?;
+    Ok(())
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    git_bytes(dir, args, None).map(|output| String::from_utf8_lossy(&output).into_owned())
+}
+
+// Runs git with `input` on stdin and returns its raw output
+fn git_bytes(dir: &Path, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut child = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(input) = input {
+        child.stdin.take().ok_or("git has no input")?.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string().into());
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+    use crate::encoding::Encoding;
+    use crate::test_dir::TestDir;
+
+    // A throwaway repository in the temporary directory, removed when dropped
+    struct Repo(TestDir);
+
+    impl Repo {
+        fn new() -> Repo {
+            let repo = Repo(TestDir::new("git"));
+            repo.git(&["init", "--quiet", "--initial-branch=main"]);
+            repo.git(&["config", "user.name", "Test"]);
+            repo.git(&["config", "user.email", "test@example.com"]);
+            repo
+        }
+
+        fn git(&self, args: &[&str]) -> String {
+            git(&self.0, args).unwrap()
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        fn commit(&self, message: &str) {
+            self.git(&["add", "--all"]);
+            self.git(&["commit", "--quiet", "--message", message]);
+        }
+
+        fn status(&self, path: &str) -> Option<FileStatus> {
+            status(&self.0).unwrap().get(&self.0.join(path))
+        }
+    }
+
+    #[test]
+    fn file_statuses() {
+        let repo = Repo::new();
+        repo.write(".gitignore", "build/\n*.log\n");
+        repo.write("clean.txt", "clean");
+        repo.write("changed.txt", "before");
+        repo.write("removed.txt", "removed");
+        repo.commit("initial");
+
+        repo.write("changed.txt", "after");
+        repo.write("staged.txt", "new");
+        repo.git(&["add", "staged.txt"]);
+        repo.write("untracked.txt", "new");
+        repo.write("debug.log", "ignored");
+        fs::remove_file(repo.0.join("removed.txt")).unwrap();
+
+        assert_eq!(repo.status("clean.txt"), None);
+        assert_eq!(repo.status("changed.txt"), Some(FileStatus::Modified));
+        assert_eq!(repo.status("staged.txt"), Some(FileStatus::Added));
+        assert_eq!(repo.status("untracked.txt"), Some(FileStatus::Untracked));
+        assert_eq!(repo.status("debug.log"), Some(FileStatus::Ignored));
+        assert_eq!(repo.status("removed.txt"), Some(FileStatus::Deleted));
+    }
+
+    #[test]
+    fn folders_aggregate_their_contents() {
+        let repo = Repo::new();
+        repo.write("src/lib.rs", "lib");
+        repo.write("src/nested/mod.rs", "mod");
+        repo.write("docs/guide.md", "guide");
+        repo.commit("initial");
+
+        repo.write("src/nested/mod.rs", "changed");
+        repo.write("docs/draft.md", "draft");
+        repo.write("assets/logo.svg", "logo");
+        repo.write("build/output", "output");
+        repo.write(".gitignore", "build/\n");
+
+        assert_eq!(repo.status("src"), Some(FileStatus::Modified));
+        assert_eq!(repo.status("src/nested"), Some(FileStatus::Modified));
+        assert_eq!(repo.status("src/lib.rs"), None);
+        assert_eq!(repo.status("docs"), Some(FileStatus::Untracked));
+        // Git lists untracked and ignored folders as a whole
+        assert_eq!(repo.status("assets"), Some(FileStatus::Untracked));
+        assert_eq!(repo.status("assets/logo.svg"), Some(FileStatus::Untracked));
+        assert_eq!(repo.status("build/output"), Some(FileStatus::Ignored));
+    }
+
+    #[test]
+    fn renames_and_conflicts() {
+        let repo = Repo::new();
+        repo.write("old.txt", "content");
+        repo.write("shared.txt", "base");
+        repo.commit("initial");
+
+        repo.git(&["checkout", "--quiet", "-b", "other"]);
+        repo.write("shared.txt", "other");
+        repo.commit("other");
+        repo.git(&["checkout", "--quiet", "main"]);
+        repo.write("shared.txt", "main");
+        repo.commit("main");
+        assert!(git(&repo.0, &["merge", "other"]).is_err());
+
+        repo.git(&["mv", "old.txt", "new.txt"]);
+
+        assert_eq!(repo.status("shared.txt"), Some(FileStatus::Conflicted));
+        assert_eq!(repo.status("new.txt"), Some(FileStatus::Added));
+        assert_eq!(repo.status("old.txt"), None);
+    }
+
+    #[test]
+    fn staging_text() {
+        let repo = Repo::new();
+        repo.write("src/file.txt", "one\ntwo\n");
+        repo.commit("initial");
+        let path = repo.0.join("src/file.txt");
+        assert_eq!(staged_bytes(&path).as_deref(), Some(&b"one\ntwo\n"[..]));
+
+        let before = index_modified(&path);
+        assert!(before.is_some());
+        stage_bytes(&path, b"one\n2\n").unwrap();
+        assert_eq!(staged_bytes(&path).as_deref(), Some(&b"one\n2\n"[..]));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        assert_eq!(repo.status("src/file.txt"), Some(FileStatus::Modified));
+
+        repo.write("untracked.txt", "new");
+        assert_eq!(staged_bytes(&repo.0.join("untracked.txt")), None);
+        assert!(stage_bytes(&repo.0.join("untracked.txt"), b"text").is_err());
+    }
+
+    #[test]
+    fn staging_a_hunk_keeps_the_encoding() {
+        let repo = Repo::new();
+        let path = repo.0.join("latin1.txt");
+        fs::write(&path, b"caf\xe9\nna\xefve\nend\n").unwrap();
+        repo.commit("initial");
+        fs::write(&path, b"caf\xe9!\nna\xefve\nfin\xe9\n").unwrap();
+
+        // What the editor does: decode both sides, splice in one hunk, encode it back
+        let staged = staged_bytes(&path).unwrap();
+        let encoding = Encoding::detect(&staged);
+        assert_eq!(encoding, Encoding::Latin1);
+        let staged = encoding.decode(&staged).unwrap();
+        let current = Encoding::Latin1.decode(&fs::read(&path).unwrap()).unwrap();
+        let hunks = diff::hunks(&staged, &current);
+        assert_eq!(hunks.len(), 2);
+        let text = diff::splice_lines(&staged, hunks[0].old.clone(), &current, hunks[0].new.clone());
+        stage_bytes(&path, &Encoding::Latin1.encode(&text).unwrap()).unwrap();
+
+        let blob = git_bytes(&repo.0, &["cat-file", "blob", ":latin1.txt"], None).unwrap();
+        assert_eq!(blob, b"caf\xe9!\nna\xefve\nend\n");
+    }
+
+    #[test]
+    fn branch_and_paths_outside() {
+        let repo = Repo::new();
+        repo.write("file.txt", "content");
+        repo.commit("initial");
+        repo.git(&["checkout", "--quiet", "-b", "feature"]);
+
+        assert_eq!(current_branch(&repo.0).as_deref(), Some("feature"));
+        let status = status(&repo.0).unwrap();
+        assert_eq!(status.get(Path::new("/elsewhere/file.txt")), None);
+    }
+}
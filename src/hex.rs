@@ -0,0 +1,573 @@
+use egui::text::LayoutJob;
+use egui::{Color32, Event, FontId, Id, Key, ScrollArea, Sense, TextFormat, TextStyle, Ui};
+use std::ops::Range;
+
+use crate::large_file::FileBytes;
+
+const BYTES_PER_ROW: usize = 16;
+// Column layout of a row in characters: offset, hex bytes (with a gap after
+// the eighth), then the ASCII column
+const HEX_START: usize = 10;
+const ASCII_START: usize = HEX_START + BYTES_PER_ROW * 3 + 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Hex,
+    Ascii,
+}
+
+// Replaces `old` at `offset` with `new`; undone by doing the reverse
+struct Edit {
+    offset: usize,
+    old: Vec<u8>,
+    new: Vec<u8>,
+}
+
+// Byte-level editor for binary files with offset, hex and ASCII columns
+pub struct HexEditor {
+    id: Id,
+    bytes: FileBytes,
+    cursor: usize,
+    column: Column,
+    // The high nibble of the byte under the cursor was typed; the next digit sets the low one
+    low_nibble: bool,
+    insert_mode: bool,
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+    modified: bool,
+    // Last search match, drawn highlighted
+    found: Option<Range<usize>>,
+    scroll_to_cursor: bool,
+    offset_query: String,
+    search_query: String,
+    search_hex: bool,
+    message: Option<String>,
+}
+
+impl HexEditor {
+    pub fn new(id: usize, bytes: FileBytes) -> Self {
+        Self {
+            id: Id::new(("hex_editor", id)),
+            bytes,
+            cursor: 0,
+            column: Column::Hex,
+            low_nibble: false,
+            insert_mode: false,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            modified: false,
+            found: None,
+            scroll_to_cursor: false,
+            offset_query: String::new(),
+            search_query: String::new(),
+            search_hex: true,
+            message: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    // Copies mapped bytes into memory first, so the file can be overwritten safely
+    pub fn bytes_for_save(&mut self) -> &[u8] {
+        self.bytes.to_mut()
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+    }
+
+    fn replace(&mut self, offset: usize, old_len: usize, new: Vec<u8>) -> Edit {
+        let bytes = self.bytes.to_mut();
+        let old = bytes.splice(offset..offset + old_len, new.iter().copied()).collect();
+        self.modified = true;
+        self.found = None;
+        Edit { offset, old, new }
+    }
+
+    fn edit(&mut self, offset: usize, old_len: usize, new: Vec<u8>) {
+        let edit = self.replace(offset, old_len, new);
+        self.undo_stack.push(edit);
+        self.redo_stack.clear();
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(edit) = self.undo_stack.pop() {
+            let redo = self.replace(edit.offset, edit.new.len(), edit.old);
+            self.cursor = redo.offset;
+            self.redo_stack.push(redo);
+            self.low_nibble = false;
+            self.scroll_to_cursor = true;
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(edit) = self.redo_stack.pop() {
+            let undo = self.replace(edit.offset, edit.new.len(), edit.old);
+            self.cursor = undo.offset;
+            self.undo_stack.push(undo);
+            self.low_nibble = false;
+            self.scroll_to_cursor = true;
+        }
+    }
+
+    fn move_to(&mut self, offset: usize) {
+        // In insert mode the cursor may sit just past the last byte to append
+        let max = if self.insert_mode { self.len() } else { self.len().saturating_sub(1) };
+        self.cursor = offset.min(max);
+        self.low_nibble = false;
+        self.scroll_to_cursor = true;
+    }
+
+    fn type_nibble(&mut self, digit: u8) {
+        let at_end = self.cursor >= self.len();
+        if self.low_nibble {
+            // Second digit of the byte: fold it into the edit made by the first one
+            let value = (self.bytes[self.cursor] & 0xF0) | digit;
+            self.bytes.to_mut()[self.cursor] = value;
+            if let Some(edit) = self.undo_stack.last_mut() {
+                if let Some(last) = edit.new.last_mut() {
+                    *last = value;
+                }
+            }
+            self.low_nibble = false;
+            self.move_to(self.cursor + 1);
+        } else {
+            if self.insert_mode || at_end {
+                self.edit(self.cursor, 0, vec![digit << 4]);
+            } else {
+                let value = (self.bytes[self.cursor] & 0x0F) | (digit << 4);
+                self.edit(self.cursor, 1, vec![value]);
+            }
+            self.low_nibble = true;
+        }
+    }
+
+    fn type_bytes(&mut self, new: &[u8]) {
+        let old_len = if self.insert_mode {
+            0
+        } else {
+            new.len().min(self.len() - self.cursor.min(self.len()))
+        };
+        self.edit(self.cursor, old_len, new.to_vec());
+        self.move_to(self.cursor + new.len());
+    }
+
+    fn delete(&mut self, offset: usize) {
+        if offset < self.len() {
+            self.edit(offset, 1, Vec::new());
+            self.move_to(offset);
+        }
+    }
+
+    fn handle_input(&mut self, ui: &Ui) {
+        let events = ui.input(|i| i.events.clone());
+        for event in events {
+            match event {
+                Event::Text(text) => match self.column {
+                    Column::Hex => {
+                        for c in text.chars() {
+                            if let Some(digit) = c.to_digit(16) {
+                                self.type_nibble(digit as u8);
+                            }
+                        }
+                    }
+                    Column::Ascii => self.type_bytes(text.as_bytes()),
+                },
+                Event::Key { key, pressed: true, modifiers, .. } => {
+                    if modifiers.command || modifiers.alt {
+                        continue;
+                    }
+                    let page = BYTES_PER_ROW * 16;
+                    match key {
+                        Key::ArrowLeft => self.move_to(self.cursor.saturating_sub(1)),
+                        Key::ArrowRight => self.move_to(self.cursor + 1),
+                        Key::ArrowUp => self.move_to(self.cursor.saturating_sub(BYTES_PER_ROW)),
+                        Key::ArrowDown => self.move_to(self.cursor + BYTES_PER_ROW),
+                        Key::PageUp => self.move_to(self.cursor.saturating_sub(page)),
+                        Key::PageDown => self.move_to(self.cursor + page),
+                        Key::Home => self.move_to(self.cursor - self.cursor % BYTES_PER_ROW),
+                        Key::End => self.move_to(self.cursor - self.cursor % BYTES_PER_ROW + BYTES_PER_ROW - 1),
+                        Key::Tab => {
+                            self.column = if self.column == Column::Hex { Column::Ascii } else { Column::Hex };
+                            self.low_nibble = false;
+                        }
+                        Key::Insert => {
+                            self.insert_mode = !self.insert_mode;
+                            self.move_to(self.cursor);
+                        }
+                        Key::Delete => self.delete(self.cursor),
+                        Key::Backspace => {
+                            if self.insert_mode && self.cursor > 0 {
+                                self.delete(self.cursor - 1);
+                            } else {
+                                self.move_to(self.cursor.saturating_sub(1));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Accepts `0x1F0`, `1F0h` or a decimal offset
+    fn go_to_offset(&mut self) {
+        let query = self.offset_query.trim();
+        let parsed = if let Some(hex) = query.strip_prefix("0x").or_else(|| query.strip_suffix('h')) {
+            usize::from_str_radix(hex, 16).ok()
+        } else {
+            query.parse().ok()
+        };
+        match parsed {
+            Some(offset) => {
+                self.move_to(offset);
+                self.message = None;
+            }
+            None => self.message = Some(format!("'{}' is not an offset", query)),
+        }
+    }
+
+    // The search pattern as bytes: hex digit pairs, or the text as UTF-8
+    fn search_pattern(&self) -> Result<Vec<u8>, String> {
+        if !self.search_hex {
+            return Ok(self.search_query.as_bytes().to_vec());
+        }
+        let digits: Vec<char> = self.search_query.chars().filter(|c| !c.is_whitespace()).collect();
+        if let Some(c) = digits.iter().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(format!("'{}' is not hex", c));
+        }
+        if !digits.len().is_multiple_of(2) {
+            return Err("hex pattern needs an even number of digits".to_string());
+        }
+        Ok(digits
+            .chunks(2)
+            .map(|pair| (pair[0].to_digit(16).unwrap_or(0) * 16 + pair[1].to_digit(16).unwrap_or(0)) as u8)
+            .collect())
+    }
+
+    // Finds the next match after the cursor, wrapping around
+    fn find_next(&mut self) {
+        let pattern = match self.search_pattern() {
+            Ok(pattern) if !pattern.is_empty() => pattern,
+            Ok(_) => return,
+            Err(e) => {
+                self.message = Some(e);
+                return;
+            }
+        };
+        let start = (self.cursor + 1).min(self.len());
+        let position = find(&self.bytes[start..], &pattern)
+            .map(|i| start + i)
+            .or_else(|| find(&self.bytes[..(start + pattern.len()).min(self.len())], &pattern));
+        match position {
+            Some(position) => {
+                self.move_to(position);
+                self.found = Some(position..position + pattern.len());
+                self.message = None;
+            }
+            None => {
+                self.found = None;
+                self.message = Some("No matches".to_string());
+            }
+        }
+    }
+
+    fn toolbar(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Go to offset:");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.offset_query).desired_width(90.0).hint_text("0x0"));
+            if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                self.go_to_offset();
+            }
+
+            ui.separator();
+            ui.label("Find:");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.search_query).desired_width(160.0));
+            if response.changed() {
+                self.found = None;
+            }
+            let enter = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+            ui.checkbox(&mut self.search_hex, "Hex");
+            if ui.button("Next").clicked() || enter {
+                self.find_next();
+            }
+
+            if let Some(message) = &self.message {
+                ui.colored_label(Color32::from_rgb(255, 200, 0), message);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.monospace(format!("Offset 0x{:08X} ({})", self.cursor, self.cursor));
+            ui.separator();
+            ui.monospace(format!("{} bytes", self.len()));
+            ui.separator();
+            let mode = if self.insert_mode { "INS" } else { "OVR" };
+            if ui.selectable_label(false, mode).on_hover_text("Toggle insert mode (Insert)").clicked() {
+                self.insert_mode = !self.insert_mode;
+                self.move_to(self.cursor);
+            }
+        });
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) {
+        self.toolbar(ui);
+        ui.separator();
+
+        let font_id = TextStyle::Monospace.resolve(ui.style());
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        let char_width = ui.fonts(|f| f.glyph_width(&font_id, '0'));
+        // One extra row in insert mode so the cursor can sit past the end
+        let rows = (self.len() + usize::from(self.insert_mode)).div_ceil(BYTES_PER_ROW).max(1);
+
+        ScrollArea::both()
+            .auto_shrink([false, false])
+            .show_viewport(ui, |ui, viewport| {
+                let width = (ASCII_START + BYTES_PER_ROW + 1) as f32 * char_width;
+                let (_, rect) = ui.allocate_space(egui::vec2(width, rows as f32 * row_height));
+                let response = ui.interact(rect, self.id, Sense::click());
+
+                if response.clicked() {
+                    response.request_focus();
+                    if let Some(pos) = response.interact_pointer_pos() {
+                        let row = ((pos.y - rect.top()) / row_height) as usize;
+                        let col = ((pos.x - rect.left()) / char_width) as usize;
+                        if let Some((column, index)) = column_at(col) {
+                            self.column = column;
+                            self.move_to(row * BYTES_PER_ROW + index);
+                            self.scroll_to_cursor = false;
+                        }
+                    }
+                }
+
+                if response.has_focus() {
+                    // Tab switches between the hex and ASCII columns and arrows move the cursor
+                    let filter = egui::EventFilter { tab: true, arrows: true, escape: false };
+                    ui.memory_mut(|m| m.set_focus_lock_filter(self.id, filter));
+                    self.handle_input(ui);
+                }
+
+                let first = (viewport.min.y / row_height).floor().max(0.0) as usize;
+                let last = ((viewport.max.y / row_height).ceil() as usize).min(rows);
+                for row in first..last {
+                    let job = self.layout_row(row, &font_id, response.has_focus());
+                    let galley = ui.fonts(|f| f.layout_job(job));
+                    let pos = rect.min + egui::vec2(0.0, row as f32 * row_height);
+                    ui.painter().galley(pos, galley);
+                }
+
+                if self.scroll_to_cursor {
+                    let row = self.cursor / BYTES_PER_ROW;
+                    let top = rect.top() + row as f32 * row_height;
+                    let cursor_rect = egui::Rect::from_min_size(egui::pos2(rect.left(), top), egui::vec2(width, row_height));
+                    ui.scroll_to_rect(cursor_rect, None);
+                    self.scroll_to_cursor = false;
+                }
+            });
+    }
+
+    fn layout_row(&self, row: usize, font_id: &FontId, focused: bool) -> LayoutJob {
+        let format = |color: Color32, background: Color32| TextFormat {
+            font_id: font_id.clone(),
+            color,
+            background,
+            ..Default::default()
+        };
+        let normal = format(Color32::LIGHT_GRAY, Color32::TRANSPARENT);
+        let cursor = format(Color32::WHITE, Color32::from_rgb(30, 144, 255));
+        let shadow = format(Color32::WHITE, Color32::from_gray(80));
+        let found = format(Color32::BLACK, Color32::from_rgb(255, 255, 0));
+
+        let start = row * BYTES_PER_ROW;
+        let mut job = LayoutJob::default();
+        job.append(&format!("{:08X}  ", start), 0.0, format(Color32::GRAY, Color32::TRANSPARENT));
+
+        let style_for = |offset: usize, column: Column| {
+            if offset == self.cursor {
+                if focused && column == self.column { cursor.clone() } else { shadow.clone() }
+            } else if self.found.as_ref().is_some_and(|r| r.contains(&offset)) {
+                found.clone()
+            } else {
+                normal.clone()
+            }
+        };
+
+        for i in 0..BYTES_PER_ROW {
+            if i == BYTES_PER_ROW / 2 {
+                job.append(" ", 0.0, normal.clone());
+            }
+            let offset = start + i;
+            match self.bytes.get(offset) {
+                Some(byte) => {
+                    let text = format!("{:02X}", byte);
+                    let style = style_for(offset, Column::Hex);
+                    if offset == self.cursor && self.low_nibble {
+                        // Only the nibble that is typed next is highlighted
+                        job.append(&text[..1], 0.0, normal.clone());
+                        job.append(&text[1..], 0.0, style);
+                    } else {
+                        job.append(&text, 0.0, style);
+                    }
+                }
+                None if offset == self.cursor => job.append("  ", 0.0, style_for(offset, Column::Hex)),
+                None => job.append("  ", 0.0, normal.clone()),
+            }
+            job.append(" ", 0.0, normal.clone());
+        }
+
+        job.append(" ", 0.0, normal.clone());
+        for i in 0..BYTES_PER_ROW {
+            let offset = start + i;
+            let text = match self.bytes.get(offset) {
+                Some(&b) if b.is_ascii_graphic() || b == b' ' => (b as char).to_string(),
+                Some(_) => ".".to_string(),
+                None if offset == self.cursor => " ".to_string(),
+                None => break,
+            };
+            job.append(&text, 0.0, style_for(offset, Column::Ascii));
+        }
+        job
+    }
+}
+
+// Maps a character column of a row to the column and byte index under it
+fn column_at(col: usize) -> Option<(Column, usize)> {
+    if (HEX_START..ASCII_START - 1).contains(&col) {
+        let mut offset = col - HEX_START;
+        if offset >= BYTES_PER_ROW / 2 * 3 {
+            offset = offset.saturating_sub(1);
+        }
+        Some((Column::Hex, (offset / 3).min(BYTES_PER_ROW - 1)))
+    } else if (ASCII_START..ASCII_START + BYTES_PER_ROW).contains(&col) {
+        Some((Column::Ascii, col - ASCII_START))
+    } else {
+        None
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(bytes: &[u8]) -> HexEditor {
+        HexEditor::new(0, FileBytes::Loaded(bytes.to_vec()))
+    }
+
+    #[test]
+    fn typing_nibbles() {
+        let mut hex = editor(&[0x12, 0x34]);
+        hex.type_nibble(0xA);
+        // The high nibble is replaced, the low one kept until the second digit
+        assert_eq!(&hex.bytes[..], [0xA2, 0x34]);
+        assert_eq!(hex.cursor, 0);
+        hex.type_nibble(0xB);
+        assert_eq!(&hex.bytes[..], [0xAB, 0x34]);
+        assert_eq!(hex.cursor, 1);
+        assert!(hex.is_modified());
+
+        // Both digits of a byte are undone together
+        hex.undo();
+        assert_eq!(&hex.bytes[..], [0x12, 0x34]);
+    }
+
+    #[test]
+    fn inserting_at_the_end() {
+        let mut hex = editor(&[0x01]);
+        hex.insert_mode = true;
+        hex.move_to(hex.len());
+        assert_eq!(hex.cursor, 1);
+        hex.type_nibble(0xF);
+        hex.type_nibble(0xE);
+        assert_eq!(&hex.bytes[..], [0x01, 0xFE]);
+
+        hex.column = Column::Ascii;
+        hex.type_bytes(b"hi");
+        assert_eq!(&hex.bytes[..], [0x01, 0xFE, b'h', b'i']);
+        assert_eq!(hex.cursor, 4);
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let original = [0x00, 0x11, 0x22, 0x33];
+        let mut hex = editor(&original);
+        hex.type_bytes(b"ab");
+        hex.delete(3);
+        let edited = hex.bytes.to_vec();
+        assert_eq!(edited, [b'a', b'b', 0x22]);
+
+        hex.undo();
+        hex.undo();
+        assert_eq!(&hex.bytes[..], original);
+        // Nothing left to undo
+        hex.undo();
+        assert_eq!(&hex.bytes[..], original);
+
+        hex.redo();
+        hex.redo();
+        assert_eq!(hex.bytes[..], edited[..]);
+
+        // Undo puts the cursor where the edit was, and a new edit drops what could be redone
+        hex.undo();
+        assert_eq!(hex.cursor, 3);
+        hex.type_bytes(b"z");
+        hex.redo();
+        assert_eq!(&hex.bytes[..], [b'a', b'b', 0x22, b'z']);
+    }
+
+    #[test]
+    fn going_to_an_offset() {
+        let mut hex = editor(&[0; 64]);
+        hex.offset_query = "0x20".to_string();
+        hex.go_to_offset();
+        assert_eq!(hex.cursor, 32);
+        hex.offset_query = "10h".to_string();
+        hex.go_to_offset();
+        assert_eq!(hex.cursor, 16);
+
+        // Past the end stops at the last byte
+        hex.offset_query = "1000".to_string();
+        hex.go_to_offset();
+        assert_eq!(hex.cursor, 63);
+        assert!(hex.message.is_none());
+
+        hex.offset_query = "0xZZ".to_string();
+        hex.go_to_offset();
+        assert_eq!(hex.cursor, 63);
+        assert!(hex.message.is_some());
+    }
+
+    #[test]
+    fn search_patterns() {
+        let mut hex = editor(b"\x00\xCA\xFE\x00\xCA\xFE");
+        hex.search_query = "ca fe".to_string();
+        assert_eq!(hex.search_pattern(), Ok(vec![0xCA, 0xFE]));
+        hex.find_next();
+        assert_eq!(hex.found, Some(1..3));
+        hex.find_next();
+        assert_eq!(hex.found, Some(4..6));
+        // Wraps around
+        hex.find_next();
+        assert_eq!(hex.found, Some(1..3));
+
+        hex.search_query = "CAF".to_string();
+        assert!(hex.search_pattern().is_err());
+        hex.find_next();
+        assert!(hex.message.is_some());
+        hex.search_query = "xy".to_string();
+        assert!(hex.search_pattern().is_err());
+
+        hex.search_hex = false;
+        assert_eq!(hex.search_pattern(), Ok(b"xy".to_vec()));
+    }
+}
@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::Args;
+
+use transport::Stream;
+
+// Sent by the server once a request is handled, or, with --wait, once its tabs are closed
+const DONE: &str = "done";
+// A request is one line, and stdin is part of it, so this is generous; a client
+// that sends more, or stalls in the middle, isn't `exodus` doing its job
+const MAX_REQUEST_LEN: u64 = 256 * 1024 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What a later `exodus` invocation asks the running instance to open
+#[derive(Serialize, Deserialize)]
+pub struct Request {
+    pub args: Args,
+    // Standard input, already decoded by the client
+    pub stdin: Option<String>,
+}
+
+// Hands the request to a running instance. Returns Ok(false) when none is running.
+// Blocks until the instance is done with it, which with --wait means until the
+// opened tabs are closed.
+pub fn forward(request: &Request) -> io::Result<bool> {
+    let Some(mut stream) = transport::connect() else {
+        return Ok(false);
+    };
+    let mut line = serde_json::to_string(request).map_err(io::Error::other)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    stream.flush()?;
+
+    // The instance may also just go away, which ends the wait as well
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(true)
+}
+
+pub struct Incoming {
+    pub request: Request,
+    stream: Stream,
+}
+
+impl Incoming {
+    // Tells the client it can exit
+    pub fn finish(mut self) {
+        let _ = writeln!(self.stream, "{}", DONE);
+    }
+}
+
+// Accepts requests from other invocations on a background thread
+pub struct Server {
+    receiver: Receiver<Incoming>,
+}
+
+impl Server {
+    // Fails when another instance is already listening
+    pub fn start(ctx: egui::Context) -> io::Result<Self> {
+        let listener = transport::listen()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                let ctx = ctx.clone();
+                // A slow client mustn't hold up the others
+                thread::spawn(move || {
+                    if let Some(request) = read_request(&stream) {
+                        if sender.send(Incoming { request, stream }).is_ok() {
+                            ctx.request_repaint();
+                        }
+                    }
+                });
+            }
+        });
+        Ok(Self { receiver })
+    }
+
+    pub fn poll(&self) -> Vec<Incoming> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+fn read_request(stream: &Stream) -> Option<Request> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT)).ok()?;
+    let mut reader = BufReader::new(stream.try_clone().ok()?).take(MAX_REQUEST_LEN);
+    if !transport::is_authorized(&mut reader) {
+        return None;
+    }
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    serde_json::from_str(&line).ok()
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        transport::cleanup();
+    }
+}
+
+// A Unix domain socket in the user's runtime directory
+#[cfg(unix)]
+mod transport {
+    use std::fs;
+    use std::io::{self, BufRead};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+
+    pub type Stream = UnixStream;
+    pub type Listener = UnixListener;
+
+    fn socket_path() -> io::Result<PathBuf> {
+        match dirs::runtime_dir() {
+            Some(dir) => Ok(dir.join("exodus.sock")),
+            // The temp directory is shared, so the socket goes in a folder only the
+            // user can enter; it's private before the socket even exists
+            None => {
+                let uid = unsafe { libc::getuid() };
+                let dir = std::env::temp_dir().join(format!("exodus-{}", uid));
+                match fs::DirBuilder::new().mode(0o700).create(&dir) {
+                    Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+                    _ => {}
+                }
+                // Someone else may have created it first
+                let metadata = fs::symlink_metadata(&dir)?;
+                if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+                    let message = format!("{} is not a private folder", dir.display());
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+                }
+                Ok(dir.join("exodus.sock"))
+            }
+        }
+    }
+
+    pub fn connect() -> Option<Stream> {
+        UnixStream::connect(socket_path().ok()?).ok()
+    }
+
+    pub fn listen() -> io::Result<Listener> {
+        let path = socket_path()?;
+        let listener = match UnixListener::bind(&path) {
+            // Left behind by an instance that crashed
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(&path).is_err() => {
+                fs::remove_file(&path)?;
+                UnixListener::bind(&path)?
+            }
+            result => result?,
+        };
+        // Only the user may ask this instance to open files
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+
+    // The socket's permissions already keep everyone else out
+    pub fn is_authorized(_reader: &mut impl BufRead) -> bool {
+        true
+    }
+
+    pub fn cleanup() {
+        if let Ok(path) = socket_path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// Elsewhere a loopback TCP port, recorded in the user's local data directory.
+// Any local process can connect to the port, so the file also holds a random
+// token that each request has to start with.
+#[cfg(not(unix))]
+mod transport {
+    use std::fs;
+    use std::io::{self, BufRead, Write};
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::sync::OnceLock;
+
+    pub type Stream = TcpStream;
+    pub type Listener = TcpListener;
+
+    static TOKEN: OnceLock<String> = OnceLock::new();
+
+    fn port_file() -> PathBuf {
+        dirs::data_local_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("exodus")
+            .join("instance.port")
+    }
+
+    // `<port> <token>`
+    fn read_port_file() -> Option<(u16, String)> {
+        let content = fs::read_to_string(port_file()).ok()?;
+        let (port, token) = content.trim().split_once(' ')?;
+        Some((port.parse().ok()?, token.to_string()))
+    }
+
+    pub fn connect() -> Option<Stream> {
+        let (port, token) = read_port_file()?;
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).ok()?;
+        writeln!(stream, "{}", token).ok()?;
+        Some(stream)
+    }
+
+    pub fn listen() -> io::Result<Listener> {
+        if connect().is_some() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "another instance is running"));
+        }
+        let mut random = [0u8; 16];
+        getrandom::getrandom(&mut random).map_err(io::Error::other)?;
+        let token = TOKEN.get_or_init(|| random.iter().map(|b| format!("{:02x}", b)).collect());
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let path = port_file();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, format!("{} {}", listener.local_addr()?.port(), token))?;
+        Ok(listener)
+    }
+
+    // Reads the token line in front of the request
+    pub fn is_authorized(reader: &mut impl BufRead) -> bool {
+        let mut line = String::new();
+        reader.read_line(&mut line).is_ok() && TOKEN.get().is_some_and(|token| line.trim_end() == token)
+    }
+
+    pub fn cleanup() {
+        let _ = fs::remove_file(port_file());
+    }
+}
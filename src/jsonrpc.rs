@@ -0,0 +1,105 @@
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+// JSON-RPC 2.0 messages with the `Content-Length` framing the Language Server
+// Protocol uses over stdio
+
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+pub enum Message {
+    Request { id: Value, method: String, params: Value },
+    Response { id: Value, result: Result<Value, ResponseError> },
+    Notification { method: String, params: Value },
+}
+
+#[derive(Debug)]
+pub struct ResponseError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl Message {
+    pub fn request(id: i64, method: &str, params: Value) -> Self {
+        Message::Request { id: json!(id), method: method.to_string(), params }
+    }
+
+    pub fn notification(method: &str, params: Value) -> Self {
+        Message::Notification { method: method.to_string(), params }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            Message::Request { id, method, params } => {
+                json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+            }
+            Message::Response { id, result: Ok(result) } => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Message::Response { id, result: Err(error) } => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": error.code, "message": error.message },
+            }),
+            Message::Notification { method, params } => {
+                json!({ "jsonrpc": "2.0", "method": method, "params": params })
+            }
+        }
+    }
+
+    pub fn from_value(mut value: Value) -> io::Result<Self> {
+        let params = value.get_mut("params").map(Value::take).unwrap_or(Value::Null);
+        let method = value.get("method").and_then(Value::as_str).map(str::to_string);
+        let id = value.get_mut("id").map(Value::take);
+
+        match (id, method) {
+            (Some(id), Some(method)) => Ok(Message::Request { id, method, params }),
+            (None, Some(method)) => Ok(Message::Notification { method, params }),
+            (Some(id), None) => {
+                let result = match value.get("error") {
+                    Some(error) => Err(ResponseError {
+                        code: error.get("code").and_then(Value::as_i64).unwrap_or(0),
+                        message: error.get("message").and_then(Value::as_str).unwrap_or("").to_string(),
+                    }),
+                    None => Ok(value.get_mut("result").map(Value::take).unwrap_or(Value::Null)),
+                };
+                Ok(Message::Response { id, result })
+            }
+            (None, None) => Err(invalid_data("message has neither an id nor a method")),
+        }
+    }
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Message) -> io::Result<()> {
+    let body = message.to_value().to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+// Returns None at the end of the stream
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Message>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>().map_err(|_| invalid_data("bad Content-Length"))?);
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid_data("missing Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let value = serde_json::from_slice(&body).map_err(|e| invalid_data(&e.to_string()))?;
+    Message::from_value(value).map(Some)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
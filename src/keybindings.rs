@@ -0,0 +1,533 @@
+use egui::{Context, Event, Key, KeyboardShortcut, Modifiers};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::commands::CommandRegistry;
+
+const ALL_KEYS: [Key; 73] = [
+    Key::ArrowDown, Key::ArrowLeft, Key::ArrowRight, Key::ArrowUp,
+    Key::Escape, Key::Tab, Key::Backspace, Key::Enter, Key::Space,
+    Key::Insert, Key::Delete, Key::Home, Key::End, Key::PageUp, Key::PageDown,
+    Key::Minus, Key::PlusEquals,
+    Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4,
+    Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+    Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+    Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8,
+    Key::F9, Key::F10, Key::F11, Key::F12, Key::F13, Key::F14, Key::F15,
+    Key::F16, Key::F17, Key::F18, Key::F19, Key::F20,
+];
+
+// One or more chords pressed one after another, e.g. `Ctrl+K Ctrl+S`
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct KeySequence(pub Vec<KeyboardShortcut>);
+
+impl KeySequence {
+    pub fn single(modifiers: Modifiers, key: Key) -> Self {
+        Self(vec![KeyboardShortcut::new(modifiers, key)])
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let chords = text
+            .split_whitespace()
+            .map(parse_chord)
+            .collect::<Result<Vec<_>, _>>()?;
+        if chords.is_empty() {
+            return Err("empty key sequence".to_string());
+        }
+        Ok(Self(chords))
+    }
+
+    pub fn is_prefix_of(&self, other: &KeySequence) -> bool {
+        self.0.len() < other.0.len() && other.0.starts_with(&self.0)
+    }
+
+    pub fn format(&self, ctx: &Context) -> String {
+        self.0
+            .iter()
+            .map(|chord| ctx.format_shortcut(chord))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// Platform independent spelling used in keybindings.toml
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let chords: Vec<String> = self
+            .0
+            .iter()
+            .map(|chord| {
+                let mut parts = Vec::new();
+                if chord.modifiers.command || chord.modifiers.ctrl {
+                    parts.push("Ctrl");
+                }
+                if chord.modifiers.alt {
+                    parts.push("Alt");
+                }
+                if chord.modifiers.shift {
+                    parts.push("Shift");
+                }
+                parts.push(chord.key.name());
+                parts.join("+")
+            })
+            .collect();
+        write!(f, "{}", chords.join(" "))
+    }
+}
+
+fn parse_chord(text: &str) -> Result<KeyboardShortcut, String> {
+    let mut modifiers = Modifiers::NONE;
+    let mut key = None;
+
+    for part in text.split('+') {
+        match part.to_ascii_lowercase().as_str() {
+            // Ctrl maps to Cmd on macOS, like the built-in shortcuts
+            "ctrl" | "control" | "cmd" | "command" => modifiers = modifiers | Modifiers::COMMAND,
+            "alt" | "option" => modifiers = modifiers | Modifiers::ALT,
+            "shift" => modifiers = modifiers | Modifiers::SHIFT,
+            name => {
+                if key.is_some() {
+                    return Err(format!("'{}' has more than one key", text));
+                }
+                key = Some(key_from_name(name).ok_or_else(|| format!("unknown key '{}'", part))?);
+            }
+        }
+    }
+
+    key.map(|key| KeyboardShortcut::new(modifiers, key))
+        .ok_or_else(|| format!("'{}' has no key", text))
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    let name = match name {
+        "esc" => "escape",
+        "return" => "enter",
+        "del" => "delete",
+        "pgup" => "pageup",
+        "pgdown" => "pagedown",
+        "-" => "minus",
+        "=" | "plus" => "plus",
+        other => other,
+    };
+    ALL_KEYS.iter().copied().find(|k| k.name().eq_ignore_ascii_case(name))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BindingSource {
+    Default,
+    User,
+}
+
+#[derive(Clone)]
+pub struct Binding {
+    pub sequence: KeySequence,
+    pub command: String,
+    pub source: BindingSource,
+}
+
+pub struct Conflict {
+    pub sequence: KeySequence,
+    pub message: String,
+}
+
+#[derive(Deserialize, Default)]
+struct KeybindingsFile {
+    #[serde(default)]
+    bindings: BTreeMap<String, String>,
+}
+
+const KEYBINDINGS_TEMPLATE: &str = r#"# Exodus keybindings
+#
+# Map a key sequence to a command id. Chords in a sequence are separated by
+# spaces, e.g. "Ctrl+K Ctrl+S". A binding replaces the command's default
+# shortcuts; map a sequence to "" to unbind it.
+# Open "Preferences: Keyboard Shortcuts" to see every command id.
+
+[bindings]
+# "Ctrl+Shift+O" = "file.open_folder"
+# "Ctrl+G" = ""
+"#;
+
+#[derive(Default)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    conflicts: Vec<Conflict>,
+    errors: Vec<String>,
+    pending: Vec<KeyboardShortcut>,
+}
+
+impl Keymap {
+    pub fn load(registry: &CommandRegistry) -> Self {
+        Self::with_user_file(registry, Self::read_user_file())
+    }
+
+    fn with_user_file(registry: &CommandRegistry, user_file: Result<KeybindingsFile, String>) -> Self {
+        let mut keymap = Self::default();
+
+        for command in registry.iter() {
+            if let Some(sequence) = &command.keybinding {
+                keymap.bindings.push(Binding {
+                    sequence: sequence.clone(),
+                    command: command.id.clone(),
+                    source: BindingSource::Default,
+                });
+            }
+        }
+
+        match user_file {
+            Ok(file) => keymap.apply_user_bindings(file, registry),
+            Err(e) => keymap.errors.push(e),
+        }
+        keymap.detect_conflicts();
+        keymap
+    }
+
+    pub fn keybindings_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let config_dir = dirs::config_dir()
+            .ok_or("Could not find config directory")?;
+        Ok(config_dir.join("Exodus").join("keybindings.toml"))
+    }
+
+    // Creates the file from a commented template if it doesn't exist yet
+    pub fn ensure_user_file() -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let path = Self::keybindings_path()?;
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, KEYBINDINGS_TEMPLATE)?;
+        }
+        Ok(path)
+    }
+
+    fn read_user_file() -> Result<KeybindingsFile, String> {
+        let path = Self::keybindings_path().map_err(|e| e.to_string())?;
+        if !path.exists() {
+            return Ok(KeybindingsFile::default());
+        }
+        let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn apply_user_bindings(&mut self, file: KeybindingsFile, registry: &CommandRegistry) {
+        let mut user_bindings = Vec::new();
+        for (text, command) in file.bindings {
+            let sequence = match KeySequence::parse(&text) {
+                Ok(sequence) => sequence,
+                Err(e) => {
+                    self.errors.push(format!("\"{}\": {}", text, e));
+                    continue;
+                }
+            };
+
+            // Unbinding removes whatever is bound to the sequence
+            if command.is_empty() {
+                self.bindings.retain(|b| b.sequence != sequence);
+                continue;
+            }
+            if registry.get(&command).is_none() {
+                self.errors.push(format!("\"{}\": unknown command '{}'", text, command));
+                continue;
+            }
+            user_bindings.push(Binding {
+                sequence,
+                command,
+                source: BindingSource::User,
+            });
+        }
+
+        // User bindings replace the defaults of the commands they rebind
+        self.bindings.retain(|b| !user_bindings.iter().any(|u| u.command == b.command));
+        self.bindings.extend(user_bindings);
+    }
+
+    fn detect_conflicts(&mut self) {
+        self.conflicts.clear();
+        for (i, a) in self.bindings.iter().enumerate() {
+            for b in &self.bindings[i + 1..] {
+                if a.sequence == b.sequence && a.command != b.command {
+                    self.conflicts.push(Conflict {
+                        sequence: a.sequence.clone(),
+                        message: format!(
+                            "{} is bound to both '{}' and '{}'",
+                            a.sequence, a.command, b.command
+                        ),
+                    });
+                } else if a.sequence.is_prefix_of(&b.sequence) || b.sequence.is_prefix_of(&a.sequence) {
+                    let (short, long) = if a.sequence.0.len() < b.sequence.0.len() { (a, b) } else { (b, a) };
+                    self.conflicts.push(Conflict {
+                        sequence: short.sequence.clone(),
+                        message: format!(
+                            "{} ('{}') is a prefix of {} ('{}'); the longer sequence can never trigger",
+                            short.sequence, short.command, long.sequence, long.command
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    pub fn bindings_for(&self, command: &str) -> impl Iterator<Item = &Binding> {
+        let command = command.to_string();
+        self.bindings.iter().filter(move |b| b.command == command)
+    }
+
+    // Whether a single key press runs `command`
+    pub fn is_bound(&self, command: &str, modifiers: Modifiers, key: Key) -> bool {
+        let typed = [KeyboardShortcut::new(modifiers, key)];
+        self.bindings_for(command).any(|b| b.sequence.0.len() == 1 && chords_match(&typed, &b.sequence.0))
+    }
+
+    pub fn is_conflicting(&self, sequence: &KeySequence) -> bool {
+        self.conflicts.iter().any(|c| &c.sequence == sequence)
+    }
+
+    // The chords typed so far of an unfinished sequence
+    pub fn pending(&self) -> Option<KeySequence> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(KeySequence(self.pending.clone()))
+        }
+    }
+
+    // Runs before any widget so bound keys reach their command even while a
+    // text field has focus. Matched key presses are removed from the input.
+    pub fn dispatch(&mut self, ctx: &Context) -> Vec<String> {
+        let mut triggered = Vec::new();
+
+        ctx.input_mut(|i| {
+            i.events.retain(|event| {
+                let Event::Key { key, pressed: true, modifiers, .. } = event else {
+                    return true;
+                };
+
+                let chord = KeyboardShortcut::new(*modifiers, *key);
+                let mut typed = self.pending.clone();
+                typed.push(chord);
+                let (mut exact, mut is_prefix) = self.lookup(&typed);
+                if exact.is_none() && !is_prefix && !self.pending.is_empty() {
+                    // No sequence continues this way: drop the pending chords and
+                    // treat the key as if it was pressed on its own
+                    typed = vec![chord];
+                    (exact, is_prefix) = self.lookup(&typed);
+                }
+
+                if let Some(command) = exact {
+                    triggered.push(command);
+                    self.pending.clear();
+                    false
+                } else if is_prefix {
+                    self.pending = typed;
+                    false
+                } else {
+                    self.pending.clear();
+                    true
+                }
+            });
+        });
+
+        triggered
+    }
+
+    // The command bound to exactly `typed`, and whether a longer sequence starts with it
+    fn lookup(&self, typed: &[KeyboardShortcut]) -> (Option<String>, bool) {
+        let mut exact = None;
+        let mut is_prefix = false;
+        for binding in &self.bindings {
+            let bound = &binding.sequence.0;
+            if bound.len() < typed.len() || !chords_match(typed, &bound[..typed.len()]) {
+                continue;
+            }
+            if bound.len() == typed.len() {
+                exact.get_or_insert_with(|| binding.command.clone());
+            } else {
+                is_prefix = true;
+            }
+        }
+        (exact, is_prefix)
+    }
+}
+
+fn chords_match(typed: &[KeyboardShortcut], bound: &[KeyboardShortcut]) -> bool {
+    typed
+        .iter()
+        .zip(bound)
+        .all(|(t, b)| t.key == b.key && t.modifiers.matches(b.modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use egui::RawInput;
+
+    fn registry() -> CommandRegistry {
+        fn nothing(_: &mut crate::Exodus, _: &Context) {}
+        let mut registry = CommandRegistry::new();
+        registry.register(Command::new("file.save", "Save", nothing).keybinding(Modifiers::COMMAND, Key::S));
+        registry.register(Command::new("file.open", "Open", nothing).keybinding(Modifiers::COMMAND, Key::O));
+        registry.register(
+            Command::new("file.open_folder", "Open Folder", nothing)
+                .chord((Modifiers::COMMAND, Key::K), (Modifiers::COMMAND, Key::O)),
+        );
+        registry.register(Command::new("view.zoom", "Zoom", nothing));
+        registry
+    }
+
+    fn load(user_file: &str) -> Keymap {
+        Keymap::with_user_file(&registry(), toml::from_str(user_file).map_err(|e| e.to_string()))
+    }
+
+    fn sequence(text: &str) -> KeySequence {
+        KeySequence::parse(text).unwrap()
+    }
+
+    fn bound(keymap: &Keymap, command: &str) -> Vec<String> {
+        keymap.bindings_for(command).map(|b| b.sequence.to_string()).collect()
+    }
+
+    // Sends the key presses through `dispatch` in one frame; returns the commands
+    // it ran and the keys it left for the widgets
+    fn press(keymap: &mut Keymap, keys: &[(Modifiers, Key)]) -> (Vec<String>, Vec<Key>) {
+        let ctx = Context::default();
+        let events = keys
+            .iter()
+            .map(|&(modifiers, key)| Event::Key { key, pressed: true, repeat: false, modifiers })
+            .collect();
+        ctx.begin_frame(RawInput { events, ..RawInput::default() });
+        let triggered = keymap.dispatch(&ctx);
+        let passed = ctx.input(|i| {
+            i.events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::Key { key, .. } => Some(*key),
+                    _ => None,
+                })
+                .collect()
+        });
+        let _ = ctx.end_frame();
+        (triggered, passed)
+    }
+
+    #[test]
+    fn parsing_sequences() {
+        let parsed = sequence("Ctrl+K Ctrl+Shift+s");
+        assert_eq!(
+            parsed,
+            KeySequence(vec![
+                KeyboardShortcut::new(Modifiers::COMMAND, Key::K),
+                KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::S),
+            ])
+        );
+        assert_eq!(parsed.to_string(), "Ctrl+K Ctrl+Shift+S");
+        assert_eq!(sequence("cmd+esc"), KeySequence::single(Modifiers::COMMAND, Key::Escape));
+        assert_eq!(sequence("Alt+-"), KeySequence::single(Modifiers::ALT, Key::Minus));
+        assert_eq!(sequence("F5"), KeySequence::single(Modifiers::NONE, Key::F5));
+        assert!(sequence("Ctrl+K").is_prefix_of(&sequence("Ctrl+K Ctrl+O")));
+        assert!(!sequence("Ctrl+K").is_prefix_of(&sequence("Ctrl+K")));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(KeySequence::parse("  "), Err("empty key sequence".to_string()));
+        assert_eq!(KeySequence::parse("Ctrl+Foo"), Err("unknown key 'Foo'".to_string()));
+        assert_eq!(KeySequence::parse("Ctrl+A+B"), Err("'Ctrl+A+B' has more than one key".to_string()));
+        assert_eq!(KeySequence::parse("Ctrl+Shift"), Err("'Ctrl+Shift' has no key".to_string()));
+    }
+
+    #[test]
+    fn user_bindings() {
+        let keymap = load("");
+        assert_eq!(bound(&keymap, "file.save"), ["Ctrl+S"]);
+        assert!(keymap.errors().is_empty() && keymap.conflicts().is_empty());
+
+        let keymap = load(
+            r#"
+            [bindings]
+            "Ctrl+Shift+S" = "file.save"
+            "F2" = "view.zoom"
+            "Ctrl+O" = ""
+            "Ctrl+Nope" = "file.open"
+            "F3" = "no.such.command"
+            "#,
+        );
+        // Rebinding a command replaces its default
+        assert_eq!(bound(&keymap, "file.save"), ["Ctrl+Shift+S"]);
+        assert_eq!(bound(&keymap, "view.zoom"), ["F2"]);
+        assert!(bound(&keymap, "file.open").is_empty());
+        assert_eq!(bound(&keymap, "file.open_folder"), ["Ctrl+K Ctrl+O"]);
+        assert_eq!(
+            keymap.errors(),
+            ["\"Ctrl+Nope\": unknown key 'Nope'", "\"F3\": unknown command 'no.such.command'"]
+        );
+
+        let keymap = Keymap::with_user_file(&registry(), Err("keybindings.toml: bad".to_string()));
+        assert_eq!(keymap.errors(), ["keybindings.toml: bad"]);
+        assert_eq!(bound(&keymap, "file.save"), ["Ctrl+S"]);
+    }
+
+    #[test]
+    fn conflicts() {
+        let keymap = load(
+            r#"
+            [bindings]
+            "Ctrl+O" = "view.zoom"
+            "Ctrl+K" = "file.save"
+            "#,
+        );
+        let messages: Vec<&str> = keymap.conflicts().iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Ctrl+O is bound to both 'file.open' and 'view.zoom'",
+                "Ctrl+K ('file.save') is a prefix of Ctrl+K Ctrl+O ('file.open_folder'); the longer sequence can never trigger",
+            ]
+        );
+        assert!(keymap.is_conflicting(&sequence("Ctrl+K")));
+        assert!(!keymap.is_conflicting(&sequence("Ctrl+K Ctrl+O")));
+    }
+
+    #[test]
+    fn dispatching() {
+        let mut keymap = load("");
+        let ctrl = Modifiers::COMMAND;
+        assert_eq!(press(&mut keymap, &[(ctrl, Key::S)]), (vec!["file.save".to_string()], vec![]));
+        assert_eq!(press(&mut keymap, &[(Modifiers::NONE, Key::S)]), (vec![], vec![Key::S]));
+
+        // The first chord of a sequence waits for the second, across frames
+        assert_eq!(press(&mut keymap, &[(ctrl, Key::K)]), (vec![], vec![]));
+        assert_eq!(keymap.pending(), Some(sequence("Ctrl+K")));
+        assert_eq!(press(&mut keymap, &[(ctrl, Key::O)]), (vec!["file.open_folder".to_string()], vec![]));
+        assert_eq!(keymap.pending(), None);
+    }
+
+    #[test]
+    fn keys_after_an_unbound_chord_pass_through() {
+        let mut keymap = load("");
+        let ctrl = Modifiers::COMMAND;
+        assert_eq!(press(&mut keymap, &[(ctrl, Key::K), (Modifiers::NONE, Key::X)]), (vec![], vec![Key::X]));
+        assert_eq!(keymap.pending(), None);
+
+        // A key that is bound on its own still runs its command
+        assert_eq!(
+            press(&mut keymap, &[(ctrl, Key::K), (ctrl, Key::S)]),
+            (vec!["file.save".to_string()], vec![])
+        );
+        // Or starts a sequence of its own
+        assert_eq!(press(&mut keymap, &[(ctrl, Key::K), (ctrl, Key::K)]), (vec![], vec![]));
+        assert_eq!(keymap.pending(), Some(sequence("Ctrl+K")));
+    }
+}
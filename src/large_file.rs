@@ -0,0 +1,199 @@
+use egui::{ScrollArea, TextStyle, Ui};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use crate::encoding::Encoding;
+
+// Bytes scanned for line starts per frame, so indexing never blocks the UI for long
+const INDEX_BUDGET: usize = 8 * 1024 * 1024;
+// Longer lines are cut off when drawn; minified files can be one huge line
+const MAX_LINE_DISPLAY: usize = 10_000;
+
+// File contents either read into memory or mapped from disk
+pub enum FileBytes {
+    Loaded(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl FileBytes {
+    pub fn map(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Mapping an empty file fails on some platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(FileBytes::Loaded(Vec::new()));
+        }
+        // Safety: the map is read-only. If another process truncates the file while it
+        // is open, reads may fault, which is the usual trade-off of mapping files.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(FileBytes::Mapped(mmap))
+    }
+
+    // Editing needs owned bytes; a mapped file is copied into memory on first use
+    pub fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let FileBytes::Mapped(mmap) = self {
+            *self = FileBytes::Loaded(mmap.to_vec());
+        }
+        match self {
+            FileBytes::Loaded(bytes) => bytes,
+            FileBytes::Mapped(_) => unreachable!(),
+        }
+    }
+}
+
+impl Deref for FileBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileBytes::Loaded(bytes) => bytes,
+            FileBytes::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+// Read-only view of a file too large to edit as one string. Lines are found
+// incrementally and decoded only when they scroll into view.
+pub struct LargeFile {
+    bytes: FileBytes,
+    // Guessed from the start of the file
+    encoding: Encoding,
+    // `\n` and `\r` as code units of the encoding
+    newline: Vec<u8>,
+    carriage_return: Vec<u8>,
+    line_starts: Vec<usize>,
+    indexed: usize,
+}
+
+impl LargeFile {
+    pub fn new(bytes: FileBytes) -> Self {
+        let encoding = Encoding::sniff(&bytes);
+        Self {
+            bytes,
+            encoding,
+            newline: encoding.code_unit(b'\n'),
+            carriage_return: encoding.code_unit(b'\r'),
+            line_starts: vec![0],
+            indexed: 0,
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.indexed >= self.bytes.len()
+    }
+
+    // Scans the next chunk of the file for line breaks. The budget is a multiple of
+    // two, so chunks of UTF-16 text always start on a code unit.
+    fn index_more(&mut self) {
+        let end = (self.indexed + INDEX_BUDGET).min(self.bytes.len());
+        let chunk = &self.bytes[self.indexed..end];
+        let offset = self.indexed;
+        let unit = self.newline.len();
+        self.line_starts.extend(
+            chunk
+                .chunks_exact(unit)
+                .enumerate()
+                .filter(|&(_, bytes)| bytes == self.newline)
+                .map(|(i, _)| offset + (i + 1) * unit),
+        );
+        self.indexed = end;
+    }
+
+    pub fn line_count(&self) -> usize {
+        // A trailing newline doesn't start another line
+        match self.line_starts.last() {
+            Some(&last) if last == self.bytes.len() && last > 0 => self.line_starts.len() - 1,
+            _ => self.line_starts.len(),
+        }
+    }
+
+    pub fn line(&self, index: usize) -> Cow<'_, str> {
+        let Some(&start) = self.line_starts.get(index) else {
+            return Cow::Borrowed("");
+        };
+        let end = self
+            .line_starts
+            .get(index + 1)
+            .map_or(self.indexed, |&next| next - self.newline.len());
+        let mut line = &self.bytes[start..end.max(start)];
+        if line.ends_with(&self.carriage_return) {
+            line = &line[..line.len() - self.carriage_return.len()];
+        }
+        // Even, so UTF-16 is never cut inside a code unit
+        if line.len() > MAX_LINE_DISPLAY {
+            let mut text = self.encoding.decode_lossy(&line[..MAX_LINE_DISPLAY]).into_owned();
+            text.push('…');
+            return Cow::Owned(text);
+        }
+        self.encoding.decode_lossy(line)
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) {
+        if !self.is_indexed() {
+            self.index_more();
+            ui.ctx().request_repaint();
+        }
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Large file ({:.1} MB): read-only, highlighting disabled", self.len() as f64 / 1_048_576.0));
+            if !self.is_indexed() {
+                ui.weak(format!("indexing lines... {}%", self.indexed * 100 / self.len().max(1)));
+            }
+        });
+        ui.separator();
+
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        let line_count = self.line_count();
+        let gutter_width = line_count.to_string().len();
+        ScrollArea::both()
+            .auto_shrink([false, false])
+            .show_rows(ui, row_height, line_count, |ui, rows| {
+                for index in rows {
+                    ui.monospace(format!("{:>width$}  {}", index + 1, self.line(index), width = gutter_width));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(bytes: Vec<u8>) -> Vec<String> {
+        let mut file = LargeFile::new(FileBytes::Loaded(bytes));
+        while !file.is_indexed() {
+            file.index_more();
+        }
+        (0..file.line_count()).map(|i| file.line(i).into_owned()).collect()
+    }
+
+    #[test]
+    fn lines_in_every_encoding() {
+        let text = "caf\u{e9}\r\nna\u{ef}ve\n\u{10437} end\n";
+        for encoding in [Encoding::Utf8, Encoding::Utf8Bom, Encoding::Utf16Le, Encoding::Utf16BeBom] {
+            assert_eq!(lines(encoding.encode(text).unwrap()), ["caf\u{e9}", "na\u{ef}ve", "\u{10437} end"], "{:?}", encoding);
+        }
+        // Characters whose UTF-16 code units contain 0x0A aren't line breaks
+        assert_eq!(lines(Encoding::Utf16Le.encode("\u{0A0A}\u{010A}\nx").unwrap()), ["\u{0A0A}\u{010A}", "x"]);
+        assert_eq!(lines(b"caf\xe9\nna\xefve".to_vec()), ["caf\u{e9}", "na\u{ef}ve"]);
+    }
+
+    #[test]
+    fn long_lines_are_cut() {
+        let text = "\u{e9}".repeat(MAX_LINE_DISPLAY);
+        let line = &lines(Encoding::Utf16Be.encode(&text).unwrap())[0];
+        assert_eq!(line.chars().count(), MAX_LINE_DISPLAY / 2 + 1);
+        assert!(line.ends_with("\u{e9}…"));
+    }
+}
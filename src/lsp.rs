@@ -0,0 +1,1153 @@
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::completion::CompletionItem;
+use crate::config::LanguageServerConfig;
+use crate::diagnostics::{self, Diagnostic, Position, Severity};
+use crate::jsonrpc::{self, Message, ResponseError};
+use crate::notifications::Level;
+
+// A server that crashes more often than this is left alone for the rest of the session
+const MAX_RESTARTS: usize = 3;
+// How long a server gets to answer `shutdown` and then to exit
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Request ids are unique across servers, so responses can be told apart by id alone
+static NEXT_REQUEST_ID: AtomicI64 = AtomicI64::new(1);
+
+fn next_request_id() -> i64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// A text buffer as the editor currently has it
+pub struct OpenDocument<'a> {
+    pub path: &'a Path,
+    pub language: &'a str,
+    pub text: &'a str,
+}
+
+// Things the rest of the app should know about, returned by `LspManager::poll`
+pub enum LspEvent {
+    // `show` asks for a toast rather than only an entry in the output log
+    Log { level: Level, message: String, show: bool },
+    // Replaces what the language's server reported for the file before
+    Diagnostics { language: String, path: PathBuf, diagnostics: Vec<Diagnostic> },
+    // The server is gone, and with it everything it reported
+    Stopped { language: String },
+    // The answer to a request made with `LspManager::request`
+    Response { id: i64, result: Result<Value, ResponseError> },
+}
+
+// What the reader threads pass back to the UI thread
+enum ServerEvent {
+    Message(Message),
+    Stderr(String),
+    Exited,
+}
+
+// The server's copy of a document: the text it was last sent
+struct Document {
+    language: String,
+    version: i64,
+    text: String,
+}
+
+enum State {
+    // Messages wait until the server has answered `initialize`
+    Initializing(Vec<Message>),
+    Running,
+}
+
+struct LanguageServer {
+    // The command, for messages
+    name: String,
+    child: Child,
+    // To the thread writing to the server's stdin, which may block when the server is busy
+    writer: Sender<Message>,
+    events: Receiver<ServerEvent>,
+    state: State,
+    initialize_id: i64,
+    capabilities: Value,
+}
+
+impl LanguageServer {
+    fn start(config: &LanguageServerConfig, root: Option<&Path>, waker: Option<egui::Context>) -> io::Result<Self> {
+        let mut command = Command::new(&config.command);
+        command
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(root) = root {
+            command.current_dir(root);
+        }
+        let mut child = command.spawn()?;
+        let (Some(stdin), Some(stdout), Some(stderr)) = (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            let _ = child.kill();
+            return Err(io::Error::other("could not connect to the server's standard streams"));
+        };
+
+        let (writer, messages) = mpsc::channel::<Message>();
+        thread::spawn(move || {
+            let mut stdin = stdin;
+            for message in messages {
+                // A server that went away is noticed by the reader thread
+                if jsonrpc::write_message(&mut stdin, &message).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let (sender, events) = mpsc::channel();
+        let stderr_sender = sender.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            // A framing error leaves the stream out of step, so it ends the connection too
+            while let Ok(Some(message)) = jsonrpc::read_message(&mut reader) {
+                if sender.send(ServerEvent::Message(message)).is_err() {
+                    return;
+                }
+                if let Some(waker) = &waker {
+                    waker.request_repaint();
+                }
+            }
+            let _ = sender.send(ServerEvent::Exited);
+            if let Some(waker) = &waker {
+                waker.request_repaint();
+            }
+        });
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if stderr_sender.send(ServerEvent::Stderr(line)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut server = Self {
+            name: config.command.clone(),
+            child,
+            writer,
+            events,
+            state: State::Initializing(Vec::new()),
+            initialize_id: next_request_id(),
+            capabilities: Value::Null,
+        };
+        let root_uri = root.map(path_to_uri);
+        let workspace_folders = root.map(|root| {
+            let name = root.file_name().map_or_else(|| root.display().to_string(), |n| n.to_string_lossy().into_owned());
+            json!([{ "uri": path_to_uri(root), "name": name }])
+        });
+        server.write(Message::request(
+            server.initialize_id,
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "clientInfo": { "name": "Exodus", "version": env!("CARGO_PKG_VERSION") },
+                "rootUri": root_uri,
+                "workspaceFolders": workspace_folders,
+                "capabilities": client_capabilities(),
+            }),
+        ));
+        Ok(server)
+    }
+
+    fn write(&mut self, message: Message) {
+        let _ = self.writer.send(message);
+    }
+
+    fn send(&mut self, message: Message) {
+        match &mut self.state {
+            State::Initializing(queue) => queue.push(message),
+            State::Running => self.write(message),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(Message::notification(method, params));
+    }
+
+    fn initialized(&mut self, capabilities: Value) {
+        self.capabilities = capabilities;
+        self.write(Message::notification("initialized", json!({})));
+        if let State::Initializing(queue) = std::mem::replace(&mut self.state, State::Running) {
+            for message in queue {
+                self.write(message);
+            }
+        }
+    }
+
+    // Whether the server takes edits as ranges or only the full text
+    fn incremental_sync(&self) -> bool {
+        let sync = &self.capabilities["textDocumentSync"];
+        let kind = sync.get("change").unwrap_or(sync);
+        kind.as_i64() == Some(2)
+    }
+
+    // Asks the server to shut down and waits a moment for it, then kills it. This
+    // happens on a thread of its own, so a slow server never holds up the UI.
+    fn shutdown(self) -> JoinHandle<()> {
+        thread::spawn(move || self.finish())
+    }
+
+    fn finish(mut self) {
+        if matches!(self.state, State::Running) {
+            let id = next_request_id();
+            self.write(Message::request(id, "shutdown", Value::Null));
+            let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match self.events.recv_timeout(remaining) {
+                    Ok(ServerEvent::Message(Message::Response { id: response, .. })) if response == json!(id) => break,
+                    Ok(ServerEvent::Exited) | Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+                    Ok(_) => {}
+                }
+            }
+            self.write(Message::notification("exit", Value::Null));
+        }
+
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Starts a server per configured language on demand and keeps it in step with
+// the editor's buffers
+pub struct LspManager {
+    configs: BTreeMap<String, LanguageServerConfig>,
+    // By language name
+    servers: HashMap<String, LanguageServer>,
+    documents: HashMap<PathBuf, Document>,
+    // Languages whose server couldn't be started or kept crashing
+    failed: HashSet<String>,
+    restarts: HashMap<String, usize>,
+    events: Vec<LspEvent>,
+    // Repaints the UI when a server sends something
+    waker: Option<egui::Context>,
+}
+
+impl LspManager {
+    pub fn new(configs: BTreeMap<String, LanguageServerConfig>, waker: Option<egui::Context>) -> Self {
+        Self {
+            configs,
+            servers: HashMap::new(),
+            documents: HashMap::new(),
+            failed: HashSet::new(),
+            restarts: HashMap::new(),
+            events: Vec::new(),
+            waker,
+        }
+    }
+
+    // Opens, changes and closes documents so each server sees what the editor
+    // has. Servers are started here the first time a document needs them.
+    pub fn sync(&mut self, documents: &[OpenDocument], root: Option<&Path>) {
+        for document in documents {
+            // The user switched the document to another language. The old server
+            // hears about it even when the new language has no server.
+            if self.documents.get(document.path).is_some_and(|d| d.language != document.language) {
+                self.close(document.path);
+            }
+            if !self.ensure_server(document.language, root) {
+                continue;
+            }
+
+            let server = self.servers.get_mut(document.language).expect("started above");
+            let uri = path_to_uri(document.path);
+            match self.documents.get_mut(document.path) {
+                None => {
+                    server.notify(
+                        "textDocument/didOpen",
+                        json!({
+                            "textDocument": {
+                                "uri": uri,
+                                "languageId": language_id(document.language),
+                                "version": 1,
+                                "text": document.text,
+                            }
+                        }),
+                    );
+                    self.documents.insert(
+                        document.path.to_path_buf(),
+                        Document {
+                            language: document.language.to_string(),
+                            version: 1,
+                            text: document.text.to_string(),
+                        },
+                    );
+                }
+                Some(open) if open.text != document.text => {
+                    open.version += 1;
+                    let change = if server.incremental_sync() {
+                        let (start, end, text) = text_change(&open.text, document.text);
+                        json!({
+                            "range": { "start": position(&open.text, start), "end": position(&open.text, end) },
+                            "text": text,
+                        })
+                    } else {
+                        json!({ "text": document.text })
+                    };
+                    server.notify(
+                        "textDocument/didChange",
+                        json!({
+                            "textDocument": { "uri": uri, "version": open.version },
+                            "contentChanges": [change],
+                        }),
+                    );
+                    open.text = document.text.to_string();
+                }
+                Some(_) => {}
+            }
+        }
+
+        let open: HashSet<&Path> = documents.iter().map(|d| d.path).collect();
+        let closed: Vec<PathBuf> = self
+            .documents
+            .keys()
+            .filter(|path| !open.contains(path.as_path()))
+            .cloned()
+            .collect();
+        for path in closed {
+            self.close(&path);
+        }
+    }
+
+    // The params of a request about the position at byte `offset` of an open
+    // document's text, as last synced
+    pub fn text_document_position(&self, path: &Path, offset: usize) -> Option<Value> {
+        let document = self.documents.get(path)?;
+        if !document.text.is_char_boundary(offset) {
+            return None;
+        }
+        Some(json!({
+            "textDocument": { "uri": path_to_uri(path) },
+            "position": position(&document.text, offset),
+        }))
+    }
+
+    // Sends a request to the server of an open document. The answer comes back
+    // from `poll` as a `Response` with the returned id.
+    pub fn request(&mut self, path: &Path, method: &str, params: Value) -> Option<i64> {
+        let language = &self.documents.get(path)?.language;
+        let server = self.servers.get_mut(language)?;
+        let id = next_request_id();
+        server.send(Message::request(id, method, params));
+        Some(id)
+    }
+
+    // Characters after which the document's server offers completions, such as `.`
+    pub fn trigger_characters(&self, path: &Path) -> Vec<String> {
+        let Some(server) = self.documents.get(path).and_then(|d| self.servers.get(&d.language)) else {
+            return Vec::new();
+        };
+        let characters = server.capabilities["completionProvider"]["triggerCharacters"].as_array();
+        characters.into_iter().flatten().filter_map(|c| c.as_str().map(str::to_string)).collect()
+    }
+
+    // Whether the document's server offers a feature, e.g. `documentFormattingProvider`
+    pub fn has_capability(&self, path: &Path, capability: &str) -> bool {
+        let Some(server) = self.documents.get(path).and_then(|d| self.servers.get(&d.language)) else {
+            return false;
+        };
+        !matches!(server.capabilities[capability], Value::Null | Value::Bool(false))
+    }
+
+    pub fn did_save(&mut self, path: &Path) {
+        let Some(document) = self.documents.get(path) else {
+            return;
+        };
+        if let Some(server) = self.servers.get_mut(&document.language) {
+            server.notify("textDocument/didSave", json!({ "textDocument": { "uri": path_to_uri(path) } }));
+        }
+    }
+
+    fn close(&mut self, path: &Path) {
+        let Some(document) = self.documents.remove(path) else {
+            return;
+        };
+        if let Some(server) = self.servers.get_mut(&document.language) {
+            server.notify("textDocument/didClose", json!({ "textDocument": { "uri": path_to_uri(path) } }));
+        }
+    }
+
+    // Returns whether a server for `language` is running or starting
+    fn ensure_server(&mut self, language: &str, root: Option<&Path>) -> bool {
+        if self.servers.contains_key(language) {
+            return true;
+        }
+        let Some(config) = self.configs.get(language) else {
+            return false;
+        };
+        if self.failed.contains(language) {
+            return false;
+        }
+
+        match LanguageServer::start(config, root, self.waker.clone()) {
+            Ok(server) => {
+                self.log(Level::Info, format!("Started {} for {}", config.command, language), false);
+                self.servers.insert(language.to_string(), server);
+                true
+            }
+            Err(e) => {
+                let message = format!("Could not start language server {} for {}: {}", config.command, language, e);
+                self.log(Level::Error, message, true);
+                self.failed.insert(language.to_string());
+                false
+            }
+        }
+    }
+
+    // Handles what the servers sent since the last call
+    pub fn poll(&mut self) -> Vec<LspEvent> {
+        let languages: Vec<String> = self.servers.keys().cloned().collect();
+        for language in languages {
+            // The server is gone from the map once it has exited
+            while let Some(server) = self.servers.get_mut(&language) {
+                let Ok(event) = server.events.try_recv() else {
+                    break;
+                };
+                match event {
+                    ServerEvent::Message(message) => self.handle_message(&language, message),
+                    ServerEvent::Stderr(line) => {
+                        let message = format!("[{}] {}", server.name, line);
+                        self.log(Level::Info, message, false);
+                    }
+                    ServerEvent::Exited => self.server_exited(&language),
+                }
+            }
+        }
+        std::mem::take(&mut self.events)
+    }
+
+    fn handle_message(&mut self, language: &str, message: Message) {
+        let Some(server) = self.servers.get_mut(language) else {
+            return;
+        };
+        match message {
+            Message::Response { id, result } if id == json!(server.initialize_id) => match result {
+                Ok(result) => server.initialized(result["capabilities"].clone()),
+                Err(error) => {
+                    let message = format!("{} failed to initialize: {}", server.name, error.message);
+                    self.log(Level::Error, message, true);
+                    self.failed.insert(language.to_string());
+                    self.stop(language);
+                }
+            },
+            Message::Response { id, result } => {
+                if let Some(id) = id.as_i64() {
+                    self.events.push(LspEvent::Response { id, result });
+                }
+            }
+            Message::Request { id, method, params } => {
+                let result = server_request(&method, &params);
+                server.write(Message::Response { id, result });
+            }
+            Message::Notification { method, params } => match method.as_str() {
+                "window/logMessage" | "window/showMessage" => {
+                    let level = match params["type"].as_i64() {
+                        Some(1) => Level::Error,
+                        Some(2) => Level::Warning,
+                        _ => Level::Info,
+                    };
+                    let message = format!("[{}] {}", server.name, params["message"].as_str().unwrap_or(""));
+                    let show = method == "window/showMessage" && level != Level::Info;
+                    self.log(level, message, show);
+                }
+                "textDocument/publishDiagnostics" => {
+                    let Some(path) = params["uri"].as_str().and_then(uri_to_path) else {
+                        return;
+                    };
+                    // Diagnostics and open documents are keyed by canonical path
+                    let path = std::fs::canonicalize(&path).unwrap_or(path);
+                    // Columns are converted with the text the server was sent, when it has it
+                    let text = self.documents.get(&path).map(|d| d.text.as_str());
+                    let diagnostics = params["diagnostics"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|d| diagnostic(d, text))
+                        .collect();
+                    self.events.push(LspEvent::Diagnostics { language: language.to_string(), path, diagnostics });
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn server_exited(&mut self, language: &str) {
+        let Some(server) = self.servers.remove(language) else {
+            return;
+        };
+        let name = server.name.clone();
+        // Reaps the process in the background
+        server.shutdown();
+        self.events.push(LspEvent::Stopped { language: language.to_string() });
+        // Reopened in the new server on the next sync
+        self.documents.retain(|_, document| document.language != language);
+
+        let restarts = self.restarts.entry(language.to_string()).or_default();
+        *restarts += 1;
+        if *restarts > MAX_RESTARTS {
+            self.failed.insert(language.to_string());
+            let message = format!("{} exited unexpectedly {} times and will not be restarted", name, MAX_RESTARTS + 1);
+            self.log(Level::Error, message, true);
+        } else {
+            self.log(Level::Warning, format!("{} exited unexpectedly; restarting it", name), true);
+        }
+    }
+
+    // Returns the thread shutting the server down, which may be left to finish on its own
+    fn stop(&mut self, language: &str) -> Option<JoinHandle<()>> {
+        let stopping = self.servers.remove(language).map(|server| {
+            self.events.push(LspEvent::Stopped { language: language.to_string() });
+            server.shutdown()
+        });
+        self.documents.retain(|_, document| document.language != language);
+        stopping
+    }
+
+    // Stops all servers at once and waits for them, so none is left running after
+    // Exodus exits. Used when the window is already gone.
+    pub fn shutdown(&mut self) {
+        let languages: Vec<String> = self.servers.keys().cloned().collect();
+        let stopping: Vec<JoinHandle<()>> = languages.iter().filter_map(|language| self.stop(language)).collect();
+        for thread in stopping {
+            let _ = thread.join();
+        }
+    }
+
+    fn log(&mut self, level: Level, message: String, show: bool) {
+        self.events.push(LspEvent::Log { level, message, show });
+    }
+}
+
+impl Drop for LspManager {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn client_capabilities() -> Value {
+    json!({
+        "textDocument": {
+            "synchronization": { "didSave": true, "dynamicRegistration": false },
+            "completion": {
+                "completionItem": { "snippetSupport": true, "documentationFormat": ["plaintext", "markdown"] },
+                "contextSupport": true,
+            },
+            "hover": { "contentFormat": ["markdown", "plaintext"] },
+            "definition": { "linkSupport": true },
+            "references": {},
+            "rename": {},
+            "formatting": {},
+            "rangeFormatting": {},
+            "codeAction": {
+                "codeActionLiteralSupport": {
+                    "codeActionKind": { "valueSet": ["quickfix", "refactor", "refactor.extract", "refactor.inline", "refactor.rewrite", "source"] },
+                },
+                "isPreferredSupport": true,
+                "disabledSupport": true,
+                "dataSupport": true,
+                "resolveSupport": { "properties": ["edit"] },
+            },
+        },
+        "workspace": {
+            "workspaceFolders": true,
+            "configuration": true,
+            "workspaceEdit": { "documentChanges": true },
+        },
+        "window": { "workDoneProgress": false },
+    })
+}
+
+// Answers requests servers make of the client. Settings are left at the
+// server's defaults.
+fn server_request(method: &str, params: &Value) -> Result<Value, ResponseError> {
+    match method {
+        "workspace/configuration" => {
+            let items = params["items"].as_array().map_or(0, Vec::len);
+            Ok(Value::Array(vec![Value::Null; items]))
+        }
+        "client/registerCapability" | "client/unregisterCapability" | "window/workDoneProgress/create" => {
+            Ok(Value::Null)
+        }
+        "window/showMessageRequest" => Ok(Value::Null),
+        _ => Err(ResponseError {
+            code: jsonrpc::METHOD_NOT_FOUND,
+            message: format!("Unhandled method {}", method),
+        }),
+    }
+}
+
+// LSP language identifiers for the language names the editor uses
+fn language_id(language: &str) -> String {
+    match language {
+        "C++" => "cpp".to_string(),
+        "C#" => "csharp".to_string(),
+        "Bourne Again Shell (bash)" | "Shell-Unix-Generic" => "shellscript".to_string(),
+        "Makefile" => "makefile".to_string(),
+        _ => language.to_lowercase().replace(' ', ""),
+    }
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    // Windows paths start with a drive letter
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // `/C:/dir` on Windows
+    if cfg!(windows) && path.as_bytes().get(2) == Some(&b':') {
+        return Some(PathBuf::from(&path[1..]));
+    }
+    Some(PathBuf::from(path))
+}
+
+// The items of a completion response, and whether the list is incomplete and
+// should be asked for again as the word grows
+pub fn completion_items(result: &Value) -> (Vec<CompletionItem>, bool) {
+    let incomplete = result["isIncomplete"].as_bool().unwrap_or(false);
+    let items = result.get("items").unwrap_or(result).as_array();
+    let items = items
+        .into_iter()
+        .flatten()
+        .map(|item| {
+            let label = item["label"].as_str().unwrap_or("").to_string();
+            let text = |key: &str| item[key].as_str().map(str::to_string);
+            let documentation = match &item["documentation"] {
+                Value::String(text) => Some(text.clone()),
+                markup => markup["value"].as_str().map(str::to_string),
+            };
+            CompletionItem {
+                kind: completion_kind(item["kind"].as_i64()),
+                detail: text("detail").filter(|d| !d.is_empty()),
+                documentation: documentation.filter(|d| !d.is_empty()),
+                insert_text: item["textEdit"]["newText"]
+                    .as_str()
+                    .map(str::to_string)
+                    .or_else(|| text("insertText"))
+                    .unwrap_or_else(|| label.clone()),
+                snippet: item["insertTextFormat"].as_i64() == Some(2),
+                filter_text: text("filterText").unwrap_or_else(|| label.clone()),
+                sort_text: text("sortText").unwrap_or_else(|| label.clone()),
+                label,
+            }
+        })
+        .collect();
+    (items, incomplete)
+}
+
+fn completion_kind(kind: Option<i64>) -> &'static str {
+    match kind {
+        Some(2) => "meth",
+        Some(3) => "fn",
+        Some(4) => "new",
+        Some(5) => "field",
+        Some(6) => "var",
+        Some(7) => "class",
+        Some(8) => "iface",
+        Some(9) => "mod",
+        Some(10) => "prop",
+        Some(12) => "value",
+        Some(13) => "enum",
+        Some(14) => "kw",
+        Some(15) => "snip",
+        Some(17) => "file",
+        Some(19) => "dir",
+        Some(20) => "member",
+        Some(21) => "const",
+        Some(22) => "struct",
+        Some(25) => "type",
+        _ => "",
+    }
+}
+
+fn diagnostic(value: &Value, text: Option<&str>) -> Diagnostic {
+    let range = &value["range"];
+    Diagnostic {
+        start: char_position(text, &range["start"]),
+        end: char_position(text, &range["end"]),
+        severity: match value["severity"].as_i64() {
+            Some(2) => Severity::Warning,
+            Some(3) => Severity::Information,
+            Some(4) => Severity::Hint,
+            // Servers that leave it out mean errors
+            _ => Severity::Error,
+        },
+        message: value["message"].as_str().unwrap_or("").to_string(),
+        source: value["source"].as_str().map(str::to_string),
+    }
+}
+
+// A diagnostic as the server reported it, for the context of code action requests
+pub fn diagnostic_value(text: &str, diagnostic: &Diagnostic) -> Value {
+    let start = diagnostics::byte_offset(text, diagnostic.start);
+    let end = diagnostics::byte_offset(text, diagnostic.end).max(start);
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Information => 3,
+        Severity::Hint => 4,
+    };
+    json!({
+        "range": { "start": position(text, start), "end": position(text, end) },
+        "severity": severity,
+        "message": diagnostic.message,
+        "source": diagnostic.source,
+    })
+}
+
+// An LSP position as a line and char column. Without the text, UTF-16 units
+// are taken as chars, which only differs past the Basic Multilingual Plane.
+fn char_position(text: Option<&str>, position: &Value) -> Position {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let units = position["character"].as_u64().unwrap_or(0) as usize;
+    let column = match text.and_then(|text| text.split('\n').nth(line)) {
+        Some(line_text) => char_column(line_text, units),
+        None => units,
+    };
+    Position { line, column }
+}
+
+// The char column `units` UTF-16 code units into a line
+pub fn char_column(line_text: &str, units: usize) -> usize {
+    let mut count = 0;
+    line_text
+        .chars()
+        .take_while(|c| {
+            count += c.len_utf16();
+            count <= units
+        })
+        .count()
+}
+
+// A place in a file as a server reports it; `character` counts UTF-16 units
+pub struct LspLocation {
+    pub path: PathBuf,
+    pub line: usize,
+    pub character: usize,
+}
+
+// The targets of a definition or references response: a location, a list of
+// locations or a list of location links
+pub fn locations(result: &Value) -> Vec<LspLocation> {
+    let values = match result {
+        Value::Array(values) => values.iter().collect(),
+        Value::Null => Vec::new(),
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .filter_map(|value| {
+            let uri = value.get("targetUri").or_else(|| value.get("uri"))?.as_str()?;
+            let range = value.get("targetSelectionRange").or_else(|| value.get("range"))?;
+            Some(LspLocation {
+                path: uri_to_path(uri)?,
+                line: range["start"]["line"].as_u64()? as usize,
+                character: range["start"]["character"].as_u64()? as usize,
+            })
+        })
+        .collect()
+}
+
+// A code action or bare command offered by a server
+#[derive(Clone)]
+pub struct CodeAction {
+    pub title: String,
+    pub kind: Option<String>,
+    pub preferred: bool,
+    // As received, for `codeAction/resolve`
+    pub value: Value,
+}
+
+impl CodeAction {
+    pub fn parse(value: &Value) -> Option<Self> {
+        Some(CodeAction {
+            title: value["title"].as_str()?.to_string(),
+            kind: value["kind"].as_str().map(str::to_string),
+            preferred: value["isPreferred"].as_bool().unwrap_or(false),
+            value: value.clone(),
+        })
+    }
+
+    pub fn edit(&self) -> Option<&Value> {
+        self.value.get("edit")
+    }
+
+    // The command to run, of an action or a bare command
+    pub fn command(&self) -> Option<&Value> {
+        match &self.value["command"] {
+            Value::String(_) => Some(&self.value),
+            Value::Object(_) => Some(&self.value["command"]),
+            _ => None,
+        }
+    }
+
+    // Servers leave out the edit until resolved when they support resolving
+    pub fn needs_resolve(&self) -> bool {
+        self.edit().is_none() && self.value.get("data").is_some()
+    }
+}
+
+// The enabled actions of a code action response, preferred fixes first
+pub fn code_actions(result: &Value) -> Vec<CodeAction> {
+    let mut actions: Vec<CodeAction> = result
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|value| value.get("disabled").is_none())
+        .filter_map(CodeAction::parse)
+        .collect();
+    actions.sort_by_key(|a| (!a.preferred, !a.kind.as_deref().is_some_and(|k| k.starts_with("quickfix"))));
+    actions
+}
+
+// The contents of a hover response as markdown
+pub fn hover_text(result: &Value) -> Option<String> {
+    fn marked_string(value: &Value) -> Option<String> {
+        match value {
+            Value::String(text) => Some(text.clone()),
+            Value::Object(_) => {
+                let text = value["value"].as_str()?;
+                Some(match value["language"].as_str() {
+                    Some(language) => format!("```{}\n{}\n```", language, text),
+                    None => text.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    let text = match &result["contents"] {
+        Value::Array(parts) => parts.iter().filter_map(marked_string).collect::<Vec<_>>().join("\n\n"),
+        contents => marked_string(contents)?,
+    };
+    Some(text).filter(|text| !text.trim().is_empty())
+}
+
+// An LSP position for a byte offset. Columns count UTF-16 code units.
+pub fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+// The smallest single edit turning `old` into `new`: the replaced byte range of
+// `old` and the text replacing it
+fn text_change<'a>(old: &str, new: &'a str) -> (usize, usize, &'a str) {
+    let mut prefix = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(prefix) || !new.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let longest_suffix = old.len().min(new.len()) - prefix;
+    let mut suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(longest_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !old.is_char_boundary(old.len() - suffix) || !new.is_char_boundary(new.len() - suffix) {
+        suffix -= 1;
+    }
+    (prefix, old.len() - suffix, &new[prefix..new.len() - suffix])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LANGUAGE: &str = "Fake";
+
+    // `examples/fake_lsp.rs`, which `cargo test` builds along with the tests
+    fn fake_server(args: &[&str]) -> BTreeMap<String, LanguageServerConfig> {
+        let exe = std::env::current_exe().unwrap();
+        let path = exe
+            .parent()
+            .and_then(Path::parent)
+            .unwrap()
+            .join("examples")
+            .join(format!("fake_lsp{}", std::env::consts::EXE_SUFFIX));
+        assert!(path.exists(), "{} is missing; run the tests with `cargo test`", path.display());
+        let config = LanguageServerConfig {
+            command: path.display().to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        };
+        BTreeMap::from([(LANGUAGE.to_string(), config)])
+    }
+
+    fn document<'a>(path: &'a Path, text: &'a str) -> OpenDocument<'a> {
+        OpenDocument { path, language: LANGUAGE, text }
+    }
+
+    // Polls until a log message matches, failing after a few seconds
+    fn wait_for_log(manager: &mut LspManager, matches: impl Fn(Level, &str) -> bool) -> String {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            for event in manager.poll() {
+                if let LspEvent::Log { level, message, .. } = event {
+                    if matches(level, &message) {
+                        return message;
+                    }
+                }
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("timed out waiting for the language server");
+    }
+
+    // The fake server logs its copy of a document as `<event> <uri> <json text>`
+    fn wait_for_text(manager: &mut LspManager, event: &str, path: &Path) -> String {
+        let prefix = format!("{} {} ", event, path_to_uri(path));
+        let message = wait_for_log(manager, |_, message| message.contains(&prefix));
+        let json = &message[message.find(&prefix).unwrap() + prefix.len()..];
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn opens_documents_once_initialized() {
+        let mut manager = LspManager::new(fake_server(&[]), None);
+        let path = Path::new("/project/main.fake");
+        manager.sync(&[document(path, "hello")], None);
+        assert_eq!(wait_for_text(&mut manager, "open", path), "hello");
+    }
+
+    #[test]
+    fn sends_edits_incrementally() {
+        let mut manager = LspManager::new(fake_server(&[]), None);
+        let path = Path::new("/project/main.fake");
+        let edits = [
+            "fn main() {\n}\n",
+            "fn main() {\n    println!(\"hi\");\n}\n",
+            "fn main() {\n    println!(\"héllo 😀\");\n}\n",
+            "fn main() {\n    println!(\"héllo 😀 wörld\");\n}\n",
+            "// start\nfn main() {\n    println!(\"héllo 😀 wörld\");\n}\n",
+            "main() {\n    println!(\"😀\");\n}",
+            "",
+            "a\r\nb",
+        ];
+        manager.sync(&[document(path, edits[0])], None);
+        assert_eq!(wait_for_text(&mut manager, "open", path), edits[0]);
+        for text in &edits[1..] {
+            manager.sync(&[document(path, text)], None);
+            assert_eq!(wait_for_text(&mut manager, "change", path), *text);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_full_sync() {
+        let mut manager = LspManager::new(fake_server(&["--full-sync"]), None);
+        let path = Path::new("/project/main.fake");
+        manager.sync(&[document(path, "one")], None);
+        wait_for_text(&mut manager, "open", path);
+        manager.sync(&[document(path, "one two")], None);
+        assert_eq!(wait_for_text(&mut manager, "change", path), "one two");
+    }
+
+    #[test]
+    fn closes_documents_no_longer_open() {
+        let mut manager = LspManager::new(fake_server(&[]), None);
+        let (a, b) = (Path::new("/project/a.fake"), Path::new("/project/b.fake"));
+        manager.sync(&[document(a, "a"), document(b, "b")], None);
+        wait_for_text(&mut manager, "open", b);
+        manager.sync(&[document(b, "b")], None);
+        let closed = format!("close {}", path_to_uri(a));
+        wait_for_log(&mut manager, |_, message| message.contains(&closed));
+    }
+
+    #[test]
+    fn closes_documents_switched_to_a_language_without_a_server() {
+        let mut manager = LspManager::new(fake_server(&[]), None);
+        let path = Path::new("/project/notes.fake");
+        manager.sync(&[document(path, "text")], None);
+        wait_for_text(&mut manager, "open", path);
+        let plain = OpenDocument { path, language: "Plain Text", text: "text" };
+        manager.sync(&[plain], None);
+        let closed = format!("close {}", path_to_uri(path));
+        wait_for_log(&mut manager, |_, message| message.contains(&closed));
+    }
+
+    #[test]
+    fn answers_server_requests() {
+        let mut manager = LspManager::new(fake_server(&[]), None);
+        manager.sync(&[document(Path::new("/project/main.fake"), "")], None);
+        let reply = wait_for_log(&mut manager, |_, message| message.contains("configuration"));
+        assert!(reply.ends_with("configuration [null]"), "{}", reply);
+    }
+
+    #[test]
+    fn restarts_a_crashed_server() {
+        let mut manager = LspManager::new(fake_server(&[]), None);
+        let path = Path::new("/project/main.fake");
+        manager.sync(&[document(path, "fine")], None);
+        wait_for_text(&mut manager, "open", path);
+
+        // The fake server exits when a document contains CRASH
+        manager.sync(&[document(path, "CRASH")], None);
+        wait_for_log(&mut manager, |level, message| level == Level::Warning && message.contains("restarting"));
+        manager.sync(&[document(path, "fine again")], None);
+        assert_eq!(wait_for_text(&mut manager, "open", path), "fine again");
+    }
+
+    #[test]
+    fn reports_servers_that_cannot_start() {
+        let configs = BTreeMap::from([(
+            LANGUAGE.to_string(),
+            LanguageServerConfig { command: "exodus-no-such-language-server".to_string(), args: Vec::new() },
+        )]);
+        let mut manager = LspManager::new(configs, None);
+        manager.sync(&[document(Path::new("/project/main.fake"), "")], None);
+        wait_for_log(&mut manager, |level, message| level == Level::Error && message.contains("Could not start"));
+        assert!(manager.servers.is_empty());
+    }
+
+    #[test]
+    fn shuts_servers_down() {
+        let marker = std::env::temp_dir().join(format!("exodus-fake-lsp-exit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let mut manager = LspManager::new(fake_server(&["--exit-marker", &marker.display().to_string()]), None);
+        let path = Path::new("/project/main.fake");
+        manager.sync(&[document(path, "")], None);
+        wait_for_text(&mut manager, "open", path);
+
+        manager.shutdown();
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "shutdown exit");
+        let _ = std::fs::remove_file(&marker);
+    }
+
+    #[test]
+    fn never_waits_for_a_stuck_server() {
+        let mut manager = LspManager::new(fake_server(&[]), None);
+        let path = Path::new("/project/main.fake");
+        manager.sync(&[document(path, "")], None);
+        wait_for_text(&mut manager, "open", path);
+        manager.sync(&[document(path, "HANG")], None);
+
+        // More than the pipe to the server holds, which it no longer reads
+        let started = Instant::now();
+        let text = "HANG".to_string() + &"x".repeat(1 << 20);
+        manager.sync(&[document(path, &text)], None);
+        manager.sync(&[], None);
+        let stopping = manager.stop(LANGUAGE).expect("the server is running");
+        assert!(started.elapsed() < SHUTDOWN_TIMEOUT);
+        // It gets killed in the background
+        stopping.join().unwrap();
+    }
+
+    #[test]
+    fn publishes_diagnostics_in_chars() {
+        let mut manager = LspManager::new(fake_server(&[]), None);
+        let path = Path::new("/project/main.fake");
+        manager.sync(&[document(path, "fine\né😀 ERROR")], None);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            for event in manager.poll() {
+                if let LspEvent::Diagnostics { language, path: reported, diagnostics } = event {
+                    assert_eq!((language.as_str(), reported.as_path()), (LANGUAGE, path));
+                    assert_eq!(diagnostics.len(), 1);
+                    assert!(diagnostics[0].start == Position { line: 1, column: 3 });
+                    assert!(diagnostics[0].end == Position { line: 1, column: 8 });
+                    assert!(diagnostics[0].severity == Severity::Error);
+                    return;
+                }
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("no diagnostics were published");
+    }
+
+    #[test]
+    fn positions_count_utf16_units() {
+        let text = "ab\nc😀d\n";
+        assert_eq!(position(text, 0), json!({ "line": 0, "character": 0 }));
+        assert_eq!(position(text, 3), json!({ "line": 1, "character": 0 }));
+        assert_eq!(position(text, 8), json!({ "line": 1, "character": 3 }));
+        assert_eq!(position(text, text.len()), json!({ "line": 2, "character": 0 }));
+    }
+
+    #[test]
+    fn text_change_is_minimal() {
+        assert_eq!(text_change("hello world", "hello brave world"), (6, 6, "brave "));
+        assert_eq!(text_change("aaa", "aa"), (2, 3, ""));
+        assert_eq!(text_change("é", "è"), (0, 2, "è"));
+        assert_eq!(text_change("same", "same"), (4, 4, ""));
+    }
+
+    #[test]
+    fn uris_are_percent_encoded() {
+        assert_eq!(path_to_uri(Path::new("/a b/ä.rs")), "file:///a%20b/%C3%A4.rs");
+        assert_eq!(uri_to_path("file:///a%20b/%C3%A4.rs"), Some(PathBuf::from("/a b/ä.rs")));
+    }
+
+    #[test]
+    fn reads_locations_and_links() {
+        let location = json!({ "uri": "file:///a.rs", "range": { "start": { "line": 1, "character": 2 } } });
+        let link = json!({
+            "targetUri": "file:///b.rs",
+            "targetRange": { "start": { "line": 0, "character": 0 } },
+            "targetSelectionRange": { "start": { "line": 3, "character": 4 } },
+        });
+        let found: Vec<_> = locations(&json!([location.clone(), link]))
+            .into_iter()
+            .map(|l| (l.path, l.line, l.character))
+            .collect();
+        assert_eq!(found, [(PathBuf::from("/a.rs"), 1, 2), (PathBuf::from("/b.rs"), 3, 4)]);
+        assert_eq!(locations(&location).len(), 1);
+        assert!(locations(&Value::Null).is_empty());
+    }
+
+    #[test]
+    fn hover_contents_become_markdown() {
+        let markup = json!({ "contents": { "kind": "markdown", "value": "**docs**" } });
+        assert_eq!(hover_text(&markup).as_deref(), Some("**docs**"));
+        let marked = json!({ "contents": [{ "language": "rust", "value": "fn f()" }, "docs"] });
+        assert_eq!(hover_text(&marked).as_deref(), Some("```rust\nfn f()\n```\n\ndocs"));
+        assert_eq!(hover_text(&json!({ "contents": "" })), None);
+    }
+}
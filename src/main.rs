@@ -1,19 +1,114 @@
 use eframe::App;
 use egui::{CentralPanel, Context, SidePanel, TopBottomPanel};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
 
 mod editor;
 mod syntax;
 mod config;
 mod plugins;
+mod commands;
+mod palette;
+mod fuzzy;
+mod keybindings;
+mod vim;
+mod picker;
+mod git;
+mod encoding;
+mod hex;
+mod large_file;
+mod error;
+mod notifications;
+mod save;
+mod cli;
+mod ipc;
+mod navigation;
+mod diff;
+mod tab_bar;
+mod jsonrpc;
+mod lsp;
+mod diagnostics;
+mod completion;
+mod markdown;
+mod references;
+mod workspace_edit;
+mod refactor;
+mod format;
+mod vt;
+mod terminal;
+mod tasks;
+#[cfg(test)]
+mod test_dir;
 
-use editor::Editor;
+use editor::{Editor, Indentation, LineEnding};
 use syntax::SyntaxHighlighter;
 use config::Config;
-use plugins::PluginManager;
+use plugins::{ExamplePlugin, Plugin, PluginContext, PluginManager};
+use commands::{Command, CommandHandler, CommandRegistry, Menu};
+use palette::CommandPalette;
+use keybindings::{BindingSource, KeySequence, Keymap};
+use vim::{Vim, VimAction};
+use picker::{Picker, PickerItem, PickerResult};
+use encoding::Encoding;
+use notifications::{Level, Notifications};
+use lsp::{LspEvent, LspManager};
+use diagnostics::{Diagnostics, Severity};
+use completion::{CompletionItem, CompletionSession, Insertion, SnippetSession, Trigger};
+use references::{ReferencesAction, ReferencesPanel};
+use workspace_edit::{EditPreview, FileEdit, PreviewAction};
+use refactor::{CodeActionMenu, MenuAction, RenameAction, RenameInput};
+use terminal::{TerminalAction, TerminalPanel};
+use tasks::{Tasks, TasksAction};
 
 fn main() -> Result<(), eframe::Error> {
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("exodus: {}\n\n{}", message, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    if args.version {
+        println!("exodus {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    // Read before the window opens; whoever pipes in is waiting on us
+    let stdin = args.stdin.then(|| {
+        let mut bytes = Vec::new();
+        if let Err(e) = std::io::stdin().read_to_end(&mut bytes) {
+            eprintln!("exodus: could not read standard input: {}", e);
+        }
+        match Encoding::detect(&bytes).decode(&bytes) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("exodus: could not read standard input: {}", e);
+                String::new()
+            }
+        }
+    });
+
+    // Hand everything to the running instance, if there is one
+    let mut request = ipc::Request { args, stdin };
+    if !request.args.new_window {
+        if let Ok(cwd) = std::env::current_dir() {
+            request.args.make_absolute(&cwd);
+        }
+        match ipc::forward(&request) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => eprintln!("exodus: could not reach the running instance: {}", e),
+        }
+    }
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([1200.0, 800.0]),
         ..Default::default()
@@ -22,106 +117,1610 @@ fn main() -> Result<(), eframe::Error> {
     eframe::run_native(
         "Exodus",
         options,
-        Box::new(|cc| Box::new(Exodus::new(cc))),
+        Box::new(move |cc| {
+            let mut app = Exodus::new(cc);
+            let tabs = app.open_request(&request);
+            if request.args.wait {
+                app.wait_for = tabs;
+            }
+            Box::new(app)
+        }),
     )
 }
 
-pub struct Exodus {
-    editor: Editor,
-    syntax_highlighter: SyntaxHighlighter,
-    config: Config,
-    plugin_manager: PluginManager,
-    show_file_explorer: bool,
-    file_explorer_width: f32,
-    search_query: String,
-    show_search: bool,
-}
+pub struct Exodus {
+    editor: Editor,
+    syntax_highlighter: SyntaxHighlighter,
+    config: Config,
+    // config.toml could not be read at startup; it isn't written until it reads cleanly
+    config_failed: bool,
+    plugin_manager: PluginManager,
+    show_file_explorer: bool,
+    file_explorer_width: f32,
+    // File being revealed in the explorer, by canonical path, and when that started
+    reveal: Option<(PathBuf, Instant)>,
+    // Explorer entry of the last file opened or revealed from it
+    explorer_selection: Option<PathBuf>,
+    search_query: String,
+    show_search: bool,
+    commands: CommandRegistry,
+    command_palette: CommandPalette,
+    keymap: Keymap,
+    show_keybindings: bool,
+    keybindings_filter: String,
+    vim: Vim,
+    // The search bar was opened with `/` and hands focus back to the editor
+    vim_search: bool,
+    search_focus_requested: bool,
+    status_picker: Option<(StatusPicker, Picker)>,
+    git_branch: Option<String>,
+    git_branch_checked: Option<Instant>,
+    git_status: git::StatusTracker,
+    notifications: Notifications,
+    show_output: bool,
+    // Tabs opened with --wait; the window closes once they are all closed
+    wait_for: Vec<usize>,
+    // Listens for later invocations of `exodus`; None when another instance already does
+    server: Option<ipc::Server>,
+    // Forwarded --wait requests and the tabs they are waiting on
+    waiting_clients: Vec<(Vec<usize>, ipc::Incoming)>,
+    lsp: LspManager,
+    // Reported by language servers, per file
+    diagnostics: Diagnostics,
+    show_problems: bool,
+    completion: Option<CompletionSession>,
+    // Set by Ctrl+Space and by typing; handled once language servers have the new text
+    completion_trigger: Option<Trigger>,
+    // Tab stops of the last inserted snippet
+    snippet: Option<SnippetSession>,
+    // Definition and references requests waiting for an answer, by request id
+    lsp_requests: HashMap<i64, LspRequest>,
+    external_formats: Vec<ExternalFormat>,
+    references: Option<ReferencesPanel>,
+    hover: Option<Hover>,
+    rename: Option<RenameInput>,
+    code_actions: Option<CodeActions>,
+    code_action_menu: Option<CodeActionMenu>,
+    // A rename or code action waiting to be applied
+    edit_preview: Option<EditPreview>,
+    terminal: TerminalPanel,
+    show_terminal: bool,
+    tasks: Tasks,
+    show_tasks: bool,
+}
+
+// How long the pointer rests on a word before its hover info is requested
+const HOVER_DELAY: Duration = Duration::from_millis(500);
+// How long the cursor rests before code actions are looked up for the lightbulb
+const CODE_ACTIONS_DELAY: Duration = Duration::from_millis(300);
+// How long saving waits for a language server to format the file
+const FORMAT_ON_SAVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// What a pending request was for
+enum LspRequest {
+    // The word at the cursor
+    Definition(String),
+    References(String),
+    // The title of the preview
+    Rename(String),
+    ResolveAction(String),
+    Command,
+    Format(FormatRequest),
+}
+
+// An external formatter running on a thread
+struct ExternalFormat {
+    request: FormatRequest,
+    // The lines of the selection being formatted
+    lines: Option<Range<usize>>,
+    result: Receiver<Result<String, String>>,
+}
+
+struct FormatRequest {
+    tab_id: usize,
+    // The text that was sent; the edits don't apply once it has changed
+    text: String,
+    // Save the tab once formatted
+    save: bool,
+    sent: Instant,
+}
+
+// An answer that is asked for once something has rested for a moment
+enum Lookup<T> {
+    Waiting,
+    Requested(i64),
+    Answered(T),
+}
+
+// Hover info for the word under the pointer
+struct Hover {
+    tab_id: usize,
+    // Char index where the word starts
+    word: usize,
+    since: Instant,
+    state: Lookup<Option<String>>,
+}
+
+// Code actions for the selection, looked up to show the lightbulb
+struct CodeActions {
+    tab_id: usize,
+    selection: Range<usize>,
+    since: Instant,
+    state: Lookup<Vec<lsp::CodeAction>>,
+    // Asked for with Alt+Enter; the menu opens with the answer
+    open_menu: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum StatusItem {
+    GoToLine,
+    Language,
+    Encoding,
+    LineEnding,
+    Indentation,
+    Branch,
+}
+
+// Which status bar item or navigation command opened the picker, with the
+// values its items stand for
+enum StatusPicker {
+    GoToLine,
+    // Zero-based (line, column) of each symbol
+    GoToSymbol(Vec<(usize, usize)>),
+    Language(Vec<String>),
+    EncodingAction,
+    // `reopen` decodes the file again instead of changing how it is saved
+    Encoding { reopen: bool },
+    LineEnding,
+    Indentation(Vec<IndentationChoice>),
+    Branch(Vec<String>),
+    Task(Vec<String>),
+}
+
+#[derive(Clone, Copy)]
+enum IndentationChoice {
+    Use(Indentation),
+    Convert(Indentation),
+}
+
+impl Exodus {
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let mut notifications = Notifications::new();
+        let (config, config_failed) = match Config::load() {
+            Ok(config) => (config, false),
+            Err(e) => {
+                notifications.error(format!("{}. Using the default settings.", e));
+                (Config::default(), true)
+            }
+        };
+        
+        let mut style = (*cc.egui_ctx.style()).clone();
+        style.visuals.window_rounding = egui::Rounding::ZERO;
+        style.visuals.menu_rounding = egui::Rounding::ZERO;
+        style.visuals.indent_has_left_vline = false;
+        style.spacing.item_spacing = egui::vec2(4.0, 2.0);
+        cc.egui_ctx.set_style(style);
+
+        let lsp = LspManager::new(config.language_servers.clone(), Some(cc.egui_ctx.clone()));
+        let mut app = Self {
+            editor: Editor::new(),
+            syntax_highlighter: SyntaxHighlighter::new(),
+            config,
+            config_failed,
+            plugin_manager: PluginManager::new(),
+            show_file_explorer: true,
+            file_explorer_width: 200.0,
+            reveal: None,
+            explorer_selection: None,
+            search_query: String::new(),
+            show_search: false,
+            commands: CommandRegistry::with_builtins(),
+            command_palette: CommandPalette::new(),
+            keymap: Keymap::default(),
+            show_keybindings: false,
+            keybindings_filter: String::new(),
+            vim: Vim::new(),
+            vim_search: false,
+            search_focus_requested: false,
+            status_picker: None,
+            git_branch: None,
+            git_branch_checked: None,
+            git_status: git::StatusTracker::new(),
+            notifications,
+            show_output: false,
+            wait_for: Vec::new(),
+            server: ipc::Server::start(cc.egui_ctx.clone()).ok(),
+            waiting_clients: Vec::new(),
+            lsp,
+            diagnostics: Diagnostics::new(),
+            show_problems: false,
+            completion: None,
+            completion_trigger: None,
+            snippet: None,
+            lsp_requests: HashMap::new(),
+            external_formats: Vec::new(),
+            references: None,
+            hover: None,
+            rename: None,
+            code_actions: None,
+            code_action_menu: None,
+            edit_preview: None,
+            terminal: TerminalPanel::new(),
+            show_terminal: false,
+            tasks: Tasks::new(),
+            show_tasks: false,
+        };
+        app.editor.set_tab_size(app.config.tab_size);
+        app.editor.set_large_file_threshold(app.config.large_file_threshold_mb * 1024 * 1024);
+        app.editor.set_save_options(save::SaveOptions {
+            backup: app.config.backup_on_save,
+            backup_directory: app.config.backup_directory.clone(),
+            in_place_fallback: app.config.save_in_place,
+        });
+        app.register_plugin(Box::new(ExamplePlugin));
+        app.reload_keybindings();
+        app
+    }
+
+    // Opens what the command line asked for and returns the ids of the tabs to wait on
+    fn open_request(&mut self, request: &ipc::Request) -> Vec<usize> {
+        let mut tabs = Vec::new();
+        // Piped text is waited on like a file
+        if let Some(text) = &request.stdin {
+            self.editor.open_text("stdin".to_string(), text.clone());
+            tabs.extend(self.editor.active_tab_id());
+        }
+
+        if let Some((old, new)) = &request.args.diff {
+            let result = self.editor.open_diff(old, new);
+            self.report(result);
+        }
+
+        for target in &request.args.targets {
+            match target {
+                cli::Target::Folder(path) => self.editor.open_folder(path.clone()),
+                cli::Target::File { path, line, column } => {
+                    if !path.exists() {
+                        self.editor.open_new_file(path.clone());
+                    } else {
+                        let result = self.editor.open_file(path.clone());
+                        if self.report(result).is_none() {
+                            continue;
+                        }
+                    }
+                    if let Some(line) = line {
+                        self.editor.go_to_line(line.saturating_sub(1), column.unwrap_or(1).saturating_sub(1));
+                    }
+                    tabs.extend(self.editor.active_tab_id());
+                }
+            }
+        }
+        tabs
+    }
+
+    // Handles requests forwarded by later invocations of `exodus`
+    fn poll_instance_requests(&mut self, ctx: &Context) {
+        let Some(server) = &self.server else {
+            return;
+        };
+        let incoming = server.poll();
+        for request in incoming {
+            let tabs = self.open_request(&request.request);
+            if request.request.args.wait && !tabs.is_empty() {
+                self.waiting_clients.push((tabs, request));
+            } else {
+                request.finish();
+            }
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        }
+
+        // Release --wait clients whose tabs have all been closed
+        let (done, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.waiting_clients)
+            .into_iter()
+            .partition(|(tabs, _)| !tabs.iter().any(|&id| self.editor.is_tab_open(id)));
+        self.waiting_clients = waiting;
+        for (_, request) in done {
+            request.finish();
+        }
+    }
+
+    // Keeps language servers in step with the open buffers and passes on what they report
+    fn sync_language_servers(&mut self) {
+        let root = self.editor.get_workspace_folder().cloned();
+        let documents = self.editor.text_documents(&self.syntax_highlighter);
+        self.lsp.sync(&documents, root.as_deref());
+        for path in self.editor.take_saved_paths() {
+            self.lsp.did_save(&path);
+            self.git_status.refresh();
+        }
+
+        for event in self.lsp.poll() {
+            match event {
+                LspEvent::Log { level, message, show: true } => match level {
+                    Level::Info => self.notifications.info(message),
+                    Level::Warning => self.notifications.warning(message),
+                    Level::Error => self.notifications.error(message),
+                },
+                LspEvent::Log { level, message, show: false } => self.notifications.log(level, message),
+                LspEvent::Diagnostics { language, path, diagnostics } => {
+                    self.diagnostics.set(path, &language, diagnostics)
+                }
+                LspEvent::Stopped { language } => self.diagnostics.clear_producer(&language),
+                LspEvent::Response { id, result } => self.lsp_response(id, result),
+            }
+        }
+    }
+
+    fn lsp_response(&mut self, id: i64, result: Result<serde_json::Value, jsonrpc::ResponseError>) {
+        if let Some(request) = self.lsp_requests.remove(&id) {
+            match (request, result) {
+                (LspRequest::Format(request), result) => self.finish_formatting(request, result),
+                (_, Err(e)) => self.notifications.warning(e.message),
+                (LspRequest::Rename(title), Ok(result)) => self.preview_edit(title, &result),
+                (LspRequest::ResolveAction(title), Ok(result)) => match lsp::CodeAction::parse(&result) {
+                    Some(action) if !action.needs_resolve() => self.run_code_action(action),
+                    _ => self.notifications.info(format!("'{}' has nothing to change", title)),
+                },
+                (LspRequest::Command, Ok(_)) => {}
+                (request, Ok(result)) => self.show_locations(request, lsp::locations(&result)),
+            }
+            return;
+        }
+        if let Some(hover) = self.hover.as_mut().filter(|h| matches!(h.state, Lookup::Requested(r) if r == id)) {
+            hover.state = Lookup::Answered(result.ok().and_then(|r| lsp::hover_text(&r)));
+            return;
+        }
+        if let Some(actions) = self.code_actions.as_mut().filter(|a| matches!(a.state, Lookup::Requested(r) if r == id)) {
+            // A failed request just means no lightbulb
+            let found = result.map(|r| lsp::code_actions(&r)).unwrap_or_default();
+            if std::mem::take(&mut actions.open_menu) {
+                if found.is_empty() {
+                    self.notifications.info("No code actions available");
+                } else {
+                    self.code_action_menu = Some(CodeActionMenu::new(found.clone()));
+                }
+            }
+            actions.state = Lookup::Answered(found);
+            return;
+        }
+        if let Some(session) = &mut self.completion {
+            // A failed request just means no suggestions from the server
+            let (items, incomplete) = result.map(|r| lsp::completion_items(&r)).unwrap_or_default();
+            session.receive(id, items, incomplete);
+        }
+    }
+
+    pub fn go_to_definition(&mut self) {
+        self.request_locations("textDocument/definition", LspRequest::Definition);
+    }
+
+    pub fn find_references(&mut self) {
+        self.request_locations("textDocument/references", LspRequest::References);
+    }
+
+    // Asks the active file's server about the symbol at the cursor
+    fn request_locations(&mut self, method: &str, request: fn(String) -> LspRequest) {
+        let (Some(path), Some(content), Some(cursor)) =
+            (self.editor.current_canonical_path(), self.editor.active_content(), self.editor.cursor_char())
+        else {
+            return;
+        };
+        let word = completion::word_at(content, cursor);
+        let id = self.lsp.text_document_position(path, editor::char_to_byte(content, cursor)).and_then(|mut params| {
+            if method == "textDocument/references" {
+                params["context"] = serde_json::json!({ "includeDeclaration": true });
+            }
+            self.lsp.request(path, method, params)
+        });
+        match id {
+            Some(id) => {
+                self.lsp_requests.insert(id, request(word));
+            }
+            None => self.notifications.info("No language server is running for this file"),
+        }
+    }
+
+    // A single definition is opened; several, and any references, are listed
+    fn show_locations(&mut self, request: LspRequest, locations: Vec<lsp::LspLocation>) {
+        let found = references::resolve(locations, |path| self.editor.text_of(path).map(str::to_string));
+        match request {
+            LspRequest::Definition(word) if found.is_empty() => {
+                self.notifications.info(format!("No definition found for '{}'", word))
+            }
+            LspRequest::Definition(_) if found.len() == 1 => {
+                let reference = &found[0];
+                let result = self.editor.open_file_at(reference.path.clone(), reference.position.line, reference.position.column);
+                self.report(result);
+            }
+            LspRequest::Definition(word) => {
+                self.references = Some(ReferencesPanel::new(format!("Definitions of '{}'", word), found));
+            }
+            LspRequest::References(word) if found.is_empty() => {
+                self.notifications.info(format!("No references found for '{}'", word))
+            }
+            LspRequest::References(word) => {
+                self.references = Some(ReferencesPanel::new(format!("References to '{}'", word), found));
+            }
+            LspRequest::Rename(_) | LspRequest::ResolveAction(_) | LspRequest::Command | LspRequest::Format(_) => {}
+        }
+    }
+
+    pub fn format_document(&mut self) {
+        if let Some(id) = self.editor.active_tab_id() {
+            self.format_tab(id, false, false);
+        }
+    }
+
+    pub fn format_selection(&mut self) {
+        if let Some(id) = self.editor.active_tab_id() {
+            self.format_tab(id, true, false);
+        }
+    }
+
+    // Formats a text tab, or the lines its selection touches, with the external
+    // formatter configured for its language, or else its language server. Only
+    // changed lines are replaced, as one undo step. With `save` the tab is saved
+    // afterwards, also when it can't be formatted.
+    fn format_tab(&mut self, id: usize, selection: bool, save: bool) {
+        let Some(tab) = self.editor.tab_text(id) else {
+            return;
+        };
+        let lines = selection.then(|| tab.selected_lines.clone());
+
+        if let Some(formatter) = self.config.formatters.get(&tab.language) {
+            let text = tab.text.to_string();
+            let result = format::start(formatter.clone(), tab.path.map(Path::to_path_buf), text.clone());
+            let request = FormatRequest { tab_id: id, text, save, sent: Instant::now() };
+            self.external_formats.push(ExternalFormat { request, lines, result });
+            return;
+        }
+
+        let capability = if lines.is_some() { "documentRangeFormattingProvider" } else { "documentFormattingProvider" };
+        let path = tab.path.filter(|path| self.lsp.has_capability(path, capability));
+        let request = path.and_then(|path| {
+            let mut params = serde_json::json!({
+                "textDocument": { "uri": lsp::path_to_uri(path) },
+                "options": { "tabSize": tab.indentation.size, "insertSpaces": !tab.indentation.use_tabs },
+            });
+            let method = match &lines {
+                Some(lines) => {
+                    let start = diagnostics::byte_offset(tab.text, diagnostics::Position { line: lines.start, column: 0 });
+                    let end = diagnostics::byte_offset(tab.text, diagnostics::Position { line: lines.end - 1, column: usize::MAX });
+                    params["range"] = serde_json::json!({
+                        "start": lsp::position(tab.text, start),
+                        "end": lsp::position(tab.text, end),
+                    });
+                    "textDocument/rangeFormatting"
+                }
+                None => "textDocument/formatting",
+            };
+            self.lsp.request(path, method, params)
+        });
+
+        match request {
+            Some(request_id) => {
+                let text = tab.text.to_string();
+                self.lsp_requests.insert(
+                    request_id,
+                    LspRequest::Format(FormatRequest { tab_id: id, text, save, sent: Instant::now() }),
+                );
+            }
+            None if save => self.save_tab(id),
+            None => {
+                let message = format!(
+                    "No formatter for {}; add one under [formatters] in the settings or configure a language server",
+                    tab.language
+                );
+                self.notifications.info(message);
+            }
+        }
+    }
+
+    fn finish_formatting(&mut self, request: FormatRequest, result: Result<serde_json::Value, jsonrpc::ResponseError>) {
+        let formatted = match result {
+            Ok(edits) => Some(workspace_edit::text_edits(PathBuf::new(), &edits).apply(&request.text)),
+            Err(e) => {
+                self.notifications.warning(format!("Formatting failed: {}", e.message));
+                None
+            }
+        };
+        self.apply_formatting(request, None, formatted);
+    }
+
+    fn poll_external_formats(&mut self, ctx: &Context) {
+        let mut index = 0;
+        while index < self.external_formats.len() {
+            let result = match self.external_formats[index].result.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => {
+                    index += 1;
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => Err("The formatter stopped unexpectedly".to_string()),
+            };
+            let format = self.external_formats.remove(index);
+            let formatted = match result {
+                Ok(formatted) => Some(formatted),
+                Err(e) => {
+                    self.notifications.error(e);
+                    None
+                }
+            };
+            self.apply_formatting(format.request, format.lines, formatted);
+        }
+        if !self.external_formats.is_empty() {
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+    }
+
+    // Replaces the changed lines with `formatted`, the formatted version of the
+    // text that was sent, unless the tab was edited since
+    fn apply_formatting(&mut self, request: FormatRequest, lines: Option<Range<usize>>, formatted: Option<String>) {
+        match (formatted, self.editor.tab_text(request.tab_id)) {
+            (Some(formatted), Some(tab)) if tab.text == request.text => {
+                let (text, cursor) = format::merge(tab.text, &formatted, lines, tab.cursor);
+                self.editor.replace_formatted(request.tab_id, text, cursor);
+            }
+            (Some(_), Some(_)) => self.notifications.warning("The file changed while it was being formatted"),
+            _ => {}
+        }
+        if request.save {
+            self.save_tab(request.tab_id);
+        }
+    }
+
+    // Saving doesn't wait forever for a server to format
+    fn expire_format_requests(&mut self, ctx: &Context) {
+        let waiting: Vec<(i64, bool)> = self
+            .lsp_requests
+            .iter()
+            .filter_map(|(&id, request)| match request {
+                LspRequest::Format(format) if format.save => Some((id, format.sent.elapsed() > FORMAT_ON_SAVE_TIMEOUT)),
+                _ => None,
+            })
+            .collect();
+        for (id, expired) in waiting {
+            if !expired {
+                ctx.request_repaint_after(Duration::from_millis(100));
+                continue;
+            }
+            if let Some(LspRequest::Format(request)) = self.lsp_requests.remove(&id) {
+                self.notifications.warning("Formatting took too long; the file was saved without it");
+                self.save_tab(request.tab_id);
+            }
+        }
+    }
+
+    // Opens the rename input for the word at the cursor
+    pub fn start_rename(&mut self) {
+        let (Some(tab_id), Some(path), Some(content), Some(cursor)) = (
+            self.editor.active_tab_id(),
+            self.editor.current_canonical_path(),
+            self.editor.active_content(),
+            self.editor.cursor_char(),
+        ) else {
+            return;
+        };
+        if self.lsp.text_document_position(path, 0).is_none() {
+            self.notifications.info("No language server is running for this file");
+            return;
+        }
+        let name = completion::word_at(content, cursor);
+        if name.is_empty() {
+            self.notifications.info("There is no symbol to rename at the cursor");
+            return;
+        }
+        let position = self.editor.cursor_rect().map_or(egui::Pos2::ZERO, |rect| rect.left_bottom());
+        self.rename = Some(RenameInput::new(tab_id, cursor, name, position));
+    }
+
+    fn rename_input(&mut self, ctx: &Context) {
+        let Some(input) = &mut self.rename else {
+            return;
+        };
+        if self.editor.active_tab_id() != Some(input.tab_id()) {
+            self.rename = None;
+            return;
+        }
+        let Some(action) = input.show(ctx) else {
+            return;
+        };
+        let input = self.rename.take();
+        self.focus_editor(ctx);
+        let (Some(input), RenameAction::Rename(new_name)) = (input, action) else {
+            return;
+        };
+        let (Some(path), Some(content)) = (self.editor.current_canonical_path(), self.editor.active_content()) else {
+            return;
+        };
+        let id = self
+            .lsp
+            .text_document_position(path, editor::char_to_byte(content, input.cursor()))
+            .and_then(|mut params| {
+                params["newName"] = serde_json::json!(new_name);
+                self.lsp.request(path, "textDocument/rename", params)
+            });
+        if let Some(id) = id {
+            let title = format!("Rename '{}' to '{}'", input.old_name(), new_name);
+            self.lsp_requests.insert(id, LspRequest::Rename(title));
+        }
+    }
+
+    // Shows the changes of a workspace edit for confirmation
+    fn preview_edit(&mut self, title: String, edit: &serde_json::Value) {
+        let files = workspace_edit::parse(edit);
+        if files.is_empty() {
+            self.notifications.info(format!("'{}' has nothing to change", title));
+            return;
+        }
+        let preview = EditPreview::new(title, files, |path| self.editor.text_of(path).map(str::to_string));
+        self.edit_preview = Some(preview);
+    }
+
+    fn edit_preview(&mut self, ctx: &Context) {
+        let Some(preview) = &mut self.edit_preview else {
+            return;
+        };
+        // The preview takes the keyboard while it is open
+        if let Some(id) = self.editor.active_text_edit_id() {
+            ctx.memory_mut(|m| m.surrender_focus(id));
+        }
+        let action = preview.show(ctx, self.editor.get_workspace_folder().map(PathBuf::as_path));
+        let Some(action) = action else {
+            return;
+        };
+        if let (Some(preview), PreviewAction::Apply) = (self.edit_preview.take(), action) {
+            self.apply_workspace_edit(preview.into_edits());
+        }
+        self.focus_editor(ctx);
+    }
+
+    // Open files change in their tabs, as one undo step each; others on disk
+    fn apply_workspace_edit(&mut self, files: Vec<FileEdit>) {
+        let mut changed = 0;
+        for file in &files {
+            let result = self.editor.edit_file(&file.path, |text| file.apply(text));
+            if self.report(result).is_some() {
+                changed += 1;
+            }
+        }
+        if changed > 1 {
+            self.notifications.info(format!("Changed {} files", changed));
+        }
+    }
+
+    // The tab and selection code actions are looked up for
+    fn code_action_target(&self) -> Option<(usize, Range<usize>)> {
+        if !self.editor.active_is_text() || self.rename.is_some() || self.edit_preview.is_some() {
+            return None;
+        }
+        Some((self.editor.active_tab_id()?, self.editor.selection()?))
+    }
+
+    // Opens the code action menu, looking the actions up first if needed
+    pub fn show_code_actions(&mut self) {
+        let Some((tab_id, selection)) = self.code_action_target() else {
+            return;
+        };
+        match &mut self.code_actions {
+            Some(actions) if actions.tab_id == tab_id && actions.selection == selection => match &actions.state {
+                Lookup::Answered(found) if found.is_empty() => self.notifications.info("No code actions available"),
+                Lookup::Answered(found) => self.code_action_menu = Some(CodeActionMenu::new(found.clone())),
+                Lookup::Waiting | Lookup::Requested(_) => actions.open_menu = true,
+            },
+            _ => {
+                self.code_actions = Some(CodeActions {
+                    tab_id,
+                    selection,
+                    since: Instant::now(),
+                    state: Lookup::Waiting,
+                    open_menu: true,
+                });
+            }
+        }
+    }
+
+    // Looks up code actions once the cursor rests, for the lightbulb in the gutter
+    fn update_code_actions(&mut self, ctx: &Context) {
+        let Some((tab_id, selection)) = self.code_action_target() else {
+            self.code_actions = None;
+            self.code_action_menu = None;
+            self.editor.set_lightbulb(None);
+            return;
+        };
+        if !self.code_actions.as_ref().is_some_and(|a| a.tab_id == tab_id && a.selection == selection) {
+            self.code_actions = Some(CodeActions {
+                tab_id,
+                selection: selection.clone(),
+                since: Instant::now(),
+                state: Lookup::Waiting,
+                open_menu: false,
+            });
+            self.code_action_menu = None;
+        }
+        let Some(actions) = &mut self.code_actions else {
+            return;
+        };
+
+        if let Lookup::Waiting = actions.state {
+            let remaining = CODE_ACTIONS_DELAY.saturating_sub(actions.since.elapsed());
+            if !actions.open_menu && !remaining.is_zero() {
+                ctx.request_repaint_after(remaining);
+            } else {
+                actions.state = match Self::request_code_actions(&self.editor, &mut self.lsp, &self.diagnostics, &selection) {
+                    Some(id) => Lookup::Requested(id),
+                    None => Lookup::Answered(Vec::new()),
+                };
+            }
+        }
+
+        let available = matches!(&actions.state, Lookup::Answered(found) if !found.is_empty());
+        let line = self.editor.active_content().map(|content| {
+            content[..editor::char_to_byte(content, selection.start)].matches('\n').count()
+        });
+        self.editor.set_lightbulb(line.filter(|_| available));
+    }
+
+    fn request_code_actions(
+        editor: &Editor,
+        lsp: &mut LspManager,
+        diagnostics: &Diagnostics,
+        selection: &Range<usize>,
+    ) -> Option<i64> {
+        let (path, content) = (editor.current_canonical_path()?, editor.active_content()?);
+        let start = editor::char_to_byte(content, selection.start);
+        let end = editor::char_to_byte(content, selection.end);
+        // Diagnostics on the selected lines, for the server to offer fixes for
+        let (first, last) = (content[..start].matches('\n').count(), content[..end].matches('\n').count());
+        let problems: Vec<_> = diagnostics
+            .for_file(path)
+            .into_iter()
+            .filter(|d| d.start.line <= last && d.end.line >= first)
+            .map(|d| lsp::diagnostic_value(content, d))
+            .collect();
+        let params = serde_json::json!({
+            "textDocument": { "uri": lsp::path_to_uri(path) },
+            "range": { "start": lsp::position(content, start), "end": lsp::position(content, end) },
+            "context": { "diagnostics": problems },
+        });
+        lsp.request(path, "textDocument/codeAction", params)
+    }
+
+    // Up, Down, Enter and Escape work the code action menu; runs before the editor
+    fn code_action_keys(&mut self, ctx: &Context) {
+        let Some(menu) = &mut self.code_action_menu else {
+            return;
+        };
+        let action = menu.handle_keys(ctx);
+        self.menu_action(action);
+    }
+
+    fn menu_action(&mut self, action: Option<MenuAction>) {
+        match action {
+            Some(MenuAction::Run(index)) => {
+                if let Some(menu) = self.code_action_menu.take() {
+                    self.run_code_action(menu.into_action(index));
+                }
+            }
+            Some(MenuAction::Close) => self.code_action_menu = None,
+            None => {}
+        }
+    }
+
+    // Previews the action's edit, resolving it first when the server left it
+    // out, or else runs its command
+    fn run_code_action(&mut self, action: lsp::CodeAction) {
+        if let Some(edit) = action.edit() {
+            let edit = edit.clone();
+            self.preview_edit(action.title, &edit);
+            return;
+        }
+        let Some(path) = self.editor.current_canonical_path() else {
+            return;
+        };
+        if action.needs_resolve() {
+            if let Some(id) = self.lsp.request(path, "codeAction/resolve", action.value.clone()) {
+                self.lsp_requests.insert(id, LspRequest::ResolveAction(action.title));
+            }
+        } else if let Some(command) = action.command() {
+            let params = serde_json::json!({ "command": command["command"], "arguments": command["arguments"] });
+            if let Some(id) = self.lsp.request(path, "workspace/executeCommand", params) {
+                self.lsp_requests.insert(id, LspRequest::Command);
+            }
+        }
+    }
+
+    fn references_panel(&mut self, ctx: &Context) {
+        let Some(panel) = &self.references else {
+            return;
+        };
+        match panel.show(ctx, self.editor.get_workspace_folder().map(PathBuf::as_path)) {
+            Some(ReferencesAction::Open(path, position)) => {
+                let result = self.editor.open_file_at(path, position.line, position.column);
+                self.report(result);
+                self.focus_editor(ctx);
+            }
+            Some(ReferencesAction::Close) => self.references = None,
+            None => {}
+        }
+    }
+
+    // Requests hover info once the pointer has rested on a word and shows the answer
+    fn update_hover(&mut self, ctx: &Context) {
+        let target = match (self.editor.active_tab_id(), self.editor.hover_char(), self.editor.active_content()) {
+            (Some(tab_id), Some(index), Some(content))
+                if content.chars().nth(index).is_some_and(completion::is_word_char) =>
+            {
+                Some((tab_id, index, completion::word_start(content, index + 1)))
+            }
+            _ => None,
+        };
+        let Some((tab_id, index, word)) = target else {
+            self.hover = None;
+            return;
+        };
+        if !self.hover.as_ref().is_some_and(|h| h.tab_id == tab_id && h.word == word) {
+            self.hover = Some(Hover { tab_id, word, since: Instant::now(), state: Lookup::Waiting });
+        }
+        let Some(hover) = &mut self.hover else {
+            return;
+        };
+
+        match &hover.state {
+            Lookup::Waiting => {
+                let remaining = HOVER_DELAY.saturating_sub(hover.since.elapsed());
+                if !remaining.is_zero() {
+                    ctx.request_repaint_after(remaining);
+                    return;
+                }
+                let (Some(path), Some(content)) = (self.editor.current_canonical_path(), self.editor.active_content())
+                else {
+                    return;
+                };
+                let id = self
+                    .lsp
+                    .text_document_position(path, editor::char_to_byte(content, index))
+                    .and_then(|params| self.lsp.request(path, "textDocument/hover", params));
+                hover.state = match id {
+                    Some(id) => Lookup::Requested(id),
+                    None => Lookup::Answered(None),
+                };
+            }
+            Lookup::Answered(Some(text)) => {
+                let id = Editor::text_edit_id(tab_id).with("hover");
+                egui::show_tooltip_at_pointer(ctx, id, |ui| {
+                    ui.set_max_width(500.0);
+                    markdown::show(ui, text);
+                });
+            }
+            Lookup::Requested(_) | Lookup::Answered(None) => {}
+        }
+    }
+
+    // What was typed into the active text tab this frame, before the editor handles it
+    fn typed_text(&self, ctx: &Context) -> Option<String> {
+        let id = self.editor.active_text_edit_id()?;
+        if !self.editor.active_is_text() || !ctx.memory(|m| m.has_focus(id)) {
+            return None;
+        }
+        if self.config.vim_mode && self.vim.mode() != vim::Mode::Insert {
+            return None;
+        }
+        ctx.input(|i| {
+            i.events.iter().rev().find_map(|event| match event {
+                egui::Event::Text(text) => Some(text.clone()),
+                _ => None,
+            })
+        })
+    }
+
+    // Starts, filters and closes the completion popup as the text and cursor change
+    fn update_completion(&mut self, typed: Option<String>) {
+        let (Some(tab_id), Some(cursor), Some(content)) =
+            (self.editor.active_tab_id(), self.editor.cursor_char(), self.editor.active_content())
+        else {
+            self.completion = None;
+            return;
+        };
+        if !self.editor.active_is_text() {
+            self.completion = None;
+            return;
+        }
+        if self.snippet.as_ref().is_some_and(|s| s.tab_id() != tab_id) {
+            self.snippet = None;
+        }
+
+        // Closed once the cursor leaves the word being completed
+        let anchor = completion::word_start(content, cursor);
+        if self
+            .completion
+            .as_ref()
+            .is_some_and(|s| s.tab_id() != tab_id || cursor < s.anchor() || anchor != s.anchor())
+        {
+            self.completion = None;
+        }
+
+        if let Some(text) = typed {
+            let before = &content[..editor::char_to_byte(content, cursor)];
+            let triggers = self.editor.current_canonical_path().map(|p| self.lsp.trigger_characters(p));
+            let character = triggers
+                .into_iter()
+                .flatten()
+                .find(|t| text.ends_with(t.as_str()) && before.ends_with(t.as_str()));
+            if let Some(character) = character {
+                self.completion_trigger = Some(Trigger::Character(character));
+            } else if self.completion.is_none() && text.chars().last().is_some_and(completion::is_word_char) {
+                self.completion_trigger = Some(Trigger::Typing);
+            }
+        }
+
+        let query: String = content.chars().skip(anchor).take(cursor - anchor).collect();
+        if let Some(trigger) = self.completion_trigger.take() {
+            let words = completion::buffer_words(content, &query);
+            let mut session = CompletionSession::new(tab_id, anchor, &query, words);
+            let context = match &trigger {
+                Trigger::Character(character) => serde_json::json!({ "triggerKind": 2, "triggerCharacter": character }),
+                Trigger::Invoked | Trigger::Typing => serde_json::json!({ "triggerKind": 1 }),
+            };
+            Self::request_completion(&self.editor, &mut self.lsp, &mut session, cursor, context);
+            self.completion = Some(session);
+        } else if let Some(session) = &mut self.completion {
+            session.set_query(&query);
+            if session.needs_request() {
+                let context = serde_json::json!({ "triggerKind": 3 });
+                Self::request_completion(&self.editor, &mut self.lsp, session, cursor, context);
+            }
+        }
+    }
+
+    fn request_completion(
+        editor: &Editor,
+        lsp: &mut LspManager,
+        session: &mut CompletionSession,
+        cursor: usize,
+        context: serde_json::Value,
+    ) {
+        let (Some(path), Some(content)) = (editor.current_canonical_path(), editor.active_content()) else {
+            return;
+        };
+        let Some(mut params) = lsp.text_document_position(path, editor::char_to_byte(content, cursor)) else {
+            return;
+        };
+        params["context"] = context;
+        if let Some(id) = lsp.request(path, "textDocument/completion", params) {
+            session.requested(id);
+        }
+    }
+
+    // Up, Down, Tab, Enter and Escape work the popup while it is shown, and Tab
+    // moves between snippet tab stops. Runs before the editor sees the keys.
+    fn completion_keys(&mut self, ctx: &Context) {
+        if let Some(session) = &mut self.completion {
+            if session.is_visible() {
+                let accepts_enter = session.accepts_enter();
+                let (up, down, tab, enter, escape) = ctx.input_mut(|i| {
+                    (
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::Tab),
+                        accepts_enter && i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                        i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+                    )
+                });
+                if up {
+                    session.select_previous();
+                }
+                if down {
+                    session.select_next();
+                }
+                if tab || enter {
+                    if let Some(item) = session.selected_item().cloned() {
+                        self.accept_completion(ctx, &item);
+                    }
+                    return;
+                }
+                if escape || ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    self.completion = None;
+                }
+                if escape {
+                    return;
+                }
+            }
+        }
+
+        let Some(snippet) = &mut self.snippet else {
+            return;
+        };
+        let (next, previous, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Tab),
+                i.consume_key(egui::Modifiers::SHIFT, egui::Key::Tab),
+                i.key_pressed(egui::Key::Escape),
+            )
+        });
+        if escape {
+            self.snippet = None;
+        } else if next || previous {
+            let length = self.editor.active_content().map_or(0, |c| c.chars().count());
+            match snippet.step(next, length) {
+                Some(stop) => self.editor.set_active_selection(stop.start, stop.end),
+                None => self.snippet = None,
+            }
+        }
+    }
+
+    // Replaces the typed word with the item and selects the snippet's first tab stop
+    fn accept_completion(&mut self, ctx: &Context, item: &CompletionItem) {
+        let Some(session) = self.completion.take() else {
+            return;
+        };
+        let (Some(tab_id), Some(cursor), Some(content)) =
+            (self.editor.active_tab_id(), self.editor.cursor_char(), self.editor.active_content())
+        else {
+            return;
+        };
+        let anchor = session.anchor();
+        let insertion = Insertion::new(item);
+        let start = editor::char_to_byte(content, anchor);
+        let end = editor::char_to_byte(content, cursor);
+        let text = format!("{}{}{}", &content[..start], insertion.text, &content[end..]);
+        let length = text.chars().count();
+        self.editor.replace_active_content(text);
+
+        let stops: Vec<_> = insertion.tab_stops.iter().map(|r| anchor + r.start..anchor + r.end).collect();
+        match stops.first() {
+            Some(first) => {
+                self.editor.set_active_selection(first.start, first.end);
+                if stops.len() > 1 {
+                    self.snippet = Some(SnippetSession::new(tab_id, stops, length));
+                }
+            }
+            None => {
+                let end = anchor + insertion.text.chars().count();
+                self.editor.set_active_selection(end, end);
+            }
+        }
+        self.focus_editor(ctx);
+    }
+
+    // Adds the plugin's commands to the registry, plus a generic entry that runs the plugin
+    fn register_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        let name = plugin.name().to_string();
+        self.plugin_manager.register_plugin(plugin);
+
+        self.commands.register(Command {
+            id: format!("plugin.{}.run", name),
+            title: format!("Run Plugin: {}", name),
+            keybinding: None,
+            menu: None,
+            handler: CommandHandler::Plugin { plugin: name.clone(), command: None },
+        });
+        for command in self.plugin_manager.commands(&name) {
+            self.commands.register(Command {
+                id: command.id.clone(),
+                title: command.title,
+                keybinding: command.keybinding.map(|k| KeySequence(vec![k])),
+                menu: Some(commands::MenuPlacement { menu: Menu::Plugins, group: 0 }),
+                handler: CommandHandler::Plugin { plugin: name.clone(), command: Some(command.id) },
+            });
+        }
+    }
+
+    fn execute_command(&mut self, id: &str, ctx: &Context) {
+        let Some(handler) = self.commands.get(id).map(|c| c.handler.clone()) else {
+            return;
+        };
+
+        match handler {
+            CommandHandler::Builtin(run) => run(self, ctx),
+            CommandHandler::Plugin { plugin, command } => {
+                let mut context = self.plugin_context();
+                match command {
+                    Some(command) => self.plugin_manager.run_command(&plugin, &command, &mut context),
+                    None => self.plugin_manager.execute_plugin(&plugin, &mut context),
+                }
+                for message in context.messages {
+                    self.notifications.info(message);
+                }
+            }
+        }
+    }
+
+    fn plugin_context(&self) -> PluginContext {
+        PluginContext {
+            selected_text: self.editor.selected_text(),
+            current_file: self.editor.current_file_path().map(|p| p.display().to_string()),
+            cursor_position: self.editor.cursor_line_col(),
+            messages: Vec::new(),
+        }
+    }
+
+    // Shows the error of a failed operation as a toast and in the output log
+    fn report<T>(&mut self, result: Result<T, error::Error>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.notifications.error(e.to_string());
+                None
+            }
+        }
+    }
+
+    fn open_file(&mut self, path: PathBuf) {
+        let result = self.editor.open_file(path);
+        self.report(result);
+    }
+
+    fn open_hex(&mut self, path: PathBuf) {
+        let result = self.editor.open_hex(path);
+        self.report(result);
+    }
+
+    fn save_current(&mut self) {
+        if self.config.format_on_save && self.editor.active_is_text() && self.editor.current_file_path().is_some() {
+            if let Some(id) = self.editor.active_tab_id() {
+                self.format_tab(id, false, true);
+                return;
+            }
+        }
+        let result = self.editor.save_current();
+        self.report(result);
+    }
+
+    fn save_tab(&mut self, id: usize) {
+        let result = self.editor.save_tab(id);
+        self.report(result);
+    }
+
+    fn save_current_as(&mut self) {
+        let result = self.editor.save_current_as();
+        self.report(result);
+    }
+
+    fn save_all(&mut self) {
+        // Formatted tabs save themselves once formatting is done
+        let formatting = if self.config.format_on_save { self.editor.modified_file_tabs() } else { Vec::new() };
+        for &id in &formatting {
+            self.format_tab(id, false, true);
+        }
+        for error in self.editor.save_all(&formatting) {
+            self.notifications.error(error.to_string());
+        }
+    }
+
+    fn revert_current(&mut self) {
+        let result = self.editor.revert_current();
+        self.report(result);
+    }
+
+    fn reopen_closed_tab(&mut self) {
+        let result = self.editor.reopen_closed_tab();
+        self.report(result);
+    }
+
+    fn reveal_current(&mut self) {
+        if let Some(path) = self.editor.current_file_path().cloned() {
+            self.reveal_in_explorer(&path);
+        }
+    }
+
+    // Opens the folders leading to `path` in the file explorer and scrolls to it
+    fn reveal_in_explorer(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let in_workspace = self
+            .editor
+            .get_workspace_folder()
+            .and_then(|folder| fs::canonicalize(folder).ok())
+            .is_some_and(|folder| path.starts_with(folder));
+        if in_workspace {
+            self.show_file_explorer = true;
+            self.reveal = Some((path, Instant::now()));
+        } else {
+            self.notifications.info(format!("{} is not in the open folder", path.display()));
+        }
+    }
+
+    // Applies a settings change and writes it to config.toml. A file that failed to
+    // load is read again first and only the change is written into it, so the
+    // defaults used meanwhile never replace the user's settings.
+    fn update_config(&mut self, change: impl Fn(&mut Config)) {
+        change(&mut self.config);
+        if self.config_failed {
+            match Config::load() {
+                Ok(mut config) => {
+                    change(&mut config);
+                    self.config = config;
+                    self.config_failed = false;
+                }
+                Err(e) => {
+                    self.notifications.warning(format!("{}. The change lasts until Exodus is closed.", e));
+                    return;
+                }
+            }
+        }
+        let result = self.config.save();
+        self.report(result);
+    }
+
+    fn toggle_search(&mut self) {
+        self.show_search = !self.show_search;
+        if self.show_search {
+            self.search_focus_requested = true;
+        } else {
+            self.close_search();
+        }
+    }
+
+    fn close_search(&mut self) {
+        self.show_search = false;
+        self.vim_search = false;
+        self.search_query.clear();
+        self.editor.clear_search_highlights();
+    }
+
+    fn toggle_vim_mode(&mut self) {
+        let vim_mode = !self.config.vim_mode;
+        self.update_config(|config| config.vim_mode = vim_mode);
+        self.vim.reset();
+    }
+
+    fn toggle_format_on_save(&mut self) {
+        let format_on_save = !self.config.format_on_save;
+        self.update_config(|config| config.format_on_save = format_on_save);
+        let state = if format_on_save { "on" } else { "off" };
+        self.notifications.info(format!("Format on save is {}", state));
+    }
+
+    fn handle_vim_input(&mut self, ctx: &Context) {
+        for action in self.vim.handle_input(ctx, &mut self.editor) {
+            match action {
+                VimAction::Undo => self.editor.undo(),
+                VimAction::Redo => self.editor.redo(),
+                VimAction::StartSearch => {
+                    self.show_search = true;
+                    self.vim_search = true;
+                    self.search_focus_requested = true;
+                    self.search_query.clear();
+                }
+                VimAction::SearchNext => self.vim_jump_to_match(true),
+                VimAction::SearchPrevious => self.vim_jump_to_match(false),
+            }
+        }
+    }
+
+    // `n`/`N` and the end of a `/` search put the block cursor on the match
+    fn vim_jump_to_match(&mut self, forward: bool) {
+        let found = if forward {
+            self.editor.find_next_match()
+        } else {
+            self.editor.find_previous_match()
+        };
+        if let (Some((start, _)), Some(content)) = (found, self.editor.active_content()) {
+            let index = editor::byte_to_char(content, start);
+            self.vim.move_cursor(&mut self.editor, index);
+        }
+    }
+
+    fn focus_editor(&self, ctx: &Context) {
+        if let Some(id) = self.editor.active_text_edit_id() {
+            ctx.memory_mut(|m| m.request_focus(id));
+        }
+    }
+
+    fn reload_keybindings(&mut self) {
+        self.keymap = Keymap::load(&self.commands);
+        for error in self.keymap.errors() {
+            self.notifications.log(Level::Warning, format!("keybindings.toml: {}", error));
+        }
+        for conflict in self.keymap.conflicts() {
+            self.notifications.log(Level::Warning, &conflict.message);
+        }
+        if !self.keymap.errors().is_empty() {
+            self.notifications.warning("Some keybindings could not be loaded; see Output for details");
+        }
+    }
+
+    fn open_keybindings_file(&mut self) {
+        match Keymap::ensure_user_file() {
+            Ok(path) => self.open_file(path),
+            Err(e) => self.notifications.error(format!("Could not create keybindings.toml: {}", e)),
+        }
+    }
+
+    fn open_settings_file(&mut self) {
+        if let Some(path) = self.report(Config::config_path()) {
+            if !path.exists() {
+                self.update_config(|_| {});
+            }
+            self.open_file(path);
+        }
+    }
+
+    fn output_panel(&mut self, ctx: &Context) {
+        if self.show_output {
+            TopBottomPanel::bottom("output_panel")
+                .resizable(true)
+                .default_height(160.0)
+                .show(ctx, |ui| {
+                    self.notifications.show_log(ui);
+                });
+        }
+    }
+
+    fn terminal_panel(&mut self, ctx: &Context) {
+        if self.terminal.is_empty() {
+            self.show_terminal = false;
+        }
+        if !self.show_terminal {
+            return;
+        }
+        let root = self.editor.get_workspace_folder().map(PathBuf::from);
+        let action = TopBottomPanel::bottom("terminal_panel")
+            .resizable(true)
+            .default_height(240.0)
+            .show(ctx, |ui| self.terminal.show(ui, root.as_deref()))
+            .inner;
+        match action {
+            Some(TerminalAction::New) => self.new_terminal(ctx),
+            Some(TerminalAction::Hide) => self.toggle_terminal(ctx),
+            Some(TerminalAction::Open(path, line, column)) => {
+                let result = self.editor.open_file_at(path, line, column);
+                if self.report(result).is_some() {
+                    self.terminal.blur();
+                    self.focus_editor(ctx);
+                }
+            }
+            None => {}
+        }
+    }
+
+    // Shows the terminal panel, starting a shell if there is none, or hides it
+    pub fn toggle_terminal(&mut self, ctx: &Context) {
+        if self.show_terminal {
+            self.show_terminal = false;
+            self.terminal.blur();
+            self.focus_editor(ctx);
+        } else if self.terminal.is_empty() {
+            self.new_terminal(ctx);
+        } else {
+            self.show_terminal = true;
+            self.terminal.focus();
+        }
+    }
 
-impl Exodus {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let config = Config::load().unwrap_or_default();
-        
-        let mut style = (*cc.egui_ctx.style()).clone();
-        style.visuals.window_rounding = egui::Rounding::ZERO;
-        style.visuals.menu_rounding = egui::Rounding::ZERO;
-        style.visuals.indent_has_left_vline = false;
-        style.spacing.item_spacing = egui::vec2(4.0, 2.0);
-        cc.egui_ctx.set_style(style);
+    pub fn new_terminal(&mut self, ctx: &Context) {
+        let directory = self.editor.get_workspace_folder().map(PathBuf::from);
+        match self.terminal.new_terminal(ctx, directory.as_deref()) {
+            Ok(()) => self.show_terminal = true,
+            Err(e) => self.notifications.error(e),
+        }
+    }
 
-        Self {
-            editor: Editor::new(),
-            syntax_highlighter: SyntaxHighlighter::new(),
-            config,
-            plugin_manager: PluginManager::new(),
-            show_file_explorer: true,
-            file_explorer_width: 200.0,
-            search_query: String::new(),
-            show_search: false,
+    pub fn kill_terminal(&mut self) {
+        self.terminal.kill_active();
+    }
+
+    // Lists the tasks in tasks.toml to run one
+    pub fn pick_task(&mut self) {
+        let Some(root) = self.editor.get_workspace_folder().cloned() else {
+            self.notifications.info("Open a folder to run its tasks");
+            return;
+        };
+        let Some(tasks) = self.report(tasks::load(&root)) else {
+            return;
+        };
+        if tasks.is_empty() {
+            self.notifications.info("No tasks yet; \"Configure Tasks\" creates a tasks.toml");
+            return;
         }
+        let items = tasks.iter().map(|(name, task)| PickerItem::new(name).detail(task.command_line())).collect();
+        let picker = Picker::new("Run Task", items);
+        self.status_picker = Some((StatusPicker::Task(tasks.into_keys().collect()), picker));
     }
 
-    fn menu_bar(&mut self, ctx: &Context) {
-        TopBottomPanel::top("menu_bar").show(ctx, |ui| {
-            egui::menu::bar(ui, |ui| {
-                ui.menu_button("File", |ui| {
-                    if ui.button("New File").clicked() {
-                        self.editor.new_file();
-                        ui.close_menu();
-                    }
-                    if ui.button("Open File").clicked() {
-                        if let Some(path) = rfd::FileDialog::new().pick_file() {
-                            self.editor.open_file(path);
-                        }
-                        ui.close_menu();
-                    }
-                    if ui.button("Open Folder").clicked(){
-                        if let Some(path) = rfd::FileDialog::new().pick_folder() {
-                            self.editor.open_folder(path);
-                        }
-                        ui.close_menu();
-                    }
-                    if ui.button("Save File").clicked() {
-                        self.editor.save_current();
-                        ui.close_menu();
-                    }
-                    ui.separator();
-                    if ui.button("Quit").clicked() {
-                        std::process::exit(0);
-                    }
+    // Reads tasks.toml again, so edits apply without a restart
+    fn run_task(&mut self, ctx: &Context, name: &str) {
+        let Some(root) = self.editor.get_workspace_folder().cloned() else {
+            return;
+        };
+        let Some(tasks) = self.report(tasks::load(&root)) else {
+            return;
+        };
+        let Some(task) = tasks.get(name) else {
+            self.notifications.error(format!("There is no task '{}' in {}", name, tasks::TASKS_FILE));
+            return;
+        };
+        match self.tasks.run(name, task, &root, &mut self.diagnostics, ctx.clone()) {
+            Ok(()) => self.show_tasks = true,
+            Err(e) => self.notifications.error(format!("Could not run {}: {}", task.command, e)),
+        }
+    }
+
+    pub fn rerun_task(&mut self, ctx: &Context) {
+        match self.tasks.last_run().map(str::to_string) {
+            Some(name) => self.run_task(ctx, &name),
+            None => self.pick_task(),
+        }
+    }
+
+    pub fn kill_task(&mut self) {
+        self.tasks.kill_selected();
+    }
+
+    fn configure_tasks(&mut self) {
+        let Some(root) = self.editor.get_workspace_folder().cloned() else {
+            self.notifications.info("Open a folder to configure its tasks");
+            return;
+        };
+        if let Some(path) = self.report(tasks::ensure_file(&root)) {
+            self.open_file(path);
+        }
+    }
+
+    fn tasks_panel(&mut self, ctx: &Context) {
+        if !self.show_tasks {
+            return;
+        }
+        let action = TopBottomPanel::bottom("tasks_panel")
+            .resizable(true)
+            .default_height(200.0)
+            .show(ctx, |ui| self.tasks.show(ui))
+            .inner;
+        match action {
+            Some(TasksAction::Open(path, position)) => {
+                let result = self.editor.open_file_at(path, position.line, position.column);
+                if self.report(result).is_some() {
+                    self.focus_editor(ctx);
+                }
+            }
+            Some(TasksAction::Rerun(name)) => self.run_task(ctx, &name),
+            Some(TasksAction::Hide) => self.show_tasks = false,
+            None => {}
+        }
+    }
+
+    // Errors and warnings across the workspace; clicking one opens it
+    fn problems_panel(&mut self, ctx: &Context) {
+        if !self.show_problems {
+            return;
+        }
+        let mut clicked = None;
+        TopBottomPanel::bottom("problems_panel")
+            .resizable(true)
+            .default_height(160.0)
+            .show(ctx, |ui| {
+                let (errors, warnings) = self.diagnostics.counts();
+                ui.horizontal(|ui| {
+                    ui.strong("Problems");
+                    ui.weak(format!("{} errors, {} warnings", errors, warnings));
                 });
+                ui.separator();
+                if errors + warnings == 0 {
+                    ui.weak("No problems have been detected in the workspace.");
+                    return;
+                }
 
-                ui.menu_button("Edit", |ui| {
-                    if ui.button("Undo").clicked() {
-                        self.editor.undo();
-                        ui.close_menu();
-                    }
-                    if ui.button("Redo").clicked() {
-                        self.editor.redo();
-                        ui.close_menu();
-                    }
-                    ui.separator();
-                    if ui.button("Find").clicked() {
-                        self.show_search = !self.show_search;
-                        ui.close_menu();
+                let root = self.editor.get_workspace_folder();
+                egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                    for (path, diagnostics) in self.diagnostics.files() {
+                        let problems: Vec<_> =
+                            diagnostics.into_iter().filter(|d| d.severity <= Severity::Warning).collect();
+                        if problems.is_empty() {
+                            continue;
+                        }
+                        let name = root
+                            .and_then(|root| path.strip_prefix(root).ok())
+                            .unwrap_or(path)
+                            .display()
+                            .to_string();
+                        egui::CollapsingHeader::new(format!("{} ({})", name, problems.len()))
+                            .id_source(path)
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                for problem in problems {
+                                    let mut text = egui::text::LayoutJob::default();
+                                    let font = egui::TextStyle::Body.resolve(ui.style());
+                                    let format = |color| egui::TextFormat::simple(font.clone(), color);
+                                    let normal = ui.visuals().text_color();
+                                    let weak = ui.visuals().weak_text_color();
+                                    text.append(problem.severity.icon(), 0.0, format(problem.severity.color()));
+                                    text.append(&problem.message, 6.0, format(normal));
+                                    if let Some(source) = &problem.source {
+                                        text.append(&format!("[{}]", source), 6.0, format(weak));
+                                    }
+                                    let position = format!("Ln {}, Col {}", problem.start.line + 1, problem.start.column + 1);
+                                    text.append(&position, 6.0, format(weak));
+                                    if ui.selectable_label(false, text).clicked() {
+                                        clicked = Some((path.to_path_buf(), problem.start));
+                                    }
+                                }
+                            });
                     }
                 });
+            });
+
+        if let Some((path, position)) = clicked {
+            let result = self.editor.open_file(path);
+            if self.report(result).is_some() {
+                self.editor.go_to_line(position.line, position.column);
+            }
+        }
+    }
+
+    // Moves the cursor to the next or previous diagnostic in the file, wrapping around
+    pub fn go_to_problem(&mut self, forward: bool) {
+        let Some(path) = self.editor.current_canonical_path() else {
+            return;
+        };
+        let (line, column) = self.editor.cursor_line_col();
+        let here = diagnostics::Position { line, column };
+        let starts: Vec<_> = self.diagnostics.for_file(path).iter().map(|d| d.start).collect();
+        let target = if forward {
+            starts.iter().find(|&&start| start > here).or(starts.first())
+        } else {
+            starts.iter().rev().find(|&&start| start < here).or(starts.last())
+        };
+        match target.copied() {
+            Some(start) => self.editor.go_to_line(start.line, start.column),
+            None => self.notifications.info("No problems in this file"),
+        }
+    }
+
+    fn menu_bar(&mut self, ctx: &Context) {
+        let mut clicked = None;
 
-                ui.menu_button("View", |ui| {
-                    if ui.button("Toggle File Explorer").clicked() {
-                        self.show_file_explorer = !self.show_file_explorer;
-                        ui.close_menu();
+        TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            egui::menu::bar(ui, |ui| {
+                for menu in Menu::ALL {
+                    let commands = self.commands.in_menu(menu);
+                    if commands.is_empty() {
+                        continue;
                     }
-                });
+
+                    ui.menu_button(menu.title(), |ui| {
+                        let mut last_group = None;
+                        for command in commands {
+                            let group = command.menu.map(|m| m.group);
+                            if last_group.is_some() && last_group != group {
+                                ui.separator();
+                            }
+                            last_group = group;
+
+                            let mut button = egui::Button::new(&command.title);
+                            if let Some(binding) = self.keymap.bindings_for(&command.id).next() {
+                                button = button.shortcut_text(binding.sequence.format(ctx));
+                            }
+                            if ui.add(button).clicked() {
+                                clicked = Some(command.id.clone());
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                }
+
+                if let Some(pending) = self.keymap.pending() {
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.weak(format!("({}) was pressed. Waiting for second key of chord...", pending.format(ctx)));
+                    });
+                }
             });
         });
+
+        if let Some(id) = clicked {
+            self.execute_command(&id, ctx);
+        }
     }
 
     fn search_bar(&mut self, ctx: &Context) {
         if self.show_search {
             TopBottomPanel::top("search_bar").show(ctx, |ui| {
                 ui.horizontal(|ui| {
-                    ui.label("Find:");
+                    ui.label(if self.vim_search { "/" } else { "Find:" });
                     let response = ui.text_edit_singleline(&mut self.search_query);
+                    if self.search_focus_requested {
+                        response.request_focus();
+                        self.search_focus_requested = false;
+                    }
                     if response.changed() {
                         self.editor.highlight_search(&self.search_query);
                     }
@@ -137,13 +1736,247 @@ impl Exodus {
                     }
                     
                     if ui.button("×").clicked() {
-                        self.show_search = false;
-                        self.search_query.clear();
-                        self.editor.clear_search_highlights();
+                        self.close_search();
+                    }
+                });
+            });
+        }
+    }
+
+    // Folder used for git: the workspace, or else the directory of the current file
+    fn git_root(&self) -> Option<PathBuf> {
+        self.editor
+            .get_workspace_folder()
+            .cloned()
+            .or_else(|| self.editor.current_file_path().and_then(|p| p.parent().map(PathBuf::from)))
+    }
+
+    pub fn stage_change(&mut self) {
+        match self.editor.stage_change() {
+            Some(Ok(())) => self.git_status.refresh(),
+            Some(Err(e)) => self.notifications.error(format!("Could not stage change: {}", e)),
+            None => self.notifications.info("No change at the cursor"),
+        }
+    }
+
+    fn refresh_git_branch(&mut self) {
+        let stale = self
+            .git_branch_checked
+            .is_none_or(|checked| checked.elapsed() > Duration::from_secs(2));
+        if stale {
+            self.git_branch = self.git_root().and_then(|root| git::current_branch(&root));
+            self.git_branch_checked = Some(Instant::now());
+        }
+    }
+
+    fn status_bar(&mut self, ctx: &Context) {
+        self.refresh_git_branch();
+        self.editor.detect_language(&self.syntax_highlighter);
+        let status = self.editor.status();
+        let mut clicked = None;
+
+        TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if self.config.vim_mode {
+                    ui.monospace(format!("-- {} --", self.vim.mode().label()));
+                    let pending = self.vim.pending_keys();
+                    if !pending.is_empty() {
+                        ui.weak(pending);
+                    }
+                }
+
+                let (errors, warnings) = self.diagnostics.counts();
+                let problems = format!("{} {}  {} {}", Severity::Error.icon(), errors, Severity::Warning.icon(), warnings);
+                if ui.add(egui::Button::new(problems).frame(false)).on_hover_text("Problems").clicked() {
+                    self.show_problems = !self.show_problems;
+                }
+
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let mut item = |ui: &mut egui::Ui, text: String, hover: &str, picker: fn() -> StatusItem| {
+                        if ui.add(egui::Button::new(text).frame(false)).on_hover_text(hover).clicked() {
+                            clicked = Some(picker());
+                        }
+                    };
+
+                    if let Some(branch) = &self.git_branch {
+                        item(ui, format!("⎇ {}", branch), "Switch Branch", || StatusItem::Branch);
+                    }
+                    if let Some(status) = &status {
+                        item(ui, status.language.clone(), "Select Language Mode", || StatusItem::Language);
+                        item(ui, status.line_ending.label().to_string(), "Select End of Line Sequence", || StatusItem::LineEnding);
+                        item(ui, status.encoding.label().to_string(), "Select Encoding", || StatusItem::Encoding);
+                        item(ui, status.indentation.label(), "Select Indentation", || StatusItem::Indentation);
+                        // Vim's normal mode cursor is a one-char selection; don't count it
+                        let normal_mode = self.config.vim_mode && self.vim.mode() == vim::Mode::Normal;
+                        let position = if status.selected > 0 && !normal_mode {
+                            format!("Ln {}, Col {} ({} selected)", status.line + 1, status.column + 1, status.selected)
+                        } else {
+                            format!("Ln {}, Col {}", status.line + 1, status.column + 1)
+                        };
+                        item(ui, position, "Go to Line", || StatusItem::GoToLine);
                     }
                 });
             });
+        });
+
+        if let Some(item) = clicked {
+            self.open_status_picker(item);
+        }
+    }
+
+    pub fn open_symbol_picker(&mut self) {
+        if !self.editor.active_is_text() {
+            return;
+        }
+        let symbols = self.editor.active_symbols(&self.syntax_highlighter);
+        let items = symbols
+            .iter()
+            .map(|symbol| PickerItem::new(&symbol.name).detail(format!("{} · line {}", symbol.kind, symbol.line + 1)))
+            .collect();
+        let positions = symbols.iter().map(|symbol| (symbol.line, symbol.column)).collect();
+        let picker = Picker::new("Go to Symbol in File", items);
+        let picker = if symbols.is_empty() { picker.hint("No symbols found") } else { picker };
+        self.status_picker = Some((StatusPicker::GoToSymbol(positions), picker));
+    }
+
+    pub fn open_status_picker(&mut self, item: StatusItem) {
+        let Some(status) = self.editor.status() else {
+            return;
+        };
+
+        self.status_picker = match item {
+            StatusItem::GoToLine => {
+                let lines = self.editor.active_content().map_or(1, |c| c.split('\n').count());
+                let hint = format!("Type a line number between 1 and {}, optionally followed by :column", lines);
+                Some((StatusPicker::GoToLine, Picker::prompt("Go to Line", &hint)))
+            }
+            StatusItem::Language => {
+                let languages = self.syntax_highlighter.language_names();
+                let current = languages.iter().position(|l| *l == status.language).unwrap_or(0);
+                let items = languages.iter().map(PickerItem::new).collect();
+                let picker = Picker::new("Select Language Mode", items).select(current);
+                Some((StatusPicker::Language(languages), picker))
+            }
+            StatusItem::Encoding => {
+                let items = vec![PickerItem::new("Reopen with Encoding"), PickerItem::new("Save with Encoding")];
+                let picker = Picker::new("Select Action", items).hint(status.encoding.label());
+                Some((StatusPicker::EncodingAction, picker))
+            }
+            StatusItem::LineEnding => {
+                let items = LineEnding::ALL.iter().map(|e| PickerItem::new(e.label())).collect();
+                let current = LineEnding::ALL.iter().position(|e| *e == status.line_ending).unwrap_or(0);
+                let picker = Picker::new("Select End of Line Sequence", items).select(current);
+                Some((StatusPicker::LineEnding, picker))
+            }
+            StatusItem::Indentation => {
+                let size = status.indentation.size;
+                let mut choices = Vec::new();
+                let mut items = Vec::new();
+                for spaces in [2, 4, 8] {
+                    choices.push(IndentationChoice::Use(Indentation { use_tabs: false, size: spaces }));
+                    items.push(PickerItem::new(format!("Indent Using Spaces: {}", spaces)));
+                }
+                choices.push(IndentationChoice::Use(Indentation { use_tabs: true, size }));
+                items.push(PickerItem::new("Indent Using Tabs"));
+                choices.push(IndentationChoice::Convert(Indentation { use_tabs: false, size }));
+                items.push(PickerItem::new("Convert Indentation to Spaces"));
+                choices.push(IndentationChoice::Convert(Indentation { use_tabs: true, size }));
+                items.push(PickerItem::new("Convert Indentation to Tabs"));
+                Some((StatusPicker::Indentation(choices), Picker::new("Select Indentation", items)))
+            }
+            StatusItem::Branch => {
+                let root = self.git_root();
+                let branches = root.as_deref().map(git::branches).transpose().unwrap_or_else(|e| {
+                    self.notifications.error(format!("Could not list branches: {}", e));
+                    None
+                });
+                let branches = branches.unwrap_or_default();
+                let items = branches
+                    .iter()
+                    .map(|b| {
+                        let item = PickerItem::new(b);
+                        if Some(b) == self.git_branch.as_ref() { item.detail("current") } else { item }
+                    })
+                    .collect();
+                Some((StatusPicker::Branch(branches), Picker::new("Switch Branch", items)))
+            }
+        };
+    }
+
+    fn show_status_picker(&mut self, ctx: &Context) {
+        let Some((_, picker)) = &mut self.status_picker else {
+            return;
+        };
+        let Some(result) = picker.show(ctx) else {
+            return;
+        };
+        let Some((kind, _)) = self.status_picker.take() else {
+            return;
+        };
+
+        match (kind, result) {
+            (StatusPicker::EncodingAction, PickerResult::Picked(index)) => {
+                let reopen = index == 0;
+                let title = if reopen { "Reopen with Encoding" } else { "Save with Encoding" };
+                let current = self.editor.status().map(|s| s.encoding);
+                let items = Encoding::ALL
+                    .iter()
+                    .map(|e| {
+                        let item = PickerItem::new(e.label());
+                        if Some(*e) == current { item.detail("current") } else { item }
+                    })
+                    .collect();
+                let selected = Encoding::ALL.iter().position(|e| Some(*e) == current).unwrap_or(0);
+                let picker = Picker::new(title, items).select(selected);
+                self.status_picker = Some((StatusPicker::Encoding { reopen }, picker));
+                return;
+            }
+            (StatusPicker::Encoding { reopen }, PickerResult::Picked(index)) => {
+                let encoding = Encoding::ALL[index];
+                if !reopen {
+                    self.editor.set_encoding(encoding);
+                } else if let Err(e) = self.editor.reopen_with_encoding(encoding) {
+                    self.notifications.error(e.to_string());
+                }
+            }
+            (StatusPicker::GoToLine, PickerResult::Submitted(text)) => {
+                let mut parts = text.trim().splitn(2, ':');
+                let line = parts.next().and_then(|l| l.trim().parse::<usize>().ok());
+                let column = parts.next().and_then(|c| c.trim().parse::<usize>().ok()).unwrap_or(1);
+                if let Some(line) = line {
+                    self.editor.go_to_line(line.saturating_sub(1), column.saturating_sub(1));
+                }
+            }
+            (StatusPicker::GoToSymbol(positions), PickerResult::Picked(index)) => {
+                let (line, column) = positions[index];
+                self.editor.go_to_line(line, column);
+            }
+            (StatusPicker::Language(languages), PickerResult::Picked(index)) => {
+                if let Some(language) = languages.into_iter().nth(index) {
+                    self.editor.set_language(language);
+                }
+            }
+            (StatusPicker::LineEnding, PickerResult::Picked(index)) => {
+                self.editor.set_line_ending(LineEnding::ALL[index]);
+            }
+            (StatusPicker::Indentation(choices), PickerResult::Picked(index)) => match choices[index] {
+                IndentationChoice::Use(indentation) => self.editor.set_indentation(indentation),
+                IndentationChoice::Convert(indentation) => self.editor.convert_indentation(indentation),
+            },
+            (StatusPicker::Task(names), PickerResult::Picked(index)) => self.run_task(ctx, &names[index]),
+            (StatusPicker::Branch(branches), PickerResult::Picked(index)) => {
+                if let Some(root) = self.git_root() {
+                    match git::checkout(&root, &branches[index]) {
+                        Ok(()) => self.notifications.info(format!("Switched to branch '{}'", branches[index])),
+                        Err(e) => self.notifications.error(format!("Could not switch to {}: {}", branches[index], e)),
+                    }
+                }
+                self.git_branch_checked = None;
+                self.git_status.refresh();
+            }
+            _ => {}
         }
+        self.focus_editor(ctx);
     }
 
     fn file_explorer(&mut self, ctx: &Context) {
@@ -163,10 +1996,25 @@ impl Exodus {
                             
                             if let Some(workspace_folder) = self.editor.get_workspace_folder().cloned() {
                                 if let Some(folder_name) = workspace_folder.file_name().and_then(|n| n.to_str()) {
-                                    ui.label(format!("📁 {}", folder_name));
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!("📁 {}", folder_name));
+                                        if let Some(branch) = &self.git_branch {
+                                            ui.weak(format!("⎇ {}", branch));
+                                        }
+                                    });
                                     ui.separator();
                                 }
                                 self.show_directory_tree(ui, &workspace_folder, 0);
+                                // Folders open over a few frames; give up on files the tree doesn't show
+                                if let Some((path, since)) = &self.reveal {
+                                    if since.elapsed() > Duration::from_secs(1) {
+                                        let message = format!("{} is hidden in the file explorer", path.display());
+                                        self.notifications.info(message);
+                                        self.reveal = None;
+                                    } else {
+                                        ui.ctx().request_repaint();
+                                    }
+                                }
                             } else {
                                 ui.label("No folder opened");
                                 ui.separator();
@@ -181,131 +2029,320 @@ impl Exodus {
         }
     }
 
+    fn keybindings_window(&mut self, ctx: &Context) {
+        let mut open = self.show_keybindings;
+        let mut open_file = false;
+
+        egui::Window::new("Keyboard Shortcuts")
+            .open(&mut open)
+            .default_size([560.0, 420.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Filter:");
+                    ui.text_edit_singleline(&mut self.keybindings_filter);
+                    if ui.button("Edit keybindings.toml").clicked() {
+                        open_file = true;
+                    }
+                });
+
+                for error in self.keymap.errors() {
+                    ui.colored_label(egui::Color32::from_rgb(255, 100, 100), format!("⚠ {}", error));
+                }
+                for conflict in self.keymap.conflicts() {
+                    ui.colored_label(egui::Color32::from_rgb(255, 200, 0), format!("⚠ {}", conflict.message));
+                }
+                ui.separator();
+
+                let filter = self.keybindings_filter.to_lowercase();
+                egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                    egui::Grid::new("keybindings_grid").striped(true).num_columns(4).show(ui, |ui| {
+                        ui.strong("Command");
+                        ui.strong("Keybinding");
+                        ui.strong("Source");
+                        ui.strong("Id");
+                        ui.end_row();
+
+                        for command in self.commands.iter() {
+                            let bindings: Vec<_> = self.keymap.bindings_for(&command.id).collect();
+                            let keys = bindings
+                                .iter()
+                                .map(|b| b.sequence.format(ctx))
+                                .collect::<Vec<_>>()
+                                .join(", ");
+                            if !filter.is_empty()
+                                && !command.title.to_lowercase().contains(&filter)
+                                && !command.id.contains(&filter)
+                                && !keys.to_lowercase().contains(&filter)
+                            {
+                                continue;
+                            }
+
+                            ui.label(&command.title);
+                            if bindings.iter().any(|b| self.keymap.is_conflicting(&b.sequence)) {
+                                ui.colored_label(egui::Color32::from_rgb(255, 200, 0), format!("{} ⚠", keys));
+                            } else {
+                                ui.monospace(keys);
+                            }
+                            let source = if bindings.iter().any(|b| b.source == BindingSource::User) {
+                                "User"
+                            } else if bindings.is_empty() {
+                                ""
+                            } else {
+                                "Default"
+                            };
+                            ui.weak(source);
+                            ui.weak(&command.id);
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+
+        self.show_keybindings = open;
+        if open_file {
+            self.open_keybindings_file();
+        }
+    }
+
+    // Whether `entry` is the file being revealed or one of its folders
+    fn is_revealing(&self, entry: &Path) -> bool {
+        self.reveal
+            .as_ref()
+            .is_some_and(|(path, _)| fs::canonicalize(entry).is_ok_and(|entry| path.starts_with(entry)))
+    }
+
     fn show_directory_tree(&mut self, ui: &mut egui::Ui, path: &PathBuf, depth: usize) {
         if depth > 5 { return; }
         
-        if let Ok(entries) = fs::read_dir(path) {
-            let mut entries: Vec<_> = entries.flatten().collect();
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                let error = error::Error::io("read", path, e);
+                ui.colored_label(egui::Color32::from_rgb(255, 100, 100), "⚠ Could not read folder")
+                    .on_hover_text(error.to_string());
+                return;
+            }
+        };
+        let mut entries: Vec<_> = entries.flatten().collect();
+        
+        entries.sort_by(|a, b| {
+            let a_is_dir = a.path().is_dir();
+            let b_is_dir = b.path().is_dir();
             
-            entries.sort_by(|a, b| {
-                let a_is_dir = a.path().is_dir();
-                let b_is_dir = b.path().is_dir();
-                
-                match (a_is_dir, b_is_dir) {
-                    (true, false) => std::cmp::Ordering::Less,
-                    (false, true) => std::cmp::Ordering::Greater,
-                    _ => a.file_name().cmp(&b.file_name()),
-                }
-            });
+            match (a_is_dir, b_is_dir) {
+                (true, false) => std::cmp::Ordering::Less,
+                (false, true) => std::cmp::Ordering::Greater,
+                _ => a.file_name().cmp(&b.file_name()),
+            }
+        });
+        
+        for entry in entries {
+            let entry_path = entry.path();
+            let name = entry_path.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("?");
             
-            for entry in entries {
-                let entry_path = entry.path();
-                let name = entry_path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("?");
-                
-                if name.starts_with('.') || 
-                   name == "target" || 
-                   name == "node_modules" || 
-                   name == "__pycache__" ||
-                   name == ".git" {
-                    continue;
-                }
+            if name.starts_with('.') || 
+               name == "target" || 
+               name == "node_modules" || 
+               name == "__pycache__" ||
+               name == ".git" {
+                continue;
+            }
+            
+            let git_status = self.git_status.status().get(&entry_path);
+            let decorate = |text: String| match git_status {
+                Some(status) => egui::RichText::new(text).color(status.color()),
+                None => egui::RichText::new(text),
+            };
+
+            ui.horizontal(|ui| {
+                ui.add_space(depth as f32 * 16.0);
                 
-                ui.horizontal(|ui| {
-                    ui.add_space(depth as f32 * 16.0);
-                    
-                    if entry_path.is_dir() {
-                        let dir_path = entry_path.clone();
-                        let response = ui.collapsing(format!("📁 {}", name), |ui| {
+                if entry_path.is_dir() {
+                    let dir_path = entry_path.clone();
+                    let response = egui::CollapsingHeader::new(decorate(format!("📁 {}", name)))
+                        .open(self.is_revealing(&entry_path).then_some(true))
+                        .show(ui, |ui| {
                             self.show_directory_tree(ui, &dir_path, depth + 1);
                         });
-                        
-                        if response.header_response.double_clicked() {
-                            // Double-click to expand/collapse
-                        }
-                    } else {
-                        let icon = match entry_path.extension().and_then(|e| e.to_str()) {
-                            Some("rs") => "🦀",
-                            Some("py") => "🐍",
-                            Some("js") | Some("ts") => "📜",
-                            Some("html") => "🌐",
-                            Some("css") => "🎨",
-                            Some("json") => "📋",
-                            Some("md") => "📝",
-                            Some("toml") | Some("yaml") | Some("yml") => "⚙️",
-                            Some("txt") => "📄",
-                            _ => "📄",
-                        };
-                        
-                        let file_path = entry_path.clone();
-                        let button_text = format!("{} {}", icon, name);
-                        if ui.add(egui::Button::new(button_text).wrap(false)).clicked() {
-                            self.editor.open_file(file_path);
-                        }
+                    if let Some(status) = git_status {
+                        response.header_response.clone().on_hover_text(status.label());
                     }
-                });
-            }
+                    
+                    if response.header_response.double_clicked() {
+                        // Double-click to expand/collapse
+                    }
+                } else {
+                    let icon = match entry_path.extension().and_then(|e| e.to_str()) {
+                        Some("rs") => "🦀",
+                        Some("py") => "🐍",
+                        Some("js") | Some("ts") => "📜",
+                        Some("html") => "🌐",
+                        Some("css") => "🎨",
+                        Some("json") => "📋",
+                        Some("md") => "📝",
+                        Some("toml") | Some("yaml") | Some("yml") => "⚙️",
+                        Some("txt") => "📄",
+                        _ => "📄",
+                    };
+                    
+                    let file_path = entry_path.clone();
+                    let button_text = decorate(format!("{} {}", icon, name));
+                    let selected = self.explorer_selection.as_ref() == Some(&entry_path);
+                    let mut response = ui.add(egui::Button::new(button_text).wrap(false).selected(selected));
+                    if self.is_revealing(&entry_path) {
+                        response.scroll_to_me(Some(egui::Align::Center));
+                        self.explorer_selection = Some(entry_path.clone());
+                        self.reveal = None;
+                    }
+                    if let Some(status) = git_status {
+                        ui.colored_label(status.color(), status.badge());
+                        response = response.on_hover_text(status.label());
+                    }
+                    if response.clicked() {
+                        self.explorer_selection = Some(file_path.clone());
+                        self.open_file(file_path);
+                    }
+                }
+            });
         }
     }
 }
 
 impl App for Exodus {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
-        // Handle keyboard shortcuts
-        ctx.input(|i| {
-            if i.modifiers.ctrl {
-                if i.key_pressed(egui::Key::N) {
-                    self.editor.new_file();
-                } else if i.key_pressed(egui::Key::O) {
-                    if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        self.editor.open_file(path);
-                    }
-                } else if i.key_pressed(egui::Key::K){
-                    if let Some(path) = rfd::FileDialog::new().pick_folder() {
-                        self.editor.open_folder(path);
-                    }
-                } else if i.key_pressed(egui::Key::S) {
-                    self.editor.save_current();
-                } else if i.key_pressed(egui::Key::Z) {
-                    self.editor.undo();
-                } else if i.key_pressed(egui::Key::Y) {
-                    self.editor.redo();
-                } else if i.key_pressed(egui::Key::F) {
-                    self.show_search = !self.show_search;
-                    if !self.show_search {
-                        self.search_query.clear();
-                        self.editor.clear_search_highlights();
-                    }
-                } else if i.key_pressed(egui::Key::B) {
-                    self.show_file_explorer = !self.show_file_explorer;
-                } else if i.key_pressed(egui::Key::G) {
-                    // Find next
-                    if !self.search_query.is_empty() {
-                        self.editor.find_next(&self.search_query);
-                    }
-                }
+        self.poll_instance_requests(ctx);
+        if !self.wait_for.is_empty() && !self.wait_for.iter().any(|&id| self.editor.is_tab_open(id)) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+
+        for (name, outcome, succeeded) in self.tasks.poll(&mut self.diagnostics) {
+            let message = format!("Task '{}' {}", name, outcome);
+            if succeeded {
+                self.notifications.info(message);
+            } else {
+                self.notifications.warning(message);
             }
-            
+        }
+
+        // The focused terminal takes keys first, except the shortcut that hides it
+        self.terminal.poll();
+        if self.show_terminal {
+            let keymap = &self.keymap;
+            self.terminal.handle_keys(ctx, |modifiers, key| keymap.is_bound("terminal.toggle", modifiers, key));
+        }
+
+        // Handle keyboard shortcuts
+        for id in self.keymap.dispatch(ctx) {
+            self.execute_command(&id, ctx);
+        }
+
+        // Only one picker is shown at a time
+        if self.command_palette.is_open() {
+            self.status_picker = None;
+        }
+        let picker_open = self.command_palette.is_open() || self.status_picker.is_some();
+        if !picker_open {
+            self.completion_keys(ctx);
+            self.code_action_keys(ctx);
+        }
+
+        let (escape, enter) = ctx.input(|i| (i.key_pressed(egui::Key::Escape), i.key_pressed(egui::Key::Enter)));
+        if !picker_open {
             // Escape to close search
-            if i.key_pressed(egui::Key::Escape) && self.show_search {
-                self.show_search = false;
-                self.search_query.clear();
-                self.editor.clear_search_highlights();
+            if escape && self.show_search {
+                if self.vim_search {
+                    self.focus_editor(ctx);
+                }
+                self.close_search();
             }
-            
+
             // Enter to find next in search
-            if i.key_pressed(egui::Key::Enter) && self.show_search && !self.search_query.is_empty() {
-                self.editor.find_next(&self.search_query);
+            if enter && self.show_search && !self.search_query.is_empty() {
+                if self.vim_search {
+                    // Like Vim, the bar closes but the matches stay highlighted for `n`/`N`
+                    self.vim_search = false;
+                    self.show_search = false;
+                    self.focus_editor(ctx);
+                    self.vim_jump_to_match(true);
+                } else {
+                    self.editor.find_next(&self.search_query);
+                }
             }
-        });
+        }
+
+        if self.config.vim_mode && !picker_open && self.editor.active_is_text() {
+            self.handle_vim_input(ctx);
+        }
 
         self.menu_bar(ctx);
         self.search_bar(ctx);
+        let git_root = self.git_root();
+        self.git_status.poll(ctx, git_root.as_deref());
         self.file_explorer(ctx);
+        self.status_bar(ctx);
+        self.output_panel(ctx);
+        self.problems_panel(ctx);
+        self.references_panel(ctx);
+        self.tasks_panel(ctx);
+        self.terminal_panel(ctx);
 
-        CentralPanel::default().show(ctx, |ui| {
-            self.editor.show(ui, &mut self.syntax_highlighter);
-        });
+        self.keybindings_window(ctx);
+        self.edit_preview(ctx);
+
+        if let Some(id) = self.command_palette.show(ctx) {
+            self.execute_command(&id, ctx);
+        }
+        self.show_status_picker(ctx);
+        if self.notifications.show_toasts(ctx) {
+            self.show_output = true;
+        }
+
+        let typed = self.typed_text(ctx);
+        let result = CentralPanel::default()
+            .show(ctx, |ui| self.editor.show(ui, &mut self.syntax_highlighter, &self.diagnostics, self.git_status.status()))
+            .inner;
+        self.report(result);
+        self.sync_language_servers();
+        self.expire_format_requests(ctx);
+        self.poll_external_formats(ctx);
+        if typed.is_some() {
+            self.hover = None;
+            self.code_actions = None;
+        }
+        self.update_completion(typed);
+        if self.editor.take_definition_click() {
+            self.go_to_definition();
+        }
+        self.update_hover(ctx);
+        self.update_code_actions(ctx);
+        if self.editor.take_lightbulb_click() {
+            self.show_code_actions();
+        }
+        for warning in self.editor.take_warnings() {
+            self.notifications.warning(warning);
+        }
+        if self.editor.take_stage_click() {
+            self.stage_change();
+        }
+        if let Some(path) = self.editor.take_reveal_click() {
+            self.reveal_in_explorer(&path);
+        }
+        self.rename_input(ctx);
+
+        let clicked = match (&mut self.completion, self.editor.cursor_rect()) {
+            (Some(session), Some(cursor)) => session.show(ctx, cursor.left_bottom()),
+            _ => None,
+        };
+        if let Some(item) = clicked {
+            self.accept_completion(ctx, &item);
+        }
+
+        let action = match (&mut self.code_action_menu, self.editor.cursor_rect()) {
+            (Some(menu), Some(cursor)) => menu.show(ctx, cursor.left_bottom()),
+            _ => None,
+        };
+        self.menu_action(action);
     }
 }
\ No newline at end of file
@@ -0,0 +1,91 @@
+use egui::{Frame, RichText, Ui};
+
+// Enough markdown for documentation from language servers: headings, fenced
+// code, rules, lists and inline code. Bold markers are dropped and links show
+// their text.
+pub fn show(ui: &mut Ui, text: &str) {
+    let mut paragraph = String::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            flush(ui, &mut paragraph);
+            let code: Vec<&str> = lines.by_ref().take_while(|l| !l.trim_start().starts_with("```")).collect();
+            Frame::none()
+                .fill(ui.visuals().extreme_bg_color)
+                .inner_margin(4.0)
+                .show(ui, |ui| ui.label(RichText::new(code.join("\n")).monospace()));
+        } else if trimmed.starts_with('#') {
+            flush(ui, &mut paragraph);
+            ui.label(RichText::new(plain(trimmed.trim_start_matches('#').trim())).strong());
+        } else if ["---", "***", "___"].contains(&trimmed) {
+            flush(ui, &mut paragraph);
+            ui.separator();
+        } else if trimmed.is_empty() {
+            flush(ui, &mut paragraph);
+        } else if let Some(item) = list_item(trimmed) {
+            flush(ui, &mut paragraph);
+            inline(ui, &format!("• {}", item));
+        } else {
+            if !paragraph.is_empty() {
+                paragraph.push(' ');
+            }
+            paragraph.push_str(trimmed);
+        }
+    }
+    flush(ui, &mut paragraph);
+}
+
+fn flush(ui: &mut Ui, paragraph: &mut String) {
+    if !paragraph.is_empty() {
+        inline(ui, paragraph);
+        paragraph.clear();
+    }
+}
+
+fn list_item(line: &str) -> Option<&str> {
+    if let Some(item) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+        return Some(item);
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    line[digits..].strip_prefix(". ").filter(|_| digits > 0)
+}
+
+// Text with `inline code` spans
+fn inline(ui: &mut Ui, text: &str) {
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        for (i, part) in text.split('`').enumerate() {
+            if i % 2 == 1 {
+                ui.code(part);
+            } else if !part.is_empty() {
+                ui.label(plain(part));
+            }
+        }
+    });
+}
+
+// Drops bold markers and escapes and turns `[text](url)` into `text`
+fn plain(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|c| c.is_ascii_punctuation()) => out.extend(chars.next()),
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+            }
+            ']' if chars.peek() == Some(&'(') => {
+                // Skip the link target
+                for c in chars.by_ref() {
+                    if c == ')' {
+                        break;
+                    }
+                }
+            }
+            '[' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,57 @@
+const MAX_HISTORY: usize = 50;
+
+// A cursor position in a tab; `cursor` is a char index
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub tab_id: usize,
+    pub cursor: usize,
+}
+
+// Back/forward list of the places jumps started from, across tabs
+#[derive(Default)]
+pub struct History {
+    back: Vec<Location>,
+    forward: Vec<Location>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records where a jump starts; like a browser, a new jump drops the forward list
+    pub fn push(&mut self, from: Location) {
+        if self.back.last() != Some(&from) {
+            if self.back.len() >= MAX_HISTORY {
+                self.back.remove(0);
+            }
+            self.back.push(from);
+        }
+        self.forward.clear();
+    }
+
+    // The previous location that `is_open` accepts; `current` becomes the way forward
+    pub fn back(&mut self, current: Option<Location>, is_open: impl Fn(&Location) -> bool) -> Option<Location> {
+        Self::step(&mut self.back, &mut self.forward, current, is_open)
+    }
+
+    pub fn forward(&mut self, current: Option<Location>, is_open: impl Fn(&Location) -> bool) -> Option<Location> {
+        Self::step(&mut self.forward, &mut self.back, current, is_open)
+    }
+
+    fn step(
+        from: &mut Vec<Location>,
+        to: &mut Vec<Location>,
+        current: Option<Location>,
+        is_open: impl Fn(&Location) -> bool,
+    ) -> Option<Location> {
+        // Entries for closed tabs are dropped on the way
+        while let Some(location) = from.pop() {
+            if is_open(&location) && Some(location) != current {
+                to.extend(current);
+                return Some(location);
+            }
+        }
+        None
+    }
+}
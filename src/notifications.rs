@@ -0,0 +1,181 @@
+use egui::{Align2, Color32, Context, ScrollArea, Ui};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+const ERROR_TOAST_DURATION: Duration = Duration::from_secs(10);
+const MAX_TOASTS: usize = 5;
+const MAX_LOG_ENTRIES: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    fn color(&self) -> Color32 {
+        match self {
+            Level::Info => Color32::LIGHT_GRAY,
+            Level::Warning => Color32::from_rgb(255, 200, 0),
+            Level::Error => Color32::from_rgb(255, 100, 100),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+struct Toast {
+    level: Level,
+    message: String,
+    shown_at: Instant,
+}
+
+struct LogEntry {
+    level: Level,
+    message: String,
+    // Wall clock time as HH:MM:SS (UTC)
+    time: String,
+}
+
+// Short-lived toasts in the corner of the window, plus a log of every message
+// for the output panel
+#[derive(Default)]
+pub struct Notifications {
+    toasts: Vec<Toast>,
+    log: Vec<LogEntry>,
+}
+
+impl Notifications {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(Level::Info, message.into());
+    }
+
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.push(Level::Warning, message.into());
+    }
+
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.push(Level::Error, message.into());
+    }
+
+    // Adds to the output log without showing a toast
+    pub fn log(&mut self, level: Level, message: impl Into<String>) {
+        if self.log.len() >= MAX_LOG_ENTRIES {
+            self.log.remove(0);
+        }
+        self.log.push(LogEntry {
+            level,
+            message: message.into(),
+            time: clock_time(),
+        });
+    }
+
+    fn push(&mut self, level: Level, message: String) {
+        self.log(level, message.clone());
+        if self.toasts.len() >= MAX_TOASTS {
+            self.toasts.remove(0);
+        }
+        self.toasts.push(Toast {
+            level,
+            message,
+            shown_at: Instant::now(),
+        });
+    }
+
+    pub fn clear_log(&mut self) {
+        self.log.clear();
+    }
+
+    // Returns true when "Show Output" was clicked on a toast
+    pub fn show_toasts(&mut self, ctx: &Context) -> bool {
+        self.toasts.retain(|toast| {
+            let duration = if toast.level == Level::Error { ERROR_TOAST_DURATION } else { TOAST_DURATION };
+            toast.shown_at.elapsed() < duration
+        });
+        if self.toasts.is_empty() {
+            return false;
+        }
+        // Wake up again to expire the toasts without other input
+        ctx.request_repaint_after(Duration::from_millis(500));
+
+        let mut dismissed = None;
+        let mut show_output = false;
+        egui::Area::new("toasts")
+            .anchor(Align2::RIGHT_BOTTOM, [-12.0, -36.0])
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                for (i, toast) in self.toasts.iter().enumerate().rev() {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(360.0);
+                        ui.horizontal(|ui| {
+                            ui.colored_label(toast.level.color(), &toast.message);
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.small_button("Dismiss").clicked() {
+                                dismissed = Some(i);
+                            }
+                            if toast.level != Level::Info && ui.small_button("Show Output").clicked() {
+                                show_output = true;
+                                dismissed = Some(i);
+                            }
+                        });
+                    });
+                    ui.add_space(4.0);
+                }
+            });
+
+        if let Some(i) = dismissed {
+            self.toasts.remove(i);
+        }
+        show_output
+    }
+
+    pub fn show_log(&mut self, ui: &mut Ui) {
+        let mut clear = false;
+        ui.horizontal(|ui| {
+            ui.strong("Output");
+            if ui.small_button("Clear").clicked() {
+                clear = true;
+            }
+        });
+        ui.separator();
+
+        ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for entry in &self.log {
+                    ui.horizontal(|ui| {
+                        ui.weak(&entry.time);
+                        ui.colored_label(entry.level.color(), format!("[{}]", entry.level.label()));
+                        ui.monospace(&entry.message);
+                    });
+                }
+                if self.log.is_empty() {
+                    ui.weak("No messages");
+                }
+            });
+
+        if clear {
+            self.clear_log();
+        }
+    }
+}
+
+fn clock_time() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    format!("{:02}:{:02}:{:02}", seconds / 3600 % 24, seconds / 60 % 60, seconds % 60)
+}
@@ -0,0 +1,54 @@
+use egui::Context;
+
+use crate::commands::CommandRegistry;
+use crate::keybindings::Keymap;
+use crate::picker::{Picker, PickerItem, PickerResult};
+
+pub struct CommandPalette {
+    picker: Option<Picker>,
+    command_ids: Vec<String>,
+}
+
+impl CommandPalette {
+    pub fn new() -> Self {
+        Self {
+            picker: None,
+            command_ids: Vec::new(),
+        }
+    }
+
+    pub fn open(&mut self, ctx: &Context, registry: &CommandRegistry, keymap: &Keymap) {
+        let mut items = Vec::new();
+        self.command_ids.clear();
+        for command in registry.iter() {
+            let shortcut = keymap
+                .bindings_for(&command.id)
+                .next()
+                .map(|binding| binding.sequence.format(ctx))
+                .unwrap_or_default();
+            items.push(PickerItem::new(&command.title).detail(shortcut).keywords(&command.id));
+            self.command_ids.push(command.id.clone());
+        }
+        self.picker = Some(Picker::new("Command Palette", items).hint("Type a command"));
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.picker.is_some()
+    }
+
+    // Returns the id of the command the user picked, if any
+    pub fn show(&mut self, ctx: &Context) -> Option<String> {
+        let result = self.picker.as_mut()?.show(ctx)?;
+        self.picker = None;
+        match result {
+            PickerResult::Picked(index) => self.command_ids.get(index).cloned(),
+            PickerResult::Submitted(_) | PickerResult::Cancelled => None,
+        }
+    }
+}
+
+impl Default for CommandPalette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,184 @@
+use egui::{Align2, Context, Key, Modifiers, ScrollArea, TextEdit};
+
+use crate::fuzzy::fuzzy_score;
+
+pub struct PickerItem {
+    pub label: String,
+    pub detail: String,
+    // Extra text the filter matches against without showing it
+    pub keywords: String,
+}
+
+impl PickerItem {
+    pub fn new(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            detail: String::new(),
+            keywords: String::new(),
+        }
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = detail.into();
+        self
+    }
+
+    pub fn keywords(mut self, keywords: impl Into<String>) -> Self {
+        self.keywords = keywords.into();
+        self
+    }
+}
+
+pub enum PickerResult {
+    // Index into the items the picker was created with
+    Picked(usize),
+    // Text entered into a prompt, which has no items
+    Submitted(String),
+    Cancelled,
+}
+
+// Fuzzy-filtered list shown at the top of the window, used by the command
+// palette and the status bar pickers. Without items it is a plain prompt.
+pub struct Picker {
+    title: String,
+    hint: String,
+    items: Vec<PickerItem>,
+    query: String,
+    selected: usize,
+    focus_requested: bool,
+}
+
+impl Picker {
+    pub fn new(title: &str, items: Vec<PickerItem>) -> Self {
+        Self {
+            title: title.to_string(),
+            hint: "Type to filter".to_string(),
+            items,
+            query: String::new(),
+            selected: 0,
+            focus_requested: true,
+        }
+    }
+
+    pub fn prompt(title: &str, hint: &str) -> Self {
+        let mut picker = Self::new(title, Vec::new());
+        picker.hint = hint.to_string();
+        picker
+    }
+
+    pub fn hint(mut self, hint: &str) -> Self {
+        self.hint = hint.to_string();
+        self
+    }
+
+    // Starts with the item at `index` highlighted, e.g. the current value
+    pub fn select(mut self, index: usize) -> Self {
+        self.selected = index;
+        self
+    }
+
+    fn matches(&self) -> Vec<usize> {
+        let mut matches: Vec<(i32, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                let score = fuzzy_score(&self.query, &item.label)
+                    .max(fuzzy_score(&self.query, &item.detail))
+                    .max(fuzzy_score(&self.query, &item.keywords));
+                score.map(|score| (score, i))
+            })
+            .collect();
+        if !self.query.is_empty() {
+            matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        }
+        matches.into_iter().map(|(_, i)| i).collect()
+    }
+
+    pub fn show(&mut self, ctx: &Context) -> Option<PickerResult> {
+        let matches = self.matches();
+
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(Modifiers::NONE, Key::ArrowUp),
+                i.consume_key(Modifiers::NONE, Key::ArrowDown),
+                i.consume_key(Modifiers::NONE, Key::Enter),
+                i.consume_key(Modifiers::NONE, Key::Escape),
+            )
+        });
+
+        if up {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        if down && self.selected + 1 < matches.len() {
+            self.selected += 1;
+        }
+        self.selected = self.selected.min(matches.len().saturating_sub(1));
+
+        let mut result = None;
+        if escape {
+            result = Some(PickerResult::Cancelled);
+        } else if enter {
+            result = if self.items.is_empty() {
+                Some(PickerResult::Submitted(self.query.clone()))
+            } else {
+                matches.get(self.selected).map(|&i| PickerResult::Picked(i))
+            };
+        }
+
+        let mut open = true;
+        egui::Window::new(&self.title)
+            .id(egui::Id::new("picker"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_TOP, [0.0, 40.0])
+            .fixed_size([480.0, if self.items.is_empty() { 40.0 } else { 320.0 }])
+            .show(ctx, |ui| {
+                let response = ui.add(
+                    TextEdit::singleline(&mut self.query)
+                        .hint_text(&self.hint)
+                        .desired_width(f32::INFINITY),
+                );
+                if self.focus_requested {
+                    response.request_focus();
+                    self.focus_requested = false;
+                }
+                if response.changed() {
+                    self.selected = 0;
+                }
+                if self.items.is_empty() {
+                    return;
+                }
+                ui.separator();
+
+                ScrollArea::vertical().max_height(280.0).show(ui, |ui| {
+                    for (row, &i) in matches.iter().enumerate() {
+                        let item = &self.items[i];
+                        ui.horizontal(|ui| {
+                            let label = ui.selectable_label(row == self.selected, &item.label);
+                            if row == self.selected && (up || down) {
+                                label.scroll_to_me(None);
+                            }
+                            if label.clicked() {
+                                result = Some(PickerResult::Picked(i));
+                            }
+                            if !item.detail.is_empty() {
+                                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                    ui.weak(&item.detail);
+                                });
+                            }
+                        });
+                    }
+                    if matches.is_empty() {
+                        ui.weak("No matches");
+                    }
+                });
+            });
+
+        if !open {
+            result = Some(PickerResult::Cancelled);
+        }
+        result
+    }
+}
@@ -1,14 +1,33 @@
+use egui::KeyboardShortcut;
 use std::collections::HashMap;
 
 pub trait Plugin {
     fn name(&self) -> &str;
     fn execute(&mut self, context: &mut PluginContext);
+
+    // Commands this plugin adds to the command palette and the Plugins menu
+    fn commands(&self) -> Vec<PluginCommand> {
+        Vec::new()
+    }
+
+    // Called when one of the commands returned by `commands` is invoked
+    fn run_command(&mut self, _command_id: &str, context: &mut PluginContext) {
+        self.execute(context);
+    }
 }
 
 pub struct PluginContext {
     pub selected_text: Option<String>,
     pub current_file: Option<String>,
     pub cursor_position: (usize, usize),
+    // Messages for the user, shown as notifications once the plugin returns
+    pub messages: Vec<String>,
+}
+
+pub struct PluginCommand {
+    pub id: String,
+    pub title: String,
+    pub keybinding: Option<KeyboardShortcut>,
 }
 
 pub struct PluginManager {
@@ -33,8 +52,17 @@ impl PluginManager {
         }
     }
 
-    pub fn list_plugins(&self) -> Vec<&str> {
-        self.plugins.keys().map(|s| s.as_str()).collect()
+    pub fn run_command(&mut self, name: &str, command_id: &str, context: &mut PluginContext) {
+        if let Some(plugin) = self.plugins.get_mut(name) {
+            plugin.run_command(command_id, context);
+        }
+    }
+
+    pub fn commands(&self, name: &str) -> Vec<PluginCommand> {
+        self.plugins
+            .get(name)
+            .map(|plugin| plugin.commands())
+            .unwrap_or_default()
     }
 }
 
@@ -53,8 +81,23 @@ impl Plugin for ExamplePlugin {
     }
 
     fn execute(&mut self, context: &mut PluginContext) {
-        if let Some(text) = &context.selected_text {
-            println!("Selected text: {}", text);
-        }
+        let (line, column) = context.cursor_position;
+        let position = match &context.current_file {
+            Some(file) => format!("{}:{}:{}", file, line + 1, column + 1),
+            None => format!("{}:{}", line + 1, column + 1),
+        };
+        let message = match &context.selected_text {
+            Some(text) => format!("Selected {} characters at {}", text.chars().count(), position),
+            None => format!("Cursor at {}", position),
+        };
+        context.messages.push(message);
+    }
+
+    fn commands(&self) -> Vec<PluginCommand> {
+        vec![PluginCommand {
+            id: "example.show_selection".to_string(),
+            title: "Example: Show Selection".to_string(),
+            keybinding: None,
+        }]
     }
 }
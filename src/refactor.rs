@@ -0,0 +1,168 @@
+use crate::lsp::CodeAction;
+use egui::{Context, Frame, Order, Pos2, RichText, ScrollArea};
+
+pub enum RenameAction {
+    Rename(String),
+    Cancel,
+}
+
+// The inline input F2 opens at the cursor, prefilled with the current name
+pub struct RenameInput {
+    tab_id: usize,
+    // Char index the rename was started at; the request is made for it
+    cursor: usize,
+    old_name: String,
+    new_name: String,
+    // Below the cursor; kept since the editor has no cursor while the input is focused
+    position: Pos2,
+    focused: bool,
+}
+
+impl RenameInput {
+    pub fn new(tab_id: usize, cursor: usize, name: String, position: Pos2) -> Self {
+        Self {
+            tab_id,
+            cursor,
+            new_name: name.clone(),
+            old_name: name,
+            position,
+            focused: false,
+        }
+    }
+
+    pub fn tab_id(&self) -> usize {
+        self.tab_id
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn old_name(&self) -> &str {
+        &self.old_name
+    }
+
+    // Enter renames and Escape, or clicking elsewhere, cancels
+    pub fn show(&mut self, ctx: &Context) -> Option<RenameAction> {
+        let mut action = None;
+        egui::Area::new("rename")
+            .order(Order::Foreground)
+            .fixed_pos(self.position)
+            .show(ctx, |ui| {
+                Frame::popup(ui.style()).show(ui, |ui| {
+                    let output = egui::TextEdit::singleline(&mut self.new_name)
+                        .font(egui::TextStyle::Monospace)
+                        .desired_width(240.0)
+                        .show(ui);
+                    if !self.focused {
+                        // Select the whole name so typing replaces it
+                        let mut state = output.state;
+                        state.set_ccursor_range(Some(egui::text::CCursorRange::two(
+                            egui::text::CCursor::new(0),
+                            egui::text::CCursor::new(self.new_name.chars().count()),
+                        )));
+                        state.store(ctx, output.response.id);
+                        output.response.request_focus();
+                        self.focused = true;
+                    }
+                    ui.weak("Enter to rename, Escape to cancel");
+                    if output.response.lost_focus() {
+                        let name = self.new_name.trim();
+                        action = if ui.input(|i| i.key_pressed(egui::Key::Enter)) && !name.is_empty() && name != self.old_name {
+                            Some(RenameAction::Rename(name.to_string()))
+                        } else {
+                            Some(RenameAction::Cancel)
+                        };
+                    }
+                });
+            });
+        action
+    }
+}
+
+pub enum MenuAction {
+    Run(usize),
+    Close,
+}
+
+// The code actions for the cursor, opened from the lightbulb or with Alt+Enter
+pub struct CodeActionMenu {
+    actions: Vec<CodeAction>,
+    selected: usize,
+    scroll_to_selected: bool,
+}
+
+impl CodeActionMenu {
+    pub fn new(actions: Vec<CodeAction>) -> Self {
+        Self {
+            actions,
+            selected: 0,
+            scroll_to_selected: false,
+        }
+    }
+
+    pub fn into_action(mut self, index: usize) -> CodeAction {
+        self.actions.swap_remove(index)
+    }
+
+    // Up, Down, Enter and Escape; runs before the editor sees the keys
+    pub fn handle_keys(&mut self, ctx: &Context) -> Option<MenuAction> {
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        let count = self.actions.len();
+        if up {
+            self.selected = (self.selected + count - 1) % count;
+            self.scroll_to_selected = true;
+        }
+        if down {
+            self.selected = (self.selected + 1) % count;
+            self.scroll_to_selected = true;
+        }
+        if enter {
+            Some(MenuAction::Run(self.selected))
+        } else if escape {
+            Some(MenuAction::Close)
+        } else {
+            None
+        }
+    }
+
+    pub fn show(&mut self, ctx: &Context, position: Pos2) -> Option<MenuAction> {
+        let mut action = None;
+        egui::Area::new("code_actions")
+            .order(Order::Foreground)
+            .fixed_pos(position)
+            .show(ctx, |ui| {
+                Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.set_min_width(240.0);
+                    ScrollArea::vertical().max_height(220.0).show(ui, |ui| {
+                        for (i, code_action) in self.actions.iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                let kind = code_action.kind.as_deref().and_then(|k| k.split('.').next()).unwrap_or("");
+                                ui.add_sized([56.0, 14.0], egui::Label::new(RichText::new(kind).weak().small()));
+                                let mut title = RichText::new(&code_action.title);
+                                if code_action.preferred {
+                                    title = title.strong();
+                                }
+                                let label = ui.selectable_label(i == self.selected, title);
+                                if i == self.selected && self.scroll_to_selected {
+                                    label.scroll_to_me(None);
+                                }
+                                if label.clicked() {
+                                    action = Some(MenuAction::Run(i));
+                                }
+                            });
+                        }
+                    });
+                });
+            });
+        self.scroll_to_selected = false;
+        action
+    }
+}
@@ -0,0 +1,108 @@
+use crate::diagnostics::Position;
+use crate::lsp::{self, LspLocation};
+use egui::{Context, TopBottomPanel};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Reference {
+    pub path: PathBuf,
+    pub position: Position,
+    // The trimmed line the reference is on
+    pub preview: String,
+}
+
+// Turns server locations into char positions with a line preview. The text of
+// open files comes from `open_text`, so it matches what the server was sent;
+// other files are read from disk once each.
+pub fn resolve(locations: Vec<LspLocation>, open_text: impl Fn(&Path) -> Option<String>) -> Vec<Reference> {
+    let mut texts: HashMap<PathBuf, Option<String>> = HashMap::new();
+    let mut references: Vec<Reference> = locations
+        .into_iter()
+        .map(|location| {
+            let text = texts
+                .entry(location.path.clone())
+                .or_insert_with(|| open_text(&location.path).or_else(|| fs::read_to_string(&location.path).ok()));
+            let line_text = text.as_deref().and_then(|t| t.split('\n').nth(location.line)).unwrap_or("");
+            Reference {
+                position: Position {
+                    line: location.line,
+                    column: lsp::char_column(line_text, location.character),
+                },
+                preview: line_text.trim().to_string(),
+                path: location.path,
+            }
+        })
+        .collect();
+    references.sort_by(|a, b| (&a.path, a.position).cmp(&(&b.path, b.position)));
+    references.dedup_by(|a, b| a.path == b.path && a.position == b.position);
+    references
+}
+
+pub enum ReferencesAction {
+    Open(PathBuf, Position),
+    Close,
+}
+
+// Results of find references, or of a definition with several targets,
+// grouped by file
+pub struct ReferencesPanel {
+    title: String,
+    references: Vec<Reference>,
+}
+
+impl ReferencesPanel {
+    pub fn new(title: String, references: Vec<Reference>) -> Self {
+        Self { title, references }
+    }
+
+    pub fn show(&self, ctx: &Context, root: Option<&Path>) -> Option<ReferencesAction> {
+        let mut action = None;
+        TopBottomPanel::bottom("references_panel")
+            .resizable(true)
+            .default_height(160.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong(&self.title);
+                    ui.weak(format!("{} results", self.references.len()));
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("✖").on_hover_text("Close").clicked() {
+                            action = Some(ReferencesAction::Close);
+                        }
+                    });
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                    for group in self.references.chunk_by(|a, b| a.path == b.path) {
+                        let path = &group[0].path;
+                        let name = root
+                            .and_then(|root| path.strip_prefix(root).ok())
+                            .unwrap_or(path)
+                            .display()
+                            .to_string();
+                        egui::CollapsingHeader::new(format!("{} ({})", name, group.len()))
+                            .id_source(("references", path))
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                for reference in group {
+                                    let position = reference.position;
+                                    let mut text = egui::text::LayoutJob::default();
+                                    let font = egui::TextStyle::Body.resolve(ui.style());
+                                    let format = |color| egui::TextFormat::simple(font.clone(), color);
+                                    let weak = ui.visuals().weak_text_color();
+                                    text.append(&format!("{}:{}", position.line + 1, position.column + 1), 0.0, format(weak));
+                                    let code = egui::TextStyle::Monospace.resolve(ui.style());
+                                    let code = egui::TextFormat::simple(code, ui.visuals().text_color());
+                                    text.append(&reference.preview, 8.0, code);
+                                    if ui.selectable_label(false, text).clicked() {
+                                        action = Some(ReferencesAction::Open(path.clone(), position));
+                                    }
+                                }
+                            });
+                    }
+                });
+            });
+        action
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Default)]
+pub struct SaveOptions {
+    // Copy the previous version of a file before overwriting it
+    pub backup: bool,
+    // Where backups go; next to the file as `<name>.bak` when unset
+    pub backup_directory: Option<PathBuf>,
+    // When the file's folder isn't writable, overwrite the file itself instead.
+    // A crash halfway through then leaves a truncated file.
+    pub in_place_fallback: bool,
+}
+
+// Writes `bytes` to `path` without ever leaving a half-written file behind: the
+// data goes to a temporary file in the same directory, which then replaces the
+// original. Symlinks are followed so the link itself survives, and the original
+// file's permissions are kept.
+pub fn write_file(path: &Path, bytes: &[u8], options: &SaveOptions) -> Result<()> {
+    let target = resolve_symlinks(path);
+    let existing = fs::metadata(&target).ok();
+
+    if options.backup && existing.is_some() {
+        let backup = backup_path(&target, options.backup_directory.as_deref());
+        if let Some(parent) = backup.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io("create", parent, e))?;
+        }
+        fs::copy(&target, &backup).map_err(|e| Error::io("back up", &target, e))?;
+    }
+
+    let (temp_path, mut temp) = match create_temp_file(&target, existing.as_ref()) {
+        Ok(temp) => temp,
+        // The directory isn't writable, but the file itself may be
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            return if options.in_place_fallback && existing.is_some() {
+                write_in_place(&target, bytes)
+            } else {
+                Err(Error::DirectoryNotWritable { path: target })
+            };
+        }
+        Err(e) => return Err(Error::io("save", &target, e)),
+    };
+
+    let result = temp
+        .write_all(bytes)
+        .and_then(|_| temp.sync_all())
+        // The umask may have masked some bits when the file was created
+        .and_then(|_| match &existing {
+            Some(metadata) => fs::set_permissions(&temp_path, metadata.permissions()),
+            None => Ok(()),
+        })
+        .and_then(|_| fs::rename(&temp_path, &target))
+        .and_then(|_| sync_directory(&target));
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(save_error(&target, e));
+    }
+    Ok(())
+}
+
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let is_symlink = fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink());
+    if is_symlink {
+        // A dangling link is written through to where it points
+        fs::canonicalize(path)
+            .or_else(|_| fs::read_link(path).map(|link| path.parent().unwrap_or(Path::new("")).join(link)))
+            .unwrap_or_else(|_| path.to_path_buf())
+    } else {
+        path.to_path_buf()
+    }
+}
+
+fn directory_of(target: &Path) -> &Path {
+    target.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."))
+}
+
+// The temporary file gets the original's mode from the start, so the contents of
+// a private file are never readable by others, not even for a moment
+fn create_temp_file(target: &Path, existing: Option<&Metadata>) -> io::Result<(PathBuf, File)> {
+    let directory = directory_of(target);
+    let name = target.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(metadata) = existing {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(metadata.permissions().mode());
+    }
+    #[cfg(not(unix))]
+    let _ = existing;
+    let mut attempt = 0;
+    loop {
+        let temp_path = directory.join(format!(".{}.{}.{}.tmp", name, std::process::id(), attempt));
+        match options.open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Makes the rename itself survive a crash, not just the file's contents
+#[cfg(unix)]
+fn sync_directory(target: &Path) -> io::Result<()> {
+    File::open(directory_of(target))?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_target: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn write_in_place(target: &Path, bytes: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(target)
+        .map_err(|e| save_error(target, e))?;
+    file.write_all(bytes)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| save_error(target, e))
+}
+
+// Exodus never escalates privileges itself, so a permission problem gets its own message
+fn save_error(target: &Path, error: io::Error) -> Error {
+    if error.kind() == io::ErrorKind::PermissionDenied {
+        Error::PermissionDenied { path: target.to_path_buf() }
+    } else {
+        Error::io("save", target, error)
+    }
+}
+
+// Backups in a shared directory are named after the file's full path, so files
+// with the same name in different folders don't overwrite each other's backup
+fn backup_path(target: &Path, directory: Option<&Path>) -> PathBuf {
+    match directory {
+        Some(directory) => {
+            let absolute = fs::canonicalize(target).unwrap_or_else(|_| target.to_path_buf());
+            let flattened: String = absolute
+                .to_string_lossy()
+                .chars()
+                .map(|c| if c == '/' || c == '\\' || c == ':' { '%' } else { c })
+                .collect();
+            directory.join(format!("{}.bak", flattened.trim_start_matches('%')))
+        }
+        None => {
+            let mut name = target.file_name().unwrap_or_default().to_os_string();
+            name.push(".bak");
+            target.with_file_name(name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn replaces_the_file() {
+        let dir = TestDir::new("save");
+        let path = dir.join("file.txt");
+        write_file(&path, b"new file", &SaveOptions::default()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new file");
+
+        fs::write(&path, "a much longer original text").unwrap();
+        write_file(&path, b"short", &SaveOptions::default()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"short");
+        // No temporary files are left behind
+        assert_eq!(entries(&dir), ["file.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_rather_than_truncates() {
+        use std::os::unix::fs::MetadataExt;
+        let dir = TestDir::new("save");
+        let path = dir.join("file.txt");
+        fs::write(&path, "old").unwrap();
+        let before = fs::metadata(&path).unwrap().ino();
+        write_file(&path, b"new", &SaveOptions::default()).unwrap();
+        assert_ne!(fs::metadata(&path).unwrap().ino(), before);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TestDir::new("save");
+        let path = dir.join("script.sh");
+        fs::write(&path, "echo old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        write_file(&path, b"echo new", &SaveOptions::default()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o750);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_files_stay_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TestDir::new("save");
+        let path = dir.join("secret.txt");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let existing = fs::metadata(&path).unwrap();
+        // Before anything is written to it
+        let (temp_path, _) = create_temp_file(&path, Some(&existing)).unwrap();
+        assert_eq!(fs::metadata(&temp_path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks() {
+        let dir = TestDir::new("save");
+        fs::create_dir(dir.join("real")).unwrap();
+        let target = dir.join("real/file.txt");
+        fs::write(&target, "old").unwrap();
+        let link = dir.join("link.txt");
+        std::os::unix::fs::symlink("real/file.txt", &link).unwrap();
+
+        write_file(&link, b"new", &SaveOptions::default()).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+
+        // A dangling link creates the file it points to
+        let dangling = dir.join("dangling.txt");
+        std::os::unix::fs::symlink("real/missing.txt", &dangling).unwrap();
+        write_file(&dangling, b"created", &SaveOptions::default()).unwrap();
+        assert_eq!(fs::read(dir.join("real/missing.txt")).unwrap(), b"created");
+    }
+
+    #[test]
+    fn backups() {
+        let dir = TestDir::new("save");
+        let path = dir.join("file.txt");
+        let options = SaveOptions { backup: true, ..SaveOptions::default() };
+        write_file(&path, b"first", &options).unwrap();
+        // Nothing to back up for a new file
+        assert_eq!(entries(&dir), ["file.txt"]);
+
+        write_file(&path, b"second", &options).unwrap();
+        assert_eq!(fs::read(dir.join("file.txt.bak")).unwrap(), b"first");
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+
+        let backups = dir.join("backups");
+        let options = SaveOptions {
+            backup: true,
+            backup_directory: Some(backups.clone()),
+            ..SaveOptions::default()
+        };
+        write_file(&path, b"third", &options).unwrap();
+        let saved: Vec<PathBuf> = fs::read_dir(&backups).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(saved.len(), 1);
+        assert!(saved[0].to_string_lossy().ends_with("%file.txt.bak"));
+        assert_eq!(fs::read(&saved[0]).unwrap(), b"second");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_folder() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TestDir::new("save");
+        let path = dir.join("file.txt");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&*dir, fs::Permissions::from_mode(0o555)).unwrap();
+        if File::create(dir.join("probe")).is_ok() {
+            // Running as root, permissions aren't enforced
+            return;
+        }
+
+        let error = write_file(&path, b"new", &SaveOptions::default()).unwrap_err();
+        assert!(matches!(error, Error::DirectoryNotWritable { .. }));
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+
+        let options = SaveOptions { in_place_fallback: true, ..SaveOptions::default() };
+        write_file(&path, b"new", &options).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+    }
+}
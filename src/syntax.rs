@@ -1,11 +1,68 @@
-use syntect::parsing::SyntaxSet;
-use syntect::highlighting::{ThemeSet, Theme};
-use syntect::easy::HighlightLines;
+use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxSet};
+use syntect::highlighting::{HighlightIterator, HighlightState, Highlighter, ThemeSet, Theme};
+use syntect::easy::ScopeRangeIterator;
+use syntect::util::LinesWithEndings;
 use egui::Color32;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const PLAIN_TEXT: &str = "Plain Text";
+
+// Bigger buffers are shown without colors; highlighting them would stall typing
+const MAX_HIGHLIGHT_BYTES: usize = 512 * 1024;
+
+// Colored byte ranges covering a whole buffer
+pub type Highlights = Arc<Vec<(Range<usize>, Color32)>>;
+
+// Scopes that mark a definition, with what to call it. Grammars put the name of
+// a function, type or heading in `entity.name.<kind>`.
+const SYMBOL_SCOPES: [(&str, &str); 13] = [
+    ("entity.name.function", "function"),
+    ("entity.name.method", "method"),
+    ("entity.name.struct", "struct"),
+    ("entity.name.class", "class"),
+    ("entity.name.enum", "enum"),
+    ("entity.name.union", "union"),
+    ("entity.name.trait", "trait"),
+    ("entity.name.interface", "interface"),
+    ("entity.name.type", "type"),
+    ("entity.name.impl", "impl"),
+    ("entity.name.namespace", "namespace"),
+    ("entity.name.module", "module"),
+    ("entity.name.section", "heading"),
+];
+
+// A definition found in a buffer; `line` and `column` are zero-based, the column in chars
+pub struct Symbol {
+    pub name: String,
+    pub kind: &'static str,
+    pub line: usize,
+    pub column: usize,
+}
 
 pub struct SyntaxHighlighter {
     syntax_set: SyntaxSet,
     theme: Theme,
+    // The buffer highlighted last, which is the one being edited
+    document: Option<Document>,
+}
+
+// A highlighted buffer kept line by line, so an edit re-highlights from the
+// changed line only until the parser is back in the state it had before
+struct Document {
+    language: String,
+    text: String,
+    lines: Vec<HighlightedLine>,
+    spans: Highlights,
+}
+
+struct HighlightedLine {
+    len: usize,
+    // Relative to the start of the line
+    spans: Vec<(Range<usize>, Color32)>,
+    // Parser state after the line
+    state: (ParseState, HighlightState),
 }
 
 impl SyntaxHighlighter {
@@ -13,24 +70,190 @@ impl SyntaxHighlighter {
         let syntax_set = SyntaxSet::load_defaults_newlines();
         let theme_set = ThemeSet::load_defaults();
         let theme = theme_set.themes["base16-ocean.dark"].clone();
-        
+
         Self {
             syntax_set,
             theme,
+            document: None,
+        }
+    }
+
+    pub fn detect_language(&self, path: Option<&Path>, content: &str) -> String {
+        let by_path = path.and_then(|path| {
+            let extension = path.extension().and_then(|e| e.to_str());
+            let file_name = path.file_name().and_then(|n| n.to_str());
+            extension
+                .and_then(|ext| self.syntax_set.find_syntax_by_extension(ext))
+                .or_else(|| file_name.and_then(|name| self.syntax_set.find_syntax_by_extension(name)))
+        });
+        let first_line = content.lines().next().unwrap_or("");
+        by_path
+            .or_else(|| self.syntax_set.find_syntax_by_first_line(first_line))
+            .map_or_else(|| PLAIN_TEXT.to_string(), |syntax| syntax.name.clone())
+    }
+
+    pub fn language_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .syntax_set
+            .syntaxes()
+            .iter()
+            .filter(|syntax| !syntax.hidden)
+            .map(|syntax| syntax.name.clone())
+            .collect();
+        names.sort_by_key(|name| name.to_lowercase());
+        names.dedup();
+        names
+    }
+
+    pub fn default_color(&self) -> Color32 {
+        self.theme.settings.foreground.map_or(Color32::LIGHT_GRAY, to_color32)
+    }
+
+    // Highlights the whole text so multi-line constructs keep their state. egui lays
+    // the editor out every frame, so unchanged text returns the last result and
+    // edited text reuses the lines before and after the edit.
+    pub fn highlight(&mut self, text: &str, language: &str) -> Highlights {
+        if language == PLAIN_TEXT || text.len() > MAX_HIGHLIGHT_BYTES {
+            return Arc::new(vec![(0..text.len(), self.default_color())]);
+        }
+        let previous = self.document.take().filter(|document| document.language == language);
+        if let Some(document) = previous.as_ref().filter(|document| document.text == text) {
+            let spans = document.spans.clone();
+            self.document = previous;
+            return spans;
         }
-    }    pub fn highlight_line(&mut self, line: &str, language: &str) -> Vec<(String, Color32)> {
-        let syntax = self.syntax_set.find_syntax_by_extension(language)
+
+        let syntax = self.syntax_set.find_syntax_by_name(language)
             .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
-        let mut highlighter = HighlightLines::new(syntax, &self.theme);
-        let ranges = highlighter.highlight_line(line, &self.syntax_set).unwrap_or_default();
-        
-        ranges.into_iter().map(|(style, text)| {
-            let color = Color32::from_rgb(
-                (style.foreground.r as f32 * 255.0) as u8,
-                (style.foreground.g as f32 * 255.0) as u8,
-                (style.foreground.b as f32 * 255.0) as u8,
-            );
-            (text.to_string(), color)
-        }).collect()
+        let highlighter = Highlighter::new(&self.theme);
+        let initial = (ParseState::new(syntax), HighlightState::new(&highlighter, ScopeStack::new()));
+
+        let new_lines: Vec<&str> = LinesWithEndings::from(text).collect();
+        let (old_text, mut old_lines) = previous.map_or((String::new(), Vec::new()), |d| (d.text, d.lines));
+        let old: Vec<&str> = LinesWithEndings::from(&old_text).collect();
+        let prefix = old.iter().zip(&new_lines).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new_lines[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let mut reused_after = old_lines.split_off(prefix.min(old_lines.len()));
+        let mut lines = old_lines;
+        let mut state = lines.last().map_or(initial.clone(), |line| line.state.clone());
+        for (index, line) in new_lines.iter().enumerate().skip(prefix) {
+            // Past the edit, the old lines are still right once the state matches again
+            let old_index = (index + old.len()).checked_sub(new_lines.len());
+            if let Some(old_index) = old_index.filter(|&i| index >= new_lines.len() - suffix && i >= prefix) {
+                let before = if old_index > prefix {
+                    reused_after.get(old_index - prefix - 1).map(|line| &line.state)
+                } else if prefix == 0 {
+                    Some(&initial)
+                } else {
+                    lines.get(prefix - 1).map(|line| &line.state)
+                };
+                if before == Some(&state) {
+                    lines.extend(reused_after.drain(old_index - prefix..));
+                    break;
+                }
+            }
+            let spans = self.highlight_line(&highlighter, &mut state, line);
+            lines.push(HighlightedLine { len: line.len(), spans, state: state.clone() });
+        }
+
+        let mut spans = Vec::new();
+        let mut offset = 0;
+        for line in &lines {
+            spans.extend(line.spans.iter().map(|(range, color)| (offset + range.start..offset + range.end, *color)));
+            offset += line.len;
+        }
+        let spans = Arc::new(spans);
+        self.document = Some(Document {
+            language: language.to_string(),
+            text: text.to_string(),
+            lines,
+            spans: spans.clone(),
+        });
+        spans
     }
-}
\ No newline at end of file
+
+    fn highlight_line(
+        &self,
+        highlighter: &Highlighter,
+        (parse_state, highlight_state): &mut (ParseState, HighlightState),
+        line: &str,
+    ) -> Vec<(Range<usize>, Color32)> {
+        let Ok(ops) = parse_state.parse_line(line, &self.syntax_set) else {
+            return vec![(0..line.len(), self.default_color())];
+        };
+        let mut spans = Vec::new();
+        let mut offset = 0;
+        for (style, piece) in HighlightIterator::new(highlight_state, &ops, line, highlighter) {
+            spans.push((offset..offset + piece.len(), to_color32(style.foreground)));
+            offset += piece.len();
+        }
+        spans
+    }
+
+    // Functions, types, headings and the like, in the order they appear
+    pub fn symbols(&self, text: &str, language: &str) -> Vec<Symbol> {
+        let Some(syntax) = self.syntax_set.find_syntax_by_name(language) else {
+            return Vec::new();
+        };
+        let kinds: Vec<(Scope, &'static str)> = SYMBOL_SCOPES
+            .iter()
+            .filter_map(|&(scope, kind)| Some((Scope::new(scope).ok()?, kind)))
+            .collect();
+        let kind_of = |stack: &ScopeStack| {
+            stack.as_slice().iter().rev().find_map(|scope| {
+                kinds.iter().find(|(prefix, _)| prefix.is_prefix_of(*scope)).map(|&(_, kind)| kind)
+            })
+        };
+
+        let mut state = ParseState::new(syntax);
+        let mut stack = ScopeStack::new();
+        let mut symbols = Vec::new();
+        for (line_index, line) in LinesWithEndings::from(text).enumerate() {
+            let Ok(ops) = state.parse_line(line, &self.syntax_set) else {
+                break;
+            };
+            // A name can be split over several ranges, e.g. a heading with inline code
+            let mut current: Option<Symbol> = None;
+            for (range, op) in ScopeRangeIterator::new(&ops, line) {
+                if stack.apply(op).is_err() {
+                    break;
+                }
+                if range.is_empty() {
+                    continue;
+                }
+                match kind_of(&stack) {
+                    Some(kind) => match &mut current {
+                        Some(symbol) if symbol.kind == kind => symbol.name.push_str(&line[range]),
+                        _ => {
+                            symbols.extend(current.take());
+                            current = Some(Symbol {
+                                name: line[range.clone()].to_string(),
+                                kind,
+                                line: line_index,
+                                column: line[..range.start].chars().count(),
+                            });
+                        }
+                    },
+                    None => symbols.extend(current.take()),
+                }
+            }
+            symbols.extend(current);
+        }
+
+        symbols.retain_mut(|symbol| {
+            symbol.name = symbol.name.trim().to_string();
+            !symbol.name.is_empty()
+        });
+        symbols
+    }
+}
+
+fn to_color32(color: syntect::highlighting::Color) -> Color32 {
+    Color32::from_rgb(color.r, color.g, color.b)
+}
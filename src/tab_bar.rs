@@ -0,0 +1,236 @@
+use egui::{Align, Color32, RichText, ScrollArea, Sense, Stroke, Ui};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+use crate::git::FileStatus;
+
+// What the tab bar needs to know about a tab
+pub struct TabLabel<'a> {
+    pub title: &'a str,
+    pub path: Option<&'a Path>,
+    pub modified: bool,
+    pub pinned: bool,
+    pub git: Option<FileStatus>,
+}
+
+pub enum TabAction {
+    Select(usize),
+    Close(usize),
+    CloseOthers(usize),
+    CloseToTheRight(usize),
+    TogglePin(usize),
+    // `to` is the tab's index after the move
+    Move { from: usize, to: usize },
+    CopyPath(usize),
+    Reveal(usize),
+}
+
+#[derive(Default)]
+pub struct TabBar {
+    // Id of the tab last scrolled into view, so the strip only jumps when the active tab changes
+    scrolled_to: Option<usize>,
+}
+
+impl TabBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Draws a horizontally scrolling strip of tabs with a menu listing every tab
+    // for when they don't fit. `active_id` identifies the active tab across reorders.
+    pub fn show(&mut self, ui: &mut Ui, tabs: &[TabLabel], active: usize, active_id: usize) -> Option<TabAction> {
+        let names = labels(tabs);
+        let mut action = None;
+
+        ui.horizontal(|ui| {
+            let strip_width = (ui.available_width() - 32.0).max(0.0);
+            ScrollArea::horizontal()
+                .id_source("tab_bar")
+                .max_width(strip_width)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        action = self.show_tabs(ui, tabs, &names, active, active_id);
+                    });
+                });
+
+            ui.menu_button("☰", |ui| {
+                ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                    for (i, name) in names.iter().enumerate() {
+                        if ui.selectable_label(i == active, name).clicked() {
+                            action = Some(TabAction::Select(i));
+                            ui.close_menu();
+                        }
+                    }
+                });
+            })
+            .response
+            .on_hover_text("Show all tabs");
+        });
+
+        action
+    }
+
+    fn show_tabs(&mut self, ui: &mut Ui, tabs: &[TabLabel], names: &[String], active: usize, active_id: usize) -> Option<TabAction> {
+        let mut action = None;
+        let mut rects = Vec::with_capacity(tabs.len());
+        let mut dragged = None;
+
+        for (i, (tab, name)) in tabs.iter().zip(names).enumerate() {
+            let text = if tab.modified { format!("● {}", name) } else { name.clone() };
+            let text = match tab.git {
+                Some(status) if !status.badge().is_empty() => {
+                    RichText::new(format!("{} {}", text, status.badge())).color(status.color())
+                }
+                Some(status) => RichText::new(text).color(status.color()),
+                None => RichText::new(text),
+            };
+            let response = ui.selectable_label(i == active, text).interact(Sense::drag());
+            let response = match (tab.path, tab.git) {
+                (Some(path), Some(status)) => response.on_hover_text(format!("{} • {}", path.display(), status.label())),
+                (Some(path), None) => response.on_hover_text(path.display().to_string()),
+                (None, _) => response,
+            };
+
+            if i == active && self.scrolled_to != Some(active_id) {
+                response.scroll_to_me(Some(Align::Center));
+                self.scrolled_to = Some(active_id);
+            }
+            if response.clicked() {
+                action = Some(TabAction::Select(i));
+            }
+            if response.middle_clicked() && !tab.pinned {
+                action = Some(TabAction::Close(i));
+            }
+            if response.dragged() || response.drag_released() {
+                dragged = Some((i, response.drag_released()));
+            }
+
+            // Pinned tabs can't be closed by accident; their button unpins them instead
+            let button = if tab.pinned {
+                ui.small_button("📌").on_hover_text("Unpin")
+            } else {
+                ui.small_button("×")
+            };
+            if button.clicked() {
+                action = Some(if tab.pinned { TabAction::TogglePin(i) } else { TabAction::Close(i) });
+            }
+            rects.push(response.rect.union(button.rect));
+
+            response.context_menu(|ui| {
+                if let Some(chosen) = context_menu(ui, tab, i) {
+                    action = Some(chosen);
+                    ui.close_menu();
+                }
+            });
+        }
+
+        if let Some((from, released)) = dragged {
+            let pointer_x = ui.ctx().pointer_interact_pos().map(|p| p.x);
+            if let Some(x) = pointer_x {
+                // Drop before the first tab whose middle is right of the pointer
+                let slot = rects.iter().position(|r| x < r.center().x).unwrap_or(rects.len());
+                let to = if slot > from { slot - 1 } else { slot };
+                if released {
+                    if to != from {
+                        action = Some(TabAction::Move { from, to });
+                    }
+                } else {
+                    let marker_x = rects.get(slot).map_or_else(|| rects[rects.len() - 1].right(), |r| r.left());
+                    let y = rects[from].y_range();
+                    ui.painter()
+                        .vline(marker_x - 1.0, y, Stroke::new(2.0, Color32::from_rgb(100, 150, 255)));
+                }
+            }
+        }
+
+        action
+    }
+}
+
+fn context_menu(ui: &mut Ui, tab: &TabLabel, index: usize) -> Option<TabAction> {
+    let mut action = None;
+    if ui.button(if tab.pinned { "Unpin" } else { "Pin" }).clicked() {
+        action = Some(TabAction::TogglePin(index));
+    }
+    ui.separator();
+    if ui.button("Close").clicked() {
+        action = Some(TabAction::Close(index));
+    }
+    if ui.button("Close Others").clicked() {
+        action = Some(TabAction::CloseOthers(index));
+    }
+    if ui.button("Close to the Right").clicked() {
+        action = Some(TabAction::CloseToTheRight(index));
+    }
+    ui.separator();
+    if ui.add_enabled(tab.path.is_some(), egui::Button::new("Copy Path")).clicked() {
+        action = Some(TabAction::CopyPath(index));
+    }
+    if ui
+        .add_enabled(tab.path.is_some(), egui::Button::new("Reveal in File Explorer"))
+        .clicked()
+    {
+        action = Some(TabAction::Reveal(index));
+    }
+    action
+}
+
+// Tab titles, with parent folders added to files that share a name. Each group
+// of same-named files gets as many trailing folders as it takes to tell them apart.
+fn labels(tabs: &[TabLabel]) -> Vec<String> {
+    let mut groups: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, tab) in tabs.iter().enumerate() {
+        if tab.path.is_some() {
+            groups.entry(tab.title).or_default().push(i);
+        }
+    }
+
+    let mut labels: Vec<String> = tabs.iter().map(|tab| tab.title.to_string()).collect();
+    for members in groups.values().filter(|members| members.len() > 1) {
+        let folders: Vec<Vec<String>> = members
+            .iter()
+            .map(|&i| tabs[i].path.map(parent_folders).unwrap_or_default())
+            .collect();
+        let deepest = folders.iter().map(Vec::len).max().unwrap_or(0);
+
+        let mut hints = Vec::new();
+        for depth in 1..=deepest.max(1) {
+            hints = folders.iter().map(|f| folder_hint(f, depth)).collect();
+            let mut unique = hints.clone();
+            unique.sort();
+            unique.dedup();
+            if unique.len() == hints.len() {
+                break;
+            }
+        }
+
+        for (&i, hint) in members.iter().zip(hints) {
+            if !hint.is_empty() {
+                labels[i] = format!("{} — {}", tabs[i].title, hint);
+            }
+        }
+    }
+    labels
+}
+
+// Names of the folders containing `path`, innermost first
+fn parent_folders(path: &Path) -> Vec<String> {
+    path.parent()
+        .map(|parent| {
+            parent
+                .components()
+                .rev()
+                .filter_map(|c| match c {
+                    Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn folder_hint(folders: &[String], depth: usize) -> String {
+    let mut hint: Vec<&str> = folders.iter().take(depth).map(String::as_str).collect();
+    hint.reverse();
+    hint.join("/")
+}
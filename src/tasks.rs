@@ -0,0 +1,566 @@
+use crate::diagnostics::{Diagnostic, Diagnostics, Position, Severity};
+use crate::error::{Error, Result};
+use egui::{Context, RichText, Ui};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const TASKS_FILE: &str = "tasks.toml";
+
+// Older output is dropped so a chatty task can't fill the memory
+const OUTPUT_LIMIT: usize = 50_000;
+
+const TASKS_TEMPLATE: &str = r#"# Tasks for this workspace, run with "Run Task..." (Ctrl+Shift+B).
+#
+# `command` is run directly, not through a shell. `cwd` is relative to the
+# workspace folder. `problem_matcher` turns compiler output into entries in
+# the Problems panel: "rustc" or "gcc" (also for clang).
+
+[tasks.build]
+command = "cargo"
+args = ["build"]
+problem_matcher = "rustc"
+
+# [tasks.test]
+# command = "cargo"
+# args = ["test"]
+# env = { RUST_BACKTRACE = "1" }
+# problem_matcher = "rustc"
+"#;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ProblemMatcher {
+    // `error[E0308]: message` followed by ` --> file:line:column`
+    Rustc,
+    // `file:line:column: error: message`, as gcc and clang print them
+    Gcc,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TaskConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub problem_matcher: Option<ProblemMatcher>,
+}
+
+impl TaskConfig {
+    pub fn command_line(&self) -> String {
+        std::iter::once(&self.command).chain(&self.args).cloned().collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct TasksFile {
+    #[serde(default)]
+    tasks: BTreeMap<String, TaskConfig>,
+}
+
+// The tasks in the workspace's tasks.toml, by name; none when there is no file
+pub fn load(root: &Path) -> Result<BTreeMap<String, TaskConfig>> {
+    let path = root.join(TASKS_FILE);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = fs::read_to_string(&path).map_err(|e| Error::io("read", &path, e))?;
+    let file: TasksFile = toml::from_str(&content).map_err(|e| Error::toml(&path, &content, e))?;
+    Ok(file.tasks)
+}
+
+// The workspace's tasks.toml, written from a template if it doesn't exist yet
+pub fn ensure_file(root: &Path) -> Result<PathBuf> {
+    let path = root.join(TASKS_FILE);
+    if !path.exists() {
+        fs::write(&path, TASKS_TEMPLATE).map_err(|e| Error::io("create", &path, e))?;
+    }
+    Ok(path)
+}
+
+// A problem as the output names it; the path may be relative
+struct Problem {
+    path: String,
+    position: Position,
+    severity: Severity,
+    message: String,
+}
+
+// Finds problems in a task's output, one line at a time
+struct Matcher {
+    kind: ProblemMatcher,
+    // rustc's message line, waiting for the location on a later line
+    pending: Option<(Severity, String)>,
+}
+
+impl Matcher {
+    fn new(kind: ProblemMatcher) -> Self {
+        Self { kind, pending: None }
+    }
+
+    fn feed(&mut self, line: &str) -> Option<Problem> {
+        match self.kind {
+            ProblemMatcher::Rustc => self.feed_rustc(line),
+            ProblemMatcher::Gcc => parse_gcc(line),
+        }
+    }
+
+    fn feed_rustc(&mut self, line: &str) -> Option<Problem> {
+        let severity = if line.starts_with("error") {
+            Some(Severity::Error)
+        } else if line.starts_with("warning") {
+            Some(Severity::Warning)
+        } else {
+            None
+        };
+        if let Some(severity) = severity {
+            // `error[E0308]: mismatched types`; other lines starting with a word,
+            // like `note:`, belong to the previous message
+            let message = line.split_once(": ").map(|(_, message)| message.to_string());
+            self.pending = message.map(|message| (severity, message));
+            return None;
+        }
+        if !line.starts_with(' ') && !line.is_empty() {
+            self.pending = None;
+            return None;
+        }
+        let location = line.trim_start().strip_prefix("--> ")?;
+        let (severity, message) = self.pending.take()?;
+        let (path, line, column) = parse_location(location)?;
+        Some(Problem {
+            path,
+            position: Position { line, column },
+            severity,
+            message,
+        })
+    }
+}
+
+fn parse_gcc(line: &str) -> Option<Problem> {
+    let (location, severity, message) = [
+        (": fatal error: ", Severity::Error),
+        (": error: ", Severity::Error),
+        (": warning: ", Severity::Warning),
+    ]
+    .into_iter()
+    .filter_map(|(marker, severity)| {
+        let index = line.find(marker)?;
+        Some((index, &line[..index], severity, &line[index + marker.len()..]))
+    })
+    .min_by_key(|(index, ..)| *index)
+    .map(|(_, location, severity, message)| (location, severity, message))?;
+    let (path, line, column) = parse_location(location)?;
+    Some(Problem {
+        path,
+        position: Position { line, column },
+        severity,
+        message: message.to_string(),
+    })
+}
+
+// `file:line:column` or `file:line`, one-based; returns zero-based positions
+fn parse_location(text: &str) -> Option<(String, usize, usize)> {
+    let (rest, last) = text.trim().rsplit_once(':')?;
+    let last: usize = last.parse().ok()?;
+    let (path, line, column) = match rest.rsplit_once(':') {
+        Some((path, line)) if line.parse::<usize>().is_ok() => (path, line.parse().ok()?, last),
+        _ => (rest, last, 1),
+    };
+    if path.is_empty() {
+        return None;
+    }
+    Some((path.to_string(), line.saturating_sub(1), column.saturating_sub(1)))
+}
+
+// Tasks get their own process group so killing one also stops what it started,
+// like the rustc processes of `cargo build`
+#[cfg(unix)]
+mod process_group {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command};
+
+    pub fn isolate(command: &mut Command) {
+        command.process_group(0);
+    }
+
+    pub fn kill(child: &mut Child) {
+        // The group's id is the id of the task, which leads it
+        let group = child.id() as libc::pid_t;
+        // SAFETY: killpg only sends a signal, it touches no memory
+        if unsafe { libc::killpg(group, libc::SIGKILL) } != 0 {
+            let _ = child.kill();
+        }
+    }
+}
+
+// Windows has no process groups to signal; taskkill ends the whole process tree
+#[cfg(not(unix))]
+mod process_group {
+    use std::process::{Child, Command};
+
+    pub fn isolate(_command: &mut Command) {}
+
+    pub fn kill(child: &mut Child) {
+        let status = Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &child.id().to_string()])
+            .status();
+        if !status.is_ok_and(|status| status.success()) {
+            let _ = child.kill();
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TaskState {
+    Running,
+    // The exit code; None when ended by a signal
+    Finished(Option<i32>),
+    Killed,
+}
+
+struct OutputLine {
+    text: String,
+    // Where a matched problem is, as a canonical path
+    problem: Option<(PathBuf, Position, Severity)>,
+}
+
+// One run of a task, with its output
+struct TaskRun {
+    name: String,
+    child: Child,
+    lines: Receiver<String>,
+    output: Vec<OutputLine>,
+    matcher: Option<Matcher>,
+    directory: PathBuf,
+    // Problems found so far, by file, for the Problems panel
+    problems: BTreeMap<PathBuf, Vec<Diagnostic>>,
+    state: TaskState,
+    started: Instant,
+    duration: Option<Duration>,
+}
+
+impl TaskRun {
+    fn start(name: &str, config: &TaskConfig, root: &Path, waker: Context) -> io::Result<Self> {
+        let directory = config.cwd.as_ref().map_or_else(|| root.to_path_buf(), |cwd| root.join(cwd));
+        let mut command = Command::new(&config.command);
+        command
+            .args(&config.args)
+            .envs(&config.env)
+            .current_dir(&directory)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        process_group::isolate(&mut command);
+        let mut child = command.spawn()?;
+
+        let (sender, lines) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            read_lines(stdout, sender.clone(), waker.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            read_lines(stderr, sender, waker);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            child,
+            lines,
+            output: vec![OutputLine { text: format!("> {}", config.command_line()), problem: None }],
+            matcher: config.problem_matcher.map(Matcher::new),
+            directory,
+            problems: BTreeMap::new(),
+            state: TaskState::Running,
+            started: Instant::now(),
+            duration: None,
+        })
+    }
+
+    fn producer(&self) -> String {
+        format!("task:{}", self.name)
+    }
+
+    // Takes in new output; returns true when the task has just ended
+    fn poll(&mut self, diagnostics: &mut Diagnostics) -> bool {
+        let producer = self.producer();
+        while let Ok(text) = self.lines.try_recv() {
+            let problem = self.matcher.as_mut().and_then(|matcher| matcher.feed(&text));
+            let problem = problem.and_then(|problem| {
+                let path = self.directory.join(&problem.path).canonicalize().ok()?;
+                let diagnostic = Diagnostic {
+                    start: problem.position,
+                    end: problem.position,
+                    severity: problem.severity,
+                    message: problem.message,
+                    source: Some(self.name.clone()),
+                };
+                let file = self.problems.entry(path.clone()).or_default();
+                file.push(diagnostic);
+                diagnostics.set(path.clone(), &producer, file.clone());
+                Some((path, problem.position, problem.severity))
+            });
+            self.output.push(OutputLine { text, problem });
+        }
+        if self.output.len() > OUTPUT_LIMIT {
+            self.output.drain(..self.output.len() - OUTPUT_LIMIT);
+        }
+
+        if self.state != TaskState::Running {
+            return false;
+        }
+        match self.child.try_wait() {
+            Ok(Some(status)) => {
+                self.state = TaskState::Finished(status.code());
+                self.duration = Some(self.started.elapsed());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn kill(&mut self) {
+        if self.state == TaskState::Running {
+            process_group::kill(&mut self.child);
+            let _ = self.child.wait();
+            self.state = TaskState::Killed;
+            self.duration = Some(self.started.elapsed());
+        }
+    }
+
+    fn status(&self) -> String {
+        let seconds = self.duration.map(|d| format!(" in {:.1}s", d.as_secs_f32())).unwrap_or_default();
+        match self.state {
+            TaskState::Running => "running".to_string(),
+            TaskState::Finished(Some(0)) => format!("succeeded{}", seconds),
+            TaskState::Finished(Some(code)) => format!("failed with exit code {}{}", code, seconds),
+            TaskState::Finished(None) => format!("ended by a signal{}", seconds),
+            TaskState::Killed => "killed".to_string(),
+        }
+    }
+}
+
+impl Drop for TaskRun {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+fn read_lines(pipe: impl Read + Send + 'static, sender: Sender<String>, waker: Context) {
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).split(b'\n').map_while(|line| line.ok()) {
+            let line = String::from_utf8_lossy(&line).trim_end_matches('\r').to_string();
+            if sender.send(line).is_err() {
+                return;
+            }
+            waker.request_repaint();
+        }
+        // The pipe closes when the task exits
+        waker.request_repaint();
+    });
+}
+
+pub enum TasksAction {
+    Open(PathBuf, Position),
+    Rerun(String),
+    Hide,
+}
+
+// Task runs and the panel showing their output
+#[derive(Default)]
+pub struct Tasks {
+    runs: Vec<TaskRun>,
+    selected: usize,
+}
+
+impl Tasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts a task, replacing an earlier run of it, which is killed if it is
+    // still running. Its problems from before are cleared.
+    pub fn run(
+        &mut self,
+        name: &str,
+        config: &TaskConfig,
+        root: &Path,
+        diagnostics: &mut Diagnostics,
+        waker: Context,
+    ) -> io::Result<()> {
+        let run = TaskRun::start(name, config, root, waker)?;
+        diagnostics.clear_producer(&run.producer());
+        match self.runs.iter().position(|run| run.name == name) {
+            Some(index) => {
+                self.runs[index] = run;
+                self.selected = index;
+            }
+            None => {
+                self.runs.push(run);
+                self.selected = self.runs.len() - 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn last_run(&self) -> Option<&str> {
+        self.runs.get(self.selected).map(|run| run.name.as_str())
+    }
+
+    pub fn kill_selected(&mut self) {
+        if let Some(run) = self.runs.get_mut(self.selected) {
+            run.kill();
+        }
+    }
+
+    // Takes in output of all runs; returns the name and outcome of runs that ended
+    pub fn poll(&mut self, diagnostics: &mut Diagnostics) -> Vec<(String, String, bool)> {
+        let mut ended = Vec::new();
+        for run in &mut self.runs {
+            if run.poll(diagnostics) {
+                ended.push((run.name.clone(), run.status(), run.state == TaskState::Finished(Some(0))));
+            }
+        }
+        ended
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) -> Option<TasksAction> {
+        let mut action = None;
+        ui.horizontal(|ui| {
+            ui.strong("Tasks");
+            for (i, run) in self.runs.iter().enumerate() {
+                let icon = match run.state {
+                    TaskState::Running => "⟳",
+                    TaskState::Finished(Some(0)) => "✔",
+                    TaskState::Finished(_) => "✖",
+                    TaskState::Killed => "■",
+                };
+                if ui.selectable_label(i == self.selected, format!("{} {}", icon, run.name)).clicked() {
+                    self.selected = i;
+                }
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.small_button("✖").on_hover_text("Hide").clicked() {
+                    action = Some(TasksAction::Hide);
+                }
+                let Some(run) = self.runs.get_mut(self.selected) else {
+                    return;
+                };
+                if run.state == TaskState::Running {
+                    if ui.small_button("■").on_hover_text("Kill Task").clicked() {
+                        run.kill();
+                    }
+                } else if ui.small_button("⟳").on_hover_text("Rerun Task").clicked() {
+                    action = Some(TasksAction::Rerun(run.name.clone()));
+                }
+                ui.weak(run.status());
+            });
+        });
+        ui.separator();
+
+        let Some(run) = self.runs.get(self.selected) else {
+            ui.weak("No task has run yet. Run one with \"Run Task...\".");
+            return action;
+        };
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::vertical()
+            .id_source(("task_output", &run.name))
+            .auto_shrink([false, false])
+            .stick_to_bottom(true)
+            .show_rows(ui, row_height, run.output.len(), |ui, rows| {
+                for line in &run.output[rows] {
+                    let text = RichText::new(&line.text).monospace();
+                    match &line.problem {
+                        Some((path, position, severity)) => {
+                            let link = ui.add(egui::Label::new(text.color(severity.color())).sense(egui::Sense::click()));
+                            if link.on_hover_text("Open").clicked() {
+                                action = Some(TasksAction::Open(path.clone(), *position));
+                            }
+                        }
+                        None => {
+                            ui.label(text);
+                        }
+                    }
+                }
+            });
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(problem: Option<Problem>) -> Option<(String, usize, usize, Severity, String)> {
+        problem.map(|p| (p.path, p.position.line, p.position.column, p.severity, p.message))
+    }
+
+    #[test]
+    fn rustc_messages() {
+        let mut matcher = Matcher::new(ProblemMatcher::Rustc);
+        let output = [
+            "   Compiling exodus v0.1.0 (/work/exodus)",
+            "error[E0308]: mismatched types",
+            "  --> src/main.rs:12:5",
+            "   |",
+            "12 |     1",
+            "   |     ^ expected `()`, found integer",
+            "warning: unused variable: `x`",
+            " --> src/lib.rs:3:9",
+            "note: some note",
+            " --> src/other.rs:1:1",
+        ];
+        let problems: Vec<_> = output.iter().filter_map(|line| problem(matcher.feed(line))).collect();
+        assert_eq!(
+            problems,
+            [
+                ("src/main.rs".to_string(), 11, 4, Severity::Error, "mismatched types".to_string()),
+                ("src/lib.rs".to_string(), 2, 8, Severity::Warning, "unused variable: `x`".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rustc_summary_lines_are_not_problems() {
+        let mut matcher = Matcher::new(ProblemMatcher::Rustc);
+        assert!(matcher.feed("error: could not compile `exodus` (bin \"exodus\") due to 1 previous error").is_none());
+        assert!(matcher.feed("").is_none());
+        assert!(matcher.feed("warning: build failed, waiting for other jobs to finish...").is_none());
+        assert!(matcher.feed("    Finished `dev` profile").is_none());
+    }
+
+    #[test]
+    fn gcc_messages() {
+        assert_eq!(
+            problem(parse_gcc("main.c:4:10: fatal error: missing.h: No such file or directory")),
+            Some(("main.c".to_string(), 3, 9, Severity::Error, "missing.h: No such file or directory".to_string()))
+        );
+        assert_eq!(
+            problem(parse_gcc("src/a.c:7:3: warning: unused variable 'x' [-Wunused-variable]")),
+            Some(("src/a.c".to_string(), 6, 2, Severity::Warning, "unused variable 'x' [-Wunused-variable]".to_string()))
+        );
+        // The first marker wins, even when the message quotes another one
+        assert_eq!(
+            problem(parse_gcc("b.c:1:1: error: expected ': warning: ' here")),
+            Some(("b.c".to_string(), 0, 0, Severity::Error, "expected ': warning: ' here".to_string()))
+        );
+        assert!(parse_gcc("In file included from main.c:1:").is_none());
+        assert!(parse_gcc("make: *** [Makefile:2: all] Error 1").is_none());
+    }
+
+    #[test]
+    fn locations() {
+        assert_eq!(parse_location("src/main.rs:12:5"), Some(("src/main.rs".to_string(), 11, 4)));
+        assert_eq!(parse_location(" Makefile:3 "), Some(("Makefile".to_string(), 2, 0)));
+        assert_eq!(parse_location("C:\\code\\a.c:2:8"), Some(("C:\\code\\a.c".to_string(), 1, 7)));
+        assert_eq!(parse_location("a.rs:0:0"), Some(("a.rs".to_string(), 0, 0)));
+        assert_eq!(parse_location(":3:4"), None);
+        assert_eq!(parse_location("main.rs"), None);
+        assert_eq!(parse_location("main.rs:x"), None);
+    }
+}
@@ -0,0 +1,638 @@
+use crate::vt::{Color, Point, Screen, Style};
+use egui::text::LayoutJob;
+use egui::{Color32, Context, CursorIcon, Event, FontId, Key, Modifiers, Pos2, Rect, Sense, Stroke, TextFormat, Ui, Vec2};
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+const ANSI_COLORS: [Color32; 16] = [
+    Color32::from_rgb(0, 0, 0),
+    Color32::from_rgb(205, 49, 49),
+    Color32::from_rgb(13, 188, 121),
+    Color32::from_rgb(229, 229, 16),
+    Color32::from_rgb(36, 114, 200),
+    Color32::from_rgb(188, 63, 188),
+    Color32::from_rgb(17, 168, 205),
+    Color32::from_rgb(229, 229, 229),
+    Color32::from_rgb(102, 102, 102),
+    Color32::from_rgb(241, 76, 76),
+    Color32::from_rgb(35, 209, 139),
+    Color32::from_rgb(245, 245, 67),
+    Color32::from_rgb(59, 142, 234),
+    Color32::from_rgb(214, 112, 214),
+    Color32::from_rgb(41, 184, 219),
+    Color32::from_rgb(255, 255, 255),
+];
+
+enum PtyEvent {
+    Output(Vec<u8>),
+    Exited,
+}
+
+// A shell running in a pseudo-terminal
+struct Terminal {
+    number: usize,
+    shell: String,
+    screen: Screen,
+    master: Box<dyn MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn Child + Send + Sync>,
+    events: Receiver<PtyEvent>,
+    exited: bool,
+    // Lines scrolled back from the bottom, and the part of a line the wheel moved
+    scroll: usize,
+    scroll_remainder: f32,
+    // Where the selection was started and where it was dragged to
+    selection: Option<(Point, Point)>,
+}
+
+impl Terminal {
+    fn start(number: usize, directory: Option<&Path>, waker: Context) -> Result<Self, String> {
+        let (rows, columns) = (24, 80);
+        let size = PtySize { rows, cols: columns, pixel_width: 0, pixel_height: 0 };
+        let pair = native_pty_system().openpty(size).map_err(|e| e.to_string())?;
+
+        let mut command = CommandBuilder::new_default_prog();
+        command.env("TERM", "xterm-256color");
+        command.env("COLORTERM", "truecolor");
+        if let Some(directory) = directory {
+            command.cwd(directory);
+        }
+        let shell = command.get_shell();
+        let shell = Path::new(&shell).file_stem().map_or(shell.clone(), |name| name.to_string_lossy().into_owned());
+        let child = pair.slave.spawn_command(command).map_err(|e| e.to_string())?;
+        // Only the shell holds the other end now, so reading ends when it exits
+        drop(pair.slave);
+        let mut reader = pair.master.try_clone_reader().map_err(|e| e.to_string())?;
+        let writer = pair.master.take_writer().map_err(|e| e.to_string())?;
+
+        let (sender, events) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 8192];
+            while let Ok(read @ 1..) = reader.read(&mut buffer) {
+                if sender.send(PtyEvent::Output(buffer[..read].to_vec())).is_err() {
+                    return;
+                }
+                waker.request_repaint();
+            }
+            let _ = sender.send(PtyEvent::Exited);
+            waker.request_repaint();
+        });
+
+        Ok(Self {
+            number,
+            shell,
+            screen: Screen::new(rows as usize, columns as usize),
+            master: pair.master,
+            writer,
+            child,
+            events,
+            exited: false,
+            scroll: 0,
+            scroll_remainder: 0.0,
+            selection: None,
+        })
+    }
+
+    fn title(&self) -> String {
+        format!("{}: {}", self.number, self.screen.title().unwrap_or(&self.shell))
+    }
+
+    fn poll(&mut self) {
+        let top = self.screen.screen_top();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                PtyEvent::Output(bytes) => {
+                    self.screen.process(&bytes);
+                    let replies = self.screen.take_replies();
+                    if !replies.is_empty() {
+                        self.write(&replies);
+                    }
+                }
+                PtyEvent::Exited => self.exited = true,
+            }
+        }
+        // While scrolled back, the view stays on the same lines as output comes in
+        if self.scroll > 0 {
+            let scrolled = self.screen.screen_top() - top;
+            self.scroll = (self.scroll + scrolled).min(self.max_scroll());
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let _ = self.writer.write_all(bytes).and_then(|_| self.writer.flush());
+    }
+
+    // Input takes the view back to the bottom
+    fn send(&mut self, bytes: &[u8]) {
+        self.scroll = 0;
+        self.write(bytes);
+    }
+
+    fn paste(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\r").replace('\n', "\r");
+        if self.screen.bracketed_paste() {
+            self.send(format!("\x1b[200~{}\x1b[201~", text).as_bytes());
+        } else {
+            self.send(text.as_bytes());
+        }
+    }
+
+    fn resize(&mut self, rows: usize, columns: usize) {
+        if (rows, columns) == (self.screen.rows(), self.screen.columns()) {
+            return;
+        }
+        self.screen.resize(rows, columns);
+        let size = PtySize { rows: rows as u16, cols: columns as u16, pixel_width: 0, pixel_height: 0 };
+        let _ = self.master.resize(size);
+        self.scroll = self.scroll.min(self.max_scroll());
+    }
+
+    fn max_scroll(&self) -> usize {
+        if self.screen.is_alternate() {
+            0
+        } else {
+            self.screen.screen_top() - self.screen.first_line()
+        }
+    }
+
+    fn scroll_by(&mut self, lines: isize) {
+        self.scroll = self.scroll.saturating_add_signed(lines).min(self.max_scroll());
+    }
+
+    // The selection in order, with an exclusive end
+    fn selected(&self) -> Option<(Point, Point)> {
+        let (anchor, head) = self.selection?;
+        let (start, end) = if anchor <= head { (anchor, head) } else { (head, anchor) };
+        Some((start, Point { line: end.line, column: end.column + 1 }))
+    }
+
+    fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selected()?;
+        Some(self.screen.text(start, end))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // Reap the shell so it doesn't linger as a zombie
+        if self.child.kill().is_ok() {
+            let _ = self.child.wait();
+        }
+    }
+}
+
+pub enum TerminalAction {
+    New,
+    Hide,
+    // A `file:line:column` link was clicked; line and column are zero-based
+    Open(PathBuf, usize, usize),
+}
+
+// The shells in the bottom panel, one shown at a time
+#[derive(Default)]
+pub struct TerminalPanel {
+    terminals: Vec<Terminal>,
+    active: usize,
+    next_number: usize,
+    // Keys go to the shell. Kept apart from egui's focus, which Escape clears
+    // while the shell needs it.
+    focused: bool,
+    focus_requested: bool,
+}
+
+impl TerminalPanel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terminals.is_empty()
+    }
+
+    pub fn has_focus(&self) -> bool {
+        self.focused && !self.terminals.is_empty()
+    }
+
+    pub fn focus(&mut self) {
+        self.focus_requested = true;
+    }
+
+    pub fn blur(&mut self) {
+        self.focused = false;
+        self.focus_requested = false;
+    }
+
+    pub fn new_terminal(&mut self, ctx: &Context, directory: Option<&Path>) -> Result<(), String> {
+        self.next_number += 1;
+        let terminal = Terminal::start(self.next_number, directory, ctx.clone())
+            .map_err(|e| format!("Could not start a terminal: {}", e))?;
+        self.terminals.push(terminal);
+        self.active = self.terminals.len() - 1;
+        self.focus_requested = true;
+        Ok(())
+    }
+
+    pub fn kill_active(&mut self) {
+        if self.active < self.terminals.len() {
+            self.terminals.remove(self.active);
+            self.active = self.active.min(self.terminals.len().saturating_sub(1));
+        }
+    }
+
+    // Takes in output of all shells, also while the panel is hidden; shells
+    // that exited are closed
+    pub fn poll(&mut self) {
+        for terminal in &mut self.terminals {
+            terminal.poll();
+        }
+        let before = self.terminals.len();
+        self.terminals.retain(|terminal| !terminal.exited);
+        if self.terminals.len() != before {
+            self.active = self.active.min(self.terminals.len().saturating_sub(1));
+        }
+    }
+
+    // Sends typed keys to the focused shell before shortcuts are dispatched,
+    // so Ctrl+C, Ctrl+R and the like reach it. Shortcuts with Ctrl+Shift, and
+    // those `skip` accepts, are left to the editor.
+    pub fn handle_keys(&mut self, ctx: &Context, skip: impl Fn(Modifiers, Key) -> bool) {
+        let other_focus = ctx.memory(|m| m.focus().is_some_and(|id| id != view_id()));
+        if other_focus {
+            self.focused = false;
+        }
+        if !self.has_focus() {
+            return;
+        }
+        let terminal = &mut self.terminals[self.active];
+        let mut input = Vec::new();
+        let mut copied = None;
+        // Alt+letter sends ESC and the letter; the text event that follows is dropped
+        let mut skip_text = false;
+
+        ctx.input_mut(|i| {
+            i.events.retain(|event| match event {
+                Event::Text(text) => {
+                    if !std::mem::take(&mut skip_text) {
+                        input.extend_from_slice(text.as_bytes());
+                    }
+                    false
+                }
+                Event::Paste(text) => {
+                    terminal.paste(text);
+                    false
+                }
+                // Handled with the key events they come with
+                Event::Copy | Event::Cut => false,
+                Event::Key { key, pressed: true, modifiers, .. } => {
+                    let (key, modifiers) = (*key, *modifiers);
+                    if modifiers.command && key == Key::V {
+                        return false;
+                    }
+                    if modifiers.command && key == Key::C && (modifiers.shift || terminal.selection.is_some()) {
+                        copied = terminal.selected_text();
+                        terminal.selection = None;
+                        return false;
+                    }
+                    if modifiers.shift && matches!(key, Key::PageUp | Key::PageDown) {
+                        let page = terminal.screen.rows() as isize;
+                        terminal.scroll_by(if key == Key::PageUp { page } else { -page });
+                        return false;
+                    }
+                    if (modifiers.command && modifiers.shift) || skip(modifiers, key) {
+                        return true;
+                    }
+                    if modifiers.alt && !modifiers.command && key.name().len() == 1 {
+                        let letter = key.name().to_string();
+                        let letter = if modifiers.shift { letter } else { letter.to_lowercase() };
+                        input.push(0x1b);
+                        input.extend_from_slice(letter.as_bytes());
+                        skip_text = true;
+                        return false;
+                    }
+                    match key_bytes(key, modifiers, terminal.screen.application_cursor()) {
+                        Some(bytes) => {
+                            input.extend(bytes);
+                            false
+                        }
+                        None => true,
+                    }
+                }
+                _ => true,
+            });
+        });
+
+        if !input.is_empty() {
+            terminal.send(&input);
+        }
+        if let Some(text) = copied {
+            ctx.output_mut(|o| o.copied_text = text);
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut Ui, root: Option<&Path>) -> Option<TerminalAction> {
+        let mut action = None;
+        ui.horizontal(|ui| {
+            for (i, terminal) in self.terminals.iter().enumerate() {
+                if ui.selectable_label(i == self.active, terminal.title()).clicked() {
+                    self.active = i;
+                    self.focus_requested = true;
+                }
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.small_button("✖").on_hover_text("Hide Terminal").clicked() {
+                    action = Some(TerminalAction::Hide);
+                }
+                if ui.small_button("🗑").on_hover_text("Kill Terminal").clicked() {
+                    self.kill_active();
+                }
+                if ui.small_button("+").on_hover_text("New Terminal").clicked() {
+                    action = Some(TerminalAction::New);
+                }
+            });
+        });
+        ui.separator();
+
+        let focus_requested = std::mem::take(&mut self.focus_requested);
+        let Some(terminal) = self.terminals.get_mut(self.active) else {
+            return action;
+        };
+
+        let font = egui::TextStyle::Monospace.resolve(ui.style());
+        let cell = ui.fonts(|f| Vec2::new(f.glyph_width(&font, 'M'), f.row_height(&font)));
+        let (rect, _) = ui.allocate_exact_size(ui.available_size(), Sense::hover());
+        let response = ui.interact(rect, view_id(), Sense::click_and_drag());
+        let rows = (rect.height() / cell.y).floor().max(1.0) as usize;
+        let columns = (rect.width() / cell.x).floor().max(2.0) as usize;
+        terminal.resize(rows, columns);
+        let top = terminal.screen.screen_top() - terminal.scroll;
+        let point_at = |pos: Pos2| Point {
+            line: top + (((pos.y - rect.top()) / cell.y).max(0.0) as usize).min(rows - 1),
+            column: (((pos.x - rect.left()) / cell.x).max(0.0) as usize).min(columns - 1),
+        };
+
+        // Focus
+        if focus_requested || response.clicked() || response.drag_started() {
+            self.focused = true;
+            ui.memory_mut(|m| m.request_focus(response.id));
+        } else if ui.input(|i| i.pointer.any_pressed()) && !response.hovered() {
+            self.focused = false;
+        }
+
+        // Scrolling back
+        if response.hovered() {
+            terminal.scroll_remainder += ui.input(|i| i.scroll_delta.y) / cell.y;
+            let lines = terminal.scroll_remainder.trunc();
+            terminal.scroll_remainder -= lines;
+            terminal.scroll_by(lines as isize);
+        }
+
+        // Selection
+        let pointer = response.interact_pointer_pos();
+        if let (true, Some(pos)) = (response.drag_started(), pointer) {
+            terminal.selection = Some((point_at(pos), point_at(pos)));
+        } else if let (true, Some(pos), Some((anchor, _))) = (response.dragged(), pointer, terminal.selection) {
+            terminal.selection = Some((anchor, point_at(pos)));
+        }
+
+        // Links, underlined and clickable while Ctrl is held
+        let ctrl = ui.input(|i| i.modifiers.command);
+        let link = response.hover_pos().filter(|_| ctrl).and_then(|pos| {
+            let point = point_at(pos);
+            let text: String = terminal.screen.line(point.line)?.cells.iter().map(|cell| cell.c).collect();
+            let link = link_at(&text, point.column)?;
+            let path = resolve_link(root, &link.path)?;
+            Some((point.line, link, path))
+        });
+        if let Some((_, link, path)) = &link {
+            ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
+            if response.clicked() {
+                let (line, column) = (link.line.unwrap_or(1), link.column.unwrap_or(1));
+                action = Some(TerminalAction::Open(path.clone(), line.saturating_sub(1), column.saturating_sub(1)));
+            }
+        } else if response.clicked() {
+            terminal.selection = None;
+        }
+
+        let painter = ui.painter_at(rect);
+        let visuals = ui.visuals();
+        let colors = Colors {
+            foreground: visuals.text_color(),
+            background: visuals.extreme_bg_color,
+            selection: visuals.selection.bg_fill,
+        };
+        painter.rect_filled(rect, 0.0, colors.background);
+        let selected = terminal.selected();
+        for row in 0..rows {
+            let number = top + row;
+            let Some(line) = terminal.screen.line(number) else {
+                continue;
+            };
+            let link_range = link.as_ref().filter(|(line, ..)| *line == number).map(|(_, link, _)| link.range.clone());
+            let selection = selected.map(|(start, end)| {
+                let from = if start.line == number { start.column } else { 0 };
+                let to = if end.line == number { end.column } else { usize::MAX };
+                if (start.line..=end.line).contains(&number) { from..to } else { 0..0 }
+            });
+
+            let mut job = LayoutJob::default();
+            let mut run = String::new();
+            let mut run_format = None;
+            for (column, cell) in line.cells.iter().enumerate() {
+                let highlighted = selection.as_ref().is_some_and(|s| s.contains(&column));
+                let linked = link_range.as_ref().is_some_and(|r| r.contains(&column));
+                let format = text_format(cell.style, &font, &colors, highlighted, linked);
+                if run_format.as_ref().is_some_and(|f| *f != format) {
+                    job.append(&std::mem::take(&mut run), 0.0, run_format.take().unwrap_or_default());
+                }
+                run.push(cell.c);
+                run_format = Some(format);
+            }
+            if let Some(format) = run_format {
+                job.append(&run, 0.0, format);
+            }
+            let galley = ui.fonts(|f| f.layout_job(job));
+            painter.galley(rect.left_top() + Vec2::new(0.0, row as f32 * cell.y), galley);
+        }
+
+        if let Some(cursor) = terminal.screen.cursor().filter(|cursor| cursor.line >= top && cursor.line < top + rows) {
+            let min = rect.left_top() + Vec2::new(cursor.column as f32 * cell.x, (cursor.line - top) as f32 * cell.y);
+            let cursor_rect = Rect::from_min_size(min, cell);
+            if self.focused {
+                painter.rect_filled(cursor_rect, 0.0, colors.foreground.gamma_multiply(0.5));
+            } else {
+                painter.rect_stroke(cursor_rect, 0.0, Stroke::new(1.0, colors.foreground.gamma_multiply(0.5)));
+            }
+        }
+        action
+    }
+}
+
+fn view_id() -> egui::Id {
+    egui::Id::new("terminal_view")
+}
+
+struct Colors {
+    foreground: Color32,
+    background: Color32,
+    selection: Color32,
+}
+
+fn text_format(style: Style, font: &FontId, colors: &Colors, selected: bool, link: bool) -> TextFormat {
+    // Bold text in one of the first 8 colours shows in its bright variant, as in xterm
+    let foreground = match style.foreground {
+        Color::Indexed(n @ 0..=7) if style.bold => Color::Indexed(n + 8),
+        color => color,
+    };
+    let mut foreground = to_color32(foreground).unwrap_or(colors.foreground);
+    let mut background = to_color32(style.background);
+    if style.inverse {
+        (foreground, background) = (background.unwrap_or(colors.background), Some(foreground));
+    }
+    if style.dim {
+        foreground = foreground.gamma_multiply(0.6);
+    }
+    if selected {
+        background = Some(colors.selection);
+    }
+    TextFormat {
+        font_id: font.clone(),
+        color: foreground,
+        background: background.unwrap_or(Color32::TRANSPARENT),
+        italics: style.italic,
+        underline: if style.underline || link { Stroke::new(1.0, foreground) } else { Stroke::NONE },
+        ..TextFormat::default()
+    }
+}
+
+// None is the default colour
+fn to_color32(color: Color) -> Option<Color32> {
+    match color {
+        Color::Default => None,
+        Color::Indexed(n @ 0..=15) => Some(ANSI_COLORS[n as usize]),
+        // A 6x6x6 colour cube, then 24 shades of grey
+        Color::Indexed(n @ 16..=231) => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let n = n - 16;
+            Some(Color32::from_rgb(level(n / 36), level(n / 6 % 6), level(n % 6)))
+        }
+        Color::Indexed(n) => {
+            let grey = 8 + (n - 232) * 10;
+            Some(Color32::from_rgb(grey, grey, grey))
+        }
+        Color::Rgb(r, g, b) => Some(Color32::from_rgb(r, g, b)),
+    }
+}
+
+// What a key sends to the shell, as xterm encodes it. Printable keys arrive
+// as text events instead.
+fn key_bytes(key: Key, modifiers: Modifiers, application_cursor: bool) -> Option<Vec<u8>> {
+    // xterm's modifier parameter: 1 plus Shift 1, Alt 2, Ctrl 4
+    let code = 1 + modifiers.shift as u8 + 2 * modifiers.alt as u8 + 4 * modifiers.command as u8;
+    let cursor = |c: char| {
+        if code > 1 {
+            format!("\x1b[1;{}{}", code, c)
+        } else if application_cursor {
+            format!("\x1bO{}", c)
+        } else {
+            format!("\x1b[{}", c)
+        }
+    };
+    let ss3 = |c: char| if code > 1 { format!("\x1b[1;{}{}", code, c) } else { format!("\x1bO{}", c) };
+    let tilde = |n: u8| if code > 1 { format!("\x1b[{};{}~", n, code) } else { format!("\x1b[{}~", n) };
+
+    let text = match key {
+        Key::Enter => "\r".to_string(),
+        Key::Tab if modifiers.shift => "\x1b[Z".to_string(),
+        Key::Tab => "\t".to_string(),
+        Key::Backspace if modifiers.command => "\x08".to_string(),
+        Key::Backspace => "\x7f".to_string(),
+        Key::Escape => "\x1b".to_string(),
+        Key::ArrowUp => cursor('A'),
+        Key::ArrowDown => cursor('B'),
+        Key::ArrowRight => cursor('C'),
+        Key::ArrowLeft => cursor('D'),
+        Key::Home => cursor('H'),
+        Key::End => cursor('F'),
+        Key::Insert => tilde(2),
+        Key::Delete => tilde(3),
+        Key::PageUp => tilde(5),
+        Key::PageDown => tilde(6),
+        Key::F1 => ss3('P'),
+        Key::F2 => ss3('Q'),
+        Key::F3 => ss3('R'),
+        Key::F4 => ss3('S'),
+        Key::F5 => tilde(15),
+        Key::F6 => tilde(17),
+        Key::F7 => tilde(18),
+        Key::F8 => tilde(19),
+        Key::F9 => tilde(20),
+        Key::F10 => tilde(21),
+        Key::F11 => tilde(23),
+        Key::F12 => tilde(24),
+        Key::Space if modifiers.command => "\0".to_string(),
+        // Ctrl+A to Ctrl+Z are the control characters 1 to 26
+        _ if modifiers.command && key.name().len() == 1 && key.name().as_bytes()[0].is_ascii_alphabetic() => {
+            return Some(vec![key.name().as_bytes()[0].to_ascii_uppercase() - b'A' + 1]);
+        }
+        _ => return None,
+    };
+    Some(text.into_bytes())
+}
+
+struct Link {
+    // Chars of the line
+    range: Range<usize>,
+    path: String,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+// A `path:line:column` reference, as compilers print them, around `column`
+// of a line of output. The line and column are optional.
+fn link_at(text: &str, column: usize) -> Option<Link> {
+    let is_separator = |c: char| c.is_whitespace() || "\"'`()[]<>|,".contains(c);
+    let chars: Vec<char> = text.chars().collect();
+    if chars.get(column).is_none_or(|&c| is_separator(c)) {
+        return None;
+    }
+    let start = chars[..column].iter().rposition(|&c| is_separator(c)).map_or(0, |i| i + 1);
+    let end = chars[column..].iter().position(|&c| is_separator(c)).map_or(chars.len(), |i| column + i);
+    let token: String = chars[start..end].iter().collect();
+    // Trailing punctuation belongs to the sentence around the link
+    let token = token.trim_end_matches(['.', ':', ';']);
+
+    let split_number = |text: &str| -> Option<(String, usize)> {
+        let (rest, number) = text.rsplit_once(':')?;
+        Some((rest.to_string(), number.parse().ok()?))
+    };
+    let (path, line, line_column) = match split_number(token) {
+        Some((rest, last)) => match split_number(&rest) {
+            Some((path, line)) => (path, Some(line), Some(last)),
+            None => (rest, Some(last), None),
+        },
+        None => (token.to_string(), None, None),
+    };
+    if path.is_empty() || !(path.contains('.') || path.contains('/') || path.contains('\\')) {
+        return None;
+    }
+    Some(Link {
+        range: start..start + token.chars().count(),
+        path,
+        line,
+        column: line_column,
+    })
+}
+
+// Relative paths are taken from the workspace folder, where shells start
+fn resolve_link(root: Option<&Path>, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let path = match root {
+        Some(root) if path.is_relative() => root.join(path),
+        _ => path.to_path_buf(),
+    };
+    path.is_file().then_some(path)
+}
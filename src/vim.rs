@@ -0,0 +1,1333 @@
+use egui::text_edit::TextEditState;
+use egui::{Context, Event, Key};
+use std::collections::HashMap;
+
+use crate::editor::Editor;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+
+impl Mode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "VISUAL LINE",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VimKey {
+    Char(char),
+    Ctrl(char),
+    Escape,
+    Enter,
+    Backspace,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+// Things the editor has to do on behalf of the modal layer
+pub enum VimAction {
+    Undo,
+    Redo,
+    StartSearch,
+    SearchNext,
+    SearchPrevious,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward { big: bool },
+    WordBackward { big: bool },
+    WordEnd { big: bool },
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FileStart,
+    FileEnd,
+    FindChar { c: char, forward: bool, till: bool },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Target {
+    Motion(Motion),
+    Line,
+    TextObject { inner: bool, object: char },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    Insert(char),
+    Put { before: bool },
+    Replace(char),
+    Join,
+    Undo,
+    Redo,
+    Repeat,
+    Visual { linewise: bool },
+    Search,
+    SearchNext,
+    SearchPrevious,
+    SwapAnchor,
+    Cancel,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Cmd {
+    register: Option<char>,
+    count: Option<usize>,
+    action: Action,
+}
+
+enum Parsed {
+    Incomplete,
+    Invalid,
+    Complete(Cmd),
+}
+
+#[derive(Clone, Default)]
+struct Register {
+    text: String,
+    linewise: bool,
+}
+
+// The buffer is edited as chars so motions can step one character at a time
+struct Buffer {
+    text: Vec<char>,
+    cursor: usize,
+    changed: bool,
+}
+
+pub struct Vim {
+    mode: Mode,
+    pending: Vec<VimKey>,
+    registers: HashMap<char, Register>,
+    last_change: Option<(Cmd, Vec<VimKey>)>,
+    // Change that entered insert mode, completed by the keys typed until Escape
+    insert_recording: Option<(Cmd, Vec<VimKey>)>,
+    visual_anchor: usize,
+    // Cursor and selection we last gave the text widget, to tell our block
+    // cursor apart from a selection the user made with the mouse
+    cursor: usize,
+    applied_range: Option<(usize, usize)>,
+}
+
+impl Vim {
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            registers: HashMap::new(),
+            last_change: None,
+            insert_recording: None,
+            visual_anchor: 0,
+            cursor: 0,
+            applied_range: None,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // Keys typed so far of an unfinished command, e.g. `2d`
+    pub fn pending_keys(&self) -> String {
+        self.pending
+            .iter()
+            .map(|key| match key {
+                VimKey::Char(c) => c.to_string(),
+                VimKey::Ctrl(c) => format!("^{}", c.to_ascii_uppercase()),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.mode = Mode::Normal;
+        self.pending.clear();
+        self.insert_recording = None;
+        self.applied_range = None;
+    }
+
+    // Takes the keyboard input meant for the focused editor before the text widget sees it.
+    // In insert mode the widget keeps handling typing and only Escape is intercepted.
+    pub fn handle_input(&mut self, ctx: &Context, editor: &mut Editor) -> Vec<VimAction> {
+        let Some(id) = editor.active_text_edit_id() else {
+            return Vec::new();
+        };
+        if !ctx.memory(|m| m.has_focus(id)) {
+            return Vec::new();
+        }
+
+        if self.mode == Mode::Insert {
+            let escaped = ctx.input_mut(|i| {
+                let mut escaped = false;
+                i.events.retain(|event| match event {
+                    Event::Key { key: Key::Escape, pressed: true, .. } => {
+                        escaped = true;
+                        false
+                    }
+                    _ => true,
+                });
+                if !escaped {
+                    self.record_insert_events(&i.events);
+                }
+                escaped
+            });
+
+            if escaped {
+                let Some(content) = editor.active_content() else {
+                    return Vec::new();
+                };
+                let mut buffer = Buffer::new(content, self.read_cursor(ctx, id));
+                self.leave_insert(&mut buffer);
+                self.apply(&buffer, editor);
+            }
+            return Vec::new();
+        }
+
+        let keys = ctx.input_mut(|i| {
+            let mut keys = Vec::new();
+            i.events.retain(|event| match event {
+                Event::Text(text) => {
+                    keys.extend(text.chars().map(VimKey::Char));
+                    false
+                }
+                Event::Key { key, pressed, modifiers, .. } => {
+                    if *pressed {
+                        if let Some(key) = vim_key(*key, modifiers.command || modifiers.ctrl) {
+                            keys.push(key);
+                        }
+                    }
+                    false
+                }
+                Event::Paste(_) | Event::Cut => false,
+                _ => true,
+            });
+            keys
+        });
+        if keys.is_empty() {
+            return Vec::new();
+        }
+
+        let Some(content) = editor.active_content() else {
+            return Vec::new();
+        };
+        let mut buffer = Buffer::new(content, self.read_cursor(ctx, id));
+        let mut actions = Vec::new();
+        for key in keys {
+            actions.extend(self.feed(key, &mut buffer));
+        }
+
+        if buffer.changed {
+            editor.replace_active_content(buffer.text.iter().collect());
+        }
+        self.apply(&buffer, editor);
+        actions
+    }
+
+    // Places the cursor on `char_index`, e.g. after a search jump
+    pub fn move_cursor(&mut self, editor: &mut Editor, char_index: usize) {
+        if let Some(content) = editor.active_content() {
+            let mut buffer = Buffer::new(content, char_index);
+            buffer.clamp_normal();
+            self.apply(&buffer, editor);
+        }
+    }
+
+    fn read_cursor(&self, ctx: &Context, id: egui::Id) -> usize {
+        let Some(range) = TextEditState::load(ctx, id).and_then(|s| s.ccursor_range()) else {
+            return self.cursor;
+        };
+        let current = (range.secondary.index, range.primary.index);
+        if self.applied_range == Some(current) {
+            self.cursor
+        } else {
+            range.primary.index
+        }
+    }
+
+    // Hands the cursor back to the text widget, as a one character block outside insert mode
+    fn apply(&mut self, buffer: &Buffer, editor: &mut Editor) {
+        let cursor = buffer.cursor.min(buffer.text.len());
+        let on_char = cursor < buffer.text.len() && buffer.text[cursor] != '\n';
+        let range = match self.mode {
+            Mode::Insert => (cursor, cursor),
+            Mode::Normal if on_char => (cursor, cursor + 1),
+            Mode::Normal => (cursor, cursor),
+            Mode::Visual | Mode::VisualLine => {
+                let (start, end) = self.visual_range(buffer);
+                if cursor < self.visual_anchor {
+                    (end, start)
+                } else {
+                    (start, end)
+                }
+            }
+        };
+        self.cursor = cursor;
+        self.applied_range = Some(range);
+        editor.set_active_selection(range.0, range.1);
+    }
+
+    fn record_insert_events(&mut self, events: &[Event]) {
+        let Some((_, keys)) = &mut self.insert_recording else {
+            return;
+        };
+        for event in events {
+            match event {
+                Event::Text(text) => keys.extend(text.chars().map(VimKey::Char)),
+                Event::Key { key: Key::Enter, pressed: true, .. } => keys.push(VimKey::Enter),
+                Event::Key { key: Key::Backspace, pressed: true, .. } => keys.push(VimKey::Backspace),
+                _ => {}
+            }
+        }
+    }
+
+    fn feed(&mut self, key: VimKey, buffer: &mut Buffer) -> Vec<VimAction> {
+        // Keys after a command that switched to insert mode in the same frame are typed text
+        if self.mode == Mode::Insert {
+            if key == VimKey::Escape {
+                self.leave_insert(buffer);
+            } else {
+                buffer.insert_key(key);
+                if let Some((_, keys)) = &mut self.insert_recording {
+                    keys.push(key);
+                }
+            }
+            return Vec::new();
+        }
+
+        self.pending.push(key);
+        let visual = matches!(self.mode, Mode::Visual | Mode::VisualLine);
+        match parse(&self.pending, visual) {
+            Parsed::Incomplete => Vec::new(),
+            Parsed::Invalid => {
+                self.pending.clear();
+                Vec::new()
+            }
+            Parsed::Complete(cmd) => {
+                self.pending.clear();
+                self.execute(cmd, buffer, true)
+            }
+        }
+    }
+
+    fn leave_insert(&mut self, buffer: &mut Buffer) {
+        self.mode = Mode::Normal;
+        if let Some(recording) = self.insert_recording.take() {
+            self.last_change = Some(recording);
+        }
+        if buffer.cursor > buffer.line_start(buffer.cursor) {
+            buffer.cursor -= 1;
+        }
+        buffer.clamp_normal();
+    }
+
+    fn execute(&mut self, cmd: Cmd, buffer: &mut Buffer, record: bool) -> Vec<VimAction> {
+        let count = cmd.count.unwrap_or(1);
+        let mut actions = Vec::new();
+
+        if matches!(self.mode, Mode::Visual | Mode::VisualLine) {
+            return self.execute_visual(cmd, buffer);
+        }
+
+        match cmd.action {
+            Action::Move(motion) => {
+                let (pos, _) = buffer.motion(motion, cmd.count, false);
+                buffer.cursor = pos;
+                buffer.clamp_normal();
+            }
+            Action::Operate(operator, target) => {
+                if let Some((start, end, linewise)) = self.target_range(buffer, operator, target, cmd.count) {
+                    self.operate(buffer, operator, start, end, linewise, cmd.register);
+                }
+            }
+            Action::Insert(kind) => {
+                match kind {
+                    'a' if buffer.cursor < buffer.line_end(buffer.cursor) => buffer.cursor += 1,
+                    'A' => buffer.cursor = buffer.line_end(buffer.cursor),
+                    'I' => buffer.cursor = buffer.first_non_blank(buffer.cursor),
+                    'o' => {
+                        let end = buffer.line_end(buffer.cursor);
+                        buffer.insert(end, "\n");
+                        buffer.cursor = end + 1;
+                    }
+                    'O' => {
+                        let start = buffer.line_start(buffer.cursor);
+                        buffer.insert(start, "\n");
+                        buffer.cursor = start;
+                    }
+                    _ => {}
+                }
+                self.mode = Mode::Insert;
+            }
+            Action::Put { before } => {
+                let register = self.registers.get(&cmd.register.unwrap_or('"')).cloned().unwrap_or_default();
+                if !register.text.is_empty() {
+                    buffer.put(&register.text.repeat(count), register.linewise, before);
+                }
+            }
+            Action::Replace(c) => {
+                let end = buffer.cursor + count;
+                if end <= buffer.line_end(buffer.cursor) {
+                    buffer.delete(buffer.cursor, end);
+                    buffer.insert(buffer.cursor, &c.to_string().repeat(count));
+                    buffer.cursor = end - 1;
+                }
+            }
+            Action::Join => {
+                for _ in 0..count.max(2) - 1 {
+                    buffer.join_line();
+                }
+            }
+            Action::Undo => actions.extend((0..count).map(|_| VimAction::Undo)),
+            Action::Redo => actions.extend((0..count).map(|_| VimAction::Redo)),
+            Action::Repeat => {
+                if let Some((mut change, keys)) = self.last_change.clone() {
+                    if cmd.count.is_some() {
+                        change.count = cmd.count;
+                    }
+                    actions.extend(self.execute(change, buffer, false));
+                    if self.mode == Mode::Insert {
+                        for key in &keys {
+                            buffer.insert_key(*key);
+                        }
+                        self.insert_recording = None;
+                        self.leave_insert(buffer);
+                    }
+                }
+                return actions;
+            }
+            Action::Visual { linewise } => {
+                self.mode = if linewise { Mode::VisualLine } else { Mode::Visual };
+                self.visual_anchor = buffer.cursor;
+            }
+            Action::Search => actions.push(VimAction::StartSearch),
+            Action::SearchNext => actions.extend((0..count).map(|_| VimAction::SearchNext)),
+            Action::SearchPrevious => actions.extend((0..count).map(|_| VimAction::SearchPrevious)),
+            Action::SwapAnchor | Action::Cancel => {}
+        }
+
+        if record && is_change(cmd.action) {
+            if self.mode == Mode::Insert {
+                self.insert_recording = Some((cmd, Vec::new()));
+            } else {
+                self.last_change = Some((cmd, Vec::new()));
+            }
+        }
+        actions
+    }
+
+    fn execute_visual(&mut self, cmd: Cmd, buffer: &mut Buffer) -> Vec<VimAction> {
+        match cmd.action {
+            Action::Move(motion) => {
+                let (pos, _) = buffer.motion(motion, cmd.count, false);
+                buffer.cursor = pos;
+                buffer.clamp_normal();
+            }
+            Action::Operate(operator, _) => {
+                let (start, end) = self.visual_range(buffer);
+                let linewise = self.mode == Mode::VisualLine;
+                self.mode = Mode::Normal;
+                if linewise {
+                    let (start, end) = buffer.line_range(start, end.saturating_sub(1), operator == Operator::Change);
+                    self.operate(buffer, operator, start, end, true, cmd.register);
+                } else {
+                    self.operate(buffer, operator, start, end, false, cmd.register);
+                }
+            }
+            Action::Put { .. } => {
+                let register = self.registers.get(&cmd.register.unwrap_or('"')).cloned().unwrap_or_default();
+                let (start, end) = self.visual_range(buffer);
+                self.mode = Mode::Normal;
+                buffer.delete(start, end);
+                buffer.cursor = start;
+                buffer.insert(start, &register.text);
+                buffer.cursor = (start + register.text.chars().count()).saturating_sub(1);
+                buffer.clamp_normal();
+            }
+            Action::Visual { linewise } => {
+                let mode = if linewise { Mode::VisualLine } else { Mode::Visual };
+                self.mode = if self.mode == mode { Mode::Normal } else { mode };
+            }
+            Action::SwapAnchor => std::mem::swap(&mut self.visual_anchor, &mut buffer.cursor),
+            Action::Cancel => self.mode = Mode::Normal,
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    // Half-open char range covered by the visual selection
+    fn visual_range(&self, buffer: &Buffer) -> (usize, usize) {
+        let start = self.visual_anchor.min(buffer.cursor);
+        let end = self.visual_anchor.max(buffer.cursor);
+        if self.mode == Mode::VisualLine {
+            let line_end = buffer.line_end(end);
+            (buffer.line_start(start), (line_end + 1).min(buffer.text.len()))
+        } else {
+            (start, (end + 1).min(buffer.text.len()))
+        }
+    }
+
+    fn target_range(
+        &self,
+        buffer: &Buffer,
+        operator: Operator,
+        target: Target,
+        count: Option<usize>,
+    ) -> Option<(usize, usize, bool)> {
+        let cursor = buffer.cursor;
+        match target {
+            Target::Line => {
+                let last = buffer.line_offset(cursor, count.unwrap_or(1) - 1);
+                let (start, end) = buffer.line_range(cursor, last, operator == Operator::Change);
+                Some((start, end, true))
+            }
+            Target::TextObject { inner, object } => {
+                let (start, end) = buffer.text_object(inner, object)?;
+                Some((start, end, false))
+            }
+            Target::Motion(motion) => {
+                // `cw` on a word behaves like `ce`
+                let motion = match motion {
+                    Motion::WordForward { big }
+                        if operator == Operator::Change
+                            && cursor < buffer.text.len()
+                            && !buffer.text[cursor].is_whitespace() =>
+                    {
+                        Motion::WordEnd { big }
+                    }
+                    other => other,
+                };
+                let (mut pos, kind) = buffer.motion(motion, count, true);
+
+                // `dw` on the last word of a line stops at the end of that line
+                if let Motion::WordForward { .. } = motion {
+                    let line_end = buffer.line_end(cursor);
+                    if pos > line_end && cursor < line_end {
+                        pos = line_end;
+                    }
+                }
+
+                let (start, end) = (cursor.min(pos), cursor.max(pos));
+                match kind {
+                    MotionKind::Exclusive => Some((start, end, false)),
+                    MotionKind::Inclusive => Some((start, (end + 1).min(buffer.text.len()), false)),
+                    MotionKind::Linewise => {
+                        let (start, end) = buffer.line_range(start, end, operator == Operator::Change);
+                        Some((start, end, true))
+                    }
+                }
+            }
+        }
+    }
+
+    fn operate(
+        &mut self,
+        buffer: &mut Buffer,
+        operator: Operator,
+        start: usize,
+        end: usize,
+        linewise: bool,
+        register: Option<char>,
+    ) {
+        if start == end && !linewise && operator != Operator::Change {
+            return;
+        }
+
+        let mut text = buffer.slice(start, end);
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.store_register(register, Register { text, linewise });
+
+        match operator {
+            Operator::Yank => {
+                buffer.cursor = start;
+                buffer.clamp_normal();
+            }
+            Operator::Delete => {
+                // Deleting the last line also removes the newline before it
+                let start = if linewise && end == buffer.text.len() && start > 0 && buffer.text[end - 1] != '\n' {
+                    start - 1
+                } else {
+                    start
+                };
+                buffer.delete(start, end);
+                buffer.cursor = if linewise {
+                    buffer.first_non_blank(start.min(buffer.text.len()))
+                } else {
+                    start
+                };
+                buffer.clamp_normal();
+            }
+            Operator::Change => {
+                buffer.delete(start, end);
+                buffer.cursor = start;
+                self.mode = Mode::Insert;
+            }
+        }
+    }
+
+    fn store_register(&mut self, register: Option<char>, value: Register) {
+        match register {
+            Some('_') => {}
+            Some(name) if name.is_ascii_uppercase() => {
+                let entry = self.registers.entry(name.to_ascii_lowercase()).or_default();
+                entry.text.push_str(&value.text);
+                entry.linewise |= value.linewise;
+                let appended = entry.clone();
+                self.registers.insert('"', appended);
+            }
+            Some(name) => {
+                self.registers.insert(name, value.clone());
+                self.registers.insert('"', value);
+            }
+            None => {
+                self.registers.insert('"', value);
+            }
+        }
+    }
+}
+
+impl Default for Vim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn vim_key(key: Key, ctrl: bool) -> Option<VimKey> {
+    if ctrl {
+        // Letter keys are the only ones egui reports with their name as a single char
+        let name = key.name();
+        let mut chars = name.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Some(VimKey::Ctrl(c.to_ascii_lowercase())),
+            _ => None,
+        };
+    }
+    match key {
+        Key::Escape => Some(VimKey::Escape),
+        Key::Enter => Some(VimKey::Enter),
+        Key::Backspace => Some(VimKey::Backspace),
+        Key::ArrowLeft => Some(VimKey::Left),
+        Key::ArrowRight => Some(VimKey::Right),
+        Key::ArrowUp => Some(VimKey::Up),
+        Key::ArrowDown => Some(VimKey::Down),
+        _ => None,
+    }
+}
+
+fn is_change(action: Action) -> bool {
+    matches!(
+        action,
+        Action::Operate(Operator::Delete | Operator::Change, _)
+            | Action::Insert(_)
+            | Action::Put { .. }
+            | Action::Replace(_)
+            | Action::Join
+    )
+}
+
+// Parses `["x][count]command`, where an operator may take its own count and a motion
+fn parse(keys: &[VimKey], visual: bool) -> Parsed {
+    let mut i = 0;
+    let mut register = None;
+    if keys.first() == Some(&VimKey::Char('"')) {
+        match keys.get(1) {
+            None => return Parsed::Incomplete,
+            Some(VimKey::Char(c)) if c.is_ascii_alphanumeric() || *c == '"' || *c == '_' => register = Some(*c),
+            Some(_) => return Parsed::Invalid,
+        }
+        i = 2;
+    }
+
+    let (count, next) = parse_count(keys, i);
+    i = next;
+    let Some(&key) = keys.get(i) else {
+        return Parsed::Incomplete;
+    };
+
+    let complete = |action| Parsed::Complete(Cmd { register, count, action });
+
+    let operator = match key {
+        VimKey::Char('d') => Some(Operator::Delete),
+        VimKey::Char('c') => Some(Operator::Change),
+        VimKey::Char('y') => Some(Operator::Yank),
+        _ => None,
+    };
+    if let Some(operator) = operator {
+        // In visual mode the selection is the target
+        if visual {
+            return complete(Action::Operate(operator, Target::Line));
+        }
+
+        let (motion_count, j) = parse_count(keys, i + 1);
+        let count = match (count, motion_count) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(1) * b.unwrap_or(1)),
+        };
+        let Some(&next_key) = keys.get(j) else {
+            return Parsed::Incomplete;
+        };
+        let target = if next_key == key {
+            Target::Line
+        } else if let VimKey::Char(c @ ('i' | 'a')) = next_key {
+            match keys.get(j + 1) {
+                None => return Parsed::Incomplete,
+                Some(VimKey::Char(object)) => Target::TextObject { inner: c == 'i', object: *object },
+                Some(_) => return Parsed::Invalid,
+            }
+        } else {
+            match parse_motion(&keys[j..]) {
+                Ok(Some(motion)) => Target::Motion(motion),
+                Ok(None) => return Parsed::Incomplete,
+                Err(()) => return Parsed::Invalid,
+            }
+        };
+        return Parsed::Complete(Cmd { register, count, action: Action::Operate(operator, target) });
+    }
+
+    let action = match key {
+        VimKey::Char('x') if visual => Action::Operate(Operator::Delete, Target::Line),
+        VimKey::Char('s') if visual => Action::Operate(Operator::Change, Target::Line),
+        VimKey::Char('o') if visual => Action::SwapAnchor,
+        VimKey::Char('x') => Action::Operate(Operator::Delete, Target::Motion(Motion::Right)),
+        VimKey::Char('X') => Action::Operate(Operator::Delete, Target::Motion(Motion::Left)),
+        VimKey::Char('D') => Action::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        VimKey::Char('C') => Action::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+        VimKey::Char('Y') => Action::Operate(Operator::Yank, Target::Line),
+        VimKey::Char('s') => Action::Operate(Operator::Change, Target::Motion(Motion::Right)),
+        VimKey::Char('S') => Action::Operate(Operator::Change, Target::Line),
+        VimKey::Char(c @ ('i' | 'a' | 'I' | 'A' | 'o' | 'O')) => Action::Insert(c),
+        VimKey::Char('p') => Action::Put { before: false },
+        VimKey::Char('P') => Action::Put { before: true },
+        VimKey::Char('r') => match keys.get(i + 1) {
+            None => return Parsed::Incomplete,
+            Some(VimKey::Char(c)) => Action::Replace(*c),
+            Some(_) => return Parsed::Invalid,
+        },
+        VimKey::Char('J') => Action::Join,
+        VimKey::Char('u') => Action::Undo,
+        VimKey::Ctrl('r') => Action::Redo,
+        VimKey::Char('.') => Action::Repeat,
+        VimKey::Char('v') => Action::Visual { linewise: false },
+        VimKey::Char('V') => Action::Visual { linewise: true },
+        VimKey::Char('/') => Action::Search,
+        VimKey::Char('n') => Action::SearchNext,
+        VimKey::Char('N') => Action::SearchPrevious,
+        VimKey::Escape => Action::Cancel,
+        _ => match parse_motion(&keys[i..]) {
+            Ok(Some(motion)) => Action::Move(motion),
+            Ok(None) => return Parsed::Incomplete,
+            Err(()) => return Parsed::Invalid,
+        },
+    };
+    complete(action)
+}
+
+fn parse_count(keys: &[VimKey], start: usize) -> (Option<usize>, usize) {
+    let mut i = start;
+    let mut count: Option<usize> = None;
+    while let Some(VimKey::Char(c)) = keys.get(i) {
+        let Some(digit) = c.to_digit(10) else {
+            break;
+        };
+        // A leading 0 is the "start of line" motion, not a count
+        if digit == 0 && count.is_none() {
+            break;
+        }
+        count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit as usize));
+        i += 1;
+    }
+    (count, i)
+}
+
+// Ok(None) means more keys are needed
+fn parse_motion(keys: &[VimKey]) -> Result<Option<Motion>, ()> {
+    let Some(&key) = keys.first() else {
+        return Ok(None);
+    };
+    let motion = match key {
+        VimKey::Char('h') | VimKey::Left | VimKey::Backspace => Motion::Left,
+        VimKey::Char('l') | VimKey::Right | VimKey::Char(' ') => Motion::Right,
+        VimKey::Char('j') | VimKey::Down => Motion::Down,
+        VimKey::Char('k') | VimKey::Up => Motion::Up,
+        VimKey::Char('w') => Motion::WordForward { big: false },
+        VimKey::Char('W') => Motion::WordForward { big: true },
+        VimKey::Char('b') => Motion::WordBackward { big: false },
+        VimKey::Char('B') => Motion::WordBackward { big: true },
+        VimKey::Char('e') => Motion::WordEnd { big: false },
+        VimKey::Char('E') => Motion::WordEnd { big: true },
+        VimKey::Char('0') => Motion::LineStart,
+        VimKey::Char('^') => Motion::FirstNonBlank,
+        VimKey::Char('$') => Motion::LineEnd,
+        VimKey::Char('G') => Motion::FileEnd,
+        VimKey::Char('g') => match keys.get(1) {
+            None => return Ok(None),
+            Some(VimKey::Char('g')) => Motion::FileStart,
+            Some(_) => return Err(()),
+        },
+        VimKey::Char(c @ ('f' | 'F' | 't' | 'T')) => match keys.get(1) {
+            None => return Ok(None),
+            Some(VimKey::Char(target)) => Motion::FindChar {
+                c: *target,
+                forward: c == 'f' || c == 't',
+                till: c == 't' || c == 'T',
+            },
+            Some(_) => return Err(()),
+        },
+        _ => return Err(()),
+    };
+    Ok(Some(motion))
+}
+
+// 0 = whitespace, 1 = keyword characters, 2 = other punctuation
+fn char_class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+impl Buffer {
+    fn new(content: &str, cursor: usize) -> Self {
+        let text: Vec<char> = content.chars().collect();
+        let cursor = cursor.min(text.len());
+        Self { text, cursor, changed: false }
+    }
+
+    fn slice(&self, start: usize, end: usize) -> String {
+        self.text[start.min(self.text.len())..end.min(self.text.len())].iter().collect()
+    }
+
+    fn delete(&mut self, start: usize, end: usize) {
+        let end = end.min(self.text.len());
+        if start < end {
+            self.text.drain(start..end);
+            self.changed = true;
+        }
+    }
+
+    fn insert(&mut self, pos: usize, text: &str) {
+        let pos = pos.min(self.text.len());
+        self.text.splice(pos..pos, text.chars());
+        self.changed |= !text.is_empty();
+    }
+
+    // Applies a key typed in insert mode, used when replaying a change with `.`
+    fn insert_key(&mut self, key: VimKey) {
+        match key {
+            VimKey::Char(c) => {
+                self.insert(self.cursor, &c.to_string());
+                self.cursor += 1;
+            }
+            VimKey::Enter => {
+                self.insert(self.cursor, "\n");
+                self.cursor += 1;
+            }
+            VimKey::Backspace if self.cursor > 0 => {
+                self.delete(self.cursor - 1, self.cursor);
+                self.cursor -= 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        let pos = pos.min(self.text.len());
+        self.text[..pos].iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1)
+    }
+
+    // Index of the line's newline, or the end of the text on the last line
+    fn line_end(&self, pos: usize) -> usize {
+        let pos = pos.min(self.text.len());
+        self.text[pos..].iter().position(|&c| c == '\n').map_or(self.text.len(), |i| pos + i)
+    }
+
+    fn first_non_blank(&self, pos: usize) -> usize {
+        let start = self.line_start(pos);
+        let end = self.line_end(pos);
+        (start..end).find(|&i| self.text[i] != ' ' && self.text[i] != '\t').unwrap_or(end)
+    }
+
+    // Start of the line `lines` lines below `pos`, stopping at the last line
+    fn line_offset(&self, pos: usize, lines: usize) -> usize {
+        let mut pos = pos;
+        for _ in 0..lines {
+            let end = self.line_end(pos);
+            if end >= self.text.len() {
+                break;
+            }
+            pos = end + 1;
+        }
+        pos
+    }
+
+    // Somewhere on the line `lines` lines above `pos`, stopping at the first line
+    fn line_up(&self, pos: usize, lines: usize) -> usize {
+        let mut pos = pos;
+        for _ in 0..lines {
+            let start = self.line_start(pos);
+            if start == 0 {
+                break;
+            }
+            pos = start - 1;
+        }
+        pos
+    }
+
+    // Char range of the lines containing `first` through `last`. With
+    // `keep_line` the final newline stays so `cc` leaves an empty line behind.
+    fn line_range(&self, first: usize, last: usize, keep_line: bool) -> (usize, usize) {
+        let start = self.line_start(first);
+        let end = self.line_end(last);
+        if keep_line {
+            (self.first_non_blank(first).min(end), end)
+        } else {
+            (start, (end + 1).min(self.text.len()))
+        }
+    }
+
+    // Normal mode keeps the cursor on a character, never on a line's newline
+    fn clamp_normal(&mut self) {
+        self.cursor = self.cursor.min(self.text.len());
+        let start = self.line_start(self.cursor);
+        let end = self.line_end(self.cursor);
+        if self.cursor >= end && end > start {
+            self.cursor = end - 1;
+        }
+    }
+
+    fn column(&self, pos: usize) -> usize {
+        pos - self.line_start(pos)
+    }
+
+    fn motion(&self, motion: Motion, count: Option<usize>, for_operator: bool) -> (usize, MotionKind) {
+        let n = count.unwrap_or(1);
+        let pos = self.cursor;
+        match motion {
+            Motion::Left => (pos.saturating_sub(n).max(self.line_start(pos)), MotionKind::Exclusive),
+            Motion::Right => {
+                let end = self.line_end(pos);
+                let limit = if for_operator { end } else { end.saturating_sub(1).max(self.line_start(pos)) };
+                ((pos + n).min(limit), MotionKind::Exclusive)
+            }
+            Motion::Down | Motion::Up => {
+                let column = self.column(pos);
+                let line = if motion == Motion::Down { self.line_offset(pos, n) } else { self.line_up(pos, n) };
+                let start = self.line_start(line);
+                let end = self.line_end(line);
+                ((start + column).min(end), MotionKind::Linewise)
+            }
+            Motion::WordForward { big } => {
+                let mut p = pos;
+                for _ in 0..n {
+                    p = self.word_forward(p, big);
+                }
+                (p, MotionKind::Exclusive)
+            }
+            Motion::WordBackward { big } => {
+                let mut p = pos;
+                for _ in 0..n {
+                    p = self.word_backward(p, big);
+                }
+                (p, MotionKind::Exclusive)
+            }
+            Motion::WordEnd { big } => {
+                let mut p = pos;
+                for _ in 0..n {
+                    p = self.word_end(p, big);
+                }
+                (p, MotionKind::Inclusive)
+            }
+            Motion::LineStart => (self.line_start(pos), MotionKind::Exclusive),
+            Motion::FirstNonBlank => (self.first_non_blank(pos), MotionKind::Exclusive),
+            Motion::LineEnd => {
+                let line = self.line_offset(pos, n - 1);
+                let end = self.line_end(line);
+                let start = self.line_start(line);
+                (if end > start { end - 1 } else { start }, MotionKind::Inclusive)
+            }
+            Motion::FileStart => {
+                let line = self.line_offset(0, count.map_or(0, |c| c - 1));
+                (self.first_non_blank(line), MotionKind::Linewise)
+            }
+            Motion::FileEnd => {
+                let line = match count {
+                    Some(c) => self.line_offset(0, c - 1),
+                    None => self.line_start(self.text.len()),
+                };
+                (self.first_non_blank(line), MotionKind::Linewise)
+            }
+            Motion::FindChar { c, forward, till } => {
+                let start = self.line_start(pos);
+                let end = self.line_end(pos);
+                let mut p = pos;
+                for _ in 0..n {
+                    let found = if forward {
+                        let from = if till { p + 2 } else { p + 1 };
+                        (from.min(end)..end).find(|&i| self.text[i] == c)
+                    } else {
+                        let to = if till { p.saturating_sub(1) } else { p };
+                        (start..to.max(start)).rev().find(|&i| self.text[i] == c)
+                    };
+                    match found {
+                        Some(i) => p = i,
+                        None => return (pos, MotionKind::Exclusive),
+                    }
+                }
+                if till {
+                    p = if forward { p - 1 } else { p + 1 };
+                }
+                (p, if forward { MotionKind::Inclusive } else { MotionKind::Exclusive })
+            }
+        }
+    }
+
+    fn word_forward(&self, pos: usize, big: bool) -> usize {
+        let len = self.text.len();
+        let mut i = pos;
+        if i >= len {
+            return len;
+        }
+        let class = char_class(self.text[i], big);
+        if class != 0 {
+            while i < len && char_class(self.text[i], big) == class {
+                i += 1;
+            }
+        }
+        while i < len && char_class(self.text[i], big) == 0 {
+            // An empty line counts as a word
+            if self.text[i] == '\n' && i + 1 < len && self.text[i + 1] == '\n' {
+                return i + 1;
+            }
+            i += 1;
+        }
+        i
+    }
+
+    fn word_backward(&self, pos: usize, big: bool) -> usize {
+        if pos == 0 {
+            return 0;
+        }
+        let mut i = pos - 1;
+        while i > 0 && char_class(self.text[i], big) == 0 {
+            if self.text[i] == '\n' && self.text[i - 1] == '\n' {
+                return i;
+            }
+            i -= 1;
+        }
+        let class = char_class(self.text[i], big);
+        while i > 0 && char_class(self.text[i - 1], big) == class {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end(&self, pos: usize, big: bool) -> usize {
+        let len = self.text.len();
+        if len == 0 {
+            return 0;
+        }
+        let mut i = pos + 1;
+        while i < len && char_class(self.text[i], big) == 0 {
+            i += 1;
+        }
+        if i >= len {
+            return len - 1;
+        }
+        let class = char_class(self.text[i], big);
+        while i + 1 < len && char_class(self.text[i + 1], big) == class {
+            i += 1;
+        }
+        i
+    }
+
+    // Half-open range of `iw`, `aw`, `i(`, `a"` and friends
+    fn text_object(&self, inner: bool, object: char) -> Option<(usize, usize)> {
+        match object {
+            'w' | 'W' => self.word_object(inner, object == 'W'),
+            '(' | ')' | 'b' => self.pair_object(inner, '(', ')'),
+            '[' | ']' => self.pair_object(inner, '[', ']'),
+            '{' | '}' | 'B' => self.pair_object(inner, '{', '}'),
+            '<' | '>' => self.pair_object(inner, '<', '>'),
+            '"' | '\'' | '`' => self.quote_object(inner, object),
+            _ => None,
+        }
+    }
+
+    fn word_object(&self, inner: bool, big: bool) -> Option<(usize, usize)> {
+        let len = self.text.len();
+        if self.cursor >= len || self.text[self.cursor] == '\n' {
+            return None;
+        }
+        let class = char_class(self.text[self.cursor], big);
+        let same = |i: usize| self.text[i] != '\n' && char_class(self.text[i], big) == class;
+        let mut start = self.cursor;
+        while start > 0 && same(start - 1) {
+            start -= 1;
+        }
+        let mut end = self.cursor + 1;
+        while end < len && same(end) {
+            end += 1;
+        }
+        if inner {
+            return Some((start, end));
+        }
+
+        // `aw` also takes the trailing blanks, or the leading ones at the end of a line
+        let blank = |i: usize| self.text[i] == ' ' || self.text[i] == '\t';
+        let mut with_trailing = end;
+        while with_trailing < len && blank(with_trailing) {
+            with_trailing += 1;
+        }
+        if with_trailing > end {
+            return Some((start, with_trailing));
+        }
+        while start > 0 && blank(start - 1) {
+            start -= 1;
+        }
+        Some((start, end))
+    }
+
+    fn pair_object(&self, inner: bool, open: char, close: char) -> Option<(usize, usize)> {
+        let len = self.text.len();
+        let mut depth = 0;
+        let mut start = None;
+        let mut i = self.cursor.min(len.saturating_sub(1)) + 1;
+        while i > 0 {
+            i -= 1;
+            let c = self.text[i];
+            if c == close && i != self.cursor {
+                depth += 1;
+            } else if c == open {
+                if depth == 0 {
+                    start = Some(i);
+                    break;
+                }
+                depth -= 1;
+            }
+        }
+        let start = start?;
+
+        let mut depth = 0;
+        let mut end = None;
+        for (i, &c) in self.text.iter().enumerate().skip(start + 1) {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                if depth == 0 {
+                    end = Some(i);
+                    break;
+                }
+                depth -= 1;
+            }
+        }
+        let end = end?;
+        if inner {
+            Some((start + 1, end))
+        } else {
+            Some((start, end + 1))
+        }
+    }
+
+    fn quote_object(&self, inner: bool, quote: char) -> Option<(usize, usize)> {
+        let line_start = self.line_start(self.cursor);
+        let line_end = self.line_end(self.cursor);
+        let quotes: Vec<usize> = (line_start..line_end).filter(|&i| self.text[i] == quote).collect();
+        let (open, close) = quotes
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|&(open, close)| open <= self.cursor && self.cursor <= close)
+            .or_else(|| {
+                // Like Vim, fall back to the first quoted string after the cursor
+                quotes
+                    .chunks(2)
+                    .filter(|pair| pair.len() == 2)
+                    .map(|pair| (pair[0], pair[1]))
+                    .find(|&(open, _)| open > self.cursor)
+            })?;
+        if inner {
+            Some((open + 1, close))
+        } else {
+            Some((open, close + 1))
+        }
+    }
+
+    fn put(&mut self, text: &str, linewise: bool, before: bool) {
+        if linewise {
+            let pos = if before {
+                self.line_start(self.cursor)
+            } else {
+                let end = self.line_end(self.cursor);
+                if end >= self.text.len() {
+                    // Last line has no newline to insert after
+                    self.insert(end, "\n");
+                    let trimmed = text.strip_suffix('\n').unwrap_or(text);
+                    self.insert(end + 1, trimmed);
+                    self.cursor = self.first_non_blank(end + 1);
+                    return;
+                }
+                end + 1
+            };
+            self.insert(pos, text);
+            self.cursor = self.first_non_blank(pos);
+        } else {
+            let pos = if before || self.cursor >= self.line_end(self.cursor) {
+                self.cursor
+            } else {
+                self.cursor + 1
+            };
+            self.insert(pos, text);
+            self.cursor = pos + text.chars().count() - 1;
+        }
+    }
+
+    // `J`: joins the next line onto this one with a single space
+    fn join_line(&mut self) {
+        let end = self.line_end(self.cursor);
+        if end >= self.text.len() {
+            return;
+        }
+        let mut next = end + 1;
+        while next < self.text.len() && (self.text[next] == ' ' || self.text[next] == '\t') {
+            next += 1;
+        }
+        let separator = if next < self.text.len() && self.text[next] != '\n' && end > self.line_start(self.cursor) {
+            " "
+        } else {
+            ""
+        };
+        self.delete(end, next);
+        self.insert(end, separator);
+        self.cursor = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `keys` to a fresh Vim in normal mode; `<Esc>` is Escape and `|` in
+    // `text` marks the cursor. Returns the text with the cursor marked the same way.
+    fn run(vim: &mut Vim, text: &str, keys: &str) -> String {
+        let cursor = text.chars().position(|c| c == '|').expect("cursor marker");
+        let mut buffer = Buffer::new(&text.replacen('|', "", 1), cursor);
+        let mut rest = keys;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("<Esc>") {
+                vim.feed(VimKey::Escape, &mut buffer);
+                rest = after;
+            } else {
+                vim.feed(VimKey::Char(c), &mut buffer);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        let mut result: String = buffer.text[..buffer.cursor].iter().collect();
+        result.push('|');
+        result.extend(&buffer.text[buffer.cursor..]);
+        result
+    }
+
+    fn keys(text: &str, keys: &str) -> String {
+        run(&mut Vim::new(), text, keys)
+    }
+
+    #[test]
+    fn motions() {
+        assert_eq!(keys("|foo bar.baz", "w"), "foo |bar.baz");
+        assert_eq!(keys("|foo bar.baz", "ww"), "foo bar|.baz");
+        assert_eq!(keys("|foo bar.baz qux", "W"), "foo |bar.baz qux");
+        assert_eq!(keys("foo ba|r", "b"), "foo |bar");
+        assert_eq!(keys("|foo bar", "e"), "fo|o bar");
+        assert_eq!(keys("  fo|o bar", "0"), "|  foo bar");
+        assert_eq!(keys("  fo|o bar", "^"), "  |foo bar");
+        assert_eq!(keys("|foo bar\nbaz", "$"), "foo ba|r\nbaz");
+        assert_eq!(keys("one\ntw|o\nthree", "gg"), "|one\ntwo\nthree");
+        assert_eq!(keys("o|ne\ntwo\nthree", "G"), "one\ntwo\n|three");
+        assert_eq!(keys("o|ne\ntwo\nthree", "j"), "one\nt|wo\nthree");
+        assert_eq!(keys("one\ntwo\nth|ree", "k"), "one\ntw|o\nthree");
+        assert_eq!(keys("|abc", "l"), "a|bc");
+        assert_eq!(keys("ab|c", "l"), "ab|c");
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(keys("|a b c d e", "3w"), "a b c |d e");
+        assert_eq!(keys("|1\n2\n3\n4", "2j"), "1\n2\n|3\n4");
+        assert_eq!(keys("|abcdef", "3x"), "|def");
+        assert_eq!(keys("|a b c d", "2dw"), "|c d");
+        assert_eq!(keys("|a b c d", "d2w"), "|c d");
+        assert_eq!(keys("|1\n2\n3\n4\n5", "2d2d"), "|5");
+    }
+
+    #[test]
+    fn change_inner_word() {
+        assert_eq!(keys("foo b|ar baz", "ciwnew<Esc>"), "foo ne|w baz");
+        assert_eq!(keys("foo b|ar baz", "cawx<Esc>"), "foo |xbaz");
+        assert_eq!(keys("call(a, |b)", "ci(x<Esc>"), "call(|x)");
+        assert_eq!(keys("say \"hel|lo\"", "ci\"bye<Esc>"), "say \"by|e\"");
+    }
+
+    #[test]
+    fn delete_lines() {
+        assert_eq!(keys("one\nt|wo\nthree", "dd"), "one\n|three");
+        assert_eq!(keys("one\ntwo\nthr|ee", "dd"), "one\n|two");
+        assert_eq!(keys("|one\ntwo\nthree", "2dd"), "|three");
+        assert_eq!(keys("|one\ntwo\nthree", "ddp"), "two\n|one\nthree");
+        assert_eq!(keys("|one\ntwo", "yyP"), "|one\none\ntwo");
+    }
+
+    #[test]
+    fn repeat() {
+        assert_eq!(keys("|a b c d", "dw."), "|c d");
+        assert_eq!(keys("|a b c d", "dw2."), "|d");
+        assert_eq!(keys("|one two three", "ciwX<Esc>w."), "X |X three");
+        assert_eq!(keys("|one\ntwo", "Ahi<Esc>j."), "onehi\ntwoh|i");
+        assert_eq!(keys("|1\n2\n3\n4", "dd."), "|3\n4");
+        assert_eq!(keys("|abc", "rx."), "|xbc");
+    }
+
+    #[test]
+    fn escape_cancels_pending_keys() {
+        let mut vim = Vim::new();
+        assert_eq!(run(&mut vim, "|one two", "d<Esc>w"), "one |two");
+        assert_eq!(vim.mode(), Mode::Normal);
+        assert!(vim.pending_keys().is_empty());
+    }
+}
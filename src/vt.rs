@@ -0,0 +1,856 @@
+use std::collections::VecDeque;
+use std::mem;
+use vte::{Params, Parser, Perform};
+
+// Lines kept above the main screen
+const SCROLLBACK: usize = 10_000;
+const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Color {
+    #[default]
+    Default,
+    // 0-15 are the ANSI colours, the rest the xterm 256-colour palette
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Style {
+    pub foreground: Color,
+    pub background: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Cell {
+    pub c: char,
+    pub style: Style,
+}
+
+impl Cell {
+    // Erasing keeps the current background, like xterm
+    fn blank(style: Style) -> Self {
+        Self {
+            c: ' ',
+            style: Style {
+                background: style.background,
+                ..Style::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Line {
+    pub cells: Vec<Cell>,
+    // The text continues on the next line because it wrapped there
+    pub wrapped: bool,
+}
+
+impl Line {
+    fn new(columns: usize, style: Style) -> Self {
+        Self {
+            cells: vec![Cell::blank(style); columns],
+            wrapped: false,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let text: String = self.cells.iter().map(|cell| cell.c).collect();
+        text.trim_end().to_string()
+    }
+}
+
+// A position in the scrollback and screen. Lines are numbered from the first
+// line ever written, so a position stays put while the scrollback moves.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Point {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Cursor {
+    row: usize,
+    column: usize,
+    style: Style,
+    // `ESC ( 0` switches to the DEC line drawing characters
+    line_drawing: bool,
+}
+
+// The state of a VT100/xterm terminal, fed with a program's output
+pub struct Screen {
+    parser: Parser,
+    rows: usize,
+    columns: usize,
+    lines: Vec<Line>,
+    scrollback: VecDeque<Line>,
+    // Lines that fell off the front of the scrollback
+    dropped: usize,
+    // The main screen, while a full-screen program uses the alternate one
+    main_screen: Option<(Vec<Line>, Cursor)>,
+    cursor: Cursor,
+    saved_cursor: Cursor,
+    // Set after printing in the last column; the next character wraps
+    wrap_pending: bool,
+    // Inclusive rows that scroll, set with DECSTBM
+    scroll_top: usize,
+    scroll_bottom: usize,
+    last_char: Option<char>,
+    autowrap: bool,
+    insert_mode: bool,
+    application_cursor: bool,
+    bracketed_paste: bool,
+    cursor_visible: bool,
+    title: Option<String>,
+    // Answers to queries such as the cursor position, to be written back to the program
+    replies: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new(rows: usize, columns: usize) -> Self {
+        let (rows, columns) = (rows.max(1), columns.max(2));
+        Self {
+            parser: Parser::new(),
+            rows,
+            columns,
+            lines: vec![Line::new(columns, Style::default()); rows],
+            scrollback: VecDeque::new(),
+            dropped: 0,
+            main_screen: None,
+            cursor: Cursor::default(),
+            saved_cursor: Cursor::default(),
+            wrap_pending: false,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            last_char: None,
+            autowrap: true,
+            insert_mode: false,
+            application_cursor: false,
+            bracketed_paste: false,
+            cursor_visible: true,
+            title: None,
+            replies: Vec::new(),
+        }
+    }
+
+    pub fn process(&mut self, bytes: &[u8]) {
+        let mut parser = mem::take(&mut self.parser);
+        parser.advance(self, bytes);
+        self.parser = parser;
+    }
+
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        mem::take(&mut self.replies)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    // Arrow keys send `ESC O A` instead of `ESC [ A`
+    pub fn application_cursor(&self) -> bool {
+        self.application_cursor
+    }
+
+    // Pasted text is wrapped in `ESC [200~` and `ESC [201~`
+    pub fn bracketed_paste(&self) -> bool {
+        self.bracketed_paste
+    }
+
+    pub fn is_alternate(&self) -> bool {
+        self.main_screen.is_some()
+    }
+
+    // The first line still kept
+    pub fn first_line(&self) -> usize {
+        self.dropped
+    }
+
+    // The line at the top of the screen
+    pub fn screen_top(&self) -> usize {
+        self.dropped + self.scrollback.len()
+    }
+
+    pub fn line(&self, number: usize) -> Option<&Line> {
+        let index = number.checked_sub(self.dropped)?;
+        match index.checked_sub(self.scrollback.len()) {
+            None => self.scrollback.get(index),
+            Some(row) => self.lines.get(row),
+        }
+    }
+
+    pub fn cursor(&self) -> Option<Point> {
+        self.cursor_visible.then(|| Point {
+            line: self.screen_top() + self.cursor.row,
+            column: self.cursor.column,
+        })
+    }
+
+    // The text from `start` up to `end`, exclusive; wrapped lines are joined
+    pub fn text(&self, start: Point, end: Point) -> String {
+        let mut text = String::new();
+        for number in start.line..=end.line {
+            let Some(line) = self.line(number) else {
+                continue;
+            };
+            let from = if number == start.line { start.column } else { 0 };
+            let to = if number == end.line { end.column } else { line.cells.len() };
+            let part: String = line.cells[from.min(line.cells.len())..to.min(line.cells.len())]
+                .iter()
+                .map(|cell| cell.c)
+                .collect();
+            if line.wrapped && number != end.line {
+                text.push_str(&part);
+            } else {
+                text.push_str(part.trim_end());
+                if number != end.line {
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+
+    // Lines that no longer fit move into the scrollback. Text isn't reflowed.
+    pub fn resize(&mut self, rows: usize, columns: usize) {
+        let (rows, columns) = (rows.max(1), columns.max(2));
+        if (rows, columns) == (self.rows, self.columns) {
+            return;
+        }
+        let style = self.cursor.style;
+        let alternate = self.is_alternate();
+        // Blank lines below the cursor go first, then lines at the top
+        let scrolled = fit_lines(&mut self.lines, rows, columns, self.cursor.row, style);
+        self.cursor.row = self.cursor.row.saturating_sub(scrolled.len()).min(rows - 1);
+        if !alternate {
+            for line in scrolled {
+                self.push_scrollback(line);
+            }
+        }
+        if let Some((lines, cursor)) = &mut self.main_screen {
+            let scrolled = fit_lines(lines, rows, columns, cursor.row, style);
+            cursor.row = cursor.row.saturating_sub(scrolled.len()).min(rows - 1);
+            cursor.column = cursor.column.min(columns - 1);
+            for line in scrolled {
+                self.scrollback.push_back(line);
+            }
+        }
+
+        self.rows = rows;
+        self.columns = columns;
+        self.cursor.column = self.cursor.column.min(columns - 1);
+        self.saved_cursor.row = self.saved_cursor.row.min(rows - 1);
+        self.saved_cursor.column = self.saved_cursor.column.min(columns - 1);
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.wrap_pending = false;
+        self.trim_scrollback();
+    }
+
+    fn push_scrollback(&mut self, line: Line) {
+        self.scrollback.push_back(line);
+        self.trim_scrollback();
+    }
+
+    fn trim_scrollback(&mut self) {
+        while self.scrollback.len() > SCROLLBACK {
+            self.scrollback.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    fn blank_line(&self) -> Line {
+        Line::new(self.columns, self.cursor.style)
+    }
+
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.cursor.row = row.min(self.rows - 1);
+        self.cursor.column = column.min(self.columns - 1);
+        self.wrap_pending = false;
+    }
+
+    fn linefeed(&mut self) {
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+        self.wrap_pending = false;
+    }
+
+    fn reverse_index(&mut self) {
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(1);
+        } else {
+            self.cursor.row = self.cursor.row.saturating_sub(1);
+        }
+        self.wrap_pending = false;
+    }
+
+    // Moves the lines of the scroll region up; off the top of a full-height
+    // region of the main screen, they go into the scrollback
+    fn scroll_up(&mut self, count: usize) {
+        let count = count.min(self.scroll_bottom - self.scroll_top + 1);
+        for _ in 0..count {
+            let line = self.lines.remove(self.scroll_top);
+            self.lines.insert(self.scroll_bottom, self.blank_line());
+            if self.scroll_top == 0 && !self.is_alternate() {
+                self.push_scrollback(line);
+            }
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        let count = count.min(self.scroll_bottom - self.scroll_top + 1);
+        for _ in 0..count {
+            self.lines.remove(self.scroll_bottom);
+            self.lines.insert(self.scroll_top, self.blank_line());
+        }
+    }
+
+    fn insert_lines(&mut self, count: usize) {
+        if !(self.scroll_top..=self.scroll_bottom).contains(&self.cursor.row) {
+            return;
+        }
+        for _ in 0..count.min(self.scroll_bottom - self.cursor.row + 1) {
+            self.lines.remove(self.scroll_bottom);
+            self.lines.insert(self.cursor.row, self.blank_line());
+        }
+        self.cursor.column = 0;
+        self.wrap_pending = false;
+    }
+
+    fn delete_lines(&mut self, count: usize) {
+        if !(self.scroll_top..=self.scroll_bottom).contains(&self.cursor.row) {
+            return;
+        }
+        for _ in 0..count.min(self.scroll_bottom - self.cursor.row + 1) {
+            self.lines.remove(self.cursor.row);
+            self.lines.insert(self.scroll_bottom, self.blank_line());
+        }
+        self.cursor.column = 0;
+        self.wrap_pending = false;
+    }
+
+    fn erase_cells(&mut self, row: usize, columns: std::ops::Range<usize>) {
+        let blank = Cell::blank(self.cursor.style);
+        let line = &mut self.lines[row];
+        let end = columns.end.min(line.cells.len());
+        for cell in &mut line.cells[columns.start.min(end)..end] {
+            *cell = blank;
+        }
+        if end == line.cells.len() {
+            line.wrapped = false;
+        }
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        let (row, column) = (self.cursor.row, self.cursor.column);
+        match mode {
+            0 => {
+                self.erase_cells(row, column..self.columns);
+                for row in row + 1..self.rows {
+                    self.lines[row] = self.blank_line();
+                }
+            }
+            1 => {
+                for row in 0..row {
+                    self.lines[row] = self.blank_line();
+                }
+                self.erase_cells(row, 0..column + 1);
+            }
+            2 => {
+                for row in 0..self.rows {
+                    self.lines[row] = self.blank_line();
+                }
+            }
+            3 => {
+                self.dropped += self.scrollback.len();
+                self.scrollback.clear();
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let (row, column) = (self.cursor.row, self.cursor.column);
+        match mode {
+            0 => self.erase_cells(row, column..self.columns),
+            1 => self.erase_cells(row, 0..column + 1),
+            2 => self.erase_cells(row, 0..self.columns),
+            _ => {}
+        }
+    }
+
+    fn insert_blanks(&mut self, count: usize) {
+        let blank = Cell::blank(self.cursor.style);
+        let (column, columns) = (self.cursor.column, self.columns);
+        let cells = &mut self.lines[self.cursor.row].cells;
+        for _ in 0..count.min(columns - column) {
+            cells.insert(column, blank);
+        }
+        cells.truncate(columns);
+    }
+
+    fn delete_chars(&mut self, count: usize) {
+        let blank = Cell::blank(self.cursor.style);
+        let (column, columns) = (self.cursor.column, self.columns);
+        let cells = &mut self.lines[self.cursor.row].cells;
+        let count = count.min(cells.len().saturating_sub(column));
+        cells.drain(column..column + count);
+        cells.resize(columns, blank);
+    }
+
+    fn set_alternate(&mut self, on: bool) {
+        if on && self.main_screen.is_none() {
+            let lines = vec![self.blank_line(); self.rows];
+            let main = mem::replace(&mut self.lines, lines);
+            self.main_screen = Some((main, self.cursor));
+        } else if !on {
+            if let Some((lines, _)) = self.main_screen.take() {
+                self.lines = lines;
+            }
+        }
+        self.wrap_pending = false;
+    }
+
+    fn set_private_mode(&mut self, mode: u16, on: bool) {
+        match mode {
+            1 => self.application_cursor = on,
+            7 => self.autowrap = on,
+            25 => self.cursor_visible = on,
+            47 | 1047 => self.set_alternate(on),
+            1049 => {
+                if on {
+                    self.saved_cursor = self.cursor;
+                    self.set_alternate(true);
+                } else {
+                    self.set_alternate(false);
+                    self.cursor = self.saved_cursor;
+                }
+            }
+            2004 => self.bracketed_paste = on,
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        let style = &mut self.cursor.style;
+        if params.is_empty() {
+            *style = Style::default();
+            return;
+        }
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            match param[0] {
+                0 => *style = Style::default(),
+                1 => style.bold = true,
+                2 => style.dim = true,
+                3 => style.italic = true,
+                // `4:0` is the colon form of "no underline"
+                4 => style.underline = param.get(1) != Some(&0),
+                7 => style.inverse = true,
+                21 | 22 => (style.bold, style.dim) = (false, false),
+                23 => style.italic = false,
+                24 => style.underline = false,
+                27 => style.inverse = false,
+                n @ 30..=37 => style.foreground = Color::Indexed(n as u8 - 30),
+                38 => style.foreground = extended_color(param, &mut params).unwrap_or(style.foreground),
+                39 => style.foreground = Color::Default,
+                n @ 40..=47 => style.background = Color::Indexed(n as u8 - 40),
+                48 => style.background = extended_color(param, &mut params).unwrap_or(style.background),
+                49 => style.background = Color::Default,
+                n @ 90..=97 => style.foreground = Color::Indexed(n as u8 - 90 + 8),
+                n @ 100..=107 => style.background = Color::Indexed(n as u8 - 100 + 8),
+                _ => {}
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        let mut screen = Self::new(self.rows, self.columns);
+        screen.scrollback = mem::take(&mut self.scrollback);
+        screen.dropped = self.dropped;
+        screen.parser = mem::take(&mut self.parser);
+        *self = screen;
+    }
+}
+
+// Brings `lines` to `rows` lines of `columns` cells and returns the lines
+// scrolled off the top
+fn fit_lines(lines: &mut Vec<Line>, rows: usize, columns: usize, cursor_row: usize, style: Style) -> Vec<Line> {
+    for line in lines.iter_mut() {
+        line.cells.resize(columns, Cell::blank(Style::default()));
+    }
+    let mut scrolled = Vec::new();
+    if rows < lines.len() {
+        let below = lines.len() - cursor_row - 1;
+        let trailing_blank = lines.iter().rev().take(below).take_while(|line| line.text().is_empty()).count();
+        lines.truncate(lines.len() - trailing_blank.min(lines.len() - rows));
+        let excess = lines.len().saturating_sub(rows);
+        scrolled.extend(lines.drain(..excess));
+        lines.truncate(rows);
+    }
+    while lines.len() < rows {
+        lines.push(Line::new(columns, style));
+    }
+    scrolled
+}
+
+// The colour after 38 or 48: `5;n` or `2;r;g;b`, or the same separated by colons
+fn extended_color<'a>(param: &[u16], rest: &mut impl Iterator<Item = &'a [u16]>) -> Option<Color> {
+    let values: Vec<u16> = if param.len() > 1 {
+        param[1..].to_vec()
+    } else {
+        let kind = rest.next()?[0];
+        let count = if kind == 2 { 3 } else { 1 };
+        std::iter::once(kind).chain(rest.take(count).map(|p| p[0])).collect()
+    };
+    match values.as_slice() {
+        [5, n, ..] => Some(Color::Indexed(*n as u8)),
+        // The colon form may have a colour space id before the components
+        [2, .., r, g, b] => Some(Color::Rgb(*r as u8, *g as u8, *b as u8)),
+        _ => None,
+    }
+}
+
+fn line_drawing(c: char) -> char {
+    match c {
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'q' => '─',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'a' => '▒',
+        '`' => '◆',
+        '~' => '·',
+        c => c,
+    }
+}
+
+impl Perform for Screen {
+    fn print(&mut self, c: char) {
+        let c = if self.cursor.line_drawing { line_drawing(c) } else { c };
+        if self.wrap_pending {
+            if self.autowrap {
+                self.lines[self.cursor.row].wrapped = true;
+                self.cursor.column = 0;
+                self.linefeed();
+            }
+            self.wrap_pending = false;
+        }
+        if self.insert_mode {
+            self.insert_blanks(1);
+        }
+        let cell = Cell { c, style: self.cursor.style };
+        self.lines[self.cursor.row].cells[self.cursor.column] = cell;
+        if self.cursor.column + 1 < self.columns {
+            self.cursor.column += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+        self.last_char = Some(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // Backspace
+            0x08 => {
+                self.cursor.column = self.cursor.column.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            // Tab
+            0x09 => {
+                let next = (self.cursor.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.move_to(self.cursor.row, next);
+            }
+            // Line feed, vertical tab and form feed
+            0x0a..=0x0c => self.linefeed(),
+            // Carriage return
+            0x0d => {
+                self.cursor.column = 0;
+                self.wrap_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+        let args: Vec<u16> = params.iter().map(|param| param[0]).collect();
+        // Missing and zero arguments both mean the default
+        let arg = |index: usize, default: usize| args.get(index).map_or(default, |&v| if v == 0 { default } else { v as usize });
+        let (row, column) = (self.cursor.row, self.cursor.column);
+
+        match (intermediates, action) {
+            ([b'?'], 'h') | ([b'?'], 'l') => {
+                for &mode in &args {
+                    self.set_private_mode(mode, action == 'h');
+                }
+            }
+            ([], 'h') | ([], 'l') if args.contains(&4) => self.insert_mode = action == 'h',
+            ([], 'm') => self.select_graphic_rendition(params),
+            ([], 'A') | ([], 'F') => {
+                let top = if row >= self.scroll_top { self.scroll_top } else { 0 };
+                let column = if action == 'F' { 0 } else { column };
+                self.move_to(row.saturating_sub(arg(0, 1)).max(top), column);
+            }
+            ([], 'B') | ([], 'e') | ([], 'E') => {
+                let bottom = if row <= self.scroll_bottom { self.scroll_bottom } else { self.rows - 1 };
+                let column = if action == 'E' { 0 } else { column };
+                self.move_to((row + arg(0, 1)).min(bottom), column);
+            }
+            ([], 'C') | ([], 'a') => self.move_to(row, column + arg(0, 1)),
+            ([], 'D') => self.move_to(row, column.saturating_sub(arg(0, 1))),
+            ([], 'G') | ([], '`') => self.move_to(row, arg(0, 1) - 1),
+            ([], 'H') | ([], 'f') => self.move_to(arg(0, 1) - 1, arg(1, 1) - 1),
+            ([], 'd') => self.move_to(arg(0, 1) - 1, column),
+            ([], 'J') => self.erase_display(args.first().copied().unwrap_or(0) as usize),
+            ([], 'K') => self.erase_line(args.first().copied().unwrap_or(0) as usize),
+            ([], 'L') => self.insert_lines(arg(0, 1)),
+            ([], 'M') => self.delete_lines(arg(0, 1)),
+            ([], 'P') => self.delete_chars(arg(0, 1)),
+            ([], '@') => self.insert_blanks(arg(0, 1)),
+            ([], 'X') => self.erase_cells(row, column..column + arg(0, 1)),
+            ([], 'S') => self.scroll_up(arg(0, 1)),
+            ([], 'T') => self.scroll_down(arg(0, 1)),
+            ([], 'b') => {
+                if let Some(c) = self.last_char {
+                    for _ in 0..arg(0, 1).min(self.columns * self.rows) {
+                        self.print(c);
+                    }
+                }
+            }
+            ([], 'r') => {
+                let (top, bottom) = (arg(0, 1) - 1, arg(1, self.rows).min(self.rows) - 1);
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            ([], 's') => self.saved_cursor = self.cursor,
+            ([], 'u') => {
+                self.cursor = self.saved_cursor;
+                self.wrap_pending = false;
+            }
+            ([], 'n') => match arg(0, 0) {
+                5 => self.replies.extend_from_slice(b"\x1b[0n"),
+                6 => self.replies.extend(format!("\x1b[{};{}R", row + 1, column + 1).bytes()),
+                _ => {}
+            },
+            // Identify as a VT100 with advanced video
+            ([], 'c') => self.replies.extend_from_slice(b"\x1b[?1;2c"),
+            ([b'>'], 'c') => self.replies.extend_from_slice(b"\x1b[>0;10;1c"),
+            ([b'!'], 'p') => {
+                self.cursor.style = Style::default();
+                self.insert_mode = false;
+                self.application_cursor = false;
+                self.cursor_visible = true;
+                self.scroll_top = 0;
+                self.scroll_bottom = self.rows - 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        match (intermediates, byte) {
+            ([], b'7') => self.saved_cursor = self.cursor,
+            ([], b'8') => {
+                self.cursor = self.saved_cursor;
+                self.wrap_pending = false;
+            }
+            ([], b'D') => self.linefeed(),
+            ([], b'E') => {
+                self.cursor.column = 0;
+                self.linefeed();
+            }
+            ([], b'M') => self.reverse_index(),
+            ([], b'c') => self.reset(),
+            ([b'('], b'0') => self.cursor.line_drawing = true,
+            ([b'('], _) => self.cursor.line_drawing = false,
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        if let [b"0" | b"2", title, ..] = params {
+            self.title = Some(String::from_utf8_lossy(title).into_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(rows: usize, columns: usize, output: &str) -> Screen {
+        let mut screen = Screen::new(rows, columns);
+        screen.process(output.as_bytes());
+        screen
+    }
+
+    // The visible rows, without trailing blanks
+    fn rows(screen: &Screen) -> Vec<String> {
+        (0..screen.rows())
+            .map(|row| screen.line(screen.screen_top() + row).unwrap().text())
+            .collect()
+    }
+
+    fn cursor(screen: &Screen) -> (usize, usize) {
+        let point = screen.cursor().unwrap();
+        (point.line - screen.screen_top(), point.column)
+    }
+
+    fn style_at(screen: &Screen, row: usize, column: usize) -> Style {
+        screen.line(screen.screen_top() + row).unwrap().cells[column].style
+    }
+
+    #[test]
+    fn graphic_rendition() {
+        let screen = screen(2, 20, "a\x1b[1;31mb\x1b[4;38;5;200mc\x1b[48;2;1;2;3md\x1b[0me\x1b[7;92mf\x1b[22;39mg");
+        assert_eq!(rows(&screen)[0], "abcdefg");
+        assert_eq!(style_at(&screen, 0, 0), Style::default());
+        let b = style_at(&screen, 0, 1);
+        assert!(b.bold);
+        assert_eq!(b.foreground, Color::Indexed(1));
+        let c = style_at(&screen, 0, 2);
+        assert!(c.bold && c.underline);
+        assert_eq!(c.foreground, Color::Indexed(200));
+        assert_eq!(style_at(&screen, 0, 3).background, Color::Rgb(1, 2, 3));
+        assert_eq!(style_at(&screen, 0, 4), Style::default());
+        let f = style_at(&screen, 0, 5);
+        assert!(f.inverse);
+        assert_eq!(f.foreground, Color::Indexed(10));
+        let g = style_at(&screen, 0, 6);
+        assert!(g.inverse && !g.bold);
+        assert_eq!(g.foreground, Color::Default);
+    }
+
+    #[test]
+    fn cursor_movement() {
+        let mut screen = screen(5, 10, "\x1b[3;4HX");
+        assert_eq!(rows(&screen)[2], "   X");
+        assert_eq!(cursor(&screen), (2, 4));
+
+        screen.process(b"\x1b[2A\x1b[3DY");
+        assert_eq!(rows(&screen)[0], " Y");
+        screen.process(b"\x1b[HZ\x1b[B\x1b[5CW");
+        assert_eq!(rows(&screen)[0], "ZY");
+        assert_eq!(rows(&screen)[1], "      W");
+        // Movement stops at the edges
+        screen.process(b"\x1b[99;99H");
+        assert_eq!(cursor(&screen), (4, 9));
+        screen.process(b"\x1b[99A\x1b[99D");
+        assert_eq!(cursor(&screen), (0, 0));
+
+        screen.process(b"\x1b[5;1Habc\rd\x08\x08e");
+        assert_eq!(rows(&screen)[4], "ebc");
+        screen.process(b"\x1b7\x1b[1;1H\x1b8f");
+        assert_eq!(rows(&screen)[4], "efc");
+    }
+
+    #[test]
+    fn wrapping_and_erasing() {
+        let mut screen = screen(3, 4, "abcdef");
+        assert_eq!(rows(&screen), ["abcd", "ef", ""]);
+        assert!(screen.line(screen.screen_top()).unwrap().wrapped);
+
+        screen.process(b"\x1b[1;3H\x1b[K");
+        assert_eq!(rows(&screen), ["ab", "ef", ""]);
+        screen.process(b"\x1b[2J");
+        assert_eq!(rows(&screen), ["", "", ""]);
+    }
+
+    #[test]
+    fn scrolling_into_the_scrollback() {
+        let screen = screen(2, 10, "one\r\ntwo\r\nthree");
+        assert_eq!(rows(&screen), ["two", "three"]);
+        assert_eq!(screen.screen_top(), 1);
+        assert_eq!(screen.line(0).unwrap().text(), "one");
+    }
+
+    #[test]
+    fn scroll_regions() {
+        let mut screen = screen(5, 10, "1\r\n2\r\n3\r\n4\r\n5");
+        // Rows 2-4 scroll; the first and last stay put
+        screen.process(b"\x1b[2;4r\x1b[4;1H\nnew");
+        assert_eq!(rows(&screen), ["1", "3", "4", "new", "5"]);
+        assert_eq!(screen.screen_top(), 0);
+
+        // Reverse index at the top of the region scrolls it down
+        screen.process(b"\x1b[2;1H\x1bMtop");
+        assert_eq!(rows(&screen), ["1", "top", "3", "4", "5"]);
+
+        screen.process(b"\x1b[3;1H\x1b[L");
+        assert_eq!(rows(&screen), ["1", "top", "", "3", "5"]);
+        screen.process(b"\x1b[2M");
+        assert_eq!(rows(&screen), ["1", "top", "", "", "5"]);
+
+        // Resetting the region scrolls the whole screen again
+        screen.process(b"\x1b[r\x1b[5;1H\nend");
+        assert_eq!(rows(&screen), ["top", "", "", "5", "end"]);
+    }
+
+    #[test]
+    fn alternate_screen() {
+        let mut screen = screen(3, 10, "shell\r\n$ ");
+        screen.process(b"\x1b[?1049h");
+        assert!(screen.is_alternate());
+        assert_eq!(rows(&screen), ["", "", ""]);
+        screen.process(b"\x1b[Hfull\r\nscreen\r\napp\r\nscrolls");
+        assert_eq!(rows(&screen), ["screen", "app", "scrolls"]);
+
+        screen.process(b"\x1b[?1049l");
+        assert!(!screen.is_alternate());
+        assert_eq!(rows(&screen), ["shell", "$", ""]);
+        assert_eq!(cursor(&screen), (1, 2));
+        // Nothing the full-screen program drew reaches the scrollback
+        assert_eq!(screen.screen_top(), 0);
+    }
+
+    #[test]
+    fn resize() {
+        let mut screen = screen(4, 10, "one\r\ntwo\r\nthree");
+        screen.resize(2, 10);
+        assert_eq!(rows(&screen), ["two", "three"]);
+        assert_eq!(screen.line(screen.first_line()).unwrap().text(), "one");
+        assert_eq!(cursor(&screen), (1, 5));
+
+        screen.resize(2, 3);
+        assert_eq!(rows(&screen), ["two", "thr"]);
+        assert_eq!(cursor(&screen), (1, 2));
+
+        screen.resize(3, 6);
+        assert_eq!(screen.rows(), 3);
+        screen.process(b"\r\n123456");
+        assert_eq!(rows(&screen)[2], "123456");
+    }
+
+    #[test]
+    fn replies_to_queries() {
+        let mut screen = screen(5, 10, "\x1b[2;3H\x1b[6n");
+        assert_eq!(screen.take_replies(), b"\x1b[2;3R");
+        assert!(screen.take_replies().is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::diagnostics::{self, Position};
+use crate::diff::{self, Change, DiffLine};
+use crate::lsp;
+use egui::{Color32, Context, RichText};
+use serde_json::Value;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// A line and UTF-16 column, as servers send them
+#[derive(Clone, Copy)]
+struct LspPosition {
+    line: usize,
+    character: usize,
+}
+
+impl LspPosition {
+    fn parse(value: &Value) -> Option<Self> {
+        Some(Self {
+            line: value["line"].as_u64()? as usize,
+            character: value["character"].as_u64()? as usize,
+        })
+    }
+
+    fn byte_offset(self, text: &str) -> usize {
+        let Some(line_text) = text.split('\n').nth(self.line) else {
+            return text.len();
+        };
+        let column = lsp::char_column(line_text, self.character);
+        diagnostics::byte_offset(text, Position { line: self.line, column })
+    }
+}
+
+struct TextEdit {
+    start: LspPosition,
+    end: LspPosition,
+    new_text: String,
+}
+
+// The edits to one file
+pub struct FileEdit {
+    pub path: PathBuf,
+    edits: Vec<TextEdit>,
+}
+
+impl FileEdit {
+    // All positions refer to the original text, so the edits are applied in
+    // order of position; an edit overlapping an earlier one is dropped
+    pub fn apply(&self, text: &str) -> String {
+        let mut ranges: Vec<(Range<usize>, &str)> = self
+            .edits
+            .iter()
+            .map(|edit| {
+                let start = edit.start.byte_offset(text);
+                (start..edit.end.byte_offset(text).max(start), edit.new_text.as_str())
+            })
+            .collect();
+        ranges.sort_by_key(|(range, _)| range.start);
+
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for (range, new_text) in ranges {
+            if range.start < last {
+                continue;
+            }
+            result.push_str(&text[last..range.start]);
+            result.push_str(new_text);
+            last = range.end;
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+}
+
+// A list of `TextEdit`s, as in a formatting response
+pub fn text_edits(path: PathBuf, edits: &Value) -> FileEdit {
+    let edits = edits.as_array().into_iter().flatten().filter_map(|edit| {
+        Some(TextEdit {
+            start: LspPosition::parse(&edit["range"]["start"])?,
+            end: LspPosition::parse(&edit["range"]["end"])?,
+            new_text: edit["newText"].as_str()?.to_string(),
+        })
+    });
+    FileEdit { path, edits: edits.collect() }
+}
+
+// A `WorkspaceEdit` from a rename or code action, by file. Creating, renaming
+// and deleting files isn't supported; those operations are skipped.
+pub fn parse(value: &Value) -> Vec<FileEdit> {
+    let mut files: Vec<FileEdit> = Vec::new();
+    let mut add = |uri: &Value, edits: &Value| {
+        let Some(path) = uri.as_str().and_then(lsp::uri_to_path) else {
+            return;
+        };
+        let edit = text_edits(path, edits);
+        match files.iter_mut().find(|file| file.path == edit.path) {
+            Some(file) => file.edits.extend(edit.edits),
+            None => files.push(edit),
+        }
+    };
+
+    if let Some(changes) = value["documentChanges"].as_array() {
+        for change in changes.iter().filter(|change| change.get("textDocument").is_some()) {
+            add(&change["textDocument"]["uri"], &change["edits"]);
+        }
+    } else if let Some(changes) = value["changes"].as_object() {
+        for (uri, edits) in changes {
+            add(&Value::String(uri.clone()), edits);
+        }
+    }
+    files.retain(|file| !file.edits.is_empty());
+    files
+}
+
+pub enum PreviewAction {
+    Apply,
+    Cancel,
+}
+
+struct PreviewFile {
+    edit: FileEdit,
+    // The changed lines, or why the file can't be changed
+    lines: Result<Vec<DiffLine>, String>,
+    open: bool,
+    include: bool,
+}
+
+// The changes of a workspace edit, shown as diffs before they are applied.
+// Files can be left out.
+pub struct EditPreview {
+    title: String,
+    files: Vec<PreviewFile>,
+}
+
+impl EditPreview {
+    // `open_text` gives the text of files open in tabs; other files are read from disk
+    pub fn new(title: String, edits: Vec<FileEdit>, open_text: impl Fn(&Path) -> Option<String>) -> Self {
+        let files = edits
+            .into_iter()
+            .map(|edit| {
+                let open = open_text(&edit.path);
+                let text = match &open {
+                    Some(text) => Ok(text.clone()),
+                    None => std::fs::read_to_string(&edit.path).map_err(|e| e.to_string()),
+                };
+                let lines = text.map(|text| {
+                    let mut lines = diff::diff_lines(&text, &edit.apply(&text));
+                    lines.retain(|line| line.change != Change::Same);
+                    lines
+                });
+                PreviewFile {
+                    include: lines.is_ok(),
+                    lines,
+                    open: open.is_some(),
+                    edit,
+                }
+            })
+            .collect();
+        Self { title, files }
+    }
+
+    // The files left in when the preview is applied
+    pub fn into_edits(self) -> Vec<FileEdit> {
+        self.files.into_iter().filter(|file| file.include).map(|file| file.edit).collect()
+    }
+
+    pub fn show(&mut self, ctx: &Context, root: Option<&Path>) -> Option<PreviewAction> {
+        let mut action = None;
+        egui::Window::new(format!("Preview: {}", self.title))
+            .id(egui::Id::new("edit_preview"))
+            .collapsible(false)
+            .resizable(true)
+            .default_size([640.0, 400.0])
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                let included = self.files.iter().filter(|file| file.include).count();
+                ui.weak(format!("{} of {} files will be changed", included, self.files.len()));
+                ui.separator();
+
+                egui::ScrollArea::vertical().max_height(ctx.screen_rect().height() * 0.6).show(ui, |ui| {
+                    for file in &mut self.files {
+                        let path = &file.edit.path;
+                        let name = root
+                            .and_then(|root| path.strip_prefix(root).ok())
+                            .unwrap_or(path)
+                            .display()
+                            .to_string();
+                        let id = ui.make_persistent_id(("edit_preview", path));
+                        egui::collapsing_header::CollapsingState::load_with_default_open(ctx, id, true)
+                            .show_header(ui, |ui| {
+                                ui.add_enabled(file.lines.is_ok(), egui::Checkbox::new(&mut file.include, name));
+                                ui.weak(if file.open { "open" } else { "on disk" });
+                            })
+                            .body(|ui| match &file.lines {
+                                Ok(lines) => {
+                                    for line in lines {
+                                        let (number, sign, color) = match line.change {
+                                            Change::Removed => (line.old_line, '-', Color32::from_rgb(255, 100, 100)),
+                                            _ => (line.new_line, '+', Color32::from_rgb(100, 200, 100)),
+                                        };
+                                        let text = format!("{:>5} {} {}", number.unwrap_or_default(), sign, line.text);
+                                        ui.label(RichText::new(text).monospace().color(color));
+                                    }
+                                }
+                                Err(e) => {
+                                    ui.colored_label(Color32::from_rgb(255, 100, 100), e);
+                                }
+                            });
+                    }
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Apply").clicked() {
+                        action = Some(PreviewAction::Apply);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        action = Some(PreviewAction::Cancel);
+                    }
+                    ui.weak("Enter applies, Escape cancels");
+                });
+            });
+
+        let (enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        if enter {
+            action = Some(PreviewAction::Apply);
+        }
+        if escape {
+            action = Some(PreviewAction::Cancel);
+        }
+        action
+    }
+}